@@ -370,10 +370,34 @@ pub async fn save_config(
             .await;
         // [NEW] 更新 User-Agent 配置
         instance.axum_server.update_user_agent(&config.proxy).await;
+        // [NEW] 更新上游端点配置
+        instance
+            .axum_server
+            .update_upstream_base_urls(&config.proxy)
+            .await;
+        // [NEW] 更新提示词屏蔽规则
+        instance.axum_server.update_prompt_filter(&config.proxy).await;
+        // [NEW] 更新终端用户限流配置
+        instance
+            .axum_server
+            .update_user_rate_limit(&config.proxy)
+            .await;
+        // 更新流式响应缓冲配置
+        instance.axum_server.update_streaming(&config.proxy).await;
         // 更新 Thinking Budget 配置
         crate::proxy::update_thinking_budget_config(config.proxy.thinking_budget.clone());
         // [NEW] 更新全局系统提示词配置
         crate::proxy::update_global_system_prompt_config(config.proxy.global_system_prompt.clone());
+        // 更新图片接口配置
+        crate::proxy::update_image_config(config.proxy.image.clone());
+        // 更新 OpenAI 兼容性配置
+        crate::proxy::update_openai_compat_config(config.proxy.openai_compat.clone());
+        // 更新按模型配置
+        crate::proxy::update_model_profiles(config.proxy.model_profiles.clone());
+        // 更新未知模型兜底配置
+        crate::proxy::update_default_model_config(config.proxy.default_model.clone());
+        // 更新访问日志配置
+        crate::proxy::update_access_log_config(config.proxy.access_log.clone());
         // 更新代理池配置
         instance
             .axum_server
@@ -384,6 +408,13 @@ pub async fn save_config(
             .token_manager
             .update_circuit_breaker_config(config.circuit_breaker.clone())
             .await;
+        // 更新账号用量统计与工作负载路由配置
+        instance
+            .token_manager
+            .update_account_usage_config(config.proxy.account_usage.clone());
+        instance
+            .token_manager
+            .update_workload_routing_config(config.proxy.workload_routing.clone());
         tracing::debug!("已同步热更新反代服务配置");
     }
 
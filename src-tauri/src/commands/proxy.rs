@@ -163,6 +163,8 @@ pub async fn internal_start_proxy_service(
 
     // 3. 加載賬號
     let active_accounts = token_manager.load_accounts().await.unwrap_or(0);
+    // [NEW] 预热并定期预刷新即将过期的 token
+    token_manager.start_token_prerefresh().await;
 
     if active_accounts == 0 {
         let zai_enabled = config.zai.enabled
@@ -236,7 +238,12 @@ pub async fn ensure_admin_server(
     let app_data_dir = crate::modules::account::get_data_dir()?;
     let token_manager = Arc::new(TokenManager::new(app_data_dir));
     // [NEW] 加载账号数据，否则管理界面统计为 0
-    let _ = token_manager.load_accounts().await;
+    let loaded = token_manager.load_accounts().await.unwrap_or(0);
+    if loaded == 0 {
+        tracing::warn!(
+            "⚠️ No accounts configured: API requests will be rejected with 503 \"no accounts configured\" until an account is added."
+        );
+    }
 
     let (axum_server, server_handle) = match crate::proxy::AxumServer::start(
         config.get_bind_address().to_string(),
@@ -260,6 +267,21 @@ pub async fn ensure_admin_server(
         Ok((server, handle)) => (server, handle),
         Err(e) => return Err(format!("启动管理服务器失败: {}", e)),
     };
+    // 初始化自定义上游端点
+    axum_server.update_upstream_base_urls(&config).await;
+    // 初始化提示词屏蔽规则
+    axum_server.update_prompt_filter(&config).await;
+    // 初始化终端用户限流配置
+    axum_server.update_user_rate_limit(&config).await;
+    // 初始化流式响应缓冲配置
+    axum_server.update_streaming(&config).await;
+    // 初始化账号用量统计与工作负载路由配置
+    axum_server
+        .token_manager
+        .update_account_usage_config(config.account_usage.clone());
+    axum_server
+        .token_manager
+        .update_workload_routing_config(config.workload_routing.clone());
 
     *admin_lock = Some(AdminServerInstance {
         axum_server,
@@ -270,6 +292,16 @@ pub async fn ensure_admin_server(
     crate::proxy::update_thinking_budget_config(config.thinking_budget.clone());
     // [NEW] 初始化全局系统提示词配置
     crate::proxy::update_global_system_prompt_config(config.global_system_prompt.clone());
+    // 初始化图片接口配置
+    crate::proxy::update_image_config(config.image.clone());
+    // 初始化 OpenAI 兼容性配置
+    crate::proxy::update_openai_compat_config(config.openai_compat.clone());
+    // 初始化按模型配置
+    crate::proxy::update_model_profiles(config.model_profiles.clone());
+    // 初始化未知模型兜底配置
+    crate::proxy::update_default_model_config(config.default_model.clone());
+    // 初始化访问日志配置
+    crate::proxy::update_access_log_config(config.access_log.clone());
 
     Ok(())
 }
@@ -37,6 +37,25 @@ fn increase_nofile_limit() {
     }
 }
 
+/// Wait for Ctrl-C, or SIGTERM on Unix (sent by container runtimes during rolling deploys)
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => info!("Received SIGTERM"),
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to install SIGTERM handler: {}", e),
+        }
+    }
+    tokio::signal::ctrl_c().await.ok();
+}
+
 // Test command
 #[tauri::command]
 fn greet(name: &str) -> String {
@@ -209,9 +228,16 @@ pub fn run() {
                 }
             }
 
-            // Wait for Ctrl-C
-            tokio::signal::ctrl_c().await.ok();
+            // Wait for Ctrl-C / SIGTERM
+            wait_for_shutdown_signal().await;
             info!("Headless mode shutting down");
+
+            // [NEW] 优雅停机: 停止接受新连接，等待进行中的请求 (含流式响应) 完成
+            let admin = proxy_state.admin_server.write().await.take();
+            if let Some(admin) = admin {
+                admin.axum_server.stop();
+                let _ = admin.server_handle.await;
+            }
         });
         return;
     }
@@ -144,17 +144,54 @@ pub struct CircuitBreakerConfig {
     /// Default: [60, 300, 1800, 7200]
     #[serde(default = "default_backoff_steps")]
     pub backoff_steps: Vec<u64>,
+
+    /// Consecutive 5xx failures on one (account, model) pair before its circuit opens
+    #[serde(default = "default_model_failure_threshold")]
+    pub model_failure_threshold: u32,
+
+    /// Window (seconds) in which those failures must occur
+    #[serde(default = "default_model_failure_window_seconds")]
+    pub model_failure_window_seconds: u64,
+
+    /// How long (seconds) an open (account, model) circuit is skipped before half-open
+    #[serde(default = "default_model_open_seconds")]
+    pub model_open_seconds: u64,
+
+    /// Once every account in the pool is rate limited, new requests fail fast with 429
+    /// for this many seconds, letting one probe request through per interval (0 disables)
+    #[serde(default = "default_pool_outage_seconds")]
+    pub pool_outage_seconds: u64,
 }
 
 fn default_backoff_steps() -> Vec<u64> {
     vec![60, 300, 1800, 7200]
 }
 
+fn default_model_failure_threshold() -> u32 {
+    3
+}
+
+fn default_model_failure_window_seconds() -> u64 {
+    60
+}
+
+fn default_model_open_seconds() -> u64 {
+    30
+}
+
+fn default_pool_outage_seconds() -> u64 {
+    15
+}
+
 impl CircuitBreakerConfig {
     pub fn new() -> Self {
         Self {
             enabled: true,
             backoff_steps: default_backoff_steps(),
+            model_failure_threshold: default_model_failure_threshold(),
+            model_failure_window_seconds: default_model_failure_window_seconds(),
+            model_open_seconds: default_model_open_seconds(),
+            pool_outage_seconds: default_pool_outage_seconds(),
         }
     }
 }
@@ -1,4 +1,5 @@
 use std::fs;
+use std::path::{Path, PathBuf};
 use serde_json;
 
 use crate::models::AppConfig;
@@ -6,10 +7,14 @@ use super::account::get_data_dir;
 
 const CONFIG_FILE: &str = "gui_config.json";
 
+/// Path of the persisted application configuration file
+pub fn get_config_path() -> Result<PathBuf, String> {
+    Ok(get_data_dir()?.join(CONFIG_FILE))
+}
+
 /// Load application configuration
 pub fn load_app_config() -> Result<AppConfig, String> {
-    let data_dir = get_data_dir()?;
-    let config_path = data_dir.join(CONFIG_FILE);
+    let config_path = get_config_path()?;
     
     if !config_path.exists() {
         let config = AppConfig::new();
@@ -18,7 +23,20 @@ pub fn load_app_config() -> Result<AppConfig, String> {
         return Ok(config);
     }
     
-    let content = fs::read_to_string(&config_path)
+    let (config, modified) = read_app_config(&config_path)?;
+    
+    // If migration occurred, auto-save once to clean up the file
+    if modified {
+        let _ = save_app_config(&config);
+    }
+
+    Ok(config)
+}
+
+/// Read and migrate a configuration file without writing it back.
+/// Returns the config and whether a legacy-field migration was applied.
+pub fn read_app_config(config_path: &Path) -> Result<(AppConfig, bool), String> {
+    let content = fs::read_to_string(config_path)
         .map_err(|e| format!("failed_to_read_config_file: {}", e))?;
     
     let mut v: serde_json::Value = serde_json::from_str(&content)
@@ -69,19 +87,13 @@ pub fn load_app_config() -> Result<AppConfig, String> {
 
     let config: AppConfig = serde_json::from_value(v)
         .map_err(|e| format!("failed_to_convert_config_after_migration: {}", e))?;
-    
-    // If migration occurred, auto-save once to clean up the file
-    if modified {
-        let _ = save_app_config(&config);
-    }
 
-    Ok(config)
+    Ok((config, modified))
 }
 
 /// Save application configuration
 pub fn save_app_config(config: &AppConfig) -> Result<(), String> {
-    let data_dir = get_data_dir()?;
-    let config_path = data_dir.join(CONFIG_FILE);
+    let config_path = get_config_path()?;
     
     let content = serde_json::to_string_pretty(config)
         .map_err(|e| format!("failed_to_serialize_config: {}", e))?;
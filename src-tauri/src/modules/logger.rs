@@ -25,6 +25,14 @@ pub fn get_log_dir() -> Result<PathBuf, String> {
     Ok(log_dir)
 }
 
+/// Whether structured JSON logging is enabled (ABV_LOG_FORMAT=json)
+/// Default remains the human-readable format
+pub fn is_json_log_format() -> bool {
+    std::env::var("ABV_LOG_FORMAT")
+        .map(|v| v.trim().eq_ignore_ascii_case("json"))
+        .unwrap_or(false)
+}
+
 /// Initialize the log system
 pub fn init_logger() {
     // Capture log macro logs
@@ -43,20 +51,42 @@ pub fn init_logger() {
     let file_appender = tracing_appender::rolling::daily(log_dir, "app.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
     
+    // JSON mode: span fields (trace_id, account_email, mapped_model, ...) are emitted as structured fields
+    let json_format = is_json_log_format();
+
     // 2. Console output layer (using local timezone)
-    let console_layer = fmt::Layer::new()
-        .with_target(false)
-        .with_thread_ids(false)
-        .with_level(true)
-        .with_timer(LocalTimer);
-        
+    let console_layer = (!json_format).then(|| {
+        fmt::Layer::new()
+            .with_target(false)
+            .with_thread_ids(false)
+            .with_level(true)
+            .with_timer(LocalTimer)
+    });
+    let console_json_layer = json_format.then(|| {
+        fmt::Layer::new()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_timer(LocalTimer)
+    });
+
     // 3. File output layer (disable ANSI formatting, use local timezone)
-    let file_layer = fmt::Layer::new()
-        .with_writer(non_blocking)
-        .with_ansi(false)
-        .with_target(true)
-        .with_level(true)
-        .with_timer(LocalTimer);
+    let file_layer = (!json_format).then(|| {
+        fmt::Layer::new()
+            .with_writer(non_blocking.clone())
+            .with_ansi(false)
+            .with_target(true)
+            .with_level(true)
+            .with_timer(LocalTimer)
+    });
+    let file_json_layer = json_format.then(|| {
+        fmt::Layer::new()
+            .json()
+            .with_writer(non_blocking)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_timer(LocalTimer)
+    });
 
     // 4. Set filtering layer (default to INFO level to reduce log size)
     let filter_layer = EnvFilter::try_from_default_env()
@@ -69,7 +99,9 @@ pub fn init_logger() {
     let _ = tracing_subscriber::registry()
         .with(filter_layer)
         .with(console_layer)
+        .with(console_json_layer)
         .with(file_layer)
+        .with(file_json_layer)
         .with(bridge_layer)
         .try_init();
 
@@ -77,7 +109,10 @@ pub fn init_logger() {
     // Recommended practice when using tracing_appender::non_blocking (if manual flushing is not needed)
     std::mem::forget(_guard);
     
-    info!("Log system initialized (Console + File persistence)");
+    info!(
+        "Log system initialized (Console + File persistence, format: {})",
+        if json_format { "json" } else { "text" }
+    );
     
     // Auto-cleanup logs older than 7 days
     if let Err(e) = cleanup_old_logs(7) {
@@ -0,0 +1,257 @@
+// 账号用量估算
+// 按账号累计固定时间窗口内的请求数与 token 数 (来自响应中的 usage / usageMetadata)，
+// 窗口长度由 account_usage.window_minutes 配置。独立于限流状态，mark_account_success /
+// mark_rate_limited 等状态切换不会清零计数
+use std::collections::VecDeque;
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+use crate::proxy::config::SlowAccountConfig;
+
+/// 单个账号在当前窗口内的用量
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AccountUsageWindow {
+    /// 窗口开始时间 (unix 秒)
+    pub window_start: i64,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// 窗口内被上游限流 (429 等) 的次数
+    pub rate_limit_hits: u64,
+}
+
+impl AccountUsageWindow {
+    fn new(now: i64) -> Self {
+        Self {
+            window_start: now,
+            ..Default::default()
+        }
+    }
+
+    fn is_expired(&self, window_secs: i64, now: i64) -> bool {
+        now - self.window_start >= window_secs
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// account_id -> 当前窗口用量
+#[derive(Default)]
+pub struct AccountUsageTracker {
+    windows: DashMap<String, AccountUsageWindow>,
+}
+
+impl AccountUsageTracker {
+    /// 累计一次请求的 token 用量，窗口过期时从当前时间开始新窗口
+    pub fn record(
+        &self,
+        account_id: &str,
+        input_tokens: u64,
+        output_tokens: u64,
+        window_secs: i64,
+        now: i64,
+    ) {
+        let mut window = self.current_window(account_id, window_secs, now);
+        window.requests += 1;
+        window.input_tokens += input_tokens;
+        window.output_tokens += output_tokens;
+    }
+
+    pub fn record_rate_limit(&self, account_id: &str, window_secs: i64, now: i64) {
+        self.current_window(account_id, window_secs, now)
+            .rate_limit_hits += 1;
+    }
+
+    /// 当前窗口用量 (已过期的窗口视为空)
+    pub fn get(&self, account_id: &str, window_secs: i64, now: i64) -> Option<AccountUsageWindow> {
+        self.windows
+            .get(account_id)
+            .filter(|w| !w.is_expired(window_secs, now))
+            .map(|w| w.clone())
+    }
+
+    pub fn remove(&self, account_id: &str) {
+        self.windows.remove(account_id);
+    }
+
+    fn current_window(
+        &self,
+        account_id: &str,
+        window_secs: i64,
+        now: i64,
+    ) -> dashmap::mapref::one::RefMut<'_, String, AccountUsageWindow> {
+        let mut window = self
+            .windows
+            .entry(account_id.to_string())
+            .or_insert_with(|| AccountUsageWindow::new(now));
+        if window.is_expired(window_secs, now) {
+            *window = AccountUsageWindow::new(now);
+        }
+        window
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// [NEW] 当前每日周期的开始时间: 最近一次已经过的重置时刻 (reset_offset_secs 为相对 UTC 零点的秒数)
+pub fn daily_period_start(now: i64, reset_offset_secs: i64) -> i64 {
+    (now - reset_offset_secs).div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY + reset_offset_secs
+}
+
+/// 下一次每日重置时间 (unix 秒)
+pub fn next_daily_reset(now: i64, reset_offset_secs: i64) -> i64 {
+    daily_period_start(now, reset_offset_secs) + SECONDS_PER_DAY
+}
+
+/// [NEW] account_id -> 当日用量 (用于每日预算)
+/// 与窗口用量不同，周期边界固定在每天的重置时刻，不随首次请求时间漂移
+#[derive(Default)]
+pub struct DailyUsageTracker {
+    days: DashMap<String, AccountUsageWindow>,
+}
+
+impl DailyUsageTracker {
+    pub fn record(
+        &self,
+        account_id: &str,
+        input_tokens: u64,
+        output_tokens: u64,
+        period_start: i64,
+    ) {
+        let mut day = self
+            .days
+            .entry(account_id.to_string())
+            .or_insert_with(|| AccountUsageWindow::new(period_start));
+        if day.window_start != period_start {
+            *day = AccountUsageWindow::new(period_start);
+        }
+        day.requests += 1;
+        day.input_tokens += input_tokens;
+        day.output_tokens += output_tokens;
+    }
+
+    /// 当前周期用量 (上一周期的记录视为空)
+    pub fn get(&self, account_id: &str, period_start: i64) -> AccountUsageWindow {
+        self.days
+            .get(account_id)
+            .filter(|d| d.window_start == period_start)
+            .map(|d| d.clone())
+            .unwrap_or_else(|| AccountUsageWindow::new(period_start))
+    }
+}
+
+/// [NEW] account_id -> 最近的首字延迟 (TTFT) 样本 (延迟毫秒, 记录时间)，由各协议 handler 的 peek 循环记录
+#[derive(Default)]
+pub struct FirstTokenLatencyTracker {
+    samples: DashMap<String, VecDeque<(u64, i64)>>,
+}
+
+impl FirstTokenLatencyTracker {
+    /// 记录一次首字延迟 (毫秒)，仅保留最近 window_size 个样本
+    pub fn record(&self, account_id: &str, latency_ms: u64, window_size: usize, now: i64) {
+        let mut samples = self.samples.entry(account_id.to_string()).or_default();
+        samples.push_back((latency_ms, now));
+        while samples.len() > window_size.max(1) {
+            samples.pop_front();
+        }
+    }
+
+    /// 窗口内的平均首字延迟与样本数；max_age_secs > 0 时忽略早于 now - max_age_secs 的样本
+    pub fn average_ms(
+        &self,
+        account_id: &str,
+        max_age_secs: u64,
+        now: i64,
+    ) -> Option<(u64, usize)> {
+        let samples = self.samples.get(account_id)?;
+        let fresh: Vec<u64> = samples
+            .iter()
+            .filter(|(_, at)| max_age_secs == 0 || now.saturating_sub(*at) <= max_age_secs as i64)
+            .map(|(latency, _)| *latency)
+            .collect();
+        if fresh.is_empty() {
+            return None;
+        }
+        let avg = fresh.iter().sum::<u64>() / fresh.len() as u64;
+        Some((avg, fresh.len()))
+    }
+
+    /// 有效样本足够且平均延迟超过阈值时视为慢账号
+    /// 被降级的账号很少再产生新样本，旧样本过期后自动恢复参与正常排序 (相当于定期重新探测)
+    pub fn is_slow(&self, account_id: &str, config: &SlowAccountConfig, now: i64) -> bool {
+        config.is_enabled()
+            && self
+                .average_ms(account_id, config.max_sample_age_secs, now)
+                .is_some_and(|(avg, count)| {
+                    count >= config.min_samples.max(1) && avg > config.ttft_threshold_ms
+                })
+    }
+
+    pub fn remove(&self, account_id: &str) {
+        self.samples.remove(account_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_accumulates_and_resets_per_window() {
+        let tracker = AccountUsageTracker::default();
+        let window = 3600;
+        tracker.record("acc1", 100, 20, window, 1_000);
+        tracker.record("acc1", 50, 10, window, 1_500);
+        tracker.record_rate_limit("acc1", window, 1_600);
+        tracker.record("acc2", 5, 5, window, 1_500);
+
+        let usage = tracker.get("acc1", window, 2_000).unwrap();
+        assert_eq!(usage.window_start, 1_000);
+        assert_eq!(usage.requests, 2);
+        assert_eq!(usage.total_tokens(), 180);
+        assert_eq!(usage.rate_limit_hits, 1);
+        assert_eq!(tracker.get("acc2", window, 2_000).unwrap().requests, 1);
+
+        // 窗口过期: 查询视为空，下次记录开启新窗口
+        assert!(tracker.get("acc1", window, 4_600).is_none());
+        tracker.record("acc1", 1, 1, window, 4_600);
+        let usage = tracker.get("acc1", window, 4_600).unwrap();
+        assert_eq!(usage.window_start, 4_600);
+        assert_eq!(usage.requests, 1);
+        assert_eq!(usage.rate_limit_hits, 0);
+    }
+
+    #[test]
+    fn test_daily_usage_resets_at_configured_boundary() {
+        // 重置时间 08:00 UTC
+        let offset = 8 * 3600;
+        let day = 20_000 * 86_400; // 某日 00:00 UTC
+        assert_eq!(
+            daily_period_start(day + 7 * 3600, offset),
+            day - 86_400 + offset
+        );
+        assert_eq!(daily_period_start(day + 8 * 3600, offset), day + offset);
+        assert_eq!(
+            next_daily_reset(day + 9 * 3600, offset),
+            day + 86_400 + offset
+        );
+
+        let tracker = DailyUsageTracker::default();
+        let period = daily_period_start(day + 9 * 3600, offset);
+        tracker.record("acc1", 100, 50, period);
+        tracker.record("acc1", 10, 5, period);
+        let usage = tracker.get("acc1", period);
+        assert_eq!(usage.requests, 2);
+        assert_eq!(usage.total_tokens(), 165);
+
+        // 跨过重置时刻后计数清零
+        let next = daily_period_start(day + 86_400 + offset, offset);
+        assert_eq!(tracker.get("acc1", next).requests, 0);
+        tracker.record("acc1", 1, 1, next);
+        assert_eq!(tracker.get("acc1", next).requests, 1);
+        assert_eq!(tracker.get("acc1", period).requests, 0);
+    }
+}
@@ -0,0 +1,247 @@
+// 账号 + 模型级熔断器
+// 同一账号在某个模型上连续返回 5xx 时，短时间内跳过该 (账号, 模型) 组合，而不是整个账号。
+// 状态: closed (正常) -> open (冷却中，调度跳过) -> half_open (冷却结束，放行试探请求)
+// half_open 下成功则关闭熔断，失败则立即重新打开
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// 熔断参数 (来自 CircuitBreakerConfig)
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerSettings {
+    /// 窗口内连续失败多少次后打开
+    pub failure_threshold: u32,
+    /// 连续失败的统计窗口，距首次失败超过该时长则重新计数
+    pub failure_window: Duration,
+    /// 打开后的冷却时长
+    pub open_duration: Duration,
+}
+
+impl BreakerSettings {
+    pub fn from_config(config: &crate::models::CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.model_failure_threshold.max(1),
+            failure_window: Duration::from_secs(config.model_failure_window_seconds),
+            open_duration: Duration::from_secs(config.model_open_seconds),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BreakerEntry {
+    consecutive_failures: u32,
+    first_failure_at: Instant,
+    open_until: Option<Instant>,
+}
+
+impl BreakerEntry {
+    fn state(&self, now: Instant) -> BreakerState {
+        match self.open_until {
+            Some(until) if now < until => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+            None => BreakerState::Closed,
+        }
+    }
+}
+
+/// 管理 API 返回的熔断状态
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerStatus {
+    pub model: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    /// open 状态下距进入 half_open 的秒数
+    pub retry_in_seconds: u64,
+}
+
+/// (account_id, model) -> 熔断记录；只保存有失败记录的组合，成功即移除
+#[derive(Default)]
+pub struct ModelCircuitBreaker {
+    entries: DashMap<(String, String), BreakerEntry>,
+}
+
+impl ModelCircuitBreaker {
+    /// 记录一次失败，返回本次是否打开了熔断
+    pub fn record_failure(
+        &self,
+        account_id: &str,
+        model: &str,
+        settings: &BreakerSettings,
+        now: Instant,
+    ) -> bool {
+        let mut entry = self
+            .entries
+            .entry((account_id.to_string(), model.to_string()))
+            .or_insert_with(|| BreakerEntry {
+                consecutive_failures: 0,
+                first_failure_at: now,
+                open_until: None,
+            });
+        match entry.state(now) {
+            // 冷却中的失败 (并发请求) 不延长冷却
+            BreakerState::Open => return false,
+            // 试探失败: 立即重新打开
+            BreakerState::HalfOpen => {
+                entry.consecutive_failures += 1;
+                entry.open_until = Some(now + settings.open_duration);
+                tracing::warn!(
+                    "[CircuitBreaker] {} / {} half-open probe failed, reopening for {}s",
+                    account_id,
+                    model,
+                    settings.open_duration.as_secs()
+                );
+                return true;
+            }
+            BreakerState::Closed => {}
+        }
+
+        if now.duration_since(entry.first_failure_at) > settings.failure_window {
+            entry.consecutive_failures = 0;
+            entry.first_failure_at = now;
+        }
+        entry.consecutive_failures += 1;
+        if entry.consecutive_failures >= settings.failure_threshold {
+            entry.open_until = Some(now + settings.open_duration);
+            tracing::warn!(
+                "[CircuitBreaker] {} / {} opened after {} consecutive failures ({}s)",
+                account_id,
+                model,
+                entry.consecutive_failures,
+                settings.open_duration.as_secs()
+            );
+            return true;
+        }
+        false
+    }
+
+    /// 记录成功: 关闭熔断并清空失败计数
+    pub fn record_success(&self, account_id: &str, model: &str) {
+        if let Some((_, entry)) = self
+            .entries
+            .remove(&(account_id.to_string(), model.to_string()))
+        {
+            if entry.open_until.is_some() {
+                tracing::info!(
+                    "[CircuitBreaker] {} / {} closed after successful request",
+                    account_id,
+                    model
+                );
+            }
+        }
+    }
+
+    pub fn state(&self, account_id: &str, model: &str, now: Instant) -> BreakerState {
+        self.entries
+            .get(&(account_id.to_string(), model.to_string()))
+            .map(|e| e.state(now))
+            .unwrap_or(BreakerState::Closed)
+    }
+
+    /// 调度时是否应跳过该组合 (仅 open 状态跳过，half_open 放行试探)
+    pub fn is_open(&self, account_id: &str, model: &str, now: Instant) -> bool {
+        self.state(account_id, model, now) == BreakerState::Open
+    }
+
+    /// 指定账号下所有有失败记录的模型
+    pub fn statuses(&self, account_id: &str, now: Instant) -> Vec<BreakerStatus> {
+        let mut statuses: Vec<BreakerStatus> = self
+            .entries
+            .iter()
+            .filter(|e| e.key().0 == account_id)
+            .map(|e| BreakerStatus {
+                model: e.key().1.clone(),
+                state: e.state(now),
+                consecutive_failures: e.consecutive_failures,
+                retry_in_seconds: e
+                    .open_until
+                    .map(|until| until.saturating_duration_since(now).as_secs())
+                    .unwrap_or(0),
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.model.cmp(&b.model));
+        statuses
+    }
+
+    pub fn remove_account(&self, account_id: &str) {
+        self.entries.retain(|(account, _), _| account != account_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> BreakerSettings {
+        BreakerSettings {
+            failure_threshold: 3,
+            failure_window: Duration::from_secs(60),
+            open_duration: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn test_three_failures_open_and_half_open_success_closes() {
+        let breaker = ModelCircuitBreaker::default();
+        let settings = settings();
+        let start = Instant::now();
+
+        assert!(!breaker.record_failure("acc", "gemini-3-pro", &settings, start));
+        assert!(!breaker.record_failure("acc", "gemini-3-pro", &settings, start));
+        assert_eq!(
+            breaker.state("acc", "gemini-3-pro", start),
+            BreakerState::Closed
+        );
+        assert!(breaker.record_failure("acc", "gemini-3-pro", &settings, start));
+        assert!(breaker.is_open("acc", "gemini-3-pro", start));
+        // 其他模型 / 其他账号不受影响
+        assert!(!breaker.is_open("acc", "gemini-2.5-flash", start));
+        assert!(!breaker.is_open("other", "gemini-3-pro", start));
+        assert_eq!(breaker.statuses("acc", start)[0].retry_in_seconds, 30);
+
+        // 冷却结束进入 half_open，成功后关闭
+        let later = start + Duration::from_secs(31);
+        assert_eq!(
+            breaker.state("acc", "gemini-3-pro", later),
+            BreakerState::HalfOpen
+        );
+        assert!(!breaker.is_open("acc", "gemini-3-pro", later));
+        breaker.record_success("acc", "gemini-3-pro");
+        assert_eq!(
+            breaker.state("acc", "gemini-3-pro", later),
+            BreakerState::Closed
+        );
+        assert!(breaker.statuses("acc", later).is_empty());
+    }
+
+    #[test]
+    fn test_half_open_failure_reopens_and_window_resets_count() {
+        let breaker = ModelCircuitBreaker::default();
+        let settings = settings();
+        let start = Instant::now();
+        for _ in 0..3 {
+            breaker.record_failure("acc", "m", &settings, start);
+        }
+        let probe = start + Duration::from_secs(31);
+        assert!(breaker.record_failure("acc", "m", &settings, probe));
+        assert!(breaker.is_open("acc", "m", probe + Duration::from_secs(1)));
+
+        // 失败间隔超过窗口时重新计数
+        let spaced = ModelCircuitBreaker::default();
+        spaced.record_failure("acc", "m", &settings, start);
+        spaced.record_failure("acc", "m", &settings, start + Duration::from_secs(10));
+        assert!(!spaced.record_failure("acc", "m", &settings, start + Duration::from_secs(120)));
+        assert_eq!(
+            spaced.state("acc", "m", start + Duration::from_secs(120)),
+            BreakerState::Closed
+        );
+    }
+}
@@ -10,3 +10,4 @@ pub mod tool_adapters;
 pub mod schema_cache;
 pub mod client_adapter;
 pub mod client_adapters;
+pub mod stream_buffer;
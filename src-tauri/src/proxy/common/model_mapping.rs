@@ -109,6 +109,23 @@ pub fn map_claude_model_to_gemini(input: &str) -> String {
     input.to_string()
 }
 
+/// 是否为内置可识别的模型 (内置映射的源/目标、gemini- 前缀或 thinking 变体)
+pub fn is_known_model(input: &str) -> bool {
+    CLAUDE_TO_GEMINI.contains_key(input)
+        || CLAUDE_TO_GEMINI.values().any(|v| *v == input)
+        || input.starts_with("gemini-")
+        || input.contains("thinking")
+}
+
+/// 兜底开启且模型为空或无法识别时返回兜底目标模型
+pub fn resolve_unknown_model(
+    original_model: &str,
+    config: &crate::proxy::config::DefaultModelConfig,
+) -> Option<String> {
+    let unknown = original_model.trim().is_empty() || !is_known_model(original_model);
+    (config.enabled && !config.model.is_empty() && unknown).then(|| config.model.clone())
+}
+
 /// 获取所有内置支持的模型列表关键字
 pub fn get_supported_models() -> Vec<String> {
     CLAUDE_TO_GEMINI.keys().map(|s| s.to_string()).collect()
@@ -173,7 +190,7 @@ pub async fn get_all_dynamic_models(
 /// - `claude-*-sonnet-*` matches `claude-3-5-sonnet-20241022` ✓
 /// - `*-thinking` matches `claude-opus-4-5-thinking` ✓
 /// - `a*b*c` matches `a123b456c` ✓
-fn wildcard_match(pattern: &str, text: &str) -> bool {
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
     let parts: Vec<&str> = pattern.split('*').collect();
 
     // No wildcard - exact match
@@ -211,7 +228,7 @@ fn wildcard_match(pattern: &str, text: &str) -> bool {
 }
 
 /// 核心模型路由解析引擎
-/// 优先级：精确匹配 > 通配符匹配 > 系统默认映射
+/// 优先级：精确匹配 > 通配符匹配 > 未知模型兜底 (需开启) > 系统默认映射
 /// 
 /// # 参数
 /// - `original_model`: 原始模型名称
@@ -252,7 +269,20 @@ pub fn resolve_model_route(
         return target.to_string();
     }
     
-    // 3. 系统默认映射
+    // 3. [NEW] 未传模型或无法识别的模型: 按配置路由到兜底模型
+    if let Some(target) = resolve_unknown_model(
+        original_model,
+        &crate::proxy::config::get_default_model_config(),
+    ) {
+        tracing::warn!(
+            "[Router] Unknown model '{}', routing to default model {}",
+            original_model,
+            target
+        );
+        return target;
+    }
+
+    // 4. 系统默认映射
     let result = map_claude_model_to_gemini(original_model);
     if result != original_model {
         crate::modules::logger::log_info(&format!("[Router] 系统默认映射: {} -> {}", original_model, result));
@@ -369,4 +399,28 @@ mod tests {
         // Multi-wildcard: "a*b*c" (3)
         assert_eq!(resolve_model_route("a-test-b-foo-c", &custom), "multi-wild");
     }
+
+    #[test]
+    fn test_unknown_model_default_routing() {
+        use crate::proxy::config::DefaultModelConfig;
+
+        let mut config = DefaultModelConfig::default();
+        // 默认关闭: 保持透传
+        assert_eq!(resolve_unknown_model("my-custom-model", &config), None);
+
+        config.enabled = true;
+        assert_eq!(
+            resolve_unknown_model("my-custom-model", &config).as_deref(),
+            Some("gemini-2.5-flash")
+        );
+        assert_eq!(
+            resolve_unknown_model("", &config).as_deref(),
+            Some("gemini-2.5-flash")
+        );
+        // 可识别的模型不受影响
+        assert_eq!(resolve_unknown_model("gpt-4", &config), None);
+        assert_eq!(resolve_unknown_model("claude-sonnet-4-5", &config), None);
+        assert_eq!(resolve_unknown_model("gemini-3-flash", &config), None);
+        assert_eq!(resolve_unknown_model("claude-opus-4-6-thinking", &config), None);
+    }
 }
@@ -0,0 +1,440 @@
+// 流式响应缓冲
+// 将多个小 SSE 分片合并后再写出，减少客户端收到的小包数量；阈值为 0 时原样透传
+// 另提供 peek 期间的心跳包装，使慢首包模型的连接在首个数据块之前保持活跃
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use tokio::time::{Duration, Instant};
+
+use crate::proxy::config::StreamingConfig;
+
+pub type BufferedByteStream<E> = Pin<Box<dyn Stream<Item = Result<Bytes, E>> + Send>>;
+
+/// 按流式配置 (AppState.streaming) 包装响应流，在 Body::from_stream 之前调用
+/// 累积数据直到达到 flush_threshold_bytes，或最早缓冲的数据等待超过 max_flush_delay_ms 时写出
+/// 上游结束或出错时先写出剩余缓冲，保证不丢数据
+pub fn with_flush_threshold<S, E>(stream: S, config: &StreamingConfig) -> BufferedByteStream<E>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let threshold = config.flush_threshold_bytes;
+    if threshold == 0 {
+        return Box::pin(stream);
+    }
+    let max_delay = Duration::from_millis(config.max_flush_delay_ms);
+
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut buf = BytesMut::new();
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            // None 表示等待超时，需要写出缓冲区
+            let next = match deadline {
+                Some(at) => tokio::select! {
+                    item = stream.next() => Some(item),
+                    _ = tokio::time::sleep_until(at) => None,
+                },
+                None => Some(stream.next().await),
+            };
+
+            match next {
+                None => {
+                    deadline = None;
+                    yield Ok(buf.split().freeze());
+                }
+                Some(Some(Ok(chunk))) => {
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    if buf.is_empty() {
+                        deadline = Some(Instant::now() + max_delay);
+                    }
+                    buf.extend_from_slice(&chunk);
+                    if buf.len() >= threshold {
+                        deadline = None;
+                        yield Ok(buf.split().freeze());
+                    }
+                }
+                Some(Some(Err(e))) => {
+                    if !buf.is_empty() {
+                        yield Ok(buf.split().freeze());
+                    }
+                    deadline = None;
+                    yield Err(e);
+                }
+                Some(None) => {
+                    if !buf.is_empty() {
+                        yield Ok(buf.split().freeze());
+                    }
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// 心跳响应发出后 peek 的结果，供调用方记录首字延迟 / 超时等指标
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeekOutcome {
+    /// 收到首个有效数据块
+    FirstData,
+    /// 首个数据块之前出错、空流或首个数据即为错误事件
+    Failed(String),
+    /// 等待首个数据块超时
+    TimedOut,
+}
+
+/// 心跳开始后 peek 失败时补发的错误事件格式 (各协议客户端只识别各自的错误事件)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SseErrorFormat {
+    /// Chat / Legacy Completions: data: {"error": {...}} + data: [DONE]
+    OpenAI,
+    /// Responses API: event: error + {"type": "error", "code", "message"}
+    Responses,
+    /// Anthropic Messages: event: error + {"type": "error", "error": {...}}
+    Claude,
+    /// Gemini: data: {"error": {"code", "message", "status"}}
+    Gemini,
+}
+
+impl SseErrorFormat {
+    fn error_event(self, message: &str) -> Bytes {
+        use serde_json::json;
+        let event = match self {
+            SseErrorFormat::OpenAI => format!(
+                "data: {}\n\ndata: [DONE]\n\n",
+                json!({ "error": { "type": "upstream_error", "message": message, "code": "peek_failed" } })
+            ),
+            SseErrorFormat::Responses => format!(
+                "event: error\ndata: {}\n\n",
+                json!({ "type": "error", "code": "peek_failed", "message": message, "param": null })
+            ),
+            SseErrorFormat::Claude => format!(
+                "event: error\ndata: {}\n\n",
+                json!({ "type": "error", "error": { "type": "api_error", "message": message } })
+            ),
+            SseErrorFormat::Gemini => format!(
+                "data: {}\n\n",
+                json!({ "error": { "code": 502, "message": message, "status": "UNAVAILABLE" } })
+            ),
+        };
+        Bytes::from(event)
+    }
+}
+
+/// 数据块中是否包含 SSE 错误事件: `event: error`，或 data 为带 error 字段 / type = "error" 的 JSON 对象
+/// 按事件解析而非子串匹配，正文中出现的 "error" 字样不会误判
+pub fn is_sse_error_event(bytes: &[u8]) -> bool {
+    String::from_utf8_lossy(bytes).lines().any(|line| {
+        let line = line.trim();
+        if let Some(event) = line.strip_prefix("event:") {
+            return event.trim() == "error";
+        }
+        let Some(data) = line.strip_prefix("data:") else {
+            return false;
+        };
+        serde_json::from_str::<serde_json::Value>(data.trim())
+            .ok()
+            .and_then(|v| v.as_object().cloned())
+            .is_some_and(|obj| {
+                obj.get("error").is_some_and(|e| !e.is_null())
+                    || obj.get("type").and_then(|t| t.as_str()) == Some("error")
+            })
+    })
+}
+
+/// streaming.peek_heartbeat_ms 对应的心跳间隔: 仅客户端流式请求、且小于 peek 超时时生效
+/// 返回的时长同时作为"开始心跳"的等待时间，在此之前的 peek 失败仍可轮换账号
+pub fn peek_heartbeat_interval(
+    config: &StreamingConfig,
+    client_wants_stream: bool,
+    peek_timeout: Duration,
+) -> Option<Duration> {
+    let interval = Duration::from_millis(config.peek_heartbeat_ms);
+    (client_wants_stream && !interval.is_zero())
+        .then_some(interval)
+        .filter(|interval| *interval < peek_timeout)
+}
+
+/// peek 期间即开始向客户端输出: 首个有效数据块到达前每隔 interval 写出 ": ping" 心跳
+/// 首个数据块之前的出错 / 空流 / 超时以 format 对应的 SSE 错误事件结束 (响应头已发出，无法再轮换账号)
+/// on_peek 在 peek 结束时调用一次
+pub fn with_peek_heartbeats(
+    stream: BufferedByteStream<String>,
+    interval: Duration,
+    peek_timeout: Duration,
+    format: SseErrorFormat,
+    on_peek: impl FnOnce(PeekOutcome) + Send + 'static,
+) -> BufferedByteStream<String> {
+    Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+        let deadline = tokio::time::sleep(peek_timeout);
+        tokio::pin!(deadline);
+
+        // failure: 需要补发的 SSE 错误事件 (首个数据本身为错误事件时已原样转发，无需补发)
+        let (outcome, failure) = loop {
+            tokio::select! {
+                item = stream.next() => match item {
+                    Some(Ok(bytes)) => {
+                        let is_data = !bytes.is_empty() && !bytes.starts_with(b":");
+                        let is_error = is_data && is_sse_error_event(&bytes);
+                        yield Ok(bytes);
+                        if is_error {
+                            break (PeekOutcome::Failed("Error event during peek".to_string()), None);
+                        }
+                        if is_data {
+                            break (PeekOutcome::FirstData, None);
+                        }
+                    }
+                    Some(Err(e)) => {
+                        let message = format!("Stream error before first data: {}", e);
+                        break (PeekOutcome::Failed(message.clone()), Some(message));
+                    }
+                    None => {
+                        let message = "Empty response stream".to_string();
+                        break (PeekOutcome::Failed(message.clone()), Some(message));
+                    }
+                },
+                _ = ticker.tick() => {
+                    yield Ok(Bytes::from_static(b": ping\n\n"));
+                }
+                _ = &mut deadline => {
+                    let message = format!("Timeout waiting for first data ({}s)", peek_timeout.as_secs());
+                    break (PeekOutcome::TimedOut, Some(message));
+                }
+            }
+        };
+        on_peek(outcome);
+
+        if let Some(message) = failure {
+            tracing::warn!("[Stream] Peek failed after response started: {}", message);
+            yield Ok(format.error_event(&message));
+            return;
+        }
+
+        while let Some(item) = stream.next().await {
+            yield item;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sse_chunks(n: usize) -> Vec<Result<Bytes, String>> {
+        // 每个事件固定 10 字节: "data: 0x\n\n"
+        (0..n)
+            .map(|i| Ok(Bytes::from(format!("data: {}x\n\n", i % 10))))
+            .collect()
+    }
+
+    async fn collect_sizes(stream: BufferedByteStream<String>) -> Vec<usize> {
+        stream.map(|item| item.unwrap().len()).collect().await
+    }
+
+    #[tokio::test]
+    async fn test_flush_threshold_changes_flush_granularity() {
+        // 阈值 0: 原样透传，每个事件单独写出
+        let passthrough = StreamingConfig {
+            flush_threshold_bytes: 0,
+            max_flush_delay_ms: 50,
+            ..Default::default()
+        };
+        let sizes = collect_sizes(with_flush_threshold(
+            futures::stream::iter(sse_chunks(10)),
+            &passthrough,
+        ))
+        .await;
+        assert_eq!(sizes, vec![10; 10]);
+
+        // 阈值 32: 每累积 4 个事件 (40 字节) 写出一次，结尾剩余部分单独写出
+        let buffered = StreamingConfig {
+            flush_threshold_bytes: 32,
+            max_flush_delay_ms: 50,
+            ..Default::default()
+        };
+        let sizes = collect_sizes(with_flush_threshold(
+            futures::stream::iter(sse_chunks(10)),
+            &buffered,
+        ))
+        .await;
+        assert_eq!(sizes, vec![40, 40, 20]);
+    }
+
+    #[tokio::test]
+    async fn test_max_delay_flushes_partial_buffer() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, String>>(4);
+        let config = StreamingConfig {
+            flush_threshold_bytes: 1024,
+            max_flush_delay_ms: 20,
+            ..Default::default()
+        };
+        let mut stream =
+            with_flush_threshold(tokio_stream::wrappers::ReceiverStream::new(rx), &config);
+
+        tx.send(Ok(Bytes::from_static(b"data: hi\n\n")))
+            .await
+            .unwrap();
+        // 上游未结束且未达到阈值，仍应在 max_flush_delay_ms 后写出
+        let first = tokio::time::timeout(Duration::from_secs(2), stream.next())
+            .await
+            .expect("partial buffer should be flushed after max delay")
+            .unwrap()
+            .unwrap();
+        assert_eq!(&first[..], b"data: hi\n\n");
+
+        // 出错前先写出剩余数据
+        tx.send(Ok(Bytes::from_static(b"data: a\n\n")))
+            .await
+            .unwrap();
+        tx.send(Err("boom".to_string())).await.unwrap();
+        drop(tx);
+        let rest: Vec<_> = stream.collect().await;
+        assert_eq!(rest.len(), 2);
+        assert_eq!(&rest[0].as_ref().unwrap()[..], b"data: a\n\n");
+        assert_eq!(rest[1].as_ref().unwrap_err(), "boom");
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_flow_during_slow_peek() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, String>>(4);
+        let outcomes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = |outcomes: &std::sync::Arc<std::sync::Mutex<Vec<PeekOutcome>>>| {
+            let outcomes = outcomes.clone();
+            move |outcome| outcomes.lock().unwrap().push(outcome)
+        };
+        let stream = with_peek_heartbeats(
+            Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)),
+            Duration::from_millis(20),
+            Duration::from_secs(5),
+            SseErrorFormat::OpenAI,
+            record(&outcomes),
+        );
+
+        tokio::spawn(async move {
+            // 模拟慢首包: 约 120ms 后才产出首个数据块
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            tx.send(Ok(Bytes::from_static(b"data: first\n\n")))
+                .await
+                .unwrap();
+            tx.send(Ok(Bytes::from_static(b"data: second\n\n")))
+                .await
+                .unwrap();
+        });
+
+        let chunks: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        let first_data = chunks
+            .iter()
+            .position(|c| &c[..] == b"data: first\n\n")
+            .expect("first chunk forwarded");
+        assert!(
+            first_data >= 2,
+            "expected heartbeats before first data: {:?}",
+            chunks
+        );
+        assert!(chunks[..first_data].iter().all(|c| &c[..] == b": ping\n\n"));
+        // 首个数据块之后不再插入心跳
+        assert_eq!(
+            &chunks[first_data + 1..],
+            &[Bytes::from_static(b"data: second\n\n")]
+        );
+
+        // peek 超时: 以错误事件结束
+        let (_tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, String>>(1);
+        let stream = with_peek_heartbeats(
+            Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)),
+            Duration::from_millis(20),
+            Duration::from_millis(70),
+            SseErrorFormat::OpenAI,
+            record(&outcomes),
+        );
+        let chunks: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        let tail = String::from_utf8_lossy(chunks.last().unwrap()).to_string();
+        assert!(tail.contains("peek_failed"), "{}", tail);
+        assert!(tail.ends_with("data: [DONE]\n\n"), "{}", tail);
+
+        // 首个数据即为错误事件: 原样转发，不补发错误，也不计为首字
+        let error_event = Bytes::from_static(b"data: {\"error\":{\"message\":\"quota\"}}\n\n");
+        let stream = with_peek_heartbeats(
+            Box::pin(futures::stream::iter(vec![Ok(error_event.clone())])),
+            Duration::from_millis(20),
+            Duration::from_secs(5),
+            SseErrorFormat::OpenAI,
+            record(&outcomes),
+        );
+        let chunks: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        assert_eq!(chunks, vec![error_event]);
+
+        // 正文中出现 "error" 字样不是错误事件
+        let text = Bytes::from_static(
+            b"data: {\"choices\":[{\"delta\":{\"content\":\"the \\\"error\\\" field\"}}]}\n\n",
+        );
+        let stream = with_peek_heartbeats(
+            Box::pin(futures::stream::iter(vec![Ok(text.clone())])),
+            Duration::from_millis(20),
+            Duration::from_secs(5),
+            SseErrorFormat::OpenAI,
+            record(&outcomes),
+        );
+        let chunks: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        assert_eq!(chunks, vec![text]);
+
+        assert_eq!(
+            outcomes.lock().unwrap().as_slice(),
+            &[
+                PeekOutcome::FirstData,
+                PeekOutcome::TimedOut,
+                PeekOutcome::Failed("Error event during peek".to_string()),
+                PeekOutcome::FirstData,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_sse_error_event() {
+        assert!(is_sse_error_event(
+            b"data: {\"error\":{\"message\":\"quota\"}}\n\n"
+        ));
+        assert!(is_sse_error_event(
+            b"event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"api_error\"}}\n\n"
+        ));
+        assert!(is_sse_error_event(
+            b"data: {\"type\":\"error\",\"code\":\"x\"}\n\n"
+        ));
+        assert!(!is_sse_error_event(
+            b"data: {\"choices\":[{\"delta\":{\"content\":\"\\\"error\\\"\"}}]}\n\n"
+        ));
+        assert!(!is_sse_error_event(
+            b"data: {\"id\":\"x\",\"error\":null}\n\n"
+        ));
+        assert!(!is_sse_error_event(b"data: [DONE]\n\n"));
+        assert!(!is_sse_error_event(b": ping\n\n"));
+    }
+
+    #[test]
+    fn test_peek_error_event_formats() {
+        let claude =
+            String::from_utf8_lossy(&SseErrorFormat::Claude.error_event("boom")).to_string();
+        assert!(claude.starts_with("event: error\ndata: "));
+        assert!(claude.contains("\"api_error\""));
+        let responses =
+            String::from_utf8_lossy(&SseErrorFormat::Responses.error_event("boom")).to_string();
+        assert!(responses.starts_with("event: error\ndata: "));
+        assert!(responses.contains("\"code\":\"peek_failed\""));
+        let gemini =
+            String::from_utf8_lossy(&SseErrorFormat::Gemini.error_event("boom")).to_string();
+        assert!(gemini.contains("\"status\":\"UNAVAILABLE\""));
+        for format in [
+            SseErrorFormat::OpenAI,
+            SseErrorFormat::Responses,
+            SseErrorFormat::Claude,
+            SseErrorFormat::Gemini,
+        ] {
+            assert!(is_sse_error_event(&format.error_event("boom")));
+        }
+    }
+}
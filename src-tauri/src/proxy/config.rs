@@ -61,22 +61,871 @@ pub fn update_global_system_prompt_config(config: GlobalSystemPromptConfig) {
         if let Ok(mut cfg) = lock.write() {
             *cfg = config.clone();
             tracing::info!(
-                "[Global-System-Prompt] Config updated: enabled={}, content_len={}",
+                "[Global-System-Prompt] Config updated: enabled={}, content_len={}, append_len={}",
                 config.enabled,
-                config.content.len()
+                config.content.len(),
+                config.append_content.len()
             );
         }
     } else {
         // 首次初始化
         let _ = GLOBAL_SYSTEM_PROMPT_CONFIG.set(RwLock::new(config.clone()));
         tracing::info!(
-            "[Global-System-Prompt] Config initialized: enabled={}, content_len={}",
+            "[Global-System-Prompt] Config initialized: enabled={}, content_len={}, append_len={}",
             config.enabled,
-            config.content.len()
+            config.content.len(),
+            config.append_content.len()
         );
     }
 }
 
+/// 请求转换 (transform_openai_request / wrap_request) 读取的全局配置快照
+/// 转换函数的 *_with_config 变体按参数接收，测试可传入指定配置而无需修改全局状态
+#[derive(Debug, Clone, Default)]
+pub struct TransformConfig {
+    pub thinking_budget: ThinkingBudgetConfig,
+    pub global_system_prompt: GlobalSystemPromptConfig,
+}
+
+impl TransformConfig {
+    /// 当前全局配置
+    pub fn current() -> Self {
+        Self {
+            thinking_budget: get_thinking_budget_config(),
+            global_system_prompt: get_global_system_prompt(),
+        }
+    }
+}
+
+// ============================================================================
+// 全局图片接口配置存储
+// 供 images handler 读取（参考图大小限制等）
+// ============================================================================
+static GLOBAL_IMAGE_CONFIG: OnceLock<RwLock<ImageConfig>> = OnceLock::new();
+
+/// 获取当前图片接口配置
+pub fn get_image_config() -> ImageConfig {
+    GLOBAL_IMAGE_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局图片接口配置
+pub fn update_image_config(config: ImageConfig) {
+    if let Some(lock) = GLOBAL_IMAGE_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            *cfg = config.clone();
+            tracing::info!(
+                "[Image-Config] Config updated: max_total_reference_bytes={}, chat_blocked_models={:?}, max_concurrency={}, max_n={}",
+                config.max_total_reference_bytes,
+                config.chat_blocked_models,
+                config.max_concurrency,
+                config.max_n
+            );
+        }
+    } else {
+        // 首次初始化
+        let _ = GLOBAL_IMAGE_CONFIG.set(RwLock::new(config.clone()));
+        tracing::info!(
+            "[Image-Config] Config initialized: max_total_reference_bytes={}, chat_blocked_models={:?}, max_concurrency={}, max_n={}",
+            config.max_total_reference_bytes,
+            config.chat_blocked_models,
+            config.max_concurrency,
+            config.max_n
+        );
+    }
+}
+
+/// 图片接口配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// images/edits 中所有参考图 (base64 编码后) 的总大小上限，单位字节
+    /// 0 表示不限制
+    #[serde(default = "default_max_total_reference_bytes")]
+    pub max_total_reference_bytes: usize,
+    /// 禁止在 /v1/chat/completions 中使用的图像生成模型 (支持 * 通配符)
+    /// 命中时返回 400 并提示改用 /v1/images/generations，默认为空 (聊天接口仍可生图)
+    #[serde(default)]
+    pub chat_blocked_models: Vec<String>,
+    /// [NEW] 单个图片请求 (n 张) 同时发往上游的最大任务数，超出部分排队分批执行
+    /// 0 表示不限制
+    #[serde(default = "default_image_max_concurrency")]
+    pub max_concurrency: usize,
+    /// [NEW] 单个图片请求允许的 n 上限，超出时直接返回 400；0 表示不限制
+    #[serde(default = "default_image_max_n")]
+    pub max_n: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            max_total_reference_bytes: default_max_total_reference_bytes(),
+            chat_blocked_models: Vec::new(),
+            max_concurrency: default_image_max_concurrency(),
+            max_n: default_image_max_n(),
+        }
+    }
+}
+
+impl ImageConfig {
+    /// 原始模型名或映射后的模型名是否在聊天接口禁用列表中
+    pub fn is_blocked_in_chat(&self, original_model: &str, mapped_model: &str) -> bool {
+        self.chat_blocked_models.iter().any(|pattern| {
+            [original_model, mapped_model]
+                .iter()
+                .any(|model| crate::proxy::common::model_mapping::wildcard_match(pattern, model))
+        })
+    }
+}
+
+fn default_max_total_reference_bytes() -> usize {
+    20 * 1024 * 1024 // Gemini 单次请求 inlineData 总量上限约 20MB
+}
+
+fn default_image_max_concurrency() -> usize {
+    4
+}
+
+fn default_image_max_n() -> usize {
+    10 // 与 OpenAI images 接口的 n 上限一致
+}
+
+// ============================================================================
+// 流式响应缓冲配置 (AppState.streaming，随配置热更新)
+// 作用于 SSE 响应体 (Body::from_stream) 的写出粒度，与上游分片无关
+// ============================================================================
+
+/// 流式响应缓冲配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamingConfig {
+    /// 累积到多少字节后再向客户端写出一次
+    /// 0 表示不缓冲，每个 SSE 事件立即写出 (默认)
+    #[serde(default)]
+    pub flush_threshold_bytes: usize,
+    /// 缓冲区中最早的数据最多等待多久 (毫秒) 就强制写出，避免低速流卡住
+    #[serde(default = "default_max_flush_delay_ms")]
+    pub max_flush_delay_ms: u64,
+    /// 流式请求等待首个数据块 (peek) 超过该时长 (毫秒) 后即开始响应，并按此间隔发送心跳
+    /// 在此之前的失败仍可轮换账号重试，之后的失败以流内错误事件返回
+    /// 0 表示关闭 (默认)：peek 成功后才返回响应，失败可轮换账号重试
+    #[serde(default)]
+    pub peek_heartbeat_ms: u64,
+    /// 不单独输出空白 content 分片，合并到相邻的正文分片 (减少逐分片渲染的客户端闪烁)，默认关闭
+    #[serde(default)]
+    pub suppress_whitespace_deltas: bool,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            flush_threshold_bytes: 0,
+            max_flush_delay_ms: default_max_flush_delay_ms(),
+            peek_heartbeat_ms: 0,
+            suppress_whitespace_deltas: false,
+        }
+    }
+}
+
+fn default_max_flush_delay_ms() -> u64 {
+    50
+}
+
+// ============================================================================
+// 全局 OpenAI 兼容性配置存储
+// 控制无法映射到 Gemini 的 OpenAI 参数 (如 logit_bias) 的处理方式
+// ============================================================================
+static GLOBAL_OPENAI_COMPAT_CONFIG: OnceLock<RwLock<OpenAICompatConfig>> = OnceLock::new();
+
+/// 获取当前 OpenAI 兼容性配置
+pub fn get_openai_compat_config() -> OpenAICompatConfig {
+    GLOBAL_OPENAI_COMPAT_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局 OpenAI 兼容性配置
+pub fn update_openai_compat_config(config: OpenAICompatConfig) {
+    if let Some(lock) = GLOBAL_OPENAI_COMPAT_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            *cfg = config.clone();
+            tracing::info!(
+                "[OpenAI-Compat] Config updated: logit_bias={:?}, safety_partial={:?}",
+                config.logit_bias,
+                config.safety_partial
+            );
+        }
+    } else {
+        // 首次初始化
+        let _ = GLOBAL_OPENAI_COMPAT_CONFIG.set(RwLock::new(config.clone()));
+        tracing::info!(
+            "[OpenAI-Compat] Config initialized: logit_bias={:?}, safety_partial={:?}",
+            config.logit_bias,
+            config.safety_partial
+        );
+    }
+}
+
+/// OpenAI 兼容性配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAICompatConfig {
+    /// logit_bias 的 key 是 OpenAI 分词器的 token id，无法映射到 Gemini
+    #[serde(default)]
+    pub logit_bias: UnsupportedParamPolicy,
+    /// Gemini 输出部分内容后以 SAFETY 结束时的处理方式
+    #[serde(default)]
+    pub safety_partial: SafetyPartialPolicy,
+    /// 非流式响应的 model 字段回显客户端请求的模型名 (而非上游映射后的 Gemini 模型名)
+    /// 真实目标模型仍通过 X-Mapped-Model 头返回，默认开启
+    #[serde(default = "default_true")]
+    pub echo_requested_model: bool,
+    /// chat / completions / responses 请求体大小上限 (字节)，超出时返回 413，0 表示不限制
+    #[serde(default = "default_max_request_body_bytes")]
+    pub max_request_body_bytes: usize,
+    /// 丢弃流式与非流式输出中的思维链 (reasoning_content)，仅保留最终回答，供不支持该字段的简单客户端使用
+    /// 请求体中的 strip_reasoning 字段可按请求覆盖
+    #[serde(default)]
+    pub strip_reasoning: bool,
+    /// prediction (Predicted Outputs) 无法映射到 Gemini，始终忽略并返回 X-Unsupported-Params 头
+    /// 开启后将预测内容作为参考文本追加到系统指令
+    #[serde(default)]
+    pub prediction_hint: bool,
+    /// 音频输出 (audio 参数 / modalities 包含 "audio") 无法由上游提供
+    /// reject (默认) 返回 400 并说明原因；ignore 降级为纯文本输出并返回 X-Unsupported-Params: audio
+    #[serde(default = "default_audio_output_policy")]
+    pub audio_output: UnsupportedParamPolicy,
+}
+
+fn default_audio_output_policy() -> UnsupportedParamPolicy {
+    UnsupportedParamPolicy::Reject
+}
+
+fn default_max_request_body_bytes() -> usize {
+    100 * 1024 * 1024 // 与全局 DefaultBodyLimit 默认值一致
+}
+
+impl Default for OpenAICompatConfig {
+    fn default() -> Self {
+        Self {
+            logit_bias: UnsupportedParamPolicy::default(),
+            safety_partial: SafetyPartialPolicy::default(),
+            echo_requested_model: true,
+            max_request_body_bytes: default_max_request_body_bytes(),
+            strip_reasoning: false,
+            prediction_hint: false,
+            audio_output: default_audio_output_policy(),
+        }
+    }
+}
+
+/// 被安全策略截断 (finishReason = SAFETY) 的响应处理策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyPartialPolicy {
+    /// 返回已生成的部分内容，finish_reason 为 content_filter
+    #[default]
+    ContentFilter,
+    /// 丢弃部分内容并返回 content_filter 错误 (流式下会缓冲到结束后再输出)
+    Error,
+}
+
+/// 不支持参数的处理策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsupportedParamPolicy {
+    /// 忽略并记录 debug 日志
+    #[default]
+    Ignore,
+    /// 返回 400，明确告知客户端该参数不受支持
+    Reject,
+}
+
+// ============================================================================
+// 全局未知模型兜底配置存储
+// 客户端未传模型或传入无法识别的模型时，可路由到默认模型而不是由上游返回 404
+// ============================================================================
+static GLOBAL_DEFAULT_MODEL_CONFIG: OnceLock<RwLock<DefaultModelConfig>> = OnceLock::new();
+
+/// 获取当前未知模型兜底配置
+pub fn get_default_model_config() -> DefaultModelConfig {
+    GLOBAL_DEFAULT_MODEL_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局未知模型兜底配置
+pub fn update_default_model_config(config: DefaultModelConfig) {
+    if let Some(lock) = GLOBAL_DEFAULT_MODEL_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            *cfg = config.clone();
+            tracing::info!(
+                "[Default-Model] Config updated: enabled={}, model={}",
+                config.enabled,
+                config.model
+            );
+        }
+    } else {
+        // 首次初始化
+        let _ = GLOBAL_DEFAULT_MODEL_CONFIG.set(RwLock::new(config.clone()));
+        tracing::info!(
+            "[Default-Model] Config initialized: enabled={}, model={}",
+            config.enabled,
+            config.model
+        );
+    }
+}
+
+/// 未知模型兜底配置
+/// 关闭时 (默认) 未知模型原样透传，由上游返回错误
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DefaultModelConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 兜底目标模型
+    #[serde(default = "default_fallback_target_model")]
+    pub model: String,
+}
+
+impl Default for DefaultModelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: default_fallback_target_model(),
+        }
+    }
+}
+
+fn default_fallback_target_model() -> String {
+    "gemini-2.5-flash".to_string()
+}
+
+// ============================================================================
+// 终端用户限流配置 (AppState.user_rate_limit，随配置热更新)
+// 按 OpenAI `user` 字段限制每个终端用户的请求频率，未携带 user 的请求不受限
+// ============================================================================
+
+/// 终端用户限流配置
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct UserRateLimitConfig {
+    /// 每个 user 每分钟最大请求数 (0 表示不限制，仅统计)
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// 每个 user 每分钟最大输入 token 数 (按请求文本估算，0 表示不限制)
+    #[serde(default)]
+    pub tokens_per_minute: u32,
+}
+
+// ============================================================================
+// 账号用量统计配置 (由 TokenManager 持有，随配置热更新)
+// ============================================================================
+
+/// 账号用量统计配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountUsageConfig {
+    /// 统计窗口长度 (分钟)，窗口结束后计数清零
+    #[serde(default = "default_account_usage_window_minutes")]
+    pub window_minutes: u64,
+    /// [NEW] 账号每日预算: 当日达到上限的账号由 get_token 跳过，到重置时间后恢复
+    #[serde(default)]
+    pub daily_quota: DailyQuotaConfig,
+    /// [NEW] 慢账号降级: 首字延迟 (TTFT) 持续偏高的账号在 get_token 排序中后移
+    #[serde(default)]
+    pub slow_account: SlowAccountConfig,
+}
+
+impl Default for AccountUsageConfig {
+    fn default() -> Self {
+        Self {
+            window_minutes: default_account_usage_window_minutes(),
+            daily_quota: DailyQuotaConfig::default(),
+            slow_account: SlowAccountConfig::default(),
+        }
+    }
+}
+
+impl AccountUsageConfig {
+    pub fn window_seconds(&self) -> i64 {
+        (self.window_minutes.max(1) * 60) as i64
+    }
+}
+
+fn default_account_usage_window_minutes() -> u64 {
+    300 // 与上游 5 小时配额刷新周期一致
+}
+
+/// 账号每日预算配置 (上限为 0 表示不限制)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyQuotaConfig {
+    /// 每个账号每日请求数上限
+    #[serde(default)]
+    pub max_requests: u64,
+    /// 每个账号每日 token (输入 + 输出) 上限
+    #[serde(default)]
+    pub max_tokens: u64,
+    /// 每日重置时间 (UTC, "HH:MM")
+    #[serde(default = "default_daily_reset_time_utc")]
+    pub reset_time_utc: String,
+    /// 按账号 email 单独配置的预算 (覆盖全局上限)
+    #[serde(default)]
+    pub accounts: HashMap<String, DailyBudget>,
+}
+
+impl Default for DailyQuotaConfig {
+    fn default() -> Self {
+        Self {
+            max_requests: 0,
+            max_tokens: 0,
+            reset_time_utc: default_daily_reset_time_utc(),
+            accounts: HashMap::new(),
+        }
+    }
+}
+
+impl DailyQuotaConfig {
+    /// 是否配置了任何每日上限
+    pub fn is_enabled(&self) -> bool {
+        !self.global_budget().is_unlimited() || self.accounts.values().any(|b| !b.is_unlimited())
+    }
+
+    /// 账号适用的预算: 单独配置优先，否则使用全局上限
+    pub fn budget_for(&self, email: &str) -> DailyBudget {
+        self.accounts
+            .iter()
+            .find(|(e, _)| e.eq_ignore_ascii_case(email))
+            .map(|(_, b)| *b)
+            .unwrap_or_else(|| self.global_budget())
+    }
+
+    fn global_budget(&self) -> DailyBudget {
+        DailyBudget {
+            max_requests: self.max_requests,
+            max_tokens: self.max_tokens,
+        }
+    }
+
+    /// 重置时间相对 UTC 零点的秒数，格式无效时按 00:00 处理
+    pub fn reset_offset_secs(&self) -> i64 {
+        let parsed = self.reset_time_utc.split_once(':').and_then(|(h, m)| {
+            let h: i64 = h.trim().parse().ok()?;
+            let m: i64 = m.trim().parse().ok()?;
+            ((0..24).contains(&h) && (0..60).contains(&m)).then_some(h * 3600 + m * 60)
+        });
+        parsed.unwrap_or_else(|| {
+            tracing::warn!(
+                "[Account-Usage] Invalid daily_quota.reset_time_utc '{}', using 00:00",
+                self.reset_time_utc
+            );
+            0
+        })
+    }
+}
+
+fn default_daily_reset_time_utc() -> String {
+    "00:00".to_string()
+}
+
+/// 单个账号的每日上限 (0 表示不限制)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub struct DailyBudget {
+    #[serde(default)]
+    pub max_requests: u64,
+    #[serde(default)]
+    pub max_tokens: u64,
+}
+
+impl DailyBudget {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_requests == 0 && self.max_tokens == 0
+    }
+
+    /// 当日用量是否已达到任一上限
+    pub fn is_exhausted(&self, requests: u64, tokens: u64) -> bool {
+        (self.max_requests > 0 && requests >= self.max_requests)
+            || (self.max_tokens > 0 && tokens >= self.max_tokens)
+    }
+}
+
+/// 慢账号降级配置 (软降级: 仅调整排序，其他账号均不可用时仍会使用)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowAccountConfig {
+    /// 平均首字延迟阈值 (毫秒)，0 表示不降级 (仍统计 TTFT)
+    #[serde(default)]
+    pub ttft_threshold_ms: u64,
+    /// 每个账号保留的最近样本数
+    #[serde(default = "default_slow_account_window_size")]
+    pub window_size: usize,
+    /// 至少累计多少个样本才参与判定
+    #[serde(default = "default_slow_account_min_samples")]
+    pub min_samples: usize,
+    /// 样本有效期 (秒)，过期样本不参与判定，使被降级的账号能重新获得流量；0 表示不过期
+    #[serde(default = "default_slow_account_max_sample_age_secs")]
+    pub max_sample_age_secs: u64,
+}
+
+impl Default for SlowAccountConfig {
+    fn default() -> Self {
+        Self {
+            ttft_threshold_ms: 0,
+            window_size: default_slow_account_window_size(),
+            min_samples: default_slow_account_min_samples(),
+            max_sample_age_secs: default_slow_account_max_sample_age_secs(),
+        }
+    }
+}
+
+impl SlowAccountConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.ttft_threshold_ms > 0
+    }
+}
+
+fn default_slow_account_window_size() -> usize {
+    20
+}
+
+fn default_slow_account_min_samples() -> usize {
+    5
+}
+
+fn default_slow_account_max_sample_age_secs() -> u64 {
+    30 * 60
+}
+
+// ============================================================================
+// 全局访问日志配置存储
+// ============================================================================
+static GLOBAL_ACCESS_LOG_CONFIG: OnceLock<RwLock<AccessLogConfig>> = OnceLock::new();
+
+/// 获取当前访问日志配置
+pub fn get_access_log_config() -> AccessLogConfig {
+    GLOBAL_ACCESS_LOG_CONFIG
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局访问日志配置
+pub fn update_access_log_config(config: AccessLogConfig) {
+    if let Some(lock) = GLOBAL_ACCESS_LOG_CONFIG.get() {
+        if let Ok(mut cfg) = lock.write() {
+            *cfg = config.clone();
+            tracing::info!(
+                "[Access-Log] Config updated: enabled={}, format={:?}",
+                config.enabled,
+                config.format
+            );
+        }
+    } else {
+        // 首次初始化
+        let _ = GLOBAL_ACCESS_LOG_CONFIG.set(RwLock::new(config.clone()));
+        tracing::info!(
+            "[Access-Log] Config initialized: enabled={}, format={:?}",
+            config.enabled,
+            config.format
+        );
+    }
+}
+
+/// 访问日志行格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    Json,
+    #[default]
+    Logfmt,
+}
+
+/// 访问日志配置: 每个请求结束时输出一行 INFO 级别的结构化日志 (target = "access_log")
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+}
+
+/// 提示词屏蔽规则配置: 用户消息命中任一规则时在转发上游前拒绝 (不消耗配额)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptFilterConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub rules: Vec<PromptFilterRule>,
+    /// 命中时返回的 HTTP 状态码 (400 或 403)
+    #[serde(default = "default_prompt_filter_status")]
+    pub status_code: u16,
+}
+
+impl Default for PromptFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rules: Vec::new(),
+            status_code: default_prompt_filter_status(),
+        }
+    }
+}
+
+fn default_prompt_filter_status() -> u16 {
+    400
+}
+
+/// 单条屏蔽规则 (均不区分大小写)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptFilterRule {
+    /// 规则标识，仅写入日志，不返回给客户端
+    pub id: String,
+    pub pattern: String,
+    /// true 时 pattern 按正则解析，否则按普通子串匹配
+    #[serde(default)]
+    pub regex: bool,
+}
+
+// ============================================================================
+// 工作负载路由配置 (由 TokenManager 持有，随配置热更新)
+// 按请求分类 (code / chat) 将请求限定到指定账号子集，未配置的分类使用全部账号
+// ============================================================================
+
+/// 工作负载路由配置
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct WorkloadRoutingConfig {
+    /// 工作负载分类 ("code" / "chat" / 按模型配置强制的 request_type) -> 专用账号邮箱列表
+    #[serde(default)]
+    pub accounts: HashMap<String, Vec<String>>,
+}
+
+impl WorkloadRoutingConfig {
+    /// 返回该分类配置的账号列表，未配置或为空时返回 None (使用全部账号)
+    pub fn accounts_for(&self, workload: &str) -> Option<&[String]> {
+        self.accounts
+            .get(workload)
+            .map(|list| list.as_slice())
+            .filter(|list| !list.is_empty())
+    }
+}
+
+// ============================================================================
+// 全局按模型配置 (Model Profiles) 存储
+// key 为模型名或别名 (支持 * 通配符)，用于在 transform 函数中按模型定制行为
+// ============================================================================
+static GLOBAL_MODEL_PROFILES: OnceLock<RwLock<HashMap<String, ModelProfile>>> = OnceLock::new();
+
+/// 获取当前全部按模型配置
+pub fn get_model_profiles() -> HashMap<String, ModelProfile> {
+    GLOBAL_MODEL_PROFILES
+        .get()
+        .and_then(|lock| lock.read().ok())
+        .map(|cfg| cfg.clone())
+        .unwrap_or_default()
+}
+
+/// 更新全局按模型配置
+pub fn update_model_profiles(profiles: HashMap<String, ModelProfile>) {
+    if let Some(lock) = GLOBAL_MODEL_PROFILES.get() {
+        if let Ok(mut cfg) = lock.write() {
+            tracing::info!("[Model-Profiles] Config updated: {} profile(s)", profiles.len());
+            *cfg = profiles;
+        }
+    } else {
+        // 首次初始化
+        tracing::info!("[Model-Profiles] Config initialized: {} profile(s)", profiles.len());
+        let _ = GLOBAL_MODEL_PROFILES.set(RwLock::new(profiles));
+    }
+}
+
+/// 测试用: 在同一把锁内向全局按模型配置加入一项 (各测试使用互不相同的模型名，互不覆盖)
+#[cfg(test)]
+pub fn insert_model_profile(model: &str, profile: ModelProfile) {
+    let lock = GLOBAL_MODEL_PROFILES.get_or_init(|| RwLock::new(HashMap::new()));
+    if let Ok(mut profiles) = lock.write() {
+        profiles.insert(model.to_string(), profile);
+    }
+}
+
+/// 按模型查找配置
+/// 优先级：原始模型名精确匹配 > 映射后模型名精确匹配 > 通配符匹配 (最具体者优先)
+pub fn resolve_model_profile(original_model: &str, mapped_model: &str) -> Option<ModelProfile> {
+    let profiles = get_model_profiles();
+    if profiles.is_empty() {
+        return None;
+    }
+    if let Some(p) = profiles.get(original_model).or_else(|| profiles.get(mapped_model)) {
+        return Some(p.clone());
+    }
+
+    profiles
+        .iter()
+        .filter(|(pattern, _)| {
+            pattern.contains('*')
+                && (crate::proxy::common::model_mapping::wildcard_match(pattern, original_model)
+                    || crate::proxy::common::model_mapping::wildcard_match(pattern, mapped_model))
+        })
+        .max_by_key(|(pattern, _)| pattern.chars().count() - pattern.matches('*').count())
+        .map(|(_, p)| p.clone())
+}
+
+/// 按模型配置
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ModelProfile {
+    /// 对该模型静默注入的系统提示词 (客户端不可见)
+    #[serde(default)]
+    pub system_prompt: Option<InjectedSystemPrompt>,
+    /// 客户端未指定 response_format 时使用的默认值 (如 "json_object")
+    #[serde(default)]
+    pub default_response_format: Option<String>,
+    /// 强制指定 request_type (如 "realtime" / "image_gen")，跳过自动识别
+    /// 仅决定令牌调度分组 (workload)，不发往上游
+    #[serde(default)]
+    pub request_type: Option<String>,
+    /// 降级模型链: 该模型的账号轮换预算耗尽后按顺序切换 (如 ["gemini-2.5-flash"])
+    #[serde(default)]
+    pub fallback_models: Vec<String>,
+    /// 按模型超时 (推理模型可放宽，快速模型可收紧)，未设置的项使用全局默认值
+    #[serde(default)]
+    pub timeouts: Option<ModelTimeouts>,
+    /// 客户端未指定时使用的默认生成参数 (客户端显式传入的值始终优先)
+    #[serde(default)]
+    pub generation_defaults: Option<GenerationDefaults>,
+    /// 不发送 systemInstruction，改为将系统指令折叠到首条 user 消息开头 (用于拒绝 systemInstruction 的模型)
+    #[serde(default)]
+    pub inline_system_prompt: bool,
+    /// 模型不支持 frequency_penalty / presence_penalty: 转换时直接省略，不再依赖 400 后剥离重试
+    #[serde(default)]
+    pub omit_penalties: bool,
+    /// 模型不接受 HARM_CATEGORY_CIVIC_INTEGRITY 安全类别: 转换时省略 (未标记的模型返回 400 后自动省略并重试)
+    #[serde(default)]
+    pub omit_civic_integrity: bool,
+}
+
+/// 按模型默认生成参数
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct GenerationDefaults {
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+}
+
+impl GenerationDefaults {
+    /// 校验取值范围，越界值钳制到上游接受的范围并记录警告
+    pub fn sanitized(&self) -> Self {
+        let clamp = |name: &str, value: Option<f64>, min: f64, max: f64| {
+            value.map(|v| {
+                let clamped = v.clamp(min, max);
+                if clamped != v {
+                    tracing::warn!(
+                        "[ModelProfile] Default {}={} out of range [{}, {}], clamped to {}",
+                        name,
+                        v,
+                        min,
+                        max,
+                        clamped
+                    );
+                }
+                clamped
+            })
+        };
+        Self {
+            temperature: clamp("temperature", self.temperature, 0.0, 2.0),
+            top_p: clamp("top_p", self.top_p, 0.0, 1.0),
+            top_k: self.top_k.map(|k| {
+                if k == 0 {
+                    tracing::warn!("[ModelProfile] Default top_k=0 out of range, clamped to 1");
+                }
+                k.max(1)
+            }),
+        }
+    }
+
+    /// 写入 generationConfig (用于客户端无法指定采样参数的接口，如图像生成)
+    pub fn apply_to(&self, gen_config: &mut serde_json::Value) {
+        if let Some(t) = self.temperature {
+            gen_config["temperature"] = serde_json::json!(t);
+        }
+        if let Some(p) = self.top_p {
+            gen_config["topP"] = serde_json::json!(p);
+        }
+        if let Some(k) = self.top_k {
+            gen_config["topK"] = serde_json::json!(k);
+        }
+    }
+}
+
+/// 解析某模型的默认生成参数 (已校验范围)
+pub fn resolve_generation_defaults(original_model: &str, mapped_model: &str) -> GenerationDefaults {
+    resolve_model_profile(original_model, mapped_model)
+        .and_then(|p| p.generation_defaults)
+        .map(|d| d.sanitized())
+        .unwrap_or_default()
+}
+
+/// 按模型超时配置 (秒)
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ModelTimeouts {
+    /// 等待上游首个数据块的时限 (流式 peek)
+    #[serde(default)]
+    pub peek_timeout_secs: Option<u64>,
+    /// 单次上游请求的总时限 (发起请求至收到响应)
+    #[serde(default)]
+    pub total_timeout_secs: Option<u64>,
+    /// 非流式请求内部收集流数据的时限
+    #[serde(default)]
+    pub collector_timeout_secs: Option<u64>,
+}
+
+/// 实际生效的超时 (None 表示不额外限制，沿用上游客户端自身的超时)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectiveTimeouts {
+    pub peek: std::time::Duration,
+    pub total: Option<std::time::Duration>,
+    pub collector: Option<std::time::Duration>,
+}
+
+/// 解析模型的实际超时，default_peek 为各协议处理器原有的 peek 时限
+pub fn resolve_model_timeouts(
+    original_model: &str,
+    mapped_model: &str,
+    default_peek: std::time::Duration,
+) -> EffectiveTimeouts {
+    let timeouts = resolve_model_profile(original_model, mapped_model)
+        .and_then(|p| p.timeouts)
+        .unwrap_or_default();
+    let secs = |v: Option<u64>| v.filter(|s| *s > 0).map(std::time::Duration::from_secs);
+    EffectiveTimeouts {
+        peek: secs(timeouts.peek_timeout_secs).unwrap_or(default_peek),
+        total: secs(timeouts.total_timeout_secs),
+        collector: secs(timeouts.collector_timeout_secs),
+    }
+}
+
+/// 注入的系统提示词
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InjectedSystemPrompt {
+    pub content: String,
+    /// 相对于客户端自身 system 消息的位置
+    #[serde(default)]
+    pub position: PromptPosition,
+}
+
+/// 提示词注入位置
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptPosition {
+    /// 置于客户端 system 消息之前
+    Prepend,
+    /// 置于客户端 system 消息之后
+    #[default]
+    Append,
+}
+
 /// 全局系统提示词配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlobalSystemPromptConfig {
@@ -86,6 +935,9 @@ pub struct GlobalSystemPromptConfig {
     /// 系统提示词内容
     #[serde(default)]
     pub content: String,
+    /// [NEW] 追加在客户端系统提示词之后的内容 (可选)
+    #[serde(default)]
+    pub append_content: String,
 }
 
 impl Default for GlobalSystemPromptConfig {
@@ -93,10 +945,34 @@ impl Default for GlobalSystemPromptConfig {
         Self {
             enabled: false,
             content: String::new(),
+            append_content: String::new(),
         }
     }
 }
 
+impl GlobalSystemPromptConfig {
+    /// 位于客户端系统提示词之前的内容，未启用或为空时返回 None
+    pub fn prefix(&self) -> Option<&str> {
+        Some(self.content.as_str()).filter(|c| self.enabled && !c.trim().is_empty())
+    }
+
+    /// 位于客户端系统提示词之后的内容，未启用或为空时返回 None
+    pub fn suffix(&self) -> Option<&str> {
+        Some(self.append_content.as_str()).filter(|c| self.enabled && !c.trim().is_empty())
+    }
+}
+
+/// 代理接口的附加 API Key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiKeyEntry {
+    pub key: String,
+    /// 备注名称 (如使用者或服务名，用于日志)
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ProxyAuthMode {
@@ -243,6 +1119,148 @@ pub struct ExperimentalConfig {
     /// 上下文压缩阈值 L3 (Fork + Summary)
     #[serde(default = "default_threshold_l3")]
     pub context_compression_threshold_l3: f32,
+
+    /// 修复截断的工具调用参数 (补全未闭合的 JSON，无法修复时替换为 {})
+    /// 仅作用于非流式响应，默认关闭
+    #[serde(default = "default_false")]
+    pub enable_tool_args_repair: bool,
+
+    /// 非流式请求在内部转为流式再收集 (配额更宽松)，默认开启
+    /// 关闭后直接调用 generateContent；单个请求可用 X-Force-Stream 头覆盖
+    #[serde(default = "default_true")]
+    pub force_stream_internally: bool,
+
+    /// 图像生成结果后处理: 按 output_format 转码 (默认 PNG) 并缩小到请求的 size，默认关闭
+    #[serde(default = "default_false")]
+    pub enable_image_postprocess: bool,
+
+    /// /v1/chat/completions 相同并发非流式请求去重 (共享首个请求的响应)，默认关闭
+    #[serde(default = "default_false")]
+    pub enable_request_dedup: bool,
+
+    /// 上传图片最大边长 (像素)，超出时发送前按比例缩小，0 表示不限制
+    #[serde(default = "default_input_image_max_dimension")]
+    pub input_image_max_dimension: u32,
+
+    /// 上传图片最大字节数，超出时缩小并重新编码，0 表示不限制
+    #[serde(default = "default_input_image_max_bytes")]
+    pub input_image_max_bytes: usize,
+
+    /// 下载远程图片时允许访问回环 / 内网地址的主机名 (默认拒绝所有非公网地址，防止 SSRF)
+    #[serde(default)]
+    pub remote_image_allowed_hosts: Vec<String>,
+
+    /// 非流式请求内部收集流时累计内容的上限 (字节)，超出时停止收集并以 finish_reason=length 返回已收集内容
+    /// 同时限制 safety_partial = error 时流式响应的缓冲大小，超出时以错误事件结束
+    /// 防止失控的超长生成占用过多内存，0 表示不限制
+    #[serde(default = "default_max_collected_bytes")]
+    pub max_collected_bytes: usize,
+
+    /// 内联媒体 (视频 / PDF 等 data URL) 解码后超过该字节数时上传至 Gemini Files API，以 fileData 引用
+    /// 需账号 token 已获 Files API (generativelanguage) 授权，被拒绝时回退为内联；默认 0 (关闭)
+    #[serde(default = "default_media_upload_threshold_bytes")]
+    pub media_upload_threshold_bytes: usize,
+
+    /// 响应附加 X-Upstream-Latency-Ms 头 (最终采用的上游调用耗时，不含重试开销)，默认关闭
+    #[serde(default = "default_false")]
+    pub expose_upstream_latency: bool,
+
+    /// 响应附加 X-Rotation-Trace 头 (本次请求的账号轮换决策，邮箱已脱敏)，默认关闭
+    #[serde(default = "default_false")]
+    pub expose_rotation_trace: bool,
+
+    /// 携带 Idempotency-Key 的成功响应缓存时长 (秒)，窗口内相同键与内容的重复提交直接返回缓存结果
+    /// 0 表示关闭 (默认关闭)
+    #[serde(default)]
+    pub idempotency_ttl_secs: u64,
+
+    /// 幂等缓存最多保留的响应条数，超出时淘汰最久未使用的条目
+    #[serde(default = "default_idempotency_max_entries")]
+    pub idempotency_max_entries: usize,
+
+    /// 幂等缓存响应体总字节数上限，超出时淘汰最久未使用的条目 (单个响应超过上限则不缓存)
+    #[serde(default = "default_idempotency_max_bytes")]
+    pub idempotency_max_bytes: usize,
+
+    /// 按客户端 Accept-Encoding 压缩 JSON 响应 (br / gzip / deflate)，SSE 流式响应不压缩，默认关闭
+    #[serde(default = "default_false")]
+    pub response_compression: bool,
+
+    /// POST /v1/chat/batch 单次可提交的最大请求数，0 表示不限制
+    #[serde(default = "default_batch_max_requests")]
+    pub batch_max_requests: usize,
+
+    /// POST /v1/chat/batch 单个批次内同时处理的请求数，0 表示不限制
+    #[serde(default = "default_batch_max_concurrency")]
+    pub batch_max_concurrency: usize,
+
+    /// 停止服务时等待进行中请求 (含流式响应) 完成的最长秒数，超时后强制断开
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+
+    /// 上游 HTTP 客户端连接池与超时配置 (重启服务后生效)
+    #[serde(default)]
+    pub upstream_http: UpstreamHttpConfig,
+}
+
+/// 上游 HTTP 客户端连接池与超时配置，默认值与此前的内置设置一致；超时类取值为 0 表示不限制
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamHttpConfig {
+    /// 建立连接超时 (秒)
+    #[serde(default = "default_upstream_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// 空闲连接保留时长 (秒)
+    #[serde(default = "default_upstream_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: u64,
+    /// 每个上游主机保留的最大空闲连接数
+    #[serde(default = "default_upstream_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    /// TCP keepalive 探测间隔 (秒)，0 表示关闭
+    #[serde(default = "default_upstream_tcp_keepalive_secs")]
+    pub tcp_keepalive_secs: u64,
+    /// 流式请求 (streamGenerateContent) 总超时 (秒)，含读取完整响应流
+    #[serde(default = "default_upstream_stream_timeout_secs")]
+    pub stream_timeout_secs: u64,
+    /// 非流式请求总超时 (秒)
+    #[serde(default = "default_upstream_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+}
+
+impl Default for UpstreamHttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: default_upstream_connect_timeout_secs(),
+            pool_idle_timeout_secs: default_upstream_pool_idle_timeout_secs(),
+            pool_max_idle_per_host: default_upstream_pool_max_idle_per_host(),
+            tcp_keepalive_secs: default_upstream_tcp_keepalive_secs(),
+            stream_timeout_secs: default_upstream_stream_timeout_secs(),
+            request_timeout_secs: default_upstream_request_timeout_secs(),
+        }
+    }
+}
+
+fn default_upstream_connect_timeout_secs() -> u64 {
+    20
+}
+
+fn default_upstream_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_upstream_pool_max_idle_per_host() -> usize {
+    16
+}
+
+fn default_upstream_tcp_keepalive_secs() -> u64 {
+    60
+}
+
+fn default_upstream_stream_timeout_secs() -> u64 {
+    600
+}
+
+fn default_upstream_request_timeout_secs() -> u64 {
+    600
 }
 
 impl Default for ExperimentalConfig {
@@ -255,10 +1273,65 @@ impl Default for ExperimentalConfig {
             context_compression_threshold_l1: 0.4,
             context_compression_threshold_l2: 0.55,
             context_compression_threshold_l3: 0.7,
+            enable_tool_args_repair: false,
+            force_stream_internally: true,
+            enable_image_postprocess: false,
+            enable_request_dedup: false,
+            input_image_max_dimension: default_input_image_max_dimension(),
+            input_image_max_bytes: default_input_image_max_bytes(),
+            remote_image_allowed_hosts: Vec::new(),
+            max_collected_bytes: default_max_collected_bytes(),
+            media_upload_threshold_bytes: default_media_upload_threshold_bytes(),
+            expose_upstream_latency: false,
+            expose_rotation_trace: false,
+            idempotency_ttl_secs: 0,
+            idempotency_max_entries: default_idempotency_max_entries(),
+            idempotency_max_bytes: default_idempotency_max_bytes(),
+            response_compression: false,
+            batch_max_requests: default_batch_max_requests(),
+            batch_max_concurrency: default_batch_max_concurrency(),
+            shutdown_grace_secs: default_shutdown_grace_secs(),
+            upstream_http: UpstreamHttpConfig::default(),
         }
     }
 }
 
+fn default_input_image_max_dimension() -> u32 {
+    3072
+}
+
+fn default_input_image_max_bytes() -> usize {
+    7 * 1024 * 1024 // Gemini 单张 inlineData 图片上限
+}
+
+fn default_media_upload_threshold_bytes() -> usize {
+    0 // 需显式开启；建议 16MB，为上游约 20MB 的请求体上限预留其余内容的空间
+}
+
+fn default_idempotency_max_entries() -> usize {
+    256
+}
+
+fn default_idempotency_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_batch_max_requests() -> usize {
+    20
+}
+
+fn default_batch_max_concurrency() -> usize {
+    4
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+fn default_max_collected_bytes() -> usize {
+    32 * 1024 * 1024
+}
+
 fn default_threshold_l1() -> f32 {
     0.4
 }
@@ -433,6 +1506,10 @@ pub struct ProxyConfig {
     /// Web UI 管理后台密码 (可选，如未设置则使用 api_key)
     pub admin_password: Option<String>,
 
+    /// [NEW] 额外允许访问代理接口的 API Key (团队共享时每人 / 每个服务一个，可单独停用)
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyEntry>,
+
     /// 是否自动启动
     pub auto_start: bool,
 
@@ -464,6 +1541,11 @@ pub struct ProxyConfig {
     #[serde(default)]
     pub user_agent_override: Option<String>,
 
+    /// 上游 v1internal 端点列表 (按顺序降级，如镜像或多区域端点)
+    /// 网络错误 / 429 / 5xx 时切换到下一个端点；为空时使用内置端点
+    #[serde(default)]
+    pub upstream_base_urls: Vec<String>,
+
     /// 账号调度配置 (粘性会话/限流重试)
     #[serde(default)]
     pub scheduling: crate::proxy::sticky_config::StickySessionConfig,
@@ -499,6 +1581,46 @@ pub struct ProxyConfig {
     /// 代理池配置
     #[serde(default)]
     pub proxy_pool: ProxyPoolConfig,
+
+    /// 图片接口配置
+    #[serde(default)]
+    pub image: ImageConfig,
+
+    /// 流式响应缓冲配置
+    #[serde(default)]
+    pub streaming: StreamingConfig,
+
+    /// OpenAI 兼容性配置 (不支持参数的处理策略)
+    #[serde(default)]
+    pub openai_compat: OpenAICompatConfig,
+
+    /// 按模型配置 (key 为模型名或别名，支持 * 通配符)
+    #[serde(default)]
+    pub model_profiles: HashMap<String, ModelProfile>,
+
+    /// 未知模型兜底配置
+    #[serde(default)]
+    pub default_model: DefaultModelConfig,
+
+    /// 终端用户 (OpenAI `user` 字段) 限流配置
+    #[serde(default)]
+    pub user_rate_limit: UserRateLimitConfig,
+
+    /// 账号用量统计配置
+    #[serde(default)]
+    pub account_usage: AccountUsageConfig,
+
+    /// 访问日志配置
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+
+    /// 提示词屏蔽规则
+    #[serde(default)]
+    pub prompt_filter: PromptFilterConfig,
+
+    /// 按请求分类 (code / chat) 路由到指定账号
+    #[serde(default)]
+    pub workload_routing: WorkloadRoutingConfig,
 }
 
 /// 上游代理配置
@@ -519,6 +1641,7 @@ impl Default for ProxyConfig {
             port: 8045,
             api_key: format!("sk-{}", uuid::Uuid::new_v4().simple()),
             admin_password: None,
+            api_keys: Vec::new(),
             auto_start: false,
             custom_mapping: std::collections::HashMap::new(),
             request_timeout: default_request_timeout(),
@@ -531,10 +1654,21 @@ impl Default for ProxyConfig {
             security_monitor: SecurityMonitorConfig::default(),
             preferred_account_id: None, // 默认使用轮询模式
             user_agent_override: None,
+            upstream_base_urls: Vec::new(),
             saved_user_agent: None,
             thinking_budget: ThinkingBudgetConfig::default(),
             global_system_prompt: GlobalSystemPromptConfig::default(),
             proxy_pool: ProxyPoolConfig::default(),
+            image: ImageConfig::default(),
+            streaming: StreamingConfig::default(),
+            openai_compat: OpenAICompatConfig::default(),
+            model_profiles: HashMap::new(),
+            default_model: DefaultModelConfig::default(),
+            user_rate_limit: UserRateLimitConfig::default(),
+            account_usage: AccountUsageConfig::default(),
+            access_log: AccessLogConfig::default(),
+            prompt_filter: PromptFilterConfig::default(),
+            workload_routing: WorkloadRoutingConfig::default(),
         }
     }
 }
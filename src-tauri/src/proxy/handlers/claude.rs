@@ -24,6 +24,11 @@ use crate::proxy::mappers::estimation_calibrator::get_calibrator;
 use crate::proxy::debug_logger;
 use crate::proxy::upstream::client::mask_email;
 use crate::proxy::common::client_adapter::CLIENT_ADAPTERS; // [NEW] Import Adapter Registry
+use crate::proxy::common::stream_buffer::{
+    peek_heartbeat_interval, with_flush_threshold, with_peek_heartbeats, BufferedByteStream,
+    SseErrorFormat,
+};
+use crate::proxy::middleware::request_span::record_attempt;
 use axum::http::HeaderMap;
 use std::sync::{atomic::Ordering, Arc};
 
@@ -105,7 +110,11 @@ The structure MUST be as follows:
 
 // ===== 统一退避策略模块 =====
 // 移除本地重复定义，使用 common 中的统一实现
-use super::common::{determine_retry_strategy, apply_retry_strategy, should_rotate_account, RetryStrategy};
+use super::common::{determine_retry_strategy, apply_retry_strategy, should_rotate_account, record_peek_outcome, with_optional_timeout, with_rotation_trace, AccountAttempt, AttemptBudget, RetryStrategy};
+use crate::proxy::config::resolve_model_timeouts;
+use crate::proxy::mappers::common_utils::{
+    has_civic_integrity_setting, is_civic_integrity_rejection, mark_civic_integrity_unsupported,
+};
 
 // ===== 退避策略模块结束 =====
 
@@ -123,11 +132,8 @@ pub async fn handle_messages(
     
     tracing::debug!("handle_messages called. Body JSON len: {}", body.to_string().len());
     
-    // 生成随机 Trace ID 用户追踪
-    let trace_id: String = rand::Rng::sample_iter(rand::thread_rng(), &rand::distributions::Alphanumeric)
-        .take(6)
-        .map(char::from)
-        .collect::<String>().to_lowercase();
+    // 与 request_span 中的 trace_id 及响应头 X-Request-Id 一致，便于按请求关联日志
+    let trace_id = crate::proxy::middleware::request_span::request_id(&headers);
     let debug_cfg = state.debug_logging.read().await.clone();
     
     // [NEW] Detect Client Adapter
@@ -159,6 +165,25 @@ pub async fn handle_messages(
         }
     };
 
+    // [NEW] 提示词屏蔽规则 (早于 z.ai 分发与账号选择，命中的规则 id 只写入日志)
+    {
+        let filter = state.prompt_filter.read().await;
+        let user_text = crate::proxy::prompt_filter::claude_user_text(&request);
+        if let Some(rule_id) = filter.check(&user_text) {
+            tracing::warn!("[{}] Request rejected by prompt filter rule {}", trace_id, rule_id);
+            return (
+                filter.status(),
+                Json(json!({
+                    "type": "error",
+                    "error": {
+                        "type": "invalid_request_error",
+                        "message": crate::proxy::prompt_filter::REJECTED_MESSAGE
+                    }
+                }))
+            ).into_response();
+        }
+    }
+
     if debug_logger::is_enabled(&debug_cfg) {
         // [FIX] 使用原始 body 副本记录日志，确保不丢失任何字段
         let original_payload = json!({
@@ -279,6 +304,7 @@ pub async fn handle_messages(
     let threshold_l1 = experimental.context_compression_threshold_l1;
     let threshold_l2 = experimental.context_compression_threshold_l2;
     let threshold_l3 = experimental.context_compression_threshold_l3;
+    let force_stream_default = experimental.force_stream_internally;
 
     // 获取最新一条“有意义”的消息内容（用于日志记录和后台任务检测）
     // 策略：反向遍历，首先筛选出所有角色为 "user" 的消息，然后从中找到第一条非 "Warmup" 且非空的文本消息
@@ -381,6 +407,33 @@ pub async fn handle_messages(
     // 3. 准备闭包
     let mut request_for_body = request.clone();
     let token_manager = state.token_manager;
+
+    // [NEW] X-Account-Override: 管理员强制指定账号
+    let account_override = {
+        let security = state.security.read().await;
+        match super::common::resolve_account_override(&headers, &security, &token_manager) {
+            Ok(v) => v,
+            Err((status, message)) => {
+                return (
+                    status,
+                    Json(json!({
+                        "type": "error",
+                        "error": {
+                            "type": "invalid_request_error",
+                            "message": message
+                        }
+                    })),
+                )
+                    .into_response();
+            }
+        }
+    };
+    // [NEW] 账号池全部限流时快速失败
+    if let Some(resp) =
+        super::common::pool_outage_response(&token_manager, account_override.as_deref()).await
+    {
+        return resp;
+    }
     
     let pool_size = token_manager.len();
     // [FIX] Ensure max_attempts is at least 2 to allow for internal retries (e.g. stripping signatures)
@@ -392,8 +445,13 @@ pub async fn handle_messages(
     let mut last_email: Option<String> = None;
     let mut last_mapped_model: Option<String> = None;
     let mut last_status = StatusCode::SERVICE_UNAVAILABLE; // Default to 503 if no response reached
+    // [NEW] 逐账号尝试记录 (X-Rotation-Trace)
+    let mut attempts: Vec<AccountAttempt> = Vec::new();
+    let rotation_trace_enabled = state.experimental.read().await.expose_rotation_trace;
+    let streaming_config = state.streaming.read().await.clone();
     
-    for attempt in 0..max_attempts {
+    let mut attempt_budget = AttemptBudget::new(max_attempts);
+    while let Some(attempt) = attempt_budget.next() {
         // 2. 模型路由解析
         let mut mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
             &request_for_body.model,
@@ -415,13 +473,16 @@ pub async fn handle_messages(
             None,  // Claude handler uses transform_claude_request_in for image gen
         );
 
+        // [NEW] 按模型超时 (peek / 单次请求总时限 / 非流式收集)
+        let timeouts = resolve_model_timeouts(&request_for_body.model, &mapped_model, Duration::from_secs(60));
+
         // 0. 尝试提取 session_id 用于粘性调度 (Phase 2/3)
         // 使用 SessionManager 生成稳定的会话指纹
         let session_id_str = crate::proxy::session_manager::SessionManager::extract_session_id(&request_for_body);
         let session_id = Some(session_id_str.as_str());
 
         let force_rotate_token = attempt > 0;
-        let (access_token, project_id, email, account_id, _wait_ms) = match token_manager.get_token(&config.request_type, force_rotate_token, session_id, &config.final_model).await {
+        let (access_token, project_id, email, account_id, _wait_ms) = match token_manager.get_token_with_override(account_override.as_deref(), &config.workload, force_rotate_token, session_id, &config.final_model).await {
             Ok(t) => t,
             Err(e) => {
                 let safe_message = if e.contains("invalid_grant") {
@@ -446,8 +507,12 @@ pub async fn handle_messages(
             }
         };
 
+        state
+            .metrics
+            .record_attempt("claude", attempt, last_email.as_deref(), &email);
         last_email = Some(email.clone());
-        info!("✓ Using account: {} (type: {})", email, config.request_type);
+        record_attempt(attempt + 1, &email, &config.final_model);
+        info!("✓ Using account: {} (type: {}, workload: {})", email, config.request_type, config.workload);
         
         
         // ===== 【优化】后台任务智能检测与降级 =====
@@ -690,16 +755,19 @@ pub async fn handle_messages(
                 "original_model": request.model,
                 "mapped_model": request_with_mapped.model,
                 "request_type": config.request_type,
+                "workload": config.workload,
                 "attempt": attempt,
                 "v1internal_request": gemini_body.clone(),
             });
             debug_logger::write_debug_payload(&debug_cfg, Some(&trace_id), "v1internal_request", &payload).await;
         }
+        // [NEW] 本次请求是否携带 CIVIC_INTEGRITY 类别 (400 时据此决定是否省略后重试)
+        let sent_civic_integrity = has_civic_integrity_setting(&gemini_body);
         
     // 4. 上游调用 - 自动转换逻辑
     let client_wants_stream = request.stream;
     // [AUTO-CONVERSION] 非 Stream 请求自动转换为 Stream 以享受更宽松的配额
-    let force_stream_internally = !client_wants_stream;
+    let force_stream_internally = super::common::resolve_force_stream(&headers, client_wants_stream, force_stream_default);
     let actual_stream = client_wants_stream || force_stream_internally;
     
     if force_stream_internally {
@@ -731,12 +799,17 @@ pub async fn handle_messages(
 
         // Upstream call configuration continued...
 
-        let call_result = match upstream
-            .call_v1_internal_with_headers(method, &access_token, gemini_body, query, extra_headers.clone(), Some(account_id.as_str()))
-            .await {
+        let upstream_started = std::time::Instant::now(); // [NEW] 首字延迟起点
+        let call_result = match with_optional_timeout(
+            timeouts.total,
+            "Upstream request",
+            upstream.call_v1_internal_with_headers(method, &access_token, gemini_body, query, extra_headers.clone(), Some(account_id.as_str())),
+        )
+        .await {
             Ok(r) => r,
             Err(e) => {
                 last_error = e.clone();
+                attempts.push(AccountAttempt::new(&email, &request_with_mapped.model, None, &last_error));
                 debug!("Request failed on attempt {}/{}: {}", attempt + 1, max_attempts, e);
                 continue;
             }
@@ -774,6 +847,7 @@ pub async fn handle_messages(
         if status.is_success() {
             // [智能限流] 请求成功，重置该账号的连续失败计数
             token_manager.mark_account_success(&email);
+            token_manager.record_model_success(&email, &request_with_mapped.model);
             
                 // Determine context limit based on model
                 let context_limit = crate::proxy::mappers::claude::utils::get_context_limit_for_model(&request_with_mapped.model);
@@ -817,10 +891,16 @@ pub async fn handle_messages(
 
                 let mut first_data_chunk = None;
                 let mut retry_this_account = false;
+                // [NEW] streaming.peek_heartbeat_ms: 超过该时长仍无首个数据块即开始响应并发送心跳
+                let heartbeat_after =
+                    peek_heartbeat_interval(&streaming_config, client_wants_stream, timeouts.peek);
+                let peek_started = std::time::Instant::now();
+                let mut start_heartbeats = false;
 
                 // Loop to skip heartbeats during peek
                 loop {
-                    match tokio::time::timeout(std::time::Duration::from_secs(60), claude_stream.next()).await {
+                    let wait = heartbeat_after.map_or(timeouts.peek, |after| after.saturating_sub(peek_started.elapsed()));
+                    match tokio::time::timeout(wait, claude_stream.next()).await {
                         Ok(Some(Ok(bytes))) => {
                             if bytes.is_empty() {
                                 continue;
@@ -834,6 +914,10 @@ pub async fn handle_messages(
                             }
 
                             // We found real data!
+                            // [NEW] 记录首字延迟 (指标 + 慢账号降级)
+                            let ttft = upstream_started.elapsed();
+                            state.metrics.record_first_token_latency("claude", ttft);
+                            token_manager.record_first_token_latency(&email, ttft.as_millis() as u64);
                             first_data_chunk = Some(bytes);
                             break;
                         }
@@ -849,9 +933,14 @@ pub async fn handle_messages(
                             retry_this_account = true;
                             break;
                         }
+                        Err(_) if heartbeat_after.is_some() => {
+                            start_heartbeats = true;
+                            break;
+                        }
                         Err(_) => {
-                            tracing::warn!("[{}] Timeout waiting for first data (60s), retrying...", trace_id);
+                            tracing::warn!("[{}] Timeout waiting for first data ({}s), retrying...", trace_id, timeouts.peek.as_secs());
                             last_error = "Timeout waiting for first data".to_string();
+                            state.metrics.record_peek_timeout("claude");
                             retry_this_account = true;
                             break;
                         }
@@ -859,25 +948,37 @@ pub async fn handle_messages(
                 }
 
                 if retry_this_account {
+                    attempts.push(AccountAttempt::new(&email, &request_with_mapped.model, None, &last_error));
                     continue;
                 }
 
-                match first_data_chunk {
-                    Some(bytes) => {
+                // 心跳已开始: 由 with_peek_heartbeats 继续等待首个数据块，失败以 Claude 错误事件结束
+                let peeked_stream: Option<BufferedByteStream<String>> = match (first_data_chunk, heartbeat_after) {
+                    (Some(bytes), _) => Some(Box::pin(futures::stream::once(async move { Ok(bytes) }).chain(claude_stream))),
+                    (None, Some(heartbeat_interval)) if start_heartbeats => Some(with_peek_heartbeats(
+                        claude_stream,
+                        heartbeat_interval,
+                        timeouts.peek.saturating_sub(peek_started.elapsed()),
+                        SseErrorFormat::Claude,
+                        record_peek_outcome(&state.metrics, &token_manager, "claude", &email, upstream_started),
+                    )),
+                    _ => None,
+                };
+
+                match peeked_stream {
+                    Some(stream) => {
                         // We have data! Construct the combined stream
-                        let stream_rest = claude_stream;
-                        let combined_stream = Box::pin(futures::stream::once(async move { Ok(bytes) })
-                            .chain(stream_rest.map(|result| -> Result<Bytes, std::io::Error> {
+                        let combined_stream = Box::pin(stream.map(|result| -> Result<Bytes, std::io::Error> {
                                 match result {
                                     Ok(b) => Ok(b),
                                     Err(e) => Ok(Bytes::from(format!("data: {{\"error\":\"{}\"}}\n\n", e))),
                                 }
-                            })));
+                            }));
 
                         // 判断客户端期望的格式
                         if client_wants_stream {
                             // 客户端本就要 Stream，直接返回 SSE
-                            return Response::builder()
+                            let resp = Response::builder()
                                 .status(StatusCode::OK)
                                 .header(header::CONTENT_TYPE, "text/event-stream")
                                 .header(header::CACHE_CONTROL, "no-cache")
@@ -886,16 +987,20 @@ pub async fn handle_messages(
                                 .header("X-Account-Email", &email)
                                 .header("X-Mapped-Model", &request_with_mapped.model)
                                 .header("X-Context-Purified", if is_purified { "true" } else { "false" })
-                                .body(Body::from_stream(combined_stream))
+                                .body(Body::from_stream(with_flush_threshold(
+                                    combined_stream,
+                                    &streaming_config,
+                                )))
                                 .unwrap();
+                            return with_rotation_trace(resp, &attempts, Some(&email), rotation_trace_enabled);
                         } else {
                             // 客户端要非 Stream，需要收集完整响应并转换为 JSON
                             use crate::proxy::mappers::claude::collect_stream_to_json;
                             
-                            match collect_stream_to_json(combined_stream).await {
+                            match with_optional_timeout(timeouts.collector, "Stream collection", collect_stream_to_json(combined_stream)).await {
                                 Ok(full_response) => {
                                     info!("[{}] ✓ Stream collected and converted to JSON", trace_id);
-                                    return Response::builder()
+                                    let resp = Response::builder()
                                         .status(StatusCode::OK)
                                         .header(header::CONTENT_TYPE, "application/json")
                                         .header("X-Account-Email", &email)
@@ -903,6 +1008,7 @@ pub async fn handle_messages(
                                         .header("X-Context-Purified", if is_purified { "true" } else { "false" })
                                         .body(Body::from(serde_json::to_string(&full_response).unwrap()))
                                         .unwrap();
+                                    return with_rotation_trace(resp, &attempts, Some(&email), rotation_trace_enabled);
                                 }
                                 Err(e) => {
                                     return (StatusCode::INTERNAL_SERVER_ERROR, format!("Stream collection error: {}", e)).into_response();
@@ -914,6 +1020,7 @@ pub async fn handle_messages(
                     None => {
                         tracing::warn!("[{}] Stream ended immediately (Empty Response), retrying...", trace_id);
                         last_error = "Empty response stream (None)".to_string();
+                        attempts.push(AccountAttempt::new(&email, &request_with_mapped.model, None, &last_error));
                         continue;
                     }
                 }
@@ -978,7 +1085,8 @@ pub async fn handle_messages(
                     cache_info
                 );
 
-                return (StatusCode::OK, [("X-Account-Email", email.as_str()), ("X-Mapped-Model", request_with_mapped.model.as_str())], Json(claude_response)).into_response();
+                let resp = (StatusCode::OK, [("X-Account-Email", email.as_str()), ("X-Mapped-Model", request_with_mapped.model.as_str())], Json(claude_response)).into_response();
+                return with_rotation_trace(resp, &attempts, Some(&email), rotation_trace_enabled);
             }
         }
         
@@ -990,6 +1098,7 @@ pub async fn handle_messages(
         // 2. 获取错误文本并转移 Response 所有权
         let error_text = response.text().await.unwrap_or_else(|_| format!("HTTP {}", status));
         last_error = format!("HTTP {}: {}", status_code, error_text);
+        attempts.push(AccountAttempt::new(&email, &request_with_mapped.model, Some(status_code), &error_text));
         debug!("[{}] Upstream Error Response: {}", trace_id, error_text);
         if debug_logger::is_enabled(&debug_cfg) {
             let payload = json!({
@@ -1012,6 +1121,14 @@ pub async fn handle_messages(
         // 🆕 传入实际使用的模型,实现模型级别限流,避免不同模型配额互相影响
         if status_code == 429 || status_code == 529 || status_code == 503 || status_code == 500 {
             token_manager.mark_rate_limited_async(&email, status_code, retry_after.as_deref(), &error_text, Some(&request_with_mapped.model)).await;
+            state.metrics.record_rate_limited("claude");
+        }
+
+        // [NEW] 模型不支持 CIVIC_INTEGRITY 安全类别: 记录后重试，重新转换时省略该类别
+        if sent_civic_integrity && is_civic_integrity_rejection(status_code, &error_text) {
+            mark_civic_integrity_unsupported(&request_with_mapped.model);
+            attempt_budget.retry_current();
+            continue;
         }
 
         // 4. 处理 400 错误 (Thinking 签名失效 或 块顺序错误)
@@ -1111,8 +1228,12 @@ pub async fn handle_messages(
             
             // [FIX] 强制重试：因为我们已经清理了 thinking block，所以这是一个新的、可以重试的请求
             // 不要使用 determine_retry_strategy，因为它会因为 retried_without_thinking=true 而返回 NoRetry
+            let strategy = RetryStrategy::FixedDelay(Duration::from_millis(200));
+            if let Some(last) = attempts.last_mut() {
+                last.strategy = Some(strategy.name());
+            }
             if apply_retry_strategy(
-                RetryStrategy::FixedDelay(Duration::from_millis(200)), 
+                strategy, 
                 attempt, 
                 max_attempts,
                 status_code, 
@@ -1154,6 +1275,9 @@ pub async fn handle_messages(
 
         // 确定重试策略
         let strategy = determine_retry_strategy(status_code, &error_text, retried_without_thinking);
+        if let Some(last) = attempts.last_mut() {
+            last.strategy = Some(strategy.name());
+        }
         
         // 执行退避
         if apply_retry_strategy(strategy, attempt, max_attempts, status_code, &trace_id).await {
@@ -1190,7 +1314,10 @@ pub async fn handle_messages(
     }
     
     
-    if let Some(email) = last_email {
+    // [NEW] 429 时附带 Retry-After (最早结束的账号冷却，无冷却信息时取默认值)
+    let retry_after =
+        super::common::exhausted_retry_after(&token_manager, last_mapped_model.as_deref());
+    let mut response = if let Some(email) = last_email {
         // [FIX] Include X-Mapped-Model in exhaustion error
         let mut headers = HeaderMap::new();
         headers.insert("X-Account-Email", header::HeaderValue::from_str(&email).unwrap());
@@ -1257,7 +1384,13 @@ pub async fn handle_messages(
                 "message": format!("All {} attempts failed. Last status: {}. Error: {}", max_attempts, last_status, last_error)
             }
         }))).into_response()
+    };
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after));
     }
+    with_rotation_trace(response, &attempts, None, rotation_trace_enabled)
 }
 
 /// 列出可用模型
@@ -3,6 +3,7 @@ use tracing::{debug, info};
 use axum::{http::StatusCode, response::{IntoResponse, Response}, Json, extract::State};
 use serde_json::{json, Value};
 use crate::proxy::server::AppState;
+use crate::proxy::upstream::client::mask_email;
 
 // ===== 统一重试与退避策略 =====
 
@@ -19,6 +20,18 @@ pub enum RetryStrategy {
     ExponentialBackoff { base_ms: u64, max_ms: u64 },
 }
 
+impl RetryStrategy {
+    /// 策略名称 (用于轮换追踪)
+    pub fn name(&self) -> &'static str {
+        match self {
+            RetryStrategy::NoRetry => "no_retry",
+            RetryStrategy::FixedDelay(_) => "fixed_delay",
+            RetryStrategy::LinearBackoff { .. } => "linear_backoff",
+            RetryStrategy::ExponentialBackoff { .. } => "exponential_backoff",
+        }
+    }
+}
+
 /// 根据错误状态码和错误信息确定重试策略
 pub fn determine_retry_strategy(
     status_code: u16,
@@ -129,6 +142,245 @@ pub async fn apply_retry_strategy(
     }
 }
 
+/// 账号池为空时返回给客户端的错误信息
+pub const NO_ACCOUNTS_MESSAGE: &str = "no accounts configured";
+
+/// 进入重试循环前检查账号池是否为空
+/// 账号池为空时直接返回 503，避免落入重试循环后得到难以理解的 Token 错误
+pub fn ensure_account_pool_not_empty(
+    token_manager: &crate::proxy::TokenManager,
+) -> Result<(), (StatusCode, String)> {
+    if token_manager.len() == 0 {
+        tracing::warn!("[Proxy] Rejecting request: account pool is empty (no accounts configured)");
+        return Err((StatusCode::SERVICE_UNAVAILABLE, NO_ACCOUNTS_MESSAGE.to_string()));
+    }
+    Ok(())
+}
+
+/// [NEW] 账号池全部限流期间直接返回 429 + Retry-After，不进入重试循环
+/// 强制指定账号 (X-Account-Override) 的请求不受影响，可用于人工试探
+pub async fn pool_outage_response(
+    token_manager: &crate::proxy::TokenManager,
+    account_override: Option<&str>,
+) -> Option<Response> {
+    if account_override.is_some() {
+        return None;
+    }
+    let retry_after = token_manager.pool_outage_retry_after().await?;
+    tracing::debug!(
+        "[Pool-Outage] Failing fast, all accounts rate limited (retry after {}s)",
+        retry_after
+    );
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(json!({
+            "error": {
+                "message": "All accounts are currently rate limited. Please retry later.",
+                "type": "capacity_exhausted",
+                "code": "pool_rate_limited",
+            }
+        })),
+    )
+        .into_response();
+    response.headers_mut().insert(
+        axum::http::header::RETRY_AFTER,
+        axum::http::HeaderValue::from(retry_after),
+    );
+    Some(response)
+}
+
+/// 强制指定账号的请求头 (仅管理凭据可用，用于排查特定账号问题)
+pub const ACCOUNT_OVERRIDE_HEADER: &str = "x-account-override";
+
+/// 管理凭据请求头: 独立的 admin_password 不是代理 key，无法通过 /v1 鉴权，
+/// 因此 Authorization 携带代理 key，管理凭据放在此请求头中
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// 解析 X-Account-Override 请求头
+/// - 未携带: Ok(None)
+/// - 未使用管理凭据 (X-Admin-Token 或 Authorization): 403，防止普通客户端指定账号
+/// - 账号不在池中: 400
+pub fn resolve_account_override(
+    headers: &axum::http::HeaderMap,
+    security: &crate::proxy::ProxySecurityConfig,
+    token_manager: &crate::proxy::TokenManager,
+) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(value) = headers.get(ACCOUNT_OVERRIDE_HEADER) else {
+        return Ok(None);
+    };
+    let email = value
+        .to_str()
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default();
+    if email.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid X-Account-Override header".to_string()));
+    }
+
+    let admin_token = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| crate::proxy::middleware::auth::extract_api_key(headers));
+    if !crate::proxy::middleware::auth::is_admin_credential(security, admin_token) {
+        tracing::warn!("[Proxy] Rejected X-Account-Override without admin credential");
+        return Err((
+            StatusCode::FORBIDDEN,
+            "X-Account-Override requires the admin token".to_string(),
+        ));
+    }
+
+    if token_manager.get_account_id_by_email(&email).is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Account not found in pool: {}", email),
+        ));
+    }
+
+    info!("[Proxy] X-Account-Override: pinning request to {}", email);
+    Ok(Some(email))
+}
+
+/// 调试模式下回写到响应体的实际采样参数字段 (非标准字段，便于评测记录复现)
+pub const EFFECTIVE_PARAMS_FIELD: &str = "x_effective_params";
+
+/// 从最终发往上游的请求体中提取实际生效的采样参数 (钳制/调整之后的值)
+pub fn extract_effective_params(upstream_body: &Value) -> Value {
+    let request = upstream_body.get("request").unwrap_or(upstream_body);
+    let gen_config = request.get("generationConfig");
+    let pick = |key: &str| gen_config.and_then(|c| c.get(key)).cloned().unwrap_or(Value::Null);
+
+    json!({
+        "model": upstream_body.get("model").cloned().unwrap_or(Value::Null),
+        "temperature": pick("temperature"),
+        "top_p": pick("topP"),
+        "top_k": pick("topK"),
+        "max_output_tokens": pick("maxOutputTokens"),
+        "candidate_count": pick("candidateCount"),
+        "thinking_budget": gen_config
+            .and_then(|c| c.get("thinkingConfig"))
+            .and_then(|t| t.get("thinkingBudget"))
+            .cloned()
+            .unwrap_or(Value::Null),
+    })
+}
+
+/// 将实际采样参数附加到 JSON 响应体 (仅调试模式调用)
+pub fn attach_effective_params<T: serde::Serialize>(response: T, params: Option<&Value>) -> Value {
+    let mut value = serde_json::to_value(response).unwrap_or(Value::Null);
+    if let (Some(params), Some(obj)) = (params, value.as_object_mut()) {
+        obj.insert(EFFECTIVE_PARAMS_FIELD.to_string(), params.clone());
+    }
+    value
+}
+
+/// 在可选时限内执行返回 Result<T, String> 的 future，超时转为错误；limit 为 None 时不限制
+pub async fn with_optional_timeout<T, F>(limit: Option<Duration>, what: &str, fut: F) -> Result<T, String>
+where
+    F: std::future::Future<Output = Result<T, String>>,
+{
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, fut)
+            .await
+            .unwrap_or_else(|_| Err(format!("{} timed out after {}s", what, limit.as_secs()))),
+        None => fut.await,
+    }
+}
+
+/// with_peek_heartbeats 的 on_peek 回调: 心跳开始后响应头已发出，不再轮换账号，
+/// peek 结果仍计入首字延迟 (指标 + 慢账号降级) / peek 超时指标
+pub fn record_peek_outcome(
+    metrics: &std::sync::Arc<crate::proxy::metrics::ProxyMetrics>,
+    token_manager: &std::sync::Arc<crate::proxy::TokenManager>,
+    provider: &'static str,
+    email: &str,
+    upstream_started: std::time::Instant,
+) -> impl FnOnce(crate::proxy::common::stream_buffer::PeekOutcome) + Send + 'static {
+    use crate::proxy::common::stream_buffer::PeekOutcome;
+
+    let metrics = metrics.clone();
+    let token_manager = token_manager.clone();
+    let email = email.to_string();
+    move |outcome| match outcome {
+        PeekOutcome::FirstData => {
+            let ttft = upstream_started.elapsed();
+            metrics.record_first_token_latency(provider, ttft);
+            token_manager.record_first_token_latency(&email, ttft.as_millis() as u64);
+        }
+        PeekOutcome::TimedOut => metrics.record_peek_timeout(provider),
+        PeekOutcome::Failed(message) => {
+            tracing::warn!("[{}] Peek failed after heartbeats: {}", provider, message)
+        }
+    }
+}
+
+/// 发生模型降级时的响应头
+pub const FALLBACK_MODEL_HEADER: &str = "X-Fallback-Model";
+
+/// 降级模型链下第 global_attempt 次尝试应使用的降级模型
+/// 每个模型独立享有 max_attempts 次账号轮换预算，None 表示仍在主模型的预算内
+pub fn fallback_model_for_attempt(
+    fallback_chain: &[String],
+    max_attempts: usize,
+    global_attempt: usize,
+) -> Option<&str> {
+    match global_attempt / max_attempts.max(1) {
+        0 => None,
+        n => fallback_chain.get(n - 1).map(|m| m.as_str()),
+    }
+}
+
+/// 上游表示模型不可用 (404 / 模型不存在或不受支持) 的错误，换账号重试无意义，直接切换到降级模型
+pub fn is_model_unavailable(status: u16, error_text: &str) -> bool {
+    let text = error_text.to_ascii_lowercase();
+    status == 404
+        || (status == 400
+            && text.contains("model")
+            && (text.contains("not found")
+                || text.contains("not supported")
+                || text.contains("unsupported")))
+}
+
+/// 模型不可用时跳到下一个降级模型预算的起点，返回需要跳过的尝试次数；没有剩余降级模型时返回 None
+pub fn skip_to_next_fallback(
+    fallback_chain: &[String],
+    max_attempts: usize,
+    global_attempt: usize,
+) -> Option<usize> {
+    let max_attempts = max_attempts.max(1);
+    let next_block = global_attempt / max_attempts + 1;
+    (next_block <= fallback_chain.len()).then(|| next_block * max_attempts - global_attempt - 1)
+}
+
+/// 发生降级时为响应附加 X-Fallback-Model 头
+pub fn with_fallback_header(mut response: Response, fallback_model: Option<&str>) -> Response {
+    if let Some(value) = fallback_model.and_then(|m| axum::http::HeaderValue::from_str(m).ok()) {
+        response.headers_mut().insert(FALLBACK_MODEL_HEADER, value);
+    }
+    response
+}
+
+/// 单个请求覆盖内部流式转换的请求头 (X-Force-Stream: false 直接调用 generateContent)
+pub const FORCE_STREAM_HEADER: &str = "x-force-stream";
+
+/// 非流式请求是否内部转为流式再收集: X-Force-Stream 头优先，否则使用配置默认值
+pub fn resolve_force_stream(
+    headers: &axum::http::HeaderMap,
+    client_wants_stream: bool,
+    default: bool,
+) -> bool {
+    if client_wants_stream {
+        return false;
+    }
+    let value = headers
+        .get(FORCE_STREAM_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_ascii_lowercase());
+    match value.as_deref() {
+        Some("false" | "0" | "no" | "off") => false,
+        Some("true" | "1" | "yes" | "on") => true,
+        _ => default,
+    }
+}
+
 /// 判断是否应该轮换账号
 pub fn should_rotate_account(status_code: u16) -> bool {
     match status_code {
@@ -140,6 +392,213 @@ pub fn should_rotate_account(status_code: u16) -> bool {
     }
 }
 
+/// 账号轮换的尝试序号，迭代行为与 `0..max` 相同
+///
+/// `retry_current()` 让下一次迭代复用当前序号 (能力降级重试等不属于账号轮换的重试不占用预算)，
+/// `skip_attempts(n)` 跳过后续 n 个序号 (模型不可用时直接切换到下一个降级模型)
+#[derive(Debug, Clone)]
+pub struct AttemptBudget {
+    next: usize,
+    max: usize,
+    /// 免预算重试的剩余次数上限，防止上游反复拒绝时死循环
+    free_retries_left: usize,
+}
+
+impl AttemptBudget {
+    pub fn new(max: usize) -> Self {
+        Self {
+            next: 0,
+            max,
+            free_retries_left: max,
+        }
+    }
+
+    pub fn retry_current(&mut self) {
+        if self.next > 0 && self.free_retries_left > 0 {
+            self.next -= 1;
+            self.free_retries_left -= 1;
+        }
+    }
+
+    pub fn skip_attempts(&mut self, n: usize) {
+        self.next += n;
+    }
+}
+
+impl Iterator for AttemptBudget {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.next >= self.max {
+            return None;
+        }
+        self.next += 1;
+        Some(self.next - 1)
+    }
+}
+
+/// 账号轮换中单次失败尝试的记录 (仅在调试模式下随耗尽错误返回)
+#[derive(Debug, Clone)]
+pub struct AccountAttempt {
+    pub email: String,
+    /// 本次尝试使用的上游模型 (用于查询模型级冷却)
+    pub model: String,
+    /// 上游 HTTP 状态码，None 表示网络错误 / 超时 / 流异常等
+    pub status: Option<u16>,
+    pub error: String,
+    /// 重试循环针对本次失败选择的退避策略 (网络错误等未经策略判定时为 None)
+    pub strategy: Option<&'static str>,
+}
+
+impl AccountAttempt {
+    pub fn new(email: &str, model: &str, status: Option<u16>, error: &str) -> Self {
+        Self {
+            email: email.to_string(),
+            model: model.to_string(),
+            status,
+            error: error.chars().take(300).collect(),
+            strategy: None,
+        }
+    }
+
+    /// 失败类别，便于区分限流、认证失败与网络问题
+    pub fn category(&self) -> &'static str {
+        match self.status {
+            Some(429) => "rate_limited",
+            Some(401 | 403) => "auth",
+            Some(500..=599) => "upstream_server",
+            Some(_) => "upstream_error",
+            None => "network",
+        }
+    }
+}
+
+/// 构造结构化的尝试明细 (附带各账号当前冷却到期时间)
+pub fn describe_attempts(
+    attempts: &[AccountAttempt],
+    token_manager: &crate::proxy::token_manager::TokenManager,
+) -> Value {
+    let now = chrono::Utc::now();
+    let items: Vec<Value> = attempts
+        .iter()
+        .map(|a| {
+            let cooldown = token_manager.get_cooldown_seconds_by_email(&a.email, Some(&a.model));
+            json!({
+                "account": a.email,
+                "model": a.model,
+                "status": a.status,
+                "category": a.category(),
+                "error": a.error,
+                "cooldown_until": (cooldown > 0)
+                    .then(|| (now + chrono::Duration::seconds(cooldown as i64)).to_rfc3339()),
+            })
+        })
+        .collect();
+    Value::Array(items)
+}
+
+/// [NEW] 随响应返回的账号轮换追踪头 (base64 JSON)，experimental.expose_rotation_trace 开启时附加
+pub const ROTATION_TRACE_HEADER: &str = "X-Rotation-Trace";
+
+/// 构造轮换决策序列: 每次失败尝试的 (账号, 状态码, 策略, 是否换号)，末项为最终成功的账号
+/// served_by 为 None 表示所有尝试均失败；账号邮箱均脱敏
+pub fn rotation_trace(attempts: &[AccountAttempt], served_by: Option<&str>) -> Value {
+    let next_accounts = attempts
+        .iter()
+        .skip(1)
+        .map(|a| Some(a.email.as_str()))
+        .chain(std::iter::once(served_by));
+    let mut items: Vec<Value> = attempts
+        .iter()
+        .zip(next_accounts)
+        .map(|(a, next)| {
+            json!({
+                "account": mask_email(&a.email),
+                "model": a.model,
+                "status": a.status,
+                "category": a.category(),
+                "strategy": a.strategy,
+                "rotated": next.is_some_and(|next| next != a.email),
+            })
+        })
+        .collect();
+    if let Some(served_by) = served_by {
+        items.push(json!({ "account": mask_email(served_by), "status": 200 }));
+    }
+    Value::Array(items)
+}
+
+/// 开启时为响应 (成功或耗尽错误) 附加 X-Rotation-Trace 头
+pub fn with_rotation_trace(
+    mut response: Response,
+    attempts: &[AccountAttempt],
+    served_by: Option<&str>,
+    enabled: bool,
+) -> Response {
+    if !enabled {
+        return response;
+    }
+    use base64::Engine as _;
+    let encoded = base64::engine::general_purpose::STANDARD
+        .encode(rotation_trace(attempts, served_by).to_string());
+    if let Ok(value) = axum::http::HeaderValue::from_str(&encoded) {
+        response.headers_mut().insert(ROTATION_TRACE_HEADER, value);
+    }
+    response
+}
+
+/// 没有任何账号处于限流冷却 (如网络错误导致失败) 时的默认 Retry-After (秒)
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 30;
+
+/// 所有账号均失败时建议客户端等待的秒数: 取最早结束的账号冷却，无冷却信息时使用默认值
+pub fn exhausted_retry_after(
+    token_manager: &crate::proxy::token_manager::TokenManager,
+    mapped_model: Option<&str>,
+) -> u64 {
+    token_manager
+        .soonest_cooldown_seconds(mapped_model)
+        .unwrap_or(DEFAULT_RETRY_AFTER_SECS)
+}
+
+/// 所有账号均失败时的 429 响应 (附带 Retry-After 头)
+/// attempts 为 Some (调试模式) 时返回包含逐账号明细的 JSON，否则保持原有纯文本
+pub fn exhausted_response(
+    last_error: &str,
+    last_email: Option<String>,
+    mapped_model: Option<&str>,
+    attempts: Option<Value>,
+    retry_after_secs: u64,
+) -> Response {
+    let message = format!("All accounts exhausted. Last error: {}", last_error);
+    let mut response = match attempts {
+        Some(attempts) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({
+                "error": {
+                    "message": message,
+                    "type": "capacity_exhausted",
+                    "code": "all_accounts_exhausted",
+                    "attempts": attempts,
+                }
+            })),
+        )
+            .into_response(),
+        None => (StatusCode::TOO_MANY_REQUESTS, message).into_response(),
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        axum::http::header::RETRY_AFTER,
+        axum::http::HeaderValue::from(retry_after_secs),
+    );
+    if let Some(v) = last_email.and_then(|e| axum::http::HeaderValue::from_str(&e).ok()) {
+        headers.insert("X-Account-Email", v);
+    }
+    if let Some(v) = mapped_model.and_then(|m| axum::http::HeaderValue::from_str(m).ok()) {
+        headers.insert("X-Mapped-Model", v);
+    }
+    response
+}
+
 /// Detects model capabilities and configuration
 /// POST /v1/models/detect
 pub async fn handle_detect_model(
@@ -9,11 +9,23 @@ use serde_json::{json, Value};
 use tracing::{debug, error, info};
 
 use crate::proxy::common::client_adapter::CLIENT_ADAPTERS;
+use crate::proxy::common::stream_buffer::{
+    peek_heartbeat_interval, with_flush_threshold, with_peek_heartbeats, BufferedByteStream,
+    SseErrorFormat,
+};
+use crate::proxy::middleware::request_span::record_attempt;
 use crate::proxy::debug_logger;
+use crate::proxy::config::resolve_model_timeouts;
 use crate::proxy::handlers::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account, RetryStrategy,
+    apply_retry_strategy, describe_attempts, determine_retry_strategy, exhausted_response,
+    exhausted_retry_after, record_peek_outcome, should_rotate_account, with_optional_timeout,
+    with_rotation_trace, AccountAttempt, AttemptBudget, RetryStrategy,
+};
+use crate::proxy::mappers::common_utils::{
+    has_civic_integrity_setting, is_civic_integrity_rejection, mark_civic_integrity_unsupported,
+    strip_civic_integrity_setting, supports_civic_integrity,
 };
-use crate::proxy::mappers::gemini::{unwrap_response, wrap_request};
+use crate::proxy::mappers::gemini::{unwrap_response, wrap_raw_request, wrap_request};
 use crate::proxy::server::AppState;
 use crate::proxy::session_manager::SessionManager;
 use crate::proxy::upstream::client::mask_email;
@@ -22,6 +34,88 @@ use tokio::time::Duration; // [NEW] Adapter Registry
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
 
+/// 等待流式响应的首个数据块: 空块 / 出错 / 提前结束 / 超时均返回错误原因，由调用方换号重试
+/// 成功时记录首字延迟 (指标 + 慢账号降级)
+/// 设置了 heartbeat_after 时只等待该时长，仍无数据返回 Ok(None)，由调用方开始响应并发送心跳
+async fn peek_first_chunk<S, E>(
+    stream: &mut S,
+    peek: Duration,
+    heartbeat_after: Option<Duration>,
+    metrics: &crate::proxy::metrics::ProxyMetrics,
+    token_manager: &crate::proxy::TokenManager,
+    email: &str,
+    upstream_started: std::time::Instant,
+) -> Result<Option<bytes::Bytes>, String>
+where
+    S: futures::Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    use futures::StreamExt;
+
+    match tokio::time::timeout(heartbeat_after.unwrap_or(peek), stream.next()).await {
+        Ok(Some(Ok(bytes))) if bytes.is_empty() => {
+            tracing::warn!("[Gemini] Empty first chunk received, retrying...");
+            Err("Empty first chunk".to_string())
+        }
+        Ok(Some(Ok(bytes))) => {
+            let ttft = upstream_started.elapsed();
+            metrics.record_first_token_latency("gemini", ttft);
+            token_manager.record_first_token_latency(email, ttft.as_millis() as u64);
+            Ok(Some(bytes))
+        }
+        Ok(Some(Err(e))) => {
+            tracing::warn!("[Gemini] Stream error during peek: {}, retrying...", e);
+            Err(format!("Stream error: {}", e))
+        }
+        Ok(None) => {
+            tracing::warn!("[Gemini] Stream ended immediately, retrying...");
+            Err("Empty response".to_string())
+        }
+        Err(_) if heartbeat_after.is_some() => Ok(None),
+        Err(_) => {
+            tracing::warn!("[Gemini] Timeout waiting for first chunk, retrying...");
+            metrics.record_peek_timeout("gemini");
+            Err("Timeout".to_string())
+        }
+    }
+}
+
+/// [NEW] 提示词屏蔽规则: 命中时返回 Gemini 格式的通用拒绝信息，命中的规则 id 只写入日志
+async fn prompt_filter_rejection(
+    state: &AppState,
+    body: &Value,
+    trace_id: &str,
+) -> Option<axum::response::Response> {
+    use crate::proxy::prompt_filter::{gemini_user_text, REJECTED_MESSAGE};
+
+    let filter = state.prompt_filter.read().await;
+    let rule_id = filter.check(&gemini_user_text(body))?;
+    tracing::warn!(
+        "[{}] Request rejected by prompt filter rule {}",
+        trace_id,
+        rule_id
+    );
+    let status = filter.status();
+    let status_name = if status == StatusCode::FORBIDDEN {
+        "PERMISSION_DENIED"
+    } else {
+        "INVALID_ARGUMENT"
+    };
+    Some(
+        (
+            status,
+            Json(json!({
+                "error": {
+                    "code": status.as_u16(),
+                    "message": REJECTED_MESSAGE,
+                    "status": status_name
+                }
+            })),
+        )
+            .into_response(),
+    )
+}
+
 /// 处理 generateContent 和 streamGenerateContent
 /// 路径参数: model_name, method (e.g. "gemini-pro", "generateContent")
 pub async fn handle_generate(
@@ -41,7 +135,7 @@ pub async fn handle_generate(
         "Received Gemini request: {}/{}",
         model_name, method
     ));
-    let trace_id = format!("req_{}", chrono::Utc::now().timestamp_subsec_millis());
+    let trace_id = crate::proxy::middleware::request_span::request_id(&headers);
     let debug_cfg = state.debug_logging.read().await.clone();
 
     // [NEW] Detect Client Adapter
@@ -60,6 +154,10 @@ pub async fn handle_generate(
             format!("Unsupported method: {}", method),
         ));
     }
+    // [NEW] 提示词屏蔽规则 (早于账号选择)
+    if let Some(rejected) = prompt_filter_rejection(&state, &body, &trace_id).await {
+        return Ok(rejected);
+    }
     if debug_logger::is_enabled(&debug_cfg) {
         let original_payload = json!({
             "kind": "original_request",
@@ -79,7 +177,11 @@ pub async fn handle_generate(
     }
     let client_wants_stream = method == "streamGenerateContent";
     // [AUTO-CONVERSION] 强制内部流式化
-    let force_stream_internally = !client_wants_stream;
+    let force_stream_internally = super::common::resolve_force_stream(
+        &headers,
+        client_wants_stream,
+        state.experimental.read().await.force_stream_internally,
+    );
     let is_stream = client_wants_stream || force_stream_internally;
 
     if force_stream_internally {
@@ -89,13 +191,28 @@ pub async fn handle_generate(
     // 2. 获取 UpstreamClient 和 TokenManager
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
+    super::common::ensure_account_pool_not_empty(&token_manager)?;
+    let account_override = {
+        let security = state.security.read().await;
+        super::common::resolve_account_override(&headers, &security, &token_manager)?
+    };
+    // [NEW] 账号池全部限流时快速失败
+    if let Some(resp) =
+        super::common::pool_outage_response(&token_manager, account_override.as_deref()).await
+    {
+        return Ok(resp);
+    }
     let pool_size = token_manager.len();
     let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size).max(1);
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    let mut attempts: Vec<AccountAttempt> = Vec::new();
+    let rotation_trace_enabled = state.experimental.read().await.expose_rotation_trace;
+    let streaming_config = state.streaming.read().await.clone();
 
-    for attempt in 0..max_attempts {
+    let mut attempt_budget = AttemptBudget::new(max_attempts);
+    while let Some(attempt) = attempt_budget.next() {
         // 3. 模型路由解析
         let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
             &model_name,
@@ -127,14 +244,18 @@ pub async fn handle_generate(
             Some(&body), // [NEW] Pass request body for imageConfig parsing
         );
 
+        // [NEW] 按模型超时 (peek / 单次请求总时限 / 非流式收集)
+        let timeouts = resolve_model_timeouts(&model_name, &mapped_model, Duration::from_secs(30));
+
         // 4. 获取 Token (使用准确的 request_type)
         // 提取 SessionId (粘性指纹)
         let session_id = SessionManager::extract_gemini_session_id(&body, &model_name);
 
         // 关键：在重试尝试 (attempt > 0) 时强制轮换账号
         let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
-            .get_token(
-                &config.request_type,
+            .get_token_with_override(
+                account_override.as_deref(),
+                &config.workload,
                 attempt > 0,
                 Some(&session_id),
                 &config.final_model,
@@ -150,8 +271,20 @@ pub async fn handle_generate(
             }
         };
 
+        state
+            .metrics
+            .record_attempt("gemini", attempt, last_email.as_deref(), &email);
         last_email = Some(email.clone());
-        info!("✓ Using account: {} (type: {})", email, config.request_type);
+        record_attempt(attempt + 1, &email, &mapped_model);
+        info!(
+            "✓ Using account: {} (type: {}, workload: {})",
+            email, config.request_type, config.workload
+        );
+
+        // [NEW] 已知不支持 CIVIC_INTEGRITY 的模型: 从客户端的 safetySettings 中移除该类别
+        if !supports_civic_integrity(&model_name, &mapped_model) {
+            strip_civic_integrity_setting(&mut body);
+        }
 
         // 5. 包装请求 (project injection)
         // [FIX #765] Pass session_id to wrap_request for signature injection
@@ -165,6 +298,7 @@ pub async fn handle_generate(
                 "original_model": model_name,
                 "mapped_model": mapped_model,
                 "request_type": config.request_type,
+                "workload": config.workload,
                 "attempt": attempt,
                 "v1internal_request": wrapped_body.clone(),
             });
@@ -195,20 +329,25 @@ pub async fn handle_generate(
             );
         }
 
-        let call_result = match upstream
-            .call_v1_internal_with_headers(
+        let upstream_started = std::time::Instant::now(); // [NEW] 首字延迟起点
+        let call_result = match with_optional_timeout(
+            timeouts.total,
+            "Upstream request",
+            upstream.call_v1_internal_with_headers(
                 upstream_method,
                 &access_token,
                 wrapped_body,
                 query_string,
                 extra_headers.clone(),
                 Some(account_id.as_str()),
-            )
-            .await
+            ),
+        )
+        .await
         {
             Ok(r) => r,
             Err(e) => {
                 last_error = e.clone();
+                attempts.push(AccountAttempt::new(&email, &mapped_model, None, &last_error));
                 debug!(
                     "Gemini Request failed on attempt {}/{}: {}",
                     attempt + 1,
@@ -256,6 +395,7 @@ pub async fn handle_generate(
         let upstream_url = response.url().to_string();
         let status = response.status();
         if status.is_success() {
+            token_manager.record_model_success(&email, &mapped_model);
             // 6. 响应处理
             if is_stream {
                 use axum::body::Body;
@@ -284,46 +424,37 @@ pub async fn handle_generate(
                 let s_id = session_id.clone(); // Clone for stream closure
 
                 // [FIX #859] Implement peek logic for Gemini stream to prevent 0-token 200 OK
-                let mut first_chunk = None;
-                let mut retry_gemini = false;
-
-                match tokio::time::timeout(
-                    std::time::Duration::from_secs(30),
-                    response_stream.next(),
+                // [NEW] streaming.peek_heartbeat_ms: 超过该时长仍无首个数据块即开始响应并发送心跳
+                let heartbeat_after =
+                    peek_heartbeat_interval(&streaming_config, client_wants_stream, timeouts.peek);
+                let peek_started = std::time::Instant::now();
+                let first_chunk = match peek_first_chunk(
+                    &mut response_stream,
+                    timeouts.peek,
+                    heartbeat_after,
+                    &state.metrics,
+                    &token_manager,
+                    &email,
+                    upstream_started,
                 )
                 .await
                 {
-                    Ok(Some(Ok(bytes))) => {
-                        if bytes.is_empty() {
-                            tracing::warn!("[Gemini] Empty first chunk received, retrying...");
-                            retry_gemini = true;
-                        } else {
-                            first_chunk = Some(bytes);
-                        }
-                    }
-                    Ok(Some(Err(e))) => {
-                        tracing::warn!("[Gemini] Stream error during peek: {}, retrying...", e);
-                        last_error = format!("Stream error: {}", e);
-                        retry_gemini = true;
-                    }
-                    Ok(None) => {
-                        tracing::warn!("[Gemini] Stream ended immediately, retrying...");
-                        last_error = "Empty response".to_string();
-                        retry_gemini = true;
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        last_error = e;
+                        attempts.push(AccountAttempt::new(
+                            &email,
+                            &mapped_model,
+                            None,
+                            &last_error,
+                        ));
+                        continue;
                     }
-                    Err(_) => {
-                        tracing::warn!("[Gemini] Timeout waiting for first chunk, retrying...");
-                        last_error = "Timeout".to_string();
-                        retry_gemini = true;
-                    }
-                }
-
-                if retry_gemini {
-                    continue;
-                }
+                };
 
                 let s_id_for_stream = s_id.clone();
                 let model_name_for_stream = mapped_model.clone();
+                let heartbeats_started = first_chunk.is_none();
                 let stream = async_stream::stream! {
                     let mut first_data = first_chunk;
                     loop {
@@ -413,8 +544,25 @@ pub async fn handle_generate(
                 };
 
                 if client_wants_stream {
-                    let body = Body::from_stream(stream);
-                    return Ok(Response::builder()
+                    // 心跳已开始: 由 with_peek_heartbeats 继续等待首个数据块，失败以 Gemini 错误事件结束
+                    let stream: BufferedByteStream<String> = match heartbeat_after {
+                        Some(heartbeat_interval) if heartbeats_started => with_peek_heartbeats(
+                            Box::pin(stream),
+                            heartbeat_interval,
+                            timeouts.peek.saturating_sub(peek_started.elapsed()),
+                            SseErrorFormat::Gemini,
+                            record_peek_outcome(
+                                &state.metrics,
+                                &token_manager,
+                                "gemini",
+                                &email,
+                                upstream_started,
+                            ),
+                        ),
+                        _ => Box::pin(stream),
+                    };
+                    let body = Body::from_stream(with_flush_threshold(stream, &streaming_config));
+                    let resp = Response::builder()
                         .header("Content-Type", "text/event-stream")
                         .header("Cache-Control", "no-cache")
                         .header("Connection", "keep-alive")
@@ -423,18 +571,30 @@ pub async fn handle_generate(
                         .header("X-Mapped-Model", &mapped_model)
                         .body(body)
                         .unwrap()
-                        .into_response());
+                        .into_response();
+                    return Ok(with_rotation_trace(
+                        resp,
+                        &attempts,
+                        Some(&email),
+                        rotation_trace_enabled,
+                    ));
                 } else {
                     // Collect to JSON
                     use crate::proxy::mappers::gemini::collector::collect_stream_to_json;
-                    match collect_stream_to_json(Box::pin(stream), &s_id).await {
+                    match with_optional_timeout(
+                        timeouts.collector,
+                        "Stream collection",
+                        collect_stream_to_json(Box::pin(stream), &s_id),
+                    )
+                    .await
+                    {
                         Ok(gemini_resp) => {
                             info!(
                                 "[{}] ✓ Stream collected and converted to JSON (Gemini)",
                                 session_id
                             );
                             let unwrapped = unwrap_response(&gemini_resp);
-                            return Ok((
+                            let resp = (
                                 StatusCode::OK,
                                 [
                                     ("X-Account-Email", email.as_str()),
@@ -442,7 +602,13 @@ pub async fn handle_generate(
                                 ],
                                 Json(unwrapped),
                             )
-                                .into_response());
+                                .into_response();
+                            return Ok(with_rotation_trace(
+                                resp,
+                                &attempts,
+                                Some(&email),
+                                rotation_trace_enabled,
+                            ));
                         }
                         Err(e) => {
                             error!("Stream collection error: {}", e);
@@ -500,7 +666,7 @@ pub async fn handle_generate(
             }
 
             let unwrapped = unwrap_response(&gemini_resp);
-            return Ok((
+            let resp = (
                 StatusCode::OK,
                 [
                     ("X-Account-Email", email.as_str()),
@@ -508,7 +674,13 @@ pub async fn handle_generate(
                 ],
                 Json(unwrapped),
             )
-                .into_response());
+                .into_response();
+            return Ok(with_rotation_trace(
+                resp,
+                &attempts,
+                Some(&email),
+                rotation_trace_enabled,
+            ));
         }
 
         // 处理错误并重试
@@ -523,6 +695,12 @@ pub async fn handle_generate(
             .await
             .unwrap_or_else(|_| format!("HTTP {}", status_code));
         last_error = format!("HTTP {}: {}", status_code, error_text);
+        attempts.push(AccountAttempt::new(
+            &email,
+            &mapped_model,
+            Some(status_code),
+            &error_text,
+        ));
         if debug_logger::is_enabled(&debug_cfg) {
             let payload = json!({
                 "kind": "upstream_response_error",
@@ -546,6 +724,15 @@ pub async fn handle_generate(
             .await;
         }
 
+        // [NEW] 模型不支持 CIVIC_INTEGRITY 安全类别: 记录后重试，重试前从 safetySettings 中移除该类别
+        if is_civic_integrity_rejection(status_code, &error_text)
+            && has_civic_integrity_setting(&body)
+        {
+            mark_civic_integrity_unsupported(&mapped_model);
+            attempt_budget.retry_current();
+            continue;
+        }
+
         // 确定重试策略
         let strategy = determine_retry_strategy(status_code, &error_text, false);
         let trace_id = format!("gemini_{}", session_id);
@@ -625,20 +812,250 @@ pub async fn handle_generate(
             .into_response());
     }
 
-    if let Some(email) = last_email {
-        Ok((
-            StatusCode::TOO_MANY_REQUESTS,
-            [("X-Account-Email", email)],
-            format!("All accounts exhausted. Last error: {}", last_error),
-        )
-            .into_response())
-    } else {
-        Ok((
-            StatusCode::TOO_MANY_REQUESTS,
-            format!("All accounts exhausted. Last error: {}", last_error),
+    // 调试模式下附带逐账号明细
+    let detail = debug_logger::is_enabled(&debug_cfg)
+        .then(|| describe_attempts(&attempts, &token_manager));
+    let last_model = attempts.last().map(|a| a.model.as_str());
+    let retry_after = exhausted_retry_after(&token_manager, last_model);
+    let resp = exhausted_response(&last_error, last_email, None, detail, retry_after);
+    Ok(with_rotation_trace(
+        resp,
+        &attempts,
+        None,
+        rotation_trace_enabled,
+    ))
+}
+
+/// [NEW] 原生透传: POST /v1/gemini/:method (generateContent / streamGenerateContent)
+/// 请求体为原生 Gemini 请求 (顶层附带 model)，仅注入轮换账号的 project 与 token，
+/// 上游响应 (含 v1internal 的 response 包装) 原样返回；仍参与重试与限流标记
+pub async fn handle_raw_passthrough(
+    State(state): State<AppState>,
+    Path(method): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    use axum::body::Body;
+    use axum::response::Response;
+
+    if method != "generateContent" && method != "streamGenerateContent" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported method: {}", method),
+        ));
+    }
+    // project 按账号逐次填入，其余部分只构建一次
+    let (model, mut wrapped_body) =
+        wrap_raw_request(&body, "").map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let is_stream = method == "streamGenerateContent";
+    let trace_id = crate::proxy::middleware::request_span::request_id(&headers);
+    info!(
+        "[{}] Raw Gemini passthrough: {}/{}",
+        trace_id, model, method
+    );
+    if let Some(rejected) = prompt_filter_rejection(&state, &body, &trace_id).await {
+        return Ok(rejected);
+    }
+
+    let upstream = state.upstream.clone();
+    let token_manager = state.token_manager;
+    super::common::ensure_account_pool_not_empty(&token_manager)?;
+    let account_override = {
+        let security = state.security.read().await;
+        super::common::resolve_account_override(&headers, &security, &token_manager)?
+    };
+    // [NEW] 账号池全部限流时快速失败
+    if let Some(resp) =
+        super::common::pool_outage_response(&token_manager, account_override.as_deref()).await
+    {
+        return Ok(resp);
+    }
+    let max_attempts = MAX_RETRY_ATTEMPTS.min(token_manager.len()).max(1);
+    let timeouts = resolve_model_timeouts(&model, &model, Duration::from_secs(30));
+
+    let mut last_error = String::new();
+    let mut last_email: Option<String> = None;
+    let mut attempts: Vec<AccountAttempt> = Vec::new();
+    let rotation_trace_enabled = state.experimental.read().await.expose_rotation_trace;
+    let streaming_config = state.streaming.read().await.clone();
+
+    for attempt in 0..max_attempts {
+        let (access_token, project_id, email, account_id, _wait_ms) = token_manager
+            .get_token_with_override(
+                account_override.as_deref(),
+                crate::proxy::mappers::common_utils::WORKLOAD_CHAT,
+                attempt > 0,
+                None,
+                &model,
+            )
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("Token error: {}", e),
+                )
+            })?;
+
+        state
+            .metrics
+            .record_attempt("gemini", attempt, last_email.as_deref(), &email);
+        last_email = Some(email.clone());
+        record_attempt(attempt + 1, &email, &model);
+
+        wrapped_body["project"] = json!(project_id);
+        let upstream_started = std::time::Instant::now();
+        let call_result = match with_optional_timeout(
+            timeouts.total,
+            "Upstream request",
+            upstream.call_v1_internal_with_headers(
+                &method,
+                &access_token,
+                wrapped_body.clone(),
+                is_stream.then_some("alt=sse"),
+                std::collections::HashMap::new(),
+                Some(account_id.as_str()),
+            ),
         )
-            .into_response())
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                last_error = e;
+                attempts.push(AccountAttempt::new(&email, &model, None, &last_error));
+                continue;
+            }
+        };
+
+        let response = call_result.response;
+        let status = response.status();
+        if status.is_success() {
+            let (content_type, body) = if is_stream {
+                // 与 streamGenerateContent 相同: 首个数据块到达前出错 / 空流 / 超时则换号重试
+                let mut response_stream = response.bytes_stream();
+                let heartbeat_after =
+                    peek_heartbeat_interval(&streaming_config, true, timeouts.peek);
+                let peek_started = std::time::Instant::now();
+                let first_chunk = match peek_first_chunk(
+                    &mut response_stream,
+                    timeouts.peek,
+                    heartbeat_after,
+                    &state.metrics,
+                    &token_manager,
+                    &email,
+                    upstream_started,
+                )
+                .await
+                {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        last_error = e;
+                        attempts.push(AccountAttempt::new(&email, &model, None, &last_error));
+                        continue;
+                    }
+                };
+                let response_stream: BufferedByteStream<String> =
+                    Box::pin(futures::StreamExt::map(response_stream, |r| {
+                        r.map_err(|e| e.to_string())
+                    }));
+                let stream = match first_chunk {
+                    Some(first_chunk) => Box::pin(futures::StreamExt::chain(
+                        futures::stream::once(async move { Ok(first_chunk) }),
+                        response_stream,
+                    )),
+                    // 仅在设置了 heartbeat_after 时 peek 返回 None: 心跳已开始，继续等待首个数据块
+                    None => with_peek_heartbeats(
+                        response_stream,
+                        heartbeat_after.unwrap_or(timeouts.peek),
+                        timeouts.peek.saturating_sub(peek_started.elapsed()),
+                        SseErrorFormat::Gemini,
+                        record_peek_outcome(
+                            &state.metrics,
+                            &token_manager,
+                            "gemini",
+                            &email,
+                            upstream_started,
+                        ),
+                    ),
+                };
+                ("text/event-stream", Body::from_stream(stream))
+            } else {
+                (
+                    "application/json",
+                    Body::from_stream(response.bytes_stream()),
+                )
+            };
+            token_manager.record_model_success(&email, &model);
+            let resp = Response::builder()
+                .status(status)
+                .header("Content-Type", content_type)
+                .header("X-Account-Email", &email)
+                .header("X-Mapped-Model", &model)
+                .body(body)
+                .unwrap();
+            return Ok(with_rotation_trace(
+                resp,
+                &attempts,
+                Some(&email),
+                rotation_trace_enabled,
+            ));
+        }
+
+        let status_code = status.as_u16();
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string());
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| format!("HTTP {}", status_code));
+        last_error = format!("HTTP {}: {}", status_code, error_text);
+        attempts.push(AccountAttempt::new(
+            &email,
+            &model,
+            Some(status_code),
+            &error_text,
+        ));
+
+        if matches!(status_code, 429 | 529 | 503 | 500) {
+            token_manager
+                .mark_rate_limited_async(
+                    &email,
+                    status_code,
+                    retry_after.as_deref(),
+                    &error_text,
+                    Some(&model),
+                )
+                .await;
+            state.metrics.record_rate_limited("gemini");
+        }
+
+        let strategy = determine_retry_strategy(status_code, &error_text, false);
+        if apply_retry_strategy(strategy, attempt, max_attempts, status_code, &trace_id).await {
+            continue;
+        }
+
+        // 不可重试的错误: 原样返回上游错误体
+        return Ok(Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .header("X-Account-Email", &email)
+            .header("X-Mapped-Model", &model)
+            .body(Body::from(error_text))
+            .unwrap());
     }
+
+    let detail = debug_logger::is_enabled(&*state.debug_logging.read().await)
+        .then(|| describe_attempts(&attempts, &token_manager));
+    let retry_after = exhausted_retry_after(&token_manager, Some(&model));
+    let resp = exhausted_response(&last_error, last_email, Some(&model), detail, retry_after);
+    Ok(with_rotation_trace(
+        resp,
+        &attempts,
+        None,
+        rotation_trace_enabled,
+    ))
 }
 
 pub async fn handle_list_models(
@@ -697,3 +1114,369 @@ pub async fn handle_count_tokens(
 
     Ok(Json(json!({"totalTokens": 0})))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::proxy_state;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_account_override_through_v1_auth_stack() {
+        use tower::ServiceExt;
+
+        let app = axum::Router::new().fallback(|| async {
+            r#"{"response":{"candidates":[{"content":{"parts":[{"text":"ok"}]}}]}}"#
+        });
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com"), ("acc2", "b@test.com")]).await;
+        *state.security.write().await = crate::proxy::ProxySecurityConfig {
+            auth_mode: crate::proxy::ProxyAuthMode::Strict,
+            api_key: "sk-api".to_string(),
+            admin_password: Some("admin123".to_string()),
+            api_keys: Vec::new(),
+            allow_lan_access: false,
+            port: 8045,
+            security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
+        };
+
+        // 与 server.rs 相同: /v1 路由经过 auth_middleware
+        let router = axum::Router::new()
+            .route(
+                "/v1/gemini/:method",
+                axum::routing::post(handle_raw_passthrough),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::proxy::middleware::auth_middleware,
+            ))
+            .with_state(state);
+        let send = |key: &str, admin_token: Option<&str>, account: Option<&str>| {
+            let mut req = axum::http::Request::builder()
+                .method("POST")
+                .uri("/v1/gemini/generateContent")
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {}", key));
+            if let Some(admin_token) = admin_token {
+                req = req.header("x-admin-token", admin_token);
+            }
+            if let Some(account) = account {
+                req = req.header("x-account-override", account);
+            }
+            let body = json!({
+                "model": "gemini-2.5-flash",
+                "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }]
+            });
+            router
+                .clone()
+                .oneshot(req.body(axum::body::Body::from(body.to_string())).unwrap())
+        };
+
+        // 代理 key + X-Admin-Token 管理凭据 -> 固定到指定账号
+        for _ in 0..3 {
+            let resp = send("sk-api", Some("admin123"), Some("b@test.com"))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["X-Account-Email"], "b@test.com");
+        }
+        // 缺少或错误的管理凭据 -> 403
+        let resp = send("sk-api", None, Some("b@test.com")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = send("sk-api", Some("wrong"), Some("b@test.com"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        // 管理密码不是代理 key，放在 Authorization 中会在 auth 层被拒绝
+        let resp = send("admin123", None, Some("b@test.com")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        // 不携带请求头 -> 正常轮换
+        let resp = send("sk-api", None, None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+    }
+
+    #[tokio::test]
+    async fn test_raw_passthrough_returns_upstream_response_untouched() {
+        // 模拟上游: 记录收到的请求体，按方法返回原始 JSON / SSE
+        let received: Arc<Mutex<Vec<(String, Value)>>> = Arc::default();
+        let raw_json = r#"{"response":{"candidates":[{"content":{"role":"model","parts":[{"text":"raw"}]},"finishReason":"STOP"}]},"traceId":"t1"}"#;
+        let raw_sse = "data: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"r\"}]}}]}}\r\n\r\n";
+        let app = {
+            let received = received.clone();
+            axum::Router::new().fallback(move |uri: axum::http::Uri, Json(body): Json<Value>| {
+                let received = received.clone();
+                async move {
+                    let path = uri.path().to_string();
+                    let stream = path.contains("streamGenerateContent");
+                    received.lock().unwrap().push((path, body));
+                    if stream {
+                        raw_sse.to_string()
+                    } else {
+                        raw_json.to_string()
+                    }
+                }
+            })
+        };
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+
+        let native = json!({
+            "model": "models/gemini-2.5-flash",
+            "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }],
+            "generationConfig": { "temperature": 0.2 }
+        });
+        let call = |method: &str| {
+            handle_raw_passthrough(
+                State(state.clone()),
+                Path(method.to_string()),
+                HeaderMap::new(),
+                Json(native.clone()),
+            )
+        };
+
+        let resp = call("generateContent").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["X-Account-Email"], "a@test.com");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, raw_json.as_bytes());
+
+        let resp = call("streamGenerateContent").await.unwrap();
+        assert_eq!(resp.headers()["Content-Type"], "text/event-stream");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, raw_sse.as_bytes());
+
+        // 上游只收到注入的 project / model，请求内容不做转换
+        let received_handle = received.clone();
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (path, sent) = &received[0];
+        assert!(path.ends_with(":generateContent"));
+        assert_eq!(sent["project"], "pid-acc1");
+        assert_eq!(sent["model"], "gemini-2.5-flash");
+        assert_eq!(sent["request"]["contents"], native["contents"]);
+        assert_eq!(
+            sent["request"]["generationConfig"],
+            native["generationConfig"]
+        );
+        assert!(sent["request"].get("model").is_none());
+        assert!(received[1].0.ends_with(":streamGenerateContent"));
+
+        // 不支持的方法与缺少 model
+        let err = call("countTokens").await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        let err = handle_raw_passthrough(
+            State(state.clone()),
+            Path("generateContent".to_string()),
+            HeaderMap::new(),
+            Json(json!({ "contents": [] })),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.1.contains("model"));
+        drop(received);
+
+        // 提示词屏蔽规则同样作用于原生 contents (透传与 generateContent 两条路径)，且不触达上游
+        *state.prompt_filter.write().await = crate::proxy::prompt_filter::PromptFilter::from_config(
+            &crate::proxy::config::PromptFilterConfig {
+                enabled: true,
+                rules: vec![crate::proxy::config::PromptFilterRule {
+                    id: "no-secrets".to_string(),
+                    pattern: "internal password".to_string(),
+                    regex: false,
+                }],
+                status_code: 403,
+            },
+        );
+        let blocked = json!({
+            "model": "gemini-2.5-flash",
+            "contents": [{ "role": "user", "parts": [{ "text": "the Internal Password is?" }] }]
+        });
+        let resp = handle_raw_passthrough(
+            State(state.clone()),
+            Path("generateContent".to_string()),
+            HeaderMap::new(),
+            Json(blocked.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = handle_generate(
+            State(state.clone()),
+            Path("gemini-2.5-flash:generateContent".to_string()),
+            HeaderMap::new(),
+            Json(blocked),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["status"], "PERMISSION_DENIED");
+        assert_eq!(
+            body["error"]["message"],
+            crate::proxy::prompt_filter::REJECTED_MESSAGE
+        );
+        assert_eq!(received_handle.lock().unwrap().len(), 2);
+
+    }
+
+    #[tokio::test]
+    async fn test_raw_passthrough_stream_retries_empty_first_chunk() {
+        // 模拟上游: 第一次返回空的 200 流，之后返回正常 SSE
+        let received: Arc<Mutex<Vec<Value>>> = Arc::default();
+        let raw_sse = "data: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"r\"}]}}]}}\r\n\r\n";
+        let app = {
+            let received = received.clone();
+            axum::Router::new().fallback(move |Json(body): Json<Value>| {
+                let received = received.clone();
+                async move {
+                    let mut received = received.lock().unwrap();
+                    received.push(body);
+                    if received.len() == 1 {
+                        String::new()
+                    } else {
+                        raw_sse.to_string()
+                    }
+                }
+            })
+        };
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com"), ("acc2", "b@test.com")]).await;
+
+        let resp = handle_raw_passthrough(
+            State(state),
+            Path("streamGenerateContent".to_string()),
+            HeaderMap::new(),
+            Json(json!({
+                "model": "gemini-2.5-flash",
+                "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }]
+            })),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let email = resp.headers()["X-Account-Email"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(bytes, raw_sse.as_bytes());
+
+        // 空流被 peek 拦下后重试；两次尝试使用同一个封装请求，仅 project 随账号变化
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(
+            received[1]["project"],
+            format!("pid-acc{}", if email == "a@test.com" { 1 } else { 2 })
+        );
+        assert_eq!(received[0]["requestId"], received[1]["requestId"]);
+
+    }
+
+    #[tokio::test]
+    async fn test_raw_passthrough_stream_sends_heartbeats_before_slow_first_chunk() {
+        // 模拟上游: 响应头立即返回，首个数据块约 150ms 后才到达
+        let raw_sse = "data: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"r\"}]}}]}}\r\n\r\n";
+        let app = axum::Router::new().fallback(move || async move {
+            let body = futures::stream::once(async move {
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                Ok::<_, std::io::Error>(raw_sse)
+            });
+            axum::body::Body::from_stream(body)
+        });
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+        *state.streaming.write().await = crate::proxy::config::StreamingConfig {
+            peek_heartbeat_ms: 20,
+            ..Default::default()
+        };
+
+        let resp = handle_raw_passthrough(
+            State(state),
+            Path("streamGenerateContent".to_string()),
+            HeaderMap::new(),
+            Json(json!({
+                "model": "gemini-2.5-flash",
+                "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }]
+            })),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8_lossy(&body).to_string();
+        assert!(body.starts_with(": ping\n\n"), "{}", body);
+        assert!(body.ends_with(raw_sse), "{}", body);
+
+    }
+
+    #[tokio::test]
+    async fn test_civic_integrity_rejection_retries_without_category() {
+        let received: Arc<Mutex<Vec<Value>>> = Arc::new(Mutex::new(Vec::new()));
+        let app = {
+            let received = received.clone();
+            axum::Router::new().fallback(move |Json(body): Json<Value>| {
+                let received = received.clone();
+                async move {
+                    let civic = has_civic_integrity_setting(&body);
+                    received.lock().unwrap().push(body);
+                    if civic {
+                        (
+                            StatusCode::BAD_REQUEST,
+                            r#"{"error":{"code":400,"message":"Invalid value at 'safety_settings[1].category' (HARM_CATEGORY_CIVIC_INTEGRITY)"}}"#,
+                        )
+                    } else {
+                        (
+                            StatusCode::OK,
+                            r#"{"response":{"candidates":[{"content":{"parts":[{"text":"ok"}]}}]}}"#,
+                        )
+                    }
+                }
+            })
+        };
+        // 单账号池的轮换预算只有 1 次: 降级重试不占用预算时才能成功
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-force-stream", "false".parse().unwrap());
+        let resp = handle_generate(
+            State(state),
+            Path("gemini-2.5-flash-civic-test:generateContent".to_string()),
+            headers,
+            Json(json!({
+                "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }],
+                "safetySettings": [
+                    { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
+                    { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "OFF" }
+                ]
+            })),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // 首次 400 后记录模型不支持该类别，重试请求只保留其余类别
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(has_civic_integrity_setting(&received[0]));
+        assert!(!has_civic_integrity_setting(&received[1]));
+        assert_eq!(
+            received[1]["request"]["safetySettings"],
+            json!([{ "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" }])
+        );
+        assert!(!supports_civic_integrity(
+            "gemini-2.5-flash-civic-test",
+            "gemini-2.5-flash-civic-test"
+        ));
+
+    }
+}
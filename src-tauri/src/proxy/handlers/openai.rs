@@ -1,38 +1,372 @@
 // OpenAI Handler
 use axum::{
-    extract::Json, extract::State, http::StatusCode, response::IntoResponse, response::Response,
+    extract::Json, extract::Path, extract::State, http::StatusCode, response::IntoResponse,
+    response::Response,
 };
 use base64::Engine as _;
 use bytes::Bytes;
 use serde_json::{json, Value};
 use tracing::{debug, error, info}; // Import Engine trait for encode method
 
+use crate::proxy::mappers::common_utils::{
+    build_safety_settings, is_civic_integrity_rejection, mark_civic_integrity_unsupported,
+    supports_civic_integrity,
+};
+use crate::proxy::mappers::openai::image_input::{
+    fit_base64_image, fit_request_images, InputImageLimits,
+};
+use crate::proxy::mappers::openai::image_output::{image_response_item, ImageOutputOptions};
+use crate::proxy::mappers::openai::json_repair::repair_tool_call_arguments;
+use crate::proxy::mappers::openai::tool_call_ids::resolve_tool_call_name;
+use crate::proxy::mappers::openai::validation::parse_openai_request;
 use crate::proxy::mappers::openai::{
-    transform_openai_request, transform_openai_response, OpenAIRequest,
+    apply_responses_reasoning, content_filter_error, has_content_filter_finish,
+    limit_to_single_tool_call, strip_reasoning_content, transform_openai_request,
+    transform_openai_response, OpenAIRequest, OpenAIResponse,
 };
 // use crate::proxy::upstream::client::UpstreamClient; // 通过 state 获取
+use crate::proxy::config::{
+    get_openai_compat_config, resolve_model_profile,
+    resolve_model_timeouts, SafetyPartialPolicy, UnsupportedParamPolicy,
+};
 use crate::proxy::debug_logger;
 use crate::proxy::server::AppState;
 use crate::proxy::upstream::client::mask_email;
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
 use super::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account, RetryStrategy,
+    apply_retry_strategy, attach_effective_params, describe_attempts, determine_retry_strategy,
+    ensure_account_pool_not_empty, exhausted_response, exhausted_retry_after,
+    extract_effective_params, fallback_model_for_attempt, is_model_unavailable,
+    pool_outage_response, record_peek_outcome, resolve_account_override, resolve_force_stream,
+    should_rotate_account, skip_to_next_fallback, with_fallback_header, with_optional_timeout, with_rotation_trace,
+    AccountAttempt, AttemptBudget, RetryStrategy,
 };
 use crate::proxy::common::client_adapter::CLIENT_ADAPTERS; // [NEW] Adapter Registry
+use crate::proxy::common::stream_buffer::{
+    is_sse_error_event, peek_heartbeat_interval, with_flush_threshold, with_peek_heartbeats,
+    SseErrorFormat,
+};
+use crate::proxy::middleware::request_span::{record_attempt, request_id};
 use crate::proxy::session_manager::SessionManager;
+use crate::proxy::response_store::{key_owner, ResponseStore, ResponseStoreOptions};
 use axum::http::HeaderMap;
 use tokio::time::Duration;
 
-pub async fn handle_chat_completions(
-    State(state): State<AppState>,
-    headers: HeaderMap, // [CHANGED] Extract headers
-    Json(mut body): Json<Value>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // [FIX] 保存原始请求体的完整副本，用于日志记录
-    // 这确保了即使结构体定义遗漏字段，日志也能完整记录所有参数
-    let original_body = body.clone();
+/// 将请求中的远程图片与文档 (file_url) 下载并内联为 data URL，失败时返回 400
+/// 内联后统一缩小超出上游限制的图片
+async fn inline_request_images(
+    state: &AppState,
+    openai_req: &mut OpenAIRequest,
+) -> Result<(), (StatusCode, String)> {
+    use crate::proxy::mappers::openai::{document_input, image_fetch};
+
+    let has_remote_images = image_fetch::has_remote_images(openai_req);
+    let has_remote_documents = document_input::has_remote_documents(openai_req);
+    if has_remote_images || has_remote_documents {
+        let client = {
+            let upstream_proxy = state.upstream_proxy.read().await;
+            let allowed_hosts = state
+                .experimental
+                .read()
+                .await
+                .remote_image_allowed_hosts
+                .clone();
+            image_fetch::build_image_client(&upstream_proxy, &allowed_hosts)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        };
+        let count = image_fetch::inline_remote_images(
+            openai_req,
+            &client,
+            image_fetch::MAX_REMOTE_IMAGE_BYTES,
+        )
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid image: {}", e)))?;
+        if count > 0 {
+            debug!("Inlined {} remote image(s)", count);
+        }
+        let count = document_input::inline_remote_documents(
+            openai_req,
+            &client,
+            document_input::MAX_INLINE_DOCUMENT_BYTES,
+        )
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid document: {}", e)))?;
+        if count > 0 {
+            debug!("Inlined {} remote document(s)", count);
+        }
+    }
+
+    let limits = InputImageLimits::from_config(&*state.experimental.read().await);
+    let fitted = fit_request_images(openai_req, &limits).await;
+    if fitted > 0 {
+        debug!("Downscaled {} oversized input image(s)", fitted);
+    }
+    Ok(())
+}
+
+/// [NEW] 超过阈值的内联媒体上传至 Gemini Files API，返回替换为文件引用后的请求
+/// 上传的文件归属于当前账号: 同账号重试复用已上传的 URI，换号时为新账号上传一次
+async fn upload_request_media(
+    upstream: &crate::proxy::upstream::client::UpstreamClient,
+    experimental: &tokio::sync::RwLock<crate::proxy::config::ExperimentalConfig>,
+    access_token: &str,
+    account_id: &str,
+    openai_req: &OpenAIRequest,
+) -> Option<OpenAIRequest> {
+    let threshold = experimental.read().await.media_upload_threshold_bytes;
+    crate::proxy::mappers::openai::media_upload::upload_large_media(
+        upstream,
+        access_token,
+        Some(account_id),
+        openai_req,
+        threshold,
+    )
+    .await
+}
+
+/// logit_bias 的 key 是 OpenAI 分词器 token id，无法映射到 Gemini
+/// 按配置策略忽略 (debug 日志) 或返回 400，避免客户端误以为偏置已生效
+fn check_logit_bias(body: &Value, policy: UnsupportedParamPolicy) -> Result<(), (StatusCode, String)> {
+    let Some(bias) = body.get("logit_bias").filter(|v| !v.is_null()) else {
+        return Ok(());
+    };
+    if bias.as_object().map(|m| m.is_empty()).unwrap_or(false) {
+        return Ok(());
+    }
+    match policy {
+        UnsupportedParamPolicy::Ignore => {
+            debug!("[OpenAI] Ignoring unsupported logit_bias: {}", bias);
+            Ok(())
+        }
+        UnsupportedParamPolicy::Reject => Err((
+            StatusCode::BAD_REQUEST,
+            "logit_bias is not supported: OpenAI token ids cannot be mapped to the upstream model's tokenizer".to_string(),
+        )),
+    }
+}
+
+/// [NEW] 音频输出 (gpt-4o-audio 的 audio 参数 / modalities: ["text", "audio"]) 上游无法提供
+/// reject 时返回 400 并说明如何修改请求；ignore 时降级为纯文本，由 X-Unsupported-Params 头告知客户端
+fn check_audio_output(
+    openai_req: &OpenAIRequest,
+    policy: UnsupportedParamPolicy,
+) -> Result<(), (StatusCode, String)> {
+    if !openai_req.wants_audio_output() {
+        return Ok(());
+    }
+    match policy {
+        UnsupportedParamPolicy::Ignore => {
+            debug!("[OpenAI] Audio output requested, downgrading to text-only");
+            Ok(())
+        }
+        UnsupportedParamPolicy::Reject => Err((
+            StatusCode::BAD_REQUEST,
+            "audio output is not supported: the upstream model only returns text. Remove the `audio` parameter and the \"audio\" entry from `modalities`".to_string(),
+        )),
+    }
+}
+
+/// Accept-Language 中权重最高的语言 (取首个非通配项，如 "zh-CN,zh;q=0.9" -> "zh-CN")
+fn preferred_language(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(axum::http::header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())?
+        .split(',')
+        .map(|tag| tag.split(';').next().unwrap_or("").trim())
+        .find(|tag| !tag.is_empty() && *tag != "*")
+        .map(|tag| tag.to_string())
+}
+
+/// 图像生成模型误用于聊天接口时返回 400，提示改用 /v1/images/generations
+fn check_image_model_in_chat(
+    config: &crate::proxy::config::ImageConfig,
+    original_model: &str,
+    mapped_model: &str,
+) -> Result<(), (StatusCode, String)> {
+    if !config.is_blocked_in_chat(original_model, mapped_model) {
+        return Ok(());
+    }
+    tracing::warn!(
+        "[OpenAI] Rejected image model {} (mapped: {}) on chat endpoint",
+        original_model,
+        mapped_model
+    );
+    Err((
+        StatusCode::BAD_REQUEST,
+        format!(
+            "Model '{}' is an image generation model and cannot be used with /v1/chat/completions. Use /v1/images/generations instead.",
+            original_model
+        ),
+    ))
+}
+
+/// safety_partial = error 时，将被安全策略截断的非流式响应替换为 content_filter 错误
+/// [NEW] 非流式响应的 model 字段改为客户端请求的模型名 (流式响应本身已使用请求模型名)
+/// 部分 SDK 会校验响应 model 与请求一致；映射后的真实模型由 X-Mapped-Model 头返回
+fn echo_requested_model(response: &mut OpenAIResponse, requested_model: &str) {
+    if get_openai_compat_config().echo_requested_model && !requested_model.is_empty() {
+        response.model = requested_model.to_string();
+    }
+}
+
+/// [NEW] 通过 X-Unsupported-Params 头告知客户端被忽略的参数 (逗号分隔)
+/// - logprobs: 请求了但上游未返回 (模型不支持或已剥离重试)，响应中保持 null
+/// - prediction: Gemini 没有 Predicted Outputs，转换时忽略
+/// - audio: 请求了音频输出，已降级为纯文本 (audio_output = ignore)
+fn with_unsupported_params_header(
+    mut response: Response,
+    logprobs_missing: bool,
+    openai_req: &OpenAIRequest,
+) -> Response {
+    let params: Vec<&str> = [
+        (logprobs_missing, "logprobs"),
+        (openai_req.prediction.is_some(), "prediction"),
+        (openai_req.wants_audio_output(), "audio"),
+    ]
+    .into_iter()
+    .filter_map(|(unsupported, name)| unsupported.then_some(name))
+    .collect();
+    if params.is_empty() {
+        return response;
+    }
+    if let Ok(value) = axum::http::HeaderValue::from_str(&params.join(", ")) {
+        response.headers_mut().insert("X-Unsupported-Params", value);
+    }
+    response
+}
+
+fn logprobs_missing(requested: bool, response: &OpenAIResponse) -> bool {
+    requested && response.choices.iter().all(|c| c.logprobs.is_none())
+}
+
+/// [FIX] Chat 响应 -> 旧版 completions choices (logprobs 转换为 tokens / token_logprobs 结构)
+fn legacy_completion_choices(chat_resp: &OpenAIResponse) -> Vec<Value> {
+    use crate::proxy::mappers::openai::response::legacy_completion_logprobs;
+
+    chat_resp
+        .choices
+        .iter()
+        .map(|c| {
+            json!({
+                "text": match &c.message.content {
+                    Some(crate::proxy::mappers::openai::OpenAIContent::String(s)) => s.clone(),
+                    _ => "".to_string()
+                },
+                "index": c.index,
+                "logprobs": c.logprobs.as_ref().map(|l| legacy_completion_logprobs(l, 0)),
+                "finish_reason": c.finish_reason
+            })
+        })
+        .collect()
+}
+
+/// Responses API 非流式响应: 追加与流式一致的 output 列表 (含 function_call 输出项)，
+/// 回写 metadata 并在 store: true 时保存，避免 GET /v1/responses/{id} 读到的响应丢失工具调用
+fn finalize_codex_response(
+    legacy_resp: &mut Value,
+    chat_resp: &OpenAIResponse,
+    store_options: &ResponseStoreOptions,
+) {
+    use crate::proxy::mappers::openai::streaming::{
+        build_responses_output, responses_function_call_item,
+    };
 
+    let message = chat_resp.choices.first().map(|c| &c.message);
+    let output_text = match message.and_then(|m| m.content.as_ref()) {
+        Some(crate::proxy::mappers::openai::OpenAIContent::String(s)) => s.as_str(),
+        _ => "",
+    };
+    let function_calls = message
+        .and_then(|m| m.tool_calls.as_ref())
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|tc| {
+                    let args: Value =
+                        serde_json::from_str(&tc.function.arguments).unwrap_or_else(|_| json!({}));
+                    let func_call = json!({ "name": tc.function.name, "args": args });
+                    responses_function_call_item(&func_call, tc.id.clone())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    legacy_resp["output"] = json!(build_responses_output(output_text, function_calls));
+    store_options.finalize(legacy_resp);
+}
+
+/// [NEW] 提示词屏蔽规则: 命中时返回通用拒绝信息，命中的规则 id 只写入日志
+async fn prompt_filter_rejection(state: &AppState, openai_req: &OpenAIRequest) -> Option<Response> {
+    use crate::proxy::prompt_filter::{openai_user_text, REJECTED_MESSAGE};
+
+    let filter = state.prompt_filter.read().await;
+    let rule_id = filter.check(&openai_user_text(openai_req))?;
+    tracing::warn!(
+        "[OpenAI] Request rejected by prompt filter rule {} (model={})",
+        rule_id,
+        openai_req.model
+    );
+    Some(
+        (
+            filter.status(),
+            Json(json!({
+                "error": {
+                    "message": REJECTED_MESSAGE,
+                    "type": "invalid_request_error",
+                    "code": "content_policy_violation"
+                }
+            })),
+        )
+            .into_response(),
+    )
+}
+
+/// [NEW] 按终端用户 (user 字段) 计数与限流 (请求数 / 估算输入 token)，各入口在获取账号之前调用
+async fn end_user_rejection(
+    state: &AppState,
+    user: Option<&str>,
+    estimated_tokens: u64,
+) -> Option<Response> {
+    let user = user.filter(|u| !u.is_empty())?;
+    crate::proxy::middleware::access_log::record_end_user(user);
+    let limits = state.user_rate_limit.read().await.clone();
+    let exceeded = state
+        .user_usage
+        .record(user, &limits, estimated_tokens)
+        .err()?;
+    tracing::warn!(
+        "[OpenAI] User {} exceeded {} {}/min",
+        user,
+        exceeded.limit,
+        exceeded.unit
+    );
+    Some(exceeded.into_response(user))
+}
+
+fn safety_blocked_response(
+    response: &OpenAIResponse,
+    policy: SafetyPartialPolicy,
+    email: &str,
+    mapped_model: &str,
+) -> Option<Response> {
+    if policy != SafetyPartialPolicy::Error || !has_content_filter_finish(response) {
+        return None;
+    }
+    tracing::warn!("[OpenAI] Response blocked by safety filter, discarding partial content");
+    Some(
+        (
+            StatusCode::BAD_REQUEST,
+            [("X-Account-Email", email), ("X-Mapped-Model", mapped_model)],
+            Json(content_filter_error()),
+        )
+            .into_response(),
+    )
+}
+
+/// Chat Completions 请求规范化: Responses 格式转换、解析校验、空消息兜底
+/// handle_chat_completions 与 /v1/debug/transform 共用，保证调试输出与实际请求一致
+fn normalize_chat_request(mut body: Value) -> Result<OpenAIRequest, (StatusCode, String)> {
     // [NEW] 自动检测并转换 Responses 格式
     // 如果请求包含 instructions 或 input 但没有 messages，则认为是 Responses 格式
     let is_responses_format = !body.get("messages").is_some()
@@ -82,8 +416,8 @@ pub async fn handle_chat_completions(
         }
     }
 
-    let mut openai_req: OpenAIRequest = serde_json::from_value(body)
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))?;
+    let mut openai_req: OpenAIRequest =
+        parse_openai_request(body).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
     // Safety: Ensure messages is not empty
     if openai_req.messages.is_empty() {
@@ -99,10 +433,43 @@ pub async fn handle_chat_completions(
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                annotations: None,
             });
     }
 
-    let trace_id = format!("req_{}", chrono::Utc::now().timestamp_subsec_millis());
+    Ok(openai_req)
+}
+
+pub async fn handle_chat_completions(
+    State(state): State<AppState>,
+    headers: HeaderMap, // [CHANGED] Extract headers
+    Json(body): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    // [FIX] 保存原始请求体的完整副本，用于日志记录
+    // 这确保了即使结构体定义遗漏字段，日志也能完整记录所有参数
+    let original_body = body.clone();
+
+    let compat = get_openai_compat_config();
+    check_logit_bias(&body, compat.logit_bias)?;
+
+    let mut openai_req = normalize_chat_request(body)?;
+    check_audio_output(&openai_req, compat.audio_output)?;
+    if openai_req.response_language.is_none() {
+        openai_req.response_language = preferred_language(&headers);
+    }
+    if let Some(rejected) = prompt_filter_rejection(&state, &openai_req).await {
+        return Ok(rejected);
+    }
+
+    let tokens = crate::proxy::user_usage::estimate_request_tokens(&openai_req);
+    if let Some(limited) = end_user_rejection(&state, openai_req.user.as_deref(), tokens).await {
+        return Ok(limited);
+    }
+
+    // [NEW] 远程 http(s) 图片需先下载内联，Gemini 无法直接拉取任意 URL
+    inline_request_images(&state, &mut openai_req).await?;
+
+    let trace_id = request_id(&headers);
     info!(
         "[{}] OpenAI Chat Request: {} | {} messages | stream: {}",
         trace_id,
@@ -111,6 +478,16 @@ pub async fn handle_chat_completions(
         openai_req.stream
     );
     let debug_cfg = state.debug_logging.read().await.clone();
+    let repair_tool_args = state.experimental.read().await.enable_tool_args_repair;
+    let safety_policy = get_openai_compat_config().safety_partial;
+    // [NEW] 请求体 strip_reasoning 优先，否则取 openai_compat.strip_reasoning
+    let strip_reasoning = openai_req
+        .strip_reasoning
+        .unwrap_or_else(|| get_openai_compat_config().strip_reasoning);
+    let force_stream_default = state.experimental.read().await.force_stream_internally;
+    let max_collected_bytes = state.experimental.read().await.max_collected_bytes;
+    let streaming_config = state.streaming.read().await.clone();
+    let logprobs_requested = openai_req.logprobs_requested();
     if debug_logger::is_enabled(&debug_cfg) {
         // [FIX] 使用原始 body 副本记录日志，确保不丢失任何字段
         let original_payload = json!({
@@ -129,6 +506,16 @@ pub async fn handle_chat_completions(
         .await;
     }
 
+    // [NEW] 回显测试模式: 直接返回合成响应，不获取账号、不请求上游
+    if is_echo_mode(&openai_req.model) {
+        let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+            &openai_req.model,
+            &*state.custom_mapping.read().await,
+        );
+        info!("[{}] Echo mode: returning synthetic response", trace_id);
+        return Ok(echo_response(&openai_req, &mapped_model));
+    }
+
     // [NEW] Detect Client Adapter
     let client_adapter = CLIENT_ADAPTERS
         .iter()
@@ -141,20 +528,63 @@ pub async fn handle_chat_completions(
     // 1. 获取 UpstreamClient (Clone handle)
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
+    ensure_account_pool_not_empty(&token_manager)?;
+    // [NEW] X-Account-Override: 强制指定账号，需在 X-Admin-Token (或 Authorization) 中携带管理凭据
+    let account_override = {
+        let security = state.security.read().await;
+        resolve_account_override(&headers, &security, &token_manager)?
+    };
+    // [NEW] 账号池全部限流时快速失败
+    if let Some(resp) = pool_outage_response(&token_manager, account_override.as_deref()).await {
+        return Ok(resp);
+    }
     let pool_size = token_manager.len();
     // [FIX] Ensure max_attempts is at least 2 to allow for internal retries
     let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size.saturating_add(1)).max(2);
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    let mut attempts: Vec<AccountAttempt> = Vec::new();
 
     // 2. 模型路由解析 (移到循环外以支持在所有路径返回 X-Mapped-Model)
-    let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+    let mut mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
         &openai_req.model,
         &*state.custom_mapping.read().await,
     );
+    check_image_model_in_chat(
+        &crate::proxy::get_image_config(),
+        &openai_req.model,
+        &mapped_model,
+    )?;
+
+    // [NEW] 降级模型链: 主模型的账号轮换预算耗尽后依次切换到降级模型，而不是直接返回 429
+    let fallback_chain = resolve_model_profile(&openai_req.model, &mapped_model)
+        .map(|p| p.fallback_models)
+        .unwrap_or_default();
+    let mut fallback_model: Option<String> = None;
+    let total_attempts = max_attempts * (fallback_chain.len() + 1);
+    // [NEW] 通过 X-Rotation-Trace 返回本次请求的账号轮换决策 (成功与耗尽均附加)
+    let rotation_trace_enabled = state.experimental.read().await.expose_rotation_trace;
+
+    let mut attempt_budget = AttemptBudget::new(total_attempts);
+    while let Some(global_attempt) = attempt_budget.next() {
+        let attempt = global_attempt % max_attempts;
+        if attempt == 0 {
+            if let Some(next) =
+                fallback_model_for_attempt(&fallback_chain, max_attempts, global_attempt)
+            {
+                tracing::warn!(
+                    "[{}] Model {} exhausted after {} attempts, falling back to {}",
+                    trace_id,
+                    mapped_model,
+                    max_attempts,
+                    next
+                );
+                mapped_model = next.to_string();
+                fallback_model = Some(mapped_model.clone());
+            }
+        }
 
-    for attempt in 0..max_attempts {
         // 将 OpenAI 工具转为 Value 数组以便探测联网
         let tools_val: Option<Vec<Value>> = openai_req
             .tools
@@ -166,17 +596,21 @@ pub async fn handle_chat_completions(
             &tools_val,
             None, // size (not used in handler, transform_openai_request handles it)
             None, // quality
-            None, // OpenAI handler uses transform_openai_request for image gen
+            Some(&original_body), // [NEW] 用于工作负载分类 (Codex 风格输入 / 大段文件上下文)
         );
 
+        // [NEW] 按模型超时 (peek / 单次请求总时限 / 非流式收集)
+        let timeouts = resolve_model_timeouts(&openai_req.model, &mapped_model, Duration::from_secs(60));
+
         // 3. 提取 SessionId (粘性指纹)
         let session_id = SessionManager::extract_openai_session_id(&openai_req);
 
         // 4. 获取 Token (使用准确的 request_type)
         // 关键：在重试尝试 (attempt > 0) 时强制轮换账号
         let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
-            .get_token(
-                &config.request_type,
+            .get_token_with_override(
+                account_override.as_deref(),
+                &config.workload,
                 attempt > 0,
                 Some(&session_id),
                 &mapped_model,
@@ -196,12 +630,34 @@ pub async fn handle_chat_completions(
             }
         };
 
+        state
+            .metrics
+            .record_attempt("openai", attempt, last_email.as_deref(), &email);
         last_email = Some(email.clone());
-        info!("✓ Using account: {} (type: {})", email, config.request_type);
+        record_attempt(attempt + 1, &email, &mapped_model);
+        info!(
+            "✓ Using account: {} (type: {}, workload: {})",
+            email, config.request_type, config.workload
+        );
 
         // 4. 转换请求 (返回内容包含 session_id 和 message_count)
-        let (gemini_body, session_id, message_count) =
-            transform_openai_request(&openai_req, &project_id, &mapped_model);
+        let uploaded_req = upload_request_media(
+            &state.upstream,
+            &state.experimental,
+            &access_token,
+            &account_id,
+            &openai_req,
+        )
+        .await;
+        let (gemini_body, session_id, message_count) = transform_openai_request(
+            uploaded_req.as_ref().unwrap_or(&openai_req),
+            &project_id,
+            &mapped_model,
+        );
+
+        // 调试模式下在响应体中回写实际采样参数
+        let effective_params = debug_logger::is_enabled(&debug_cfg)
+            .then(|| extract_effective_params(&gemini_body));
 
         if debug_logger::is_enabled(&debug_cfg) {
             let payload = json!({
@@ -211,6 +667,7 @@ pub async fn handle_chat_completions(
                 "original_model": openai_req.model,
                 "mapped_model": mapped_model,
                 "request_type": config.request_type,
+                "workload": config.workload,
                 "attempt": attempt,
                 "v1internal_request": gemini_body.clone(),
             });
@@ -230,7 +687,8 @@ pub async fn handle_chat_completions(
 
         // 5. 发送请求
         let client_wants_stream = openai_req.stream;
-        let force_stream_internally = !client_wants_stream;
+        let force_stream_internally =
+            resolve_force_stream(&headers, client_wants_stream, force_stream_default);
         let actual_stream = client_wants_stream || force_stream_internally;
 
         if force_stream_internally {
@@ -260,20 +718,25 @@ pub async fn handle_chat_completions(
             );
         }
 
-        let call_result = match upstream
-            .call_v1_internal_with_headers(
+        let upstream_started = std::time::Instant::now(); // [NEW] 首字延迟起点
+        let call_result = match with_optional_timeout(
+            timeouts.total,
+            "Upstream request",
+            upstream.call_v1_internal_with_headers(
                 method,
                 &access_token,
                 gemini_body,
                 query_string,
                 extra_headers.clone(),
                 Some(account_id.as_str()),
-            )
-            .await
+            ),
+        )
+        .await
         {
             Ok(r) => r,
             Err(e) => {
                 last_error = e.clone();
+                attempts.push(AccountAttempt::new(&email, &mapped_model, None, &last_error));
                 debug!(
                     "OpenAI Request failed on attempt {}/{}: {}",
                     attempt + 1,
@@ -321,6 +784,7 @@ pub async fn handle_chat_completions(
         let upstream_url = response.url().to_string();
         let status = response.status();
         if status.is_success() {
+            token_manager.record_model_success(&email, &mapped_model);
             // 5. 处理流式 vs 非流式
             if actual_stream {
                 use axum::body::Body;
@@ -347,23 +811,46 @@ pub async fn handle_chat_completions(
 
                 // [P1 FIX] Enhanced Peek logic to handle heartbeats and slow start
                 // Pre-read until we find meaningful content, skip heartbeats
-                use crate::proxy::mappers::openai::streaming::create_openai_sse_stream;
+                use crate::proxy::mappers::openai::streaming::{
+                    apply_stream_safety_policy, coalesce_whitespace_deltas,
+                    create_openai_sse_stream, limit_stream_to_single_tool_call,
+                    strip_reasoning_deltas,
+                };
                 let mut openai_stream = create_openai_sse_stream(
                     gemini_stream,
                     openai_req.model.clone(),
                     session_id,
                     message_count,
                 );
+                // [NEW] parallel_tool_calls: false 仅保留第一个工具调用 (流式与内部收集共用)
+                if openai_req.parallel_tool_calls == Some(false) {
+                    openai_stream = limit_stream_to_single_tool_call(openai_stream);
+                }
+                // [NEW] strip_reasoning: 丢弃思维链分片，内部收集的非流式结果同样不含思维链
+                if strip_reasoning {
+                    openai_stream = strip_reasoning_deltas(openai_stream);
+                }
+                // [NEW] 空白分片合并到相邻正文分片
+                if streaming_config.suppress_whitespace_deltas {
+                    openai_stream = coalesce_whitespace_deltas(openai_stream);
+                }
+
+                // [NEW] streaming.peek_heartbeat_ms > 0: 先按常规 peek 等待该时长，期间出错仍可轮换账号；
+                // 超过该时长仍无首个数据块才开始响应并发送心跳，之后的失败以流内错误事件返回
+                let heartbeat_after =
+                    peek_heartbeat_interval(&streaming_config, client_wants_stream, timeouts.peek);
+                let peek_started = std::time::Instant::now();
+                let mut start_heartbeats = false;
 
                 let mut first_data_chunk = None;
                 let mut retry_this_account = false;
 
                 // Loop to skip heartbeats during peek
                 loop {
-                    match tokio::time::timeout(
-                        std::time::Duration::from_secs(60),
-                        openai_stream.next(),
-                    )
+                    let wait = heartbeat_after.map_or(timeouts.peek, |after| {
+                        after.saturating_sub(peek_started.elapsed())
+                    });
+                    match tokio::time::timeout(wait, openai_stream.next())
                     .await
                     {
                         Ok(Some(Ok(bytes))) => {
@@ -379,7 +866,7 @@ pub async fn handle_chat_completions(
                             }
 
                             // Check for error events
-                            if text.contains("\"error\"") {
+                            if is_sse_error_event(&bytes) {
                                 tracing::warn!("[OpenAI] Error detected during peek, retrying...");
                                 last_error = "Error event during peek".to_string();
                                 retry_this_account = true;
@@ -387,6 +874,10 @@ pub async fn handle_chat_completions(
                             }
 
                             // We found real data!
+                            // [NEW] 记录首字延迟 (指标 + 慢账号降级)
+                            let ttft = upstream_started.elapsed();
+                            state.metrics.record_first_token_latency("openai", ttft);
+                            token_manager.record_first_token_latency(&email, ttft.as_millis() as u64);
                             first_data_chunk = Some(bytes);
                             break;
                         }
@@ -404,11 +895,17 @@ pub async fn handle_chat_completions(
                             retry_this_account = true;
                             break;
                         }
+                        Err(_) if heartbeat_after.is_some() => {
+                            start_heartbeats = true;
+                            break;
+                        }
                         Err(_) => {
                             tracing::warn!(
-                                "[OpenAI] Timeout waiting for first data (60s), retrying..."
+                                "[OpenAI] Timeout waiting for first data ({}s), retrying...",
+                                timeouts.peek.as_secs()
                             );
                             last_error = "Timeout waiting for first data".to_string();
+                            state.metrics.record_peek_timeout("openai");
                             retry_this_account = true;
                             break;
                         }
@@ -416,9 +913,49 @@ pub async fn handle_chat_completions(
                 }
 
                 if retry_this_account {
+                    attempts.push(AccountAttempt::new(&email, &mapped_model, None, &last_error));
                     continue; // Rotate to next account
                 }
 
+                if let (true, Some(heartbeat_interval)) = (start_heartbeats, heartbeat_after) {
+                    // 响应头即将发出，不再轮换账号；peek 结果仍计入首字延迟 / 超时指标
+                    let on_peek = record_peek_outcome(
+                        &state.metrics,
+                        &token_manager,
+                        "openai",
+                        &email,
+                        upstream_started,
+                    );
+                    let stream = apply_stream_safety_policy(
+                        with_peek_heartbeats(
+                            openai_stream,
+                            heartbeat_interval,
+                            timeouts.peek.saturating_sub(peek_started.elapsed()),
+                            SseErrorFormat::OpenAI,
+                            on_peek,
+                        ),
+                        safety_policy,
+                        max_collected_bytes,
+                    );
+                    let resp = Response::builder()
+                        .header("Content-Type", "text/event-stream")
+                        .header("Cache-Control", "no-cache")
+                        .header("Connection", "keep-alive")
+                        .header("X-Accel-Buffering", "no")
+                        .header("X-Account-Email", &email)
+                        .header("X-Mapped-Model", &mapped_model)
+                        .body(Body::from_stream(with_flush_threshold(
+                            stream,
+                            &streaming_config,
+                        )))
+                        .unwrap()
+                        .into_response();
+                    let resp = with_unsupported_params_header(resp, false, &openai_req);
+                    let resp =
+                        with_rotation_trace(resp, &attempts, Some(&email), rotation_trace_enabled);
+                    return Ok(with_fallback_header(resp, fallback_model.as_deref()));
+                }
+
                 // Combine first chunk with remaining stream
                 let combined_stream =
                     futures::stream::once(
@@ -428,8 +965,15 @@ pub async fn handle_chat_completions(
 
                 if client_wants_stream {
                     // 客户端请求流式，返回 SSE
-                    let body = Body::from_stream(combined_stream);
-                    return Ok(Response::builder()
+                    // [NEW] 在 peek 之后应用，避免错误事件触发账号轮换重试
+                    let combined_stream = apply_stream_safety_policy(
+                        Box::pin(combined_stream),
+                        safety_policy,
+                        max_collected_bytes,
+                    );
+                    let body =
+                        Body::from_stream(with_flush_threshold(combined_stream, &streaming_config));
+                    let resp = Response::builder()
                         .header("Content-Type", "text/event-stream")
                         .header("Cache-Control", "no-cache")
                         .header("Connection", "keep-alive")
@@ -438,24 +982,65 @@ pub async fn handle_chat_completions(
                         .header("X-Mapped-Model", &mapped_model)
                         .body(body)
                         .unwrap()
-                        .into_response());
+                        .into_response();
+                    let resp = with_unsupported_params_header(
+                        resp,
+                        logprobs_requested && !openai_req.logprobs_requested(),
+                        &openai_req,
+                    );
+                    let resp =
+                        with_rotation_trace(resp, &attempts, Some(&email), rotation_trace_enabled);
+                    return Ok(with_fallback_header(resp, fallback_model.as_deref()));
                 } else {
                     // 客户端请求非流式，但内部强制转为流式
                     // 收集流数据并聚合为 JSON
+                    // 客户端断开时连接任务会丢弃本 handler future，收集随之中止并丢弃上游流
                     use crate::proxy::mappers::openai::collector::collect_stream_to_json;
 
-                    match collect_stream_to_json(Box::pin(combined_stream)).await {
-                        Ok(full_response) => {
+                    match with_optional_timeout(
+                        timeouts.collector,
+                        "Stream collection",
+                        collect_stream_to_json(
+                            Box::pin(combined_stream),
+                            max_collected_bytes,
+                        ),
+                    )
+                    .await
+                    {
+                        Ok(mut full_response) => {
                             info!("[{}] ✓ Stream collected and converted to JSON", trace_id);
-                            return Ok((
+                            if let Some(blocked) = safety_blocked_response(
+                                &full_response,
+                                safety_policy,
+                                &email,
+                                &mapped_model,
+                            ) {
+                                return Ok(with_fallback_header(blocked, fallback_model.as_deref()));
+                            }
+                            if repair_tool_args {
+                                repair_tool_call_arguments(&mut full_response);
+                            }
+                            let missing = logprobs_missing(logprobs_requested, &full_response);
+                            let resp = (
                                 StatusCode::OK,
                                 [
                                     ("X-Account-Email", email.as_str()),
                                     ("X-Mapped-Model", mapped_model.as_str()),
                                 ],
-                                Json(full_response),
+                                Json(attach_effective_params(
+                                    full_response,
+                                    effective_params.as_ref(),
+                                )),
                             )
-                                .into_response());
+                                .into_response();
+                            let resp = with_unsupported_params_header(resp, missing, &openai_req);
+                            let resp = with_rotation_trace(
+                                resp,
+                                &attempts,
+                                Some(&email),
+                                rotation_trace_enabled,
+                            );
+                            return Ok(with_fallback_header(resp, fallback_model.as_deref()));
                         }
                         Err(e) => {
                             error!("[{}] Stream collection error: {}", trace_id, e);
@@ -474,17 +1059,39 @@ pub async fn handle_chat_completions(
                 .await
                 .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)))?;
 
-            let openai_response =
+            let mut openai_response =
                 transform_openai_response(&gemini_resp, Some(&session_id), message_count);
-            return Ok((
+            if let Some(blocked) =
+                safety_blocked_response(&openai_response, safety_policy, &email, &mapped_model)
+            {
+                return Ok(with_fallback_header(blocked, fallback_model.as_deref()));
+            }
+            echo_requested_model(&mut openai_response, &openai_req.model);
+            if openai_req.parallel_tool_calls == Some(false) {
+                limit_to_single_tool_call(&mut openai_response);
+            }
+            if strip_reasoning {
+                strip_reasoning_content(&mut openai_response);
+            }
+            if repair_tool_args {
+                repair_tool_call_arguments(&mut openai_response);
+            }
+            let missing = logprobs_missing(logprobs_requested, &openai_response);
+            let resp = (
                 StatusCode::OK,
                 [
                     ("X-Account-Email", email.as_str()),
                     ("X-Mapped-Model", mapped_model.as_str()),
                 ],
-                Json(openai_response),
+                Json(attach_effective_params(
+                    openai_response,
+                    effective_params.as_ref(),
+                )),
             )
-                .into_response());
+                .into_response();
+            let resp = with_unsupported_params_header(resp, missing, &openai_req);
+            let resp = with_rotation_trace(resp, &attempts, Some(&email), rotation_trace_enabled);
+            return Ok(with_fallback_header(resp, fallback_model.as_deref()));
         }
 
         // 处理特定错误并重试
@@ -499,6 +1106,7 @@ pub async fn handle_chat_completions(
             .await
             .unwrap_or_else(|_| format!("HTTP {}", status_code));
         last_error = format!("HTTP {}: {}", status_code, error_text);
+        attempts.push(AccountAttempt::new(&email, &mapped_model, Some(status_code), &error_text));
 
         // [New] 打印错误报文日志
         tracing::error!(
@@ -531,6 +1139,9 @@ pub async fn handle_chat_completions(
 
         // 确定重试策略
         let strategy = determine_retry_strategy(status_code, &error_text, false);
+        if let Some(last) = attempts.last_mut() {
+            last.strategy = Some(strategy.name());
+        }
 
         // 3. 标记限流状态(用于 UI 显示)
         if status_code == 429 || status_code == 529 || status_code == 503 || status_code == 500 {
@@ -544,6 +1155,7 @@ pub async fn handle_chat_completions(
                     Some(&mapped_model),
                 )
                 .await;
+            state.metrics.record_rate_limited("openai");
         }
 
         // 执行退避
@@ -635,6 +1247,39 @@ pub async fn handle_chat_completions(
             continue; // 重试
         }
 
+        // [NEW] 模型不支持重复惩罚参数: 剥离后重试 (可通过 model_profiles.omit_penalties 预先省略)
+        if status_code == 400
+            && error_text.to_lowercase().contains("penalty")
+            && openai_req.strip_penalties()
+        {
+            tracing::warn!(
+                "[OpenAI] Model {} rejected penalty parameters, retrying without them",
+                mapped_model
+            );
+            continue;
+        }
+
+        // [NEW] 模型不支持 CIVIC_INTEGRITY 安全类别: 记录后重试，重新转换时省略该类别
+        if is_civic_integrity_rejection(status_code, &error_text)
+            && supports_civic_integrity(&openai_req.model, &mapped_model)
+        {
+            mark_civic_integrity_unsupported(&mapped_model);
+            attempt_budget.retry_current();
+            continue;
+        }
+
+        // [NEW] 模型不支持 logprobs: 剥离后重试，响应中保持 null 并返回 X-Unsupported-Params 头
+        if status_code == 400
+            && error_text.to_lowercase().contains("logprob")
+            && openai_req.strip_logprobs()
+        {
+            tracing::warn!(
+                "[OpenAI] Model {} rejected logprobs, retrying without them",
+                mapped_model
+            );
+            continue;
+        }
+
         // 只有 403 (权限/地区限制) 和 401 (认证失效) 触发账号轮换
         if status_code == 403 || status_code == 401 {
             if apply_retry_strategy(
@@ -696,12 +1341,28 @@ pub async fn handle_chat_completions(
             }
         }
 
+        // [NEW] 模型不可用 (404 / 不受支持): 存在降级模型时直接切换，不在当前模型上继续轮换
+        if is_model_unavailable(status_code, &error_text) {
+            if let Some(skip) = skip_to_next_fallback(&fallback_chain, max_attempts, global_attempt)
+            {
+                tracing::warn!(
+                    "[{}] Model {} unavailable ({}), switching to next fallback model",
+                    trace_id,
+                    mapped_model,
+                    status_code
+                );
+                // 模型不可用时跳过当前模型剩余的账号轮换预算
+                attempt_budget.skip_attempts(skip);
+                continue;
+            }
+        }
+
         // 404 等由于模型配置或路径错误的 HTTP 异常，直接报错，不进行无效轮换
         error!(
             "OpenAI Upstream non-retryable error {} on account {}: {}",
             status_code, email, error_text
         );
-        return Ok((
+        let resp = (
             status,
             [
                 ("X-Account-Email", email.as_str()),
@@ -716,242 +1377,675 @@ pub async fn handle_chat_completions(
                 }
             })),
         )
-            .into_response());
+            .into_response();
+        return Ok(with_fallback_header(resp, fallback_model.as_deref()));
     }
 
-    // 所有尝试均失败
-    if let Some(email) = last_email {
-        Ok((
-            StatusCode::TOO_MANY_REQUESTS,
-            [("X-Account-Email", email), ("X-Mapped-Model", mapped_model)],
-            format!("All accounts exhausted. Last error: {}", last_error),
-        )
-            .into_response())
-    } else {
-        Ok((
-            StatusCode::TOO_MANY_REQUESTS,
-            [("X-Mapped-Model", mapped_model)],
-            format!("All accounts exhausted. Last error: {}", last_error),
-        )
-            .into_response())
-    }
+    // 所有尝试均失败 (调试模式下附带逐账号明细)
+    let detail = debug_logger::is_enabled(&debug_cfg)
+        .then(|| describe_attempts(&attempts, &token_manager));
+    let retry_after = exhausted_retry_after(&token_manager, Some(&mapped_model));
+    let resp = exhausted_response(
+        &last_error,
+        last_email,
+        Some(&mapped_model),
+        detail,
+        retry_after,
+    );
+    let resp = with_rotation_trace(resp, &attempts, None, rotation_trace_enabled);
+    Ok(with_fallback_header(resp, fallback_model.as_deref()))
 }
 
-/// 处理 Legacy Completions API (/v1/completions)
-/// 将 Prompt 转换为 Chat Message 格式，复用 handle_chat_completions
-pub async fn handle_completions(
+/// 批量对话 (POST /v1/chat/batch)
+/// 请求体 { "requests": [chatReq, ...] }，每项按非流式走 handle_chat_completions 完整流程 (含账号轮换与重试)，
+/// 返回 { "responses": [...] } 与请求按下标对齐，单项失败时对应位置为 error 对象
+pub async fn handle_chat_batch(
     State(state): State<AppState>,
-    Json(mut body): Json<Value>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
 ) -> Response {
-    debug!(
-        "Received /v1/completions or /v1/responses payload: {:?}",
-        body
-    );
-
-    let is_codex_style = body.get("input").is_some() || body.get("instructions").is_some();
-
-    // 1. Convert Payload to Messages (Shared Chat Format)
-    if is_codex_style {
-        let instructions = body
-            .get("instructions")
-            .and_then(|v| v.as_str())
-            .unwrap_or_default();
-        let input_items = body.get("input").and_then(|v| v.as_array());
-
-        let mut messages = Vec::new();
-
-        // System Instructions
-        if !instructions.is_empty() {
-            messages.push(json!({ "role": "system", "content": instructions }));
+    let (max_requests, max_concurrency) = {
+        let experimental = state.experimental.read().await;
+        (
+            experimental.batch_max_requests,
+            experimental.batch_max_concurrency,
+        )
+    };
+    let requests = match body.get("requests").and_then(|r| r.as_array()) {
+        Some(requests) if !requests.is_empty() => requests.clone(),
+        _ => {
+            return batch_error(
+                StatusCode::BAD_REQUEST,
+                "'requests' must be a non-empty array of chat completion requests".to_string(),
+            )
         }
+    };
+    if max_requests > 0 && requests.len() > max_requests {
+        return batch_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Batch contains {} requests, exceeding the limit of {}",
+                requests.len(),
+                max_requests
+            ),
+        );
+    }
 
-        let mut call_id_to_name = std::collections::HashMap::new();
-
-        // Pass 1: Build Call ID to Name Map
-        if let Some(items) = input_items {
-            for item in items {
-                let item_type = item.get("type").and_then(|v| v.as_str()).unwrap_or("");
-                match item_type {
-                    "function_call" | "local_shell_call" | "web_search_call" => {
-                        let call_id = item
-                            .get("call_id")
-                            .and_then(|v| v.as_str())
-                            .or_else(|| item.get("id").and_then(|v| v.as_str()))
-                            .unwrap_or("unknown");
+    info!(
+        "[OpenAI-Batch] Processing {} requests (concurrency: {})",
+        requests.len(),
+        max_concurrency
+    );
+    let responses = run_chat_batch(requests, max_concurrency, |item| {
+        let state = state.clone();
+        let headers = headers.clone();
+        async move {
+            handle_chat_completions(State(state), headers, Json(item))
+                .await
+                .into_response()
+        }
+    })
+    .await;
+    Json(json!({ "object": "batch", "responses": responses })).into_response()
+}
 
-                        let name = if item_type == "local_shell_call" {
-                            "shell"
-                        } else if item_type == "web_search_call" {
-                            "google_search"
-                        } else {
-                            item.get("name")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("unknown")
-                        };
+fn batch_error(status: StatusCode, message: String) -> Response {
+    (
+        status,
+        Json(json!({
+            "error": {
+                "message": message,
+                "type": "invalid_request_error",
+                "code": "invalid_batch"
+            }
+        })),
+    )
+        .into_response()
+}
 
-                        call_id_to_name.insert(call_id.to_string(), name.to_string());
-                        tracing::debug!("Mapped call_id {} to name {}", call_id, name);
-                    }
-                    _ => {}
-                }
+/// 并发执行批量请求 (max_concurrency 为 0 时不限制)，结果顺序与输入一致
+async fn run_chat_batch<F, Fut>(
+    requests: Vec<Value>,
+    max_concurrency: usize,
+    handler: F,
+) -> Vec<Value>
+where
+    F: Fn(Value) -> Fut,
+    Fut: std::future::Future<Output = Response>,
+{
+    use futures::StreamExt;
+
+    let concurrency = match max_concurrency {
+        0 => requests.len().max(1),
+        n => n,
+    };
+    let handler = &handler;
+    futures::stream::iter(requests)
+        .map(|mut item| async move {
+            // 批量结果以 JSON 返回，逐项强制非流式
+            if let Some(obj) = item.as_object_mut() {
+                obj.insert("stream".to_string(), Value::Bool(false));
             }
+            batch_item_result(handler(item).await).await
+        })
+        .buffered(concurrency)
+        .collect()
+        .await
+}
+
+/// 单项响应转换为批量结果: 成功时为 chat.completion 对象，失败时为带 status 的 error 对象
+async fn batch_item_result(response: Response) -> Value {
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let parsed = serde_json::from_slice::<Value>(&body).ok();
+    if status.is_success() {
+        if let Some(value) = parsed {
+            return value;
         }
+    }
+    let mut error = parsed
+        .and_then(|v| v.get("error").filter(|e| e.is_object()).cloned())
+        .unwrap_or_else(|| {
+            json!({
+                "message": String::from_utf8_lossy(&body),
+                "type": "upstream_error"
+            })
+        });
+    error["status"] = json!(status.as_u16());
+    json!({ "error": error })
+}
 
-        // Pass 2: Map Input Items to Messages
-        if let Some(items) = input_items {
-            for item in items {
-                let item_type = item.get("type").and_then(|v| v.as_str()).unwrap_or("");
-                match item_type {
-                    "message" => {
-                        let role = item.get("role").and_then(|v| v.as_str()).unwrap_or("user");
-                        let content = item.get("content").and_then(|v| v.as_array());
-                        let mut text_parts = Vec::new();
-                        let mut image_parts: Vec<Value> = Vec::new();
-
-                        if let Some(parts) = content {
-                            for part in parts {
-                                // 处理文本块
-                                if let Some(text) = part.get("text").and_then(|v| v.as_str()) {
-                                    text_parts.push(text.to_string());
-                                }
-                                // [NEW] 处理图像块 (Codex input_image 格式)
-                                else if part.get("type").and_then(|v| v.as_str())
-                                    == Some("input_image")
-                                {
-                                    if let Some(image_url) =
-                                        part.get("image_url").and_then(|v| v.as_str())
-                                    {
-                                        image_parts.push(json!({
-                                            "type": "image_url",
-                                            "image_url": { "url": image_url }
-                                        }));
-                                        debug!("[Codex] Found input_image: {}", image_url);
-                                    }
-                                }
-                                // [NEW] 兼容标准 OpenAI image_url 格式
-                                else if part.get("type").and_then(|v| v.as_str())
-                                    == Some("image_url")
+/// 统计输入 token 数 (POST /v1/tokenize)
+/// 接受 Chat Completions 格式请求体，经 transform_openai_request 转换后调用上游 countTokens
+pub async fn handle_count_tokens(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Response, (StatusCode, String)> {
+    let original_body = body.clone();
+    let mut openai_req = parse_openai_request(body).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    // 仅统计 token，不产生生成用量: 计入请求数，不计 token
+    if let Some(limited) = end_user_rejection(&state, openai_req.user.as_deref(), 0).await {
+        return Ok(limited);
+    }
+    // 远程图片 / 文档与生成请求一致地内联后计数
+    inline_request_images(&state, &mut openai_req).await?;
+
+    let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+        &openai_req.model,
+        &*state.custom_mapping.read().await,
+    );
+    let tools_val: Option<Vec<Value>> = openai_req.tools.clone();
+    let config = crate::proxy::mappers::common_utils::resolve_request_config(
+        &openai_req.model,
+        &mapped_model,
+        &tools_val,
+        None,
+        None,
+        Some(&original_body),
+    );
+
+    // 与 chat 相同的选号逻辑: 账号覆盖 / 限流快速失败 / 会话粘性 / 工作负载 / 冷却
+    let token_manager = state.token_manager.clone();
+    ensure_account_pool_not_empty(&token_manager)?;
+    let account_override = {
+        let security = state.security.read().await;
+        resolve_account_override(&headers, &security, &token_manager)?
+    };
+    if let Some(resp) = pool_outage_response(&token_manager, account_override.as_deref()).await {
+        return Ok(resp);
+    }
+    let session_id = SessionManager::extract_openai_session_id(&openai_req);
+    let (access_token, project_id, email, account_id, _wait_ms) = token_manager
+        .get_token_with_override(
+            account_override.as_deref(),
+            &config.workload,
+            false,
+            Some(&session_id),
+            &mapped_model,
+        )
+        .await
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)))?;
+
+    let (gemini_body, _session_id, _message_count) =
+        transform_openai_request(&openai_req, &project_id, &mapped_model);
+    let count_body = build_count_tokens_body(&gemini_body, &config.final_model);
+
+    let call_result = state
+        .upstream
+        .call_v1_internal("countTokens", &access_token, count_body, None, Some(account_id.as_str()))
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Upstream error: {}", e)))?;
+    let response = call_result.response;
+    let status = response.status();
+    if !status.is_success() {
+        let status_code = status.as_u16();
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string());
+        let error_text = response.text().await.unwrap_or_default();
+        // 记录冷却，后续请求 (含同会话) 不再选中该账号
+        if status_code == 429 || status_code == 529 || status_code == 503 || status_code == 500 {
+            token_manager
+                .mark_rate_limited_async(
+                    &email,
+                    status_code,
+                    retry_after.as_deref(),
+                    &error_text,
+                    Some(&mapped_model),
+                )
+                .await;
+            state.metrics.record_rate_limited("openai");
+        }
+        return Ok((
+            status,
+            [("X-Account-Email", email.as_str()), ("X-Mapped-Model", mapped_model.as_str())],
+            error_text,
+        )
+            .into_response());
+    }
+
+    let upstream_resp: Value = response
+        .json()
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)))?;
+    let input_tokens = parse_count_tokens_response(&upstream_resp).ok_or((
+        StatusCode::BAD_GATEWAY,
+        format!("Unexpected countTokens response: {}", upstream_resp),
+    ))?;
+    debug!("[OpenAI] countTokens for {}: {}", mapped_model, input_tokens);
+
+    Ok((
+        StatusCode::OK,
+        [("X-Account-Email", email.as_str()), ("X-Mapped-Model", mapped_model.as_str())],
+        Json(json!({ "model": openai_req.model, "input_tokens": input_tokens })),
+    )
+        .into_response())
+}
+
+/// 由 generateContent 请求体构造 v1internal:countTokens 请求体
+/// 系统提示词与工具声明同样计入输入 token，存在时一并转发
+fn build_count_tokens_body(gemini_body: &Value, model: &str) -> Value {
+    let mut request = json!({
+        "model": format!("models/{}", model),
+        "contents": gemini_body["request"]["contents"].clone(),
+    });
+    for key in ["systemInstruction", "tools", "toolConfig"] {
+        if let Some(value) = gemini_body["request"].get(key).filter(|v| !v.is_null()) {
+            request[key] = value.clone();
+        }
+    }
+    json!({ "request": request })
+}
+
+fn parse_count_tokens_response(resp: &Value) -> Option<u64> {
+    resp.get("response")
+        .unwrap_or(resp)
+        .get("totalTokens")
+        .and_then(|v| v.as_u64())
+}
+
+/// 调试: 返回 OpenAI 请求转换后的 Gemini 请求体 (POST /v1/debug/transform，需管理员鉴权)
+/// 与 handle_chat_completions 使用相同的规范化与路由逻辑，但不获取账号、不请求上游
+pub async fn handle_debug_transform(
+    State(state): State<AppState>,
+    Json(body): Json<Value>,
+) -> Result<Response, (StatusCode, String)> {
+    let mut openai_req = normalize_chat_request(body)?;
+    inline_request_images(&state, &mut openai_req).await?;
+
+    let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+        &openai_req.model,
+        &*state.custom_mapping.read().await,
+    );
+    Ok(Json(build_debug_transform(&openai_req, &mapped_model)).into_response())
+}
+
+fn build_debug_transform(openai_req: &OpenAIRequest, mapped_model: &str) -> Value {
+    let config = crate::proxy::mappers::common_utils::resolve_request_config(
+        &openai_req.model,
+        mapped_model,
+        &openai_req.tools,
+        None,
+        None,
+        None,
+    );
+    // project_id 在真实请求中来自所选账号，这里使用占位值
+    let (gemini_body, session_id, message_count) =
+        transform_openai_request(openai_req, "dry-run", mapped_model);
+
+    json!({
+        "original_model": openai_req.model,
+        "mapped_model": mapped_model,
+        "request_config": {
+            "request_type": config.request_type,
+            "final_model": config.final_model,
+            "inject_google_search": config.inject_google_search,
+            "image_config": config.image_config,
+        },
+        "session_id": session_id,
+        "message_count": message_count,
+        "gemini_body": gemini_body,
+    })
+}
+
+/// [NEW] 回显测试模式使用的模型名
+pub const ECHO_TEST_MODEL: &str = "echo-test";
+// 每个流式分片携带的回显文本字符数
+const ECHO_STREAM_CHUNK_CHARS: usize = 64;
+
+/// 回显测试模式: 仅对专用模型名 echo-test 生效，真实模型的请求总是走上游
+fn is_echo_mode(model: &str) -> bool {
+    model == ECHO_TEST_MODEL
+}
+
+/// 回显测试模式: 不获取账号、不请求上游，以转换后的请求构造确定性的合成 Gemini 响应，
+/// 再经过与真实请求相同的流式 / 非流式转换返回，供下游项目在无账号的 CI 中测试
+fn echo_response(openai_req: &OpenAIRequest, mapped_model: &str) -> Response {
+    let transformed = build_debug_transform(openai_req, mapped_model);
+    // systemInstruction 含全局注入的系统提示词等服务端配置，不回显给调用方
+    let mut request = transformed["gemini_body"]["request"].clone();
+    if let Some(obj) = request.as_object_mut() {
+        obj.remove("systemInstruction");
+    }
+    let text = serde_json::to_string(&json!({
+        "mapped_model": transformed["mapped_model"],
+        "request_type": transformed["request_config"]["request_type"],
+        "request": request,
+    }))
+    .unwrap_or_default();
+    let session_id = transformed["session_id"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let message_count = transformed["message_count"].as_u64().unwrap_or(0) as usize;
+    let usage = json!({ "promptTokenCount": 0, "candidatesTokenCount": 0, "totalTokenCount": 0 });
+
+    if openai_req.stream {
+        // 按固定长度切分为多个 SSE 分片，最后一片携带 finishReason
+        let chars: Vec<char> = text.chars().collect();
+        let chunks: Vec<String> = chars
+            .chunks(ECHO_STREAM_CHUNK_CHARS)
+            .map(|c| c.iter().collect())
+            .collect();
+        let last = chunks.len().saturating_sub(1);
+        let events: Vec<Result<Bytes, reqwest::Error>> = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut candidate =
+                    json!({ "content": { "role": "model", "parts": [{ "text": chunk }] } });
+                let mut event = json!({ "modelVersion": mapped_model });
+                if i == last {
+                    candidate["finishReason"] = json!("STOP");
+                    event["usageMetadata"] = usage.clone();
+                }
+                event["candidates"] = json!([candidate]);
+                Ok(Bytes::from(format!("data: {}\n\n", event)))
+            })
+            .collect();
+        let stream = crate::proxy::mappers::openai::streaming::create_openai_sse_stream(
+            Box::pin(futures::stream::iter(events)),
+            openai_req.model.clone(),
+            session_id,
+            message_count,
+        );
+        return Response::builder()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .header("X-Mapped-Model", mapped_model)
+            .header("X-Echo-Mode", "true")
+            .body(axum::body::Body::from_stream(stream))
+            .unwrap()
+            .into_response();
+    }
+
+    let gemini_resp = json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{ "text": text }] },
+            "finishReason": "STOP"
+        }],
+        "usageMetadata": usage,
+        "modelVersion": mapped_model,
+    });
+    let mut openai_resp = transform_openai_response(&gemini_resp, Some(&session_id), message_count);
+    openai_resp.model = openai_req.model.clone();
+    (
+        StatusCode::OK,
+        [("X-Mapped-Model", mapped_model), ("X-Echo-Mode", "true")],
+        Json(openai_resp),
+    )
+        .into_response()
+}
+
+/// 读取以 store: true 保存的 Responses API 响应 (GET /v1/responses/{id})
+/// 仅创建该响应的 API Key 可读取，其他 Key 一律返回 404
+pub async fn handle_get_response(Path(response_id): Path<String>, headers: HeaderMap) -> Response {
+    match ResponseStore::global().get(&response_id, &key_owner(&headers)) {
+        Some(resp) => Json(resp).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": {
+                    "message": format!("No response found with id '{}'", response_id),
+                    "type": "invalid_request_error",
+                    "code": "not_found"
+                }
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Responses API 的 instructions + input 转换为 Chat Completions messages
+/// input 中的 system / developer 消息保留原角色，与 instructions 一起由 transform_openai_request 合并为 systemInstruction
+fn responses_input_to_messages(body: &Value) -> Vec<Value> {
+    let instructions = body
+        .get("instructions")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let input_items = body.get("input").and_then(|v| v.as_array());
+
+    let mut messages = Vec::new();
+
+    // System Instructions
+    if !instructions.is_empty() {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    // [FIX] input 为纯字符串时作为单条 user 消息
+    if let Some(text) = body.get("input").and_then(|v| v.as_str()) {
+        messages.push(json!({ "role": "user", "content": text }));
+    }
+
+    let mut call_id_to_name = std::collections::HashMap::new();
+
+    // Pass 1: Build Call ID to Name Map
+    if let Some(items) = input_items {
+        for item in items {
+            let item_type = item.get("type").and_then(|v| v.as_str()).unwrap_or("");
+            match item_type {
+                "function_call" | "local_shell_call" | "web_search_call" => {
+                    let call_id = item
+                        .get("call_id")
+                        .and_then(|v| v.as_str())
+                        .or_else(|| item.get("id").and_then(|v| v.as_str()))
+                        .unwrap_or("unknown");
+
+                    let name = if item_type == "local_shell_call" {
+                        "shell"
+                    } else if item_type == "web_search_call" {
+                        "google_search"
+                    } else {
+                        item.get("name")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("unknown")
+                    };
+
+                    call_id_to_name.insert(call_id.to_string(), name.to_string());
+                    tracing::debug!("Mapped call_id {} to name {}", call_id, name);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Pass 2: Map Input Items to Messages
+    if let Some(items) = input_items {
+        for item in items {
+            // [FIX] 简写消息 { role, content } 可省略 type
+            let item_type = match item.get("type").and_then(|v| v.as_str()) {
+                Some(t) => t,
+                None if item.get("role").is_some() => "message",
+                None => "",
+            };
+            match item_type {
+                "message" => {
+                    let role = item.get("role").and_then(|v| v.as_str()).unwrap_or("user");
+                    let content = item.get("content").and_then(|v| v.as_array());
+                    let mut text_parts = Vec::new();
+                    let mut image_parts: Vec<Value> = Vec::new();
+                    // [FIX] content 为纯字符串
+                    if let Some(text) = item.get("content").and_then(|v| v.as_str()) {
+                        text_parts.push(text.to_string());
+                    }
+
+                    if let Some(parts) = content {
+                        for part in parts {
+                            // 处理文本块
+                            if let Some(text) = part.get("text").and_then(|v| v.as_str()) {
+                                text_parts.push(text.to_string());
+                            }
+                            // [NEW] 处理图像块 (Codex input_image 格式)
+                            else if part.get("type").and_then(|v| v.as_str())
+                                == Some("input_image")
+                            {
+                                if let Some(image_url) =
+                                    part.get("image_url").and_then(|v| v.as_str())
                                 {
-                                    if let Some(url_obj) = part.get("image_url") {
-                                        image_parts.push(json!({
-                                            "type": "image_url",
-                                            "image_url": url_obj.clone()
-                                        }));
-                                    }
+                                    image_parts.push(json!({
+                                        "type": "image_url",
+                                        "image_url": { "url": image_url }
+                                    }));
+                                    debug!("[Codex] Found input_image: {}", image_url);
                                 }
                             }
+                            // [NEW] 兼容标准 OpenAI image_url 格式
+                            else if part.get("type").and_then(|v| v.as_str())
+                                == Some("image_url")
+                            {
+                                if let Some(url_obj) = part.get("image_url") {
+                                    image_parts.push(json!({
+                                        "type": "image_url",
+                                        "image_url": url_obj.clone()
+                                    }));
+                                }
+                            }
+                            // [NEW] 文档块 (input_file: file_data / file_url 平铺) 与 Chat file 块
+                            else if part.get("type").and_then(|v| v.as_str())
+                                == Some("input_file")
+                            {
+                                image_parts.push(crate::proxy::mappers::openai::document_input::input_file_to_chat_block(part));
+                            } else if part.get("type").and_then(|v| v.as_str()) == Some("file") {
+                                image_parts.push(part.clone());
+                            }
                         }
+                    }
 
-                        // 构造消息内容：如果有图像则使用数组格式
-                        if image_parts.is_empty() {
-                            messages.push(json!({
-                                "role": role,
-                                "content": text_parts.join("\n")
-                            }));
-                        } else {
-                            let mut content_blocks: Vec<Value> = Vec::new();
-                            if !text_parts.is_empty() {
-                                content_blocks.push(json!({
-                                    "type": "text",
-                                    "text": text_parts.join("\n")
-                                }));
-                            }
-                            content_blocks.extend(image_parts);
-                            messages.push(json!({
-                                "role": role,
-                                "content": content_blocks
+                    // 构造消息内容：如果有图像则使用数组格式
+                    if image_parts.is_empty() {
+                        messages.push(json!({
+                            "role": role,
+                            "content": text_parts.join("\n")
+                        }));
+                    } else {
+                        let mut content_blocks: Vec<Value> = Vec::new();
+                        if !text_parts.is_empty() {
+                            content_blocks.push(json!({
+                                "type": "text",
+                                "text": text_parts.join("\n")
                             }));
                         }
+                        content_blocks.extend(image_parts);
+                        messages.push(json!({
+                            "role": role,
+                            "content": content_blocks
+                        }));
                     }
-                    "function_call" | "local_shell_call" | "web_search_call" => {
-                        let mut name = item
-                            .get("name")
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("unknown");
-                        let mut args_str = item
-                            .get("arguments")
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("{}")
-                            .to_string();
-                        let call_id = item
-                            .get("call_id")
-                            .and_then(|v| v.as_str())
-                            .or_else(|| item.get("id").and_then(|v| v.as_str()))
-                            .unwrap_or("unknown");
-
-                        // Handle native shell calls
-                        if item_type == "local_shell_call" {
-                            name = "shell";
-                            if let Some(action) = item.get("action") {
-                                if let Some(exec) = action.get("exec") {
-                                    // Map to ShellCommandToolCallParams (string command) or ShellToolCallParams (array command)
-                                    // Most LLMs prefer a single string for shell
-                                    let mut args_obj = serde_json::Map::new();
-                                    if let Some(cmd) = exec.get("command") {
-                                        // CRITICAL FIX: The 'shell' tool schema defines 'command' as an ARRAY of strings.
-                                        // We MUST pass it as an array, not a joined string, otherwise Gemini rejects with 400 INVALID_ARGUMENT.
-                                        let cmd_val = if cmd.is_string() {
-                                            json!([cmd]) // Wrap in array
-                                        } else {
-                                            cmd.clone() // Assume already array
-                                        };
-                                        args_obj.insert("command".to_string(), cmd_val);
-                                    }
-                                    if let Some(wd) =
-                                        exec.get("working_directory").or(exec.get("workdir"))
-                                    {
-                                        args_obj.insert("workdir".to_string(), wd.clone());
-                                    }
-                                    args_str = serde_json::to_string(&args_obj)
-                                        .unwrap_or("{}".to_string());
-                                }
-                            }
-                        } else if item_type == "web_search_call" {
-                            name = "google_search";
-                            if let Some(action) = item.get("action") {
+                }
+                "function_call" | "local_shell_call" | "web_search_call" => {
+                    let mut name = item
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("unknown");
+                    let mut args_str = item
+                        .get("arguments")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("{}")
+                        .to_string();
+                    let call_id = item
+                        .get("call_id")
+                        .and_then(|v| v.as_str())
+                        .or_else(|| item.get("id").and_then(|v| v.as_str()))
+                        .unwrap_or("unknown");
+
+                    // Handle native shell calls
+                    if item_type == "local_shell_call" {
+                        name = "shell";
+                        if let Some(action) = item.get("action") {
+                            if let Some(exec) = action.get("exec") {
+                                // Map to ShellCommandToolCallParams (string command) or ShellToolCallParams (array command)
+                                // Most LLMs prefer a single string for shell
                                 let mut args_obj = serde_json::Map::new();
-                                if let Some(q) = action.get("query") {
-                                    args_obj.insert("query".to_string(), q.clone());
+                                if let Some(cmd) = exec.get("command") {
+                                    // CRITICAL FIX: The 'shell' tool schema defines 'command' as an ARRAY of strings.
+                                    // We MUST pass it as an array, not a joined string, otherwise Gemini rejects with 400 INVALID_ARGUMENT.
+                                    let cmd_val = if cmd.is_string() {
+                                        json!([cmd]) // Wrap in array
+                                    } else {
+                                        cmd.clone() // Assume already array
+                                    };
+                                    args_obj.insert("command".to_string(), cmd_val);
+                                }
+                                if let Some(wd) =
+                                    exec.get("working_directory").or(exec.get("workdir"))
+                                {
+                                    args_obj.insert("workdir".to_string(), wd.clone());
                                 }
-                                args_str =
-                                    serde_json::to_string(&args_obj).unwrap_or("{}".to_string());
+                                args_str = serde_json::to_string(&args_obj)
+                                    .unwrap_or("{}".to_string());
+                            }
+                        }
+                    } else if item_type == "web_search_call" {
+                        name = "google_search";
+                        if let Some(action) = item.get("action") {
+                            let mut args_obj = serde_json::Map::new();
+                            if let Some(q) = action.get("query") {
+                                args_obj.insert("query".to_string(), q.clone());
                             }
+                            args_str =
+                                serde_json::to_string(&args_obj).unwrap_or("{}".to_string());
                         }
+                    }
 
+                    messages.push(json!({
+                        "role": "assistant",
+                        "tool_calls": [
+                            {
+                                "id": call_id,
+                                "type": "function",
+                                "function": {
+                                    "name": name,
+                                    "arguments": args_str
+                                }
+                            }
+                        ]
+                    }));
+                }
+                // [NEW] 之前轮次生成的图像 (image_generation_call) 回传为助手图像消息
+                "image_generation_call" => {
+                    if let Some(result) = item.get("result").and_then(|v| v.as_str()) {
+                        let format = item
+                            .get("output_format")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("png");
                         messages.push(json!({
                             "role": "assistant",
-                            "tool_calls": [
-                                {
-                                    "id": call_id,
-                                    "type": "function",
-                                    "function": {
-                                        "name": name,
-                                        "arguments": args_str
-                                    }
-                                }
-                            ]
+                            "content": [{
+                                "type": "image_url",
+                                "image_url": { "url": format!("data:image/{};base64,{}", format, result) }
+                            }]
                         }));
                     }
-                    "function_call_output" | "custom_tool_call_output" => {
-                        let call_id = item
-                            .get("call_id")
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("unknown");
-                        let output = item.get("output");
-                        let output_str = if let Some(o) = output {
-                            if o.is_string() {
-                                o.as_str().unwrap().to_string()
-                            } else if let Some(content) = o.get("content").and_then(|v| v.as_str())
-                            {
-                                content.to_string()
-                            } else {
-                                o.to_string()
-                            }
+                }
+                "function_call_output" | "custom_tool_call_output" => {
+                    let call_id = item
+                        .get("call_id")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("unknown");
+                    let output = item.get("output");
+                    let output_str = if let Some(o) = output {
+                        if o.is_string() {
+                            o.as_str().unwrap().to_string()
+                        } else if let Some(content) = o.get("content").and_then(|v| v.as_str())
+                        {
+                            content.to_string()
                         } else {
-                            "".to_string()
-                        };
+                            o.to_string()
+                        }
+                    } else {
+                        "".to_string()
+                    };
 
-                        let name = call_id_to_name.get(call_id).cloned().unwrap_or_else(|| {
+                    let name = call_id_to_name
+                        .get(call_id)
+                        .cloned()
+                        // [NEW] 输入中缺少对应的 function_call 时，按代理此前发出的 call_id 解析
+                        .or_else(|| resolve_tool_call_name(call_id))
+                        .unwrap_or_else(|| {
                             // Fallback: if unknown and we see function_call_output, it's likely "shell" in this context
                             tracing::warn!(
                                 "Unknown tool name for call_id {}, defaulting to 'shell'",
@@ -960,18 +2054,51 @@ pub async fn handle_completions(
                             "shell".to_string()
                         });
 
-                        messages.push(json!({
-                            "role": "tool",
-                            "tool_call_id": call_id,
-                            "name": name,
-                            "content": output_str
-                        }));
-                    }
-                    _ => {}
+                    messages.push(json!({
+                        "role": "tool",
+                        "tool_call_id": call_id,
+                        "name": name,
+                        "content": output_str
+                    }));
                 }
+                _ => {}
             }
         }
+    }
+
+    messages
+}
+
+/// 处理 Legacy Completions API (/v1/completions)
+/// 将 Prompt 转换为 Chat Message 格式，复用 handle_chat_completions
+pub async fn handle_completions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut body): Json<Value>,
+) -> Response {
+    debug!(
+        "Received /v1/completions or /v1/responses payload: {:?}",
+        body
+    );
+
+    let compat = get_openai_compat_config();
+    if let Err(e) = check_logit_bias(&body, compat.logit_bias) {
+        return e.into_response();
+    }
+
+    let is_codex_style = body.get("input").is_some() || body.get("instructions").is_some();
+
+    // Responses API: store / metadata 不参与转换，仅回写到最终响应对象
+    let store_options = ResponseStoreOptions::from_body(&body, key_owner(&headers));
+    if is_codex_style && store_options.store {
+        info!("[Codex] store=true, final response will be kept for GET /v1/responses/{{id}}");
+    }
+    // [NEW] Responses API: reasoning.effort -> thinking 预算，reasoning.summary 控制是否返回推理摘要
+    let reasoning_summary = is_codex_style && apply_responses_reasoning(&mut body);
 
+    // 1. Convert Payload to Messages (Shared Chat Format)
+    if is_codex_style {
+        let messages = responses_input_to_messages(&body);
         if let Some(obj) = body.as_object_mut() {
             obj.insert("messages".to_string(), json!(messages));
         }
@@ -1092,12 +2219,23 @@ pub async fn handle_completions(
         );
     }
 
-    let mut openai_req: OpenAIRequest = match serde_json::from_value(body.clone()) {
+    let mut openai_req: OpenAIRequest = match parse_openai_request(body.clone()) {
         Ok(req) => req,
         Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)).into_response();
+            return (StatusCode::BAD_REQUEST, e).into_response();
         }
     };
+    if let Err(e) = check_audio_output(&openai_req, compat.audio_output) {
+        return e.into_response();
+    }
+    // [NEW] 在 Codex / Responses 输入规范化为 messages 之后检查
+    if let Some(rejected) = prompt_filter_rejection(&state, &openai_req).await {
+        return rejected;
+    }
+    let tokens = crate::proxy::user_usage::estimate_request_tokens(&openai_req);
+    if let Some(limited) = end_user_rejection(&state, openai_req.user.as_deref(), tokens).await {
+        return limited;
+    }
 
     // Safety: Inject empty message if needed
     if openai_req.messages.is_empty() {
@@ -1112,26 +2250,50 @@ pub async fn handle_completions(
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                annotations: None,
             });
     }
 
+    if let Err(e) = inline_request_images(&state, &mut openai_req).await {
+        return e.into_response();
+    }
+
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
+    if let Err(e) = ensure_account_pool_not_empty(&token_manager) {
+        return e.into_response();
+    }
+    let account_override = {
+        let security = state.security.read().await;
+        match resolve_account_override(&headers, &security, &token_manager) {
+            Ok(v) => v,
+            Err(e) => return e.into_response(),
+        }
+    };
+    // [NEW] 账号池全部限流时快速失败
+    if let Some(resp) = pool_outage_response(&token_manager, account_override.as_deref()).await {
+        return resp;
+    }
+    let force_stream_default = state.experimental.read().await.force_stream_internally;
+    let max_collected_bytes = state.experimental.read().await.max_collected_bytes;
+    let streaming_config = state.streaming.read().await.clone();
     let pool_size = token_manager.len();
     // [FIX] Ensure max_attempts is at least 2 to allow for internal retries
     let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size.saturating_add(1)).max(2);
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    let mut attempts: Vec<AccountAttempt> = Vec::new();
 
     // 2. 模型路由解析 (移到循环外以支持在所有路径返回 X-Mapped-Model)
     let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
         &openai_req.model,
         &*state.custom_mapping.read().await,
     );
-    let trace_id = format!("req_{}", chrono::Utc::now().timestamp_subsec_millis());
+    let trace_id = request_id(&headers);
 
-    for attempt in 0..max_attempts {
+    let mut attempt_budget = AttemptBudget::new(max_attempts);
+    while let Some(attempt) = attempt_budget.next() {
         // 3. 模型配置解析
         // 将 OpenAI 工具转为 Value 数组以便探测联网
         let tools_val: Option<Vec<Value>> = openai_req
@@ -1144,9 +2306,12 @@ pub async fn handle_completions(
             &tools_val,
             None, // size
             None, // quality
-            None, // OpenAI handler uses transform_openai_request for image gen
+            Some(&body), // [NEW] 用于工作负载分类 (Codex 风格输入 / 大段文件上下文)
         );
 
+        // [NEW] 按模型超时 (peek / 单次请求总时限 / 非流式收集)
+        let timeouts = resolve_model_timeouts(&openai_req.model, &mapped_model, Duration::from_secs(60));
+
         // 3. 提取 SessionId (复用)
         // [New] 使用 TokenManager 内部逻辑提取 session_id，支持粘性调度
         let session_id_str = SessionManager::extract_openai_session_id(&openai_req);
@@ -1156,8 +2321,9 @@ pub async fn handle_completions(
         let force_rotate = attempt > 0;
 
         let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
-            .get_token(
-                &config.request_type,
+            .get_token_with_override(
+                account_override.as_deref(),
+                &config.workload,
                 force_rotate,
                 session_id,
                 &mapped_model,
@@ -1175,12 +2341,30 @@ pub async fn handle_completions(
             }
         };
 
+        state
+            .metrics
+            .record_attempt("openai", attempt, last_email.as_deref(), &email);
         last_email = Some(email.clone());
+        record_attempt(attempt + 1, &email, &mapped_model);
 
-        info!("✓ Using account: {} (type: {})", email, config.request_type);
+        info!(
+            "✓ Using account: {} (type: {}, workload: {})",
+            email, config.request_type, config.workload
+        );
 
-        let (gemini_body, session_id, message_count) =
-            transform_openai_request(&openai_req, &project_id, &mapped_model);
+        let uploaded_req = upload_request_media(
+            &state.upstream,
+            &state.experimental,
+            &access_token,
+            &account_id,
+            &openai_req,
+        )
+        .await;
+        let (gemini_body, session_id, message_count) = transform_openai_request(
+            uploaded_req.as_ref().unwrap_or(&openai_req),
+            &project_id,
+            &mapped_model,
+        );
 
         // [New] 打印转换后的报文 (Gemini Body) 供调试 (Codex 路径) ———— 缩减为 simple debug
         debug!(
@@ -1194,7 +2378,8 @@ pub async fn handle_completions(
 
         // [AUTO-CONVERSION] For Legacy/Codex as well
         let client_wants_stream = openai_req.stream;
-        let force_stream_internally = !client_wants_stream;
+        let force_stream_internally =
+            resolve_force_stream(&headers, client_wants_stream, force_stream_default);
         let list_response = client_wants_stream || force_stream_internally;
         let method = if list_response {
             "streamGenerateContent"
@@ -1203,19 +2388,24 @@ pub async fn handle_completions(
         };
         let query_string = if list_response { Some("alt=sse") } else { None };
 
-        let call_result = match upstream
-            .call_v1_internal(
+        let upstream_started = std::time::Instant::now(); // 首字延迟起点
+        let call_result = match with_optional_timeout(
+            timeouts.total,
+            "Upstream request",
+            upstream.call_v1_internal(
                 method,
                 &access_token,
                 gemini_body,
                 query_string,
                 Some(account_id.as_str()),
-            )
-            .await
+            ),
+        )
+        .await
         {
             Ok(r) => r,
             Err(e) => {
                 last_error = e.clone();
+                attempts.push(AccountAttempt::new(&email, &mapped_model, None, &last_error));
                 debug!(
                     "Codex Request failed on attempt {}/{}: {}",
                     attempt + 1,
@@ -1231,6 +2421,7 @@ pub async fn handle_completions(
         if status.is_success() {
             // [智能限流] 请求成功，重置该账号的连续失败计数
             token_manager.mark_account_success(&email);
+            token_manager.record_model_success(&email, &mapped_model);
 
             if list_response {
                 use axum::body::Body;
@@ -1252,6 +2443,8 @@ pub async fn handle_completions(
                             openai_req.model.clone(),
                             session_id,
                             message_count,
+                            store_options.clone(),
+                            reasoning_summary,
                         )
                     } else {
                         use crate::proxy::mappers::openai::streaming::create_legacy_sse_stream;
@@ -1264,16 +2457,19 @@ pub async fn handle_completions(
                     };
 
                     // [P1 FIX] Enhanced Peek logic (Reused from above/standard)
+                    // streaming.peek_heartbeat_ms 与 chat 相同: 超过该时长仍无首个数据块即开始响应并发送心跳
+                    let heartbeat_after =
+                        peek_heartbeat_interval(&streaming_config, true, timeouts.peek);
+                    let peek_started = std::time::Instant::now();
+                    let mut start_heartbeats = false;
                     let mut first_data_chunk = None;
                     let mut retry_this_account = false;
 
                     loop {
-                        match tokio::time::timeout(
-                            std::time::Duration::from_secs(60),
-                            openai_stream.next(),
-                        )
-                        .await
-                        {
+                        let wait = heartbeat_after.map_or(timeouts.peek, |after| {
+                            after.saturating_sub(peek_started.elapsed())
+                        });
+                        match tokio::time::timeout(wait, openai_stream.next()).await {
                             Ok(Some(Ok(bytes))) => {
                                 if bytes.is_empty() {
                                     continue;
@@ -1284,7 +2480,7 @@ pub async fn handle_completions(
                                 {
                                     continue;
                                 }
-                                if text.contains("\"error\"") {
+                                if is_sse_error_event(&bytes) {
                                     last_error = "Error event during peek".to_string();
                                     retry_this_account = true;
                                     break;
@@ -1302,8 +2498,13 @@ pub async fn handle_completions(
                                 retry_this_account = true;
                                 break;
                             }
+                            Err(_) if heartbeat_after.is_some() => {
+                                start_heartbeats = true;
+                                break;
+                            }
                             Err(_) => {
                                 last_error = "Timeout waiting for first data".to_string();
+                                state.metrics.record_peek_timeout("openai");
                                 retry_this_account = true;
                                 break;
                             }
@@ -1311,13 +2512,35 @@ pub async fn handle_completions(
                     }
 
                     if retry_this_account {
+                        attempts.push(AccountAttempt::new(&email, &mapped_model, None, &last_error));
                         continue;
                     }
 
-                    let combined_stream = futures::stream::once(async move {
-                        Ok::<Bytes, String>(first_data_chunk.unwrap())
-                    })
-                    .chain(openai_stream);
+                    let combined_stream = match (start_heartbeats, heartbeat_after) {
+                        (true, Some(heartbeat_interval)) => with_peek_heartbeats(
+                            openai_stream,
+                            heartbeat_interval,
+                            timeouts.peek.saturating_sub(peek_started.elapsed()),
+                            if is_codex_style {
+                                SseErrorFormat::Responses
+                            } else {
+                                SseErrorFormat::OpenAI
+                            },
+                            record_peek_outcome(
+                                &state.metrics,
+                                &token_manager,
+                                "openai",
+                                &email,
+                                upstream_started,
+                            ),
+                        ),
+                        _ => Box::pin(
+                            futures::stream::once(async move {
+                                Ok::<Bytes, String>(first_data_chunk.unwrap())
+                            })
+                            .chain(openai_stream),
+                        ),
+                    };
 
                     return Response::builder()
                         .header("Content-Type", "text/event-stream")
@@ -1325,7 +2548,10 @@ pub async fn handle_completions(
                         .header("Connection", "keep-alive")
                         .header("X-Account-Email", &email)
                         .header("X-Mapped-Model", &mapped_model)
-                        .body(Body::from_stream(combined_stream))
+                        .body(Body::from_stream(with_flush_threshold(
+                            combined_stream,
+                            &streaming_config,
+                        )))
                         .unwrap()
                         .into_response();
                 } else {
@@ -1345,10 +2571,7 @@ pub async fn handle_completions(
                     let mut first_data_chunk = None;
                     let mut retry_this_account = false;
                     loop {
-                        match tokio::time::timeout(
-                            std::time::Duration::from_secs(60),
-                            openai_stream.next(),
-                        )
+                        match tokio::time::timeout(timeouts.peek, openai_stream.next())
                         .await
                         {
                             Ok(Some(Ok(bytes))) => {
@@ -1361,7 +2584,7 @@ pub async fn handle_completions(
                                 {
                                     continue;
                                 }
-                                if text.contains("\"error\"") {
+                                if is_sse_error_event(&bytes) {
                                     last_error = "Error event in internal stream".to_string();
                                     retry_this_account = true;
                                     break;
@@ -1381,12 +2604,14 @@ pub async fn handle_completions(
                             }
                             Err(_) => {
                                 last_error = "Timeout peek internal".to_string();
+                                state.metrics.record_peek_timeout("openai");
                                 retry_this_account = true;
                                 break;
                             }
                         }
                     }
                     if retry_this_account {
+                        attempts.push(AccountAttempt::new(&email, &mapped_model, None, &last_error));
                         continue;
                     }
 
@@ -1397,22 +2622,22 @@ pub async fn handle_completions(
 
                     // Collect
                     use crate::proxy::mappers::openai::collector::collect_stream_to_json;
-                    match collect_stream_to_json(Box::pin(combined_stream)).await {
-                        Ok(chat_resp) => {
+                    match with_optional_timeout(
+                        timeouts.collector,
+                        "Stream collection",
+                        collect_stream_to_json(
+                            Box::pin(combined_stream),
+                            max_collected_bytes,
+                        ),
+                    )
+                    .await
+                    {
+                        Ok(mut chat_resp) => {
+                            echo_requested_model(&mut chat_resp, &openai_req.model);
                             // NOW: Convert Chat Response -> Legacy Response (Same logic as below)
-                            let choices = chat_resp.choices.iter().map(|c| {
-                                json!({
-                                    "text": match &c.message.content {
-                                        Some(crate::proxy::mappers::openai::OpenAIContent::String(s)) => s.clone(),
-                                        _ => "".to_string()
-                                    },
-                                    "index": c.index,
-                                    "logprobs": null,
-                                    "finish_reason": c.finish_reason
-                                })
-                            }).collect::<Vec<_>>();
-
-                            let legacy_resp = json!({
+                            let choices = legacy_completion_choices(&chat_resp);
+
+                            let mut legacy_resp = json!({
                                 "id": chat_resp.id,
                                 "object": "text_completion",
                                 "created": chat_resp.created,
@@ -1420,8 +2645,15 @@ pub async fn handle_completions(
                                 "choices": choices,
                                 "usage": chat_resp.usage
                             });
+                            if is_codex_style {
+                                finalize_codex_response(
+                                    &mut legacy_resp,
+                                    &chat_resp,
+                                    &store_options,
+                                );
+                            }
 
-                            return (
+                            let resp = (
                                 StatusCode::OK,
                                 [
                                     ("X-Account-Email", email.as_str()),
@@ -1430,6 +2662,9 @@ pub async fn handle_completions(
                                 Json(legacy_resp),
                             )
                                 .into_response();
+                            let missing =
+                                logprobs_missing(openai_req.logprobs_requested(), &chat_resp);
+                            return with_unsupported_params_header(resp, missing, &openai_req);
                         }
                         Err(e) => {
                             return (
@@ -1454,22 +2689,13 @@ pub async fn handle_completions(
                 }
             };
 
-            let chat_resp = transform_openai_response(&gemini_resp, Some("session-123"), 1);
+            let mut chat_resp = transform_openai_response(&gemini_resp, Some("session-123"), 1);
+            echo_requested_model(&mut chat_resp, &openai_req.model);
 
             // Map Chat Response -> Legacy Completions Response
-            let choices = chat_resp.choices.iter().map(|c| {
-                json!({
-                    "text": match &c.message.content {
-                        Some(crate::proxy::mappers::openai::OpenAIContent::String(s)) => s.clone(),
-                        _ => "".to_string()
-                    },
-                    "index": c.index,
-                    "logprobs": null,
-                    "finish_reason": c.finish_reason
-                })
-            }).collect::<Vec<_>>();
+            let choices = legacy_completion_choices(&chat_resp);
 
-            let legacy_resp = json!({
+            let mut legacy_resp = json!({
                 "id": chat_resp.id,
                 "object": "text_completion",
                 "created": chat_resp.created,
@@ -1477,8 +2703,11 @@ pub async fn handle_completions(
                 "choices": choices,
                 "usage": chat_resp.usage
             });
+            if is_codex_style {
+                finalize_codex_response(&mut legacy_resp, &chat_resp, &store_options);
+            }
 
-            return (
+            let resp = (
                 StatusCode::OK,
                 [
                     ("X-Account-Email", email.as_str()),
@@ -1487,6 +2716,8 @@ pub async fn handle_completions(
                 Json(legacy_resp),
             )
                 .into_response();
+            let missing = logprobs_missing(openai_req.logprobs_requested(), &chat_resp);
+            return with_unsupported_params_header(resp, missing, &openai_req);
         }
 
         // Handle errors and retry
@@ -1501,6 +2732,7 @@ pub async fn handle_completions(
             .await
             .unwrap_or_else(|_| format!("HTTP {}", status_code));
         last_error = format!("HTTP {}: {}", status_code, error_text);
+        attempts.push(AccountAttempt::new(&email, &mapped_model, Some(status_code), &error_text));
 
         tracing::error!(
             "[Codex-Upstream] Error Response {}: {}",
@@ -1508,6 +2740,15 @@ pub async fn handle_completions(
             error_text
         );
 
+        // [NEW] 模型不支持 CIVIC_INTEGRITY 安全类别: 记录后重试，重新转换时省略该类别
+        if is_civic_integrity_rejection(status_code, &error_text)
+            && supports_civic_integrity(&openai_req.model, &mapped_model)
+        {
+            mark_civic_integrity_unsupported(&mapped_model);
+            attempt_budget.retry_current();
+            continue;
+        }
+
         // 3. 标记限流状态(用于 UI 显示)
         if status_code == 429 || status_code == 529 || status_code == 503 || status_code == 500 {
             token_manager
@@ -1519,6 +2760,7 @@ pub async fn handle_completions(
                     Some(&mapped_model),
                 )
                 .await;
+            state.metrics.record_rate_limited("openai");
         }
 
         // 确定重试策略
@@ -1541,22 +2783,17 @@ pub async fn handle_completions(
         }
     }
 
-    // 所有尝试均失败
-    if let Some(email) = last_email {
-        (
-            StatusCode::TOO_MANY_REQUESTS,
-            [("X-Account-Email", email), ("X-Mapped-Model", mapped_model)],
-            format!("All accounts exhausted. Last error: {}", last_error),
-        )
-            .into_response()
-    } else {
-        (
-            StatusCode::TOO_MANY_REQUESTS,
-            [("X-Mapped-Model", mapped_model)],
-            format!("All accounts exhausted. Last error: {}", last_error),
-        )
-            .into_response()
-    }
+    // 所有尝试均失败 (调试模式下附带逐账号明细)
+    let detail = debug_logger::is_enabled(&*state.debug_logging.read().await)
+        .then(|| describe_attempts(&attempts, &token_manager));
+    let retry_after = exhausted_retry_after(&token_manager, Some(&mapped_model));
+    exhausted_response(
+        &last_error,
+        last_email,
+        Some(&mapped_model),
+        detail,
+        retry_after,
+    )
 }
 
 pub async fn handle_list_models(State(state): State<AppState>) -> impl IntoResponse {
@@ -1600,6 +2837,14 @@ pub async fn handle_images_generations(
         .unwrap_or("gemini-3-pro-image");
 
     let n = body.get("n").and_then(|v| v.as_u64()).unwrap_or(1) as usize;
+    let permits = image_fanout_permits(n, &crate::proxy::get_image_config())
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
+    let user = body.get("user").and_then(|v| v.as_str());
+    let tokens = crate::proxy::mappers::context_manager::estimate_tokens_from_str(prompt) as u64;
+    if let Some(limited) = end_user_rejection(&state, user, tokens).await {
+        return Ok(limited);
+    }
 
     let size = body
         .get("size")
@@ -1652,26 +2897,37 @@ pub async fn handle_images_generations(
     // 注意：不再在外部获取 Token，而是移入 Task 内部并在重试时获取
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager.clone();
+    ensure_account_pool_not_empty(&token_manager)?;
     let max_pool_size = token_manager.len();
     let max_attempts = MAX_RETRY_ATTEMPTS
         .min(max_pool_size.saturating_add(1))
         .max(2);
 
+    // [NEW] 按模型默认生成参数 (图像接口客户端无法指定采样参数)
+    let gen_defaults =
+        crate::proxy::config::resolve_generation_defaults(model, "gemini-3-pro-image");
+
     let mut tasks = Vec::new();
 
     for _ in 0..n {
         let upstream = upstream.clone();
         let token_manager = token_manager.clone();
+        let metrics = state.metrics.clone();
+        let permits = permits.clone();
         let final_prompt = final_prompt.clone();
         let image_config = image_config.clone(); // 使用解析后的完整配置
+        let gen_defaults = gen_defaults.clone();
         let _response_format = response_format.to_string();
 
         let model_to_use = "gemini-3-pro-image".to_string();
 
         tasks.push(tokio::spawn(async move {
+            // [NEW] 超出 max_concurrency 的任务在此排队，分批发往上游；每个任务独立取号以分散到不同账号
+            let _permit = permits.acquire_owned().await;
             let mut last_error = String::new();
 
-            for attempt in 0..max_attempts {
+            let mut attempt_budget = AttemptBudget::new(max_attempts);
+            while let Some(attempt) = attempt_budget.next() {
                 // 4.1 获取 Token
                 let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
                     .get_token("image_gen", attempt > 0, None, "dall-e-3")
@@ -1687,8 +2943,9 @@ pub async fn handle_images_generations(
                         break;
                     }
                 };
+                metrics.record_attempt("images", attempt, None, &email);
 
-                let gemini_body = json!({
+                let mut gemini_body = json!({
                     "project": project_id,
                     "requestId": format!("agent-{}", uuid::Uuid::new_v4()),
                     "model": model_to_use,
@@ -1703,15 +2960,13 @@ pub async fn handle_images_generations(
                             "candidateCount": 1, // 强制单张
                             "imageConfig": image_config // ✅ 使用完整配置（包含 aspectRatio 和 imageSize）
                         },
-                        "safetySettings": [
-                            { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "OFF" },
-                        ]
+                        "safetySettings": build_safety_settings(
+                            "OFF",
+                            supports_civic_integrity(&model_to_use, &model_to_use),
+                        )
                     }
                 });
+                gen_defaults.apply_to(&mut gemini_body["request"]["generationConfig"]);
 
                 match upstream
                     .call_v1_internal(
@@ -1731,6 +2986,15 @@ pub async fn handle_images_generations(
                             let status_code = status.as_u16();
                             last_error = format!("Upstream error {}: {}", status, err_text);
 
+                            // [NEW] 模型不支持 CIVIC_INTEGRITY 安全类别: 记录后重试 (下次构造请求体时省略)
+                            if is_civic_integrity_rejection(status_code, &err_text)
+                                && supports_civic_integrity(&model_to_use, &model_to_use)
+                            {
+                                mark_civic_integrity_unsupported(&model_to_use);
+                                attempt_budget.retry_current();
+                                continue;
+                            }
+
                             // 429/500/503 等错误进行标记和重试
                             if status_code == 429 || status_code == 503 || status_code == 500 {
                                 tracing::warn!(
@@ -1747,6 +3011,7 @@ pub async fn handle_images_generations(
                                         Some("dall-e-3"),
                                     )
                                     .await;
+                                metrics.record_rate_limited("images");
                                 continue; // Retry loop
                             }
 
@@ -1770,6 +3035,19 @@ pub async fn handle_images_generations(
         }));
     }
 
+    // [NEW] 生成结果后处理 (转码 / 缩放)，未开启时仅做校验与 mime 修正
+    let output_options = state
+        .experimental
+        .read()
+        .await
+        .enable_image_postprocess
+        .then(|| {
+            ImageOutputOptions::from_request(
+                body.get("output_format").and_then(|v| v.as_str()),
+                Some(size),
+            )
+        });
+
     // 5. 收集结果
     let mut images: Vec<Value> = Vec::new();
     let mut errors: Vec<String> = Vec::new();
@@ -1779,6 +3057,7 @@ pub async fn handle_images_generations(
         match task.await {
             Ok(result) => match result {
                 Ok((gemini_resp, email_used)) => {
+                    state.metrics.record_image_generation(true);
                     // Capture the email from the first successful task for logging
                     if used_email.is_none() {
                         used_email = Some(email_used);
@@ -1793,22 +3072,22 @@ pub async fn handle_images_generations(
                     {
                         for part in parts {
                             if let Some(img) = part.get("inlineData") {
-                                let data = img.get("data").and_then(|v| v.as_str()).unwrap_or("");
-                                if !data.is_empty() {
-                                    if response_format == "url" {
-                                        let mime_type = img
-                                            .get("mimeType")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("image/png");
-                                        images.push(json!({
-                                            "url": format!("data:{};base64,{}", mime_type, data)
-                                        }));
-                                    } else {
-                                        images.push(json!({
-                                            "b64_json": data
-                                        }));
+                                match image_response_item(
+                                    img,
+                                    response_format,
+                                    output_options.as_ref(),
+                                )
+                                .await
+                                {
+                                    Ok(Some(item)) => {
+                                        images.push(item);
+                                        tracing::debug!("[Images] Task {} succeeded", idx);
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => {
+                                        tracing::error!("[Images] Task {} returned invalid image: {}", idx, e);
+                                        errors.push(e);
                                     }
-                                    tracing::debug!("[Images] Task {} succeeded", idx);
                                 }
                             }
                         }
@@ -1816,12 +3095,14 @@ pub async fn handle_images_generations(
                 }
                 Err(e) => {
                     tracing::error!("[Images] Task {} failed: {}", idx, e);
+                    state.metrics.record_image_generation(false);
                     errors.push(e);
                 }
             },
             Err(e) => {
                 let err_msg = format!("Task join error: {}", e);
                 tracing::error!("[Images] Task {} join error: {}", idx, e);
+                state.metrics.record_image_generation(false);
                 errors.push(err_msg);
             }
         }
@@ -1881,6 +3162,59 @@ pub async fn handle_images_generations(
         .into_response())
 }
 
+/// [NEW] 校验图片数量 n，并返回本次请求内各生成任务共享的并发许可
+/// max_n / max_concurrency 为 0 时不限制
+fn image_fanout_permits(
+    n: usize,
+    config: &crate::proxy::config::ImageConfig,
+) -> Result<std::sync::Arc<tokio::sync::Semaphore>, String> {
+    if n == 0 {
+        return Err("'n' must be at least 1".to_string());
+    }
+    if config.max_n > 0 && n > config.max_n {
+        return Err(format!(
+            "'n' must be less than or equal to {} (got {})",
+            config.max_n, n
+        ));
+    }
+    let permits = match config.max_concurrency {
+        0 => n,
+        limit => limit.min(n),
+    };
+    Ok(std::sync::Arc::new(tokio::sync::Semaphore::new(permits)))
+}
+
+/// 检查参考图 (base64) 总大小是否超出限制，limit 为 0 时不限制
+fn check_reference_images_payload(reference_images: &[String], limit: usize) -> Result<(), String> {
+    let sizes: Vec<usize> = reference_images.iter().map(|r| r.len()).collect();
+    let total: usize = sizes.iter().sum();
+    if limit == 0 || total <= limit {
+        return Ok(());
+    }
+    let listed = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| format!("image{}={} bytes", i + 1, size))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(format!(
+        "Reference images payload too large: {} bytes total exceeds limit of {} bytes ({})",
+        total, limit, listed
+    ))
+}
+
+/// 构造图像编辑的 inlineData 片段，超出上游限制的图片先缩小
+async fn fitted_image_part(data: String, mime_type: &str, limits: &InputImageLimits) -> Value {
+    match fit_base64_image(&data, limits).await {
+        Some((data, fitted_mime)) => json!({
+            "inlineData": { "mimeType": fitted_mime, "data": data }
+        }),
+        None => json!({
+            "inlineData": { "mimeType": mime_type, "data": data }
+        }),
+    }
+}
+
 pub async fn handle_images_edits(
     State(state): State<AppState>,
     mut multipart: axum::extract::Multipart,
@@ -1898,6 +3232,11 @@ pub async fn handle_images_edits(
     let mut aspect_ratio: Option<String> = None;
     let mut image_size_param: Option<String> = None;
     let mut style: Option<String> = None;
+    let mut output_format: Option<String> = None;
+    // [NEW] 以 URL (http(s) 或 data:) 形式提供的主图 / 参考图，字段解析完后统一下载
+    let mut image_url: Option<String> = None;
+    let mut reference_image_urls: Vec<String> = Vec::new();
+    let mut user: Option<String> = None;
 
     while let Some(field) = multipart
         .next_field()
@@ -1918,6 +3257,18 @@ pub async fn handle_images_edits(
                 .await
                 .map_err(|e| (StatusCode::BAD_REQUEST, format!("Mask read error: {}", e)))?;
             mask_data = Some(base64::engine::general_purpose::STANDARD.encode(data));
+        } else if name == "image_url" {
+            if let Ok(val) = field.text().await {
+                if !val.trim().is_empty() {
+                    image_url = Some(val.trim().to_string());
+                }
+            }
+        } else if name == "image_urls" || name.starts_with("reference_image_url") {
+            // 支持重复字段、JSON 数组或逗号分隔
+            if let Ok(val) = field.text().await {
+                reference_image_urls
+                    .extend(crate::proxy::mappers::openai::image_fetch::parse_image_url_list(&val));
+            }
         } else if name.starts_with("image") && name != "image_size" {
             // Support image1, image2, etc.
             let data = field.bytes().await.map_err(|e| {
@@ -1956,21 +3307,92 @@ pub async fn handle_images_edits(
             if let Ok(val) = field.text().await {
                 response_format = val;
             }
+        } else if name == "output_format" {
+            if let Ok(val) = field.text().await {
+                output_format = Some(val);
+            }
         } else if name == "model" {
             if let Ok(val) = field.text().await {
                 if !val.is_empty() {
                     model = val;
                 }
             }
+        } else if name == "user" {
+            if let Ok(val) = field.text().await {
+                user = Some(val);
+            }
         }
     }
 
+    let tokens = crate::proxy::mappers::context_manager::estimate_tokens_from_str(&prompt) as u64;
+    if let Some(limited) = end_user_rejection(&state, user.as_deref(), tokens).await {
+        return Ok(limited);
+    }
+
     // Validation: Require either 'image' (standard edit) OR 'prompt' (generation)
     // If reference images are present, we treat it as generation with image context
     if prompt.is_empty() {
         return Err((StatusCode::BAD_REQUEST, "Missing prompt".to_string()));
     }
 
+    // [NEW] 下载 URL 形式的图片: 主图失败直接 400；参考图失败仅跳过，除非它是唯一的图片
+    if image_url.is_some() || !reference_image_urls.is_empty() {
+        use crate::proxy::mappers::openai::image_fetch;
+
+        let client = {
+            let upstream_proxy = state.upstream_proxy.read().await;
+            let allowed_hosts = state
+                .experimental
+                .read()
+                .await
+                .remote_image_allowed_hosts
+                .clone();
+            image_fetch::build_image_client(&upstream_proxy, &allowed_hosts)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        };
+        if let Some(url) = image_url.filter(|_| image_data.is_none()) {
+            let (_, data) = image_fetch::load_image_reference(
+                &client,
+                &url,
+                image_fetch::MAX_REMOTE_IMAGE_BYTES,
+            )
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid image_url: {}", e)))?;
+            image_data = Some(data);
+        }
+        let mut first_error = None;
+        for url in &reference_image_urls {
+            match image_fetch::load_image_reference(
+                &client,
+                url,
+                image_fetch::MAX_REMOTE_IMAGE_BYTES,
+            )
+            .await
+            {
+                Ok((_, data)) => reference_images.push(data),
+                Err(e) => {
+                    tracing::warn!("[Images] Skipping reference image: {}", e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_error {
+            if image_data.is_none() && reference_images.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid reference image: {}", e),
+                ));
+            }
+        }
+    }
+
+    // [NEW] 参考图总大小限制，超限时在调用上游前直接返回 413
+    let image_limits = crate::proxy::get_image_config();
+    check_reference_images_payload(&reference_images, image_limits.max_total_reference_bytes)
+        .map_err(|msg| (StatusCode::PAYLOAD_TOO_LARGE, msg))?;
+    let permits =
+        image_fanout_permits(n, &image_limits).map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
     tracing::info!(
         "[Images] Edit/Ref Request: model={}, prompt={}, n={}, size={}, aspect_ratio={:?}, image_size={:?}, style={:?}, refs={}, has_main_image={}",
         model,
@@ -2017,58 +3439,61 @@ pub async fn handle_images_edits(
         "text": final_prompt
     }));
 
+    // [NEW] 超出上游限制的上传图片先缩小 (JPEG 保持 JPEG，其余重新编码为 PNG)
+    let input_limits = InputImageLimits::from_config(&*state.experimental.read().await);
+
     // Add Main Image (if standard edit)
+    // [FIX] 按实际内容识别 MIME，识别不出时沿用原默认值
+    let sniff_mime = crate::proxy::mappers::openai::image_fetch::sniff_base64_image_mime;
     if let Some(data) = image_data {
-        contents_parts.push(json!({
-            "inlineData": {
-                "mimeType": "image/png",
-                "data": data
-            }
-        }));
+        let mime_type = sniff_mime(&data).unwrap_or("image/png");
+        contents_parts.push(fitted_image_part(data, mime_type, &input_limits).await);
     }
 
     // Add Mask (if standard edit)
     if let Some(data) = mask_data {
-        contents_parts.push(json!({
-            "inlineData": {
-                "mimeType": "image/png",
-                "data": data
-            }
-        }));
+        contents_parts.push(fitted_image_part(data, "image/png", &input_limits).await);
     }
 
     // Add Reference Images (Image-to-Image)
     for ref_data in reference_images {
-        contents_parts.push(json!({
-            "inlineData": {
-                "mimeType": "image/jpeg", // Assume JPEG for refs as per spec suggestion, or auto-detect
-                "data": ref_data
-            }
-        }));
+        // Assume JPEG for refs as per spec suggestion, or auto-detect
+        let mime_type = sniff_mime(&ref_data).unwrap_or("image/jpeg");
+        contents_parts.push(fitted_image_part(ref_data, mime_type, &input_limits).await);
     }
 
     // 4. 并发发送请求
     // 注意：不再在外部获取 Token，而是移入 Task 内部
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager.clone();
+    ensure_account_pool_not_empty(&token_manager)?;
     let max_pool_size = token_manager.len();
     let max_attempts = MAX_RETRY_ATTEMPTS
         .min(max_pool_size.saturating_add(1))
         .max(2);
 
+    // [NEW] 按模型默认生成参数 (覆盖下方内置的 temperature / topP / topK)
+    let gen_defaults = crate::proxy::config::resolve_generation_defaults(&model, &model);
+
     let mut tasks = Vec::new();
     for _ in 0..n {
         let upstream = upstream.clone();
         let token_manager = token_manager.clone();
+        let metrics = state.metrics.clone();
+        let permits = permits.clone();
         let contents_parts = contents_parts.clone();
         let image_config = image_config.clone();
         let response_format = response_format.clone();
         let model = model.clone();
+        let gen_defaults = gen_defaults.clone();
 
         tasks.push(tokio::spawn(async move {
+            // [NEW] 超出 max_concurrency 的任务在此排队，分批发往上游；每个任务独立取号以分散到不同账号
+            let _permit = permits.acquire_owned().await;
             let mut last_error = String::new();
 
-            for attempt in 0..max_attempts {
+            let mut attempt_budget = AttemptBudget::new(max_attempts);
+            while let Some(attempt) = attempt_budget.next() {
                 // 4.1 获取 Token
                 let (access_token, project_id, email, account_id, _wait_ms) = match token_manager
                     .get_token("image_gen", attempt > 0, None, "dall-e-3")
@@ -2084,9 +3509,10 @@ pub async fn handle_images_edits(
                         break;
                     }
                 };
+                metrics.record_attempt("images", attempt, None, &email);
 
                 // 4.2 Construct Request Body (Need project_id)
-                let gemini_body = json!({
+                let mut gemini_body = json!({
                     "project": project_id,
                     "requestId": format!("img-edit-{}", uuid::Uuid::new_v4()),
                     "model": model,
@@ -2106,15 +3532,13 @@ pub async fn handle_images_edits(
                             "topP": 0.95,
                             "topK": 40
                         },
-                        "safetySettings": [
-                            { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "OFF" },
-                            { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "OFF" },
-                        ]
+                        "safetySettings": build_safety_settings(
+                            "OFF",
+                            supports_civic_integrity(&model, &model),
+                        )
                     }
                 });
+                gen_defaults.apply_to(&mut gemini_body["request"]["generationConfig"]);
 
                 match upstream
                     .call_v1_internal(
@@ -2134,6 +3558,15 @@ pub async fn handle_images_edits(
                             let status_code = status.as_u16();
                             last_error = format!("Upstream error {}: {}", status, err_text);
 
+                            // [NEW] 模型不支持 CIVIC_INTEGRITY 安全类别: 记录后重试 (下次构造请求体时省略)
+                            if is_civic_integrity_rejection(status_code, &err_text)
+                                && supports_civic_integrity(&model, &model)
+                            {
+                                mark_civic_integrity_unsupported(&model);
+                                attempt_budget.retry_current();
+                                continue;
+                            }
+
                             // 429/500/503 等错误进行标记和重试
                             if status_code == 429 || status_code == 503 || status_code == 500 {
                                 tracing::warn!(
@@ -2150,6 +3583,7 @@ pub async fn handle_images_edits(
                                         Some("dall-e-3"),
                                     )
                                     .await;
+                                metrics.record_rate_limited("images");
                                 continue; // Retry loop
                             }
                             return Err(last_error);
@@ -2169,6 +3603,14 @@ pub async fn handle_images_edits(
         }));
     }
 
+    // [NEW] 生成结果后处理 (转码 / 缩放)，未开启时仅做校验与 mime 修正
+    let output_options = state
+        .experimental
+        .read()
+        .await
+        .enable_image_postprocess
+        .then(|| ImageOutputOptions::from_request(output_format.as_deref(), Some(&size)));
+
     // 5. Collect Results
     let mut images: Vec<Value> = Vec::new();
     let mut errors: Vec<String> = Vec::new();
@@ -2178,6 +3620,7 @@ pub async fn handle_images_edits(
         match task.await {
             Ok(result) => match result {
                 Ok((gemini_resp, response_format, email_used)) => {
+                    state.metrics.record_image_generation(true);
                     if used_email.is_none() {
                         used_email = Some(email_used);
                     }
@@ -2191,22 +3634,22 @@ pub async fn handle_images_edits(
                     {
                         for part in parts {
                             if let Some(img) = part.get("inlineData") {
-                                let data = img.get("data").and_then(|v| v.as_str()).unwrap_or("");
-                                if !data.is_empty() {
-                                    if response_format == "url" {
-                                        let mime_type = img
-                                            .get("mimeType")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("image/png");
-                                        images.push(json!({
-                                            "url": format!("data:{};base64,{}", mime_type, data)
-                                        }));
-                                    } else {
-                                        images.push(json!({
-                                            "b64_json": data
-                                        }));
+                                match image_response_item(
+                                    img,
+                                    &response_format,
+                                    output_options.as_ref(),
+                                )
+                                .await
+                                {
+                                    Ok(Some(item)) => {
+                                        images.push(item);
+                                        tracing::debug!("[Images] Task {} succeeded", idx);
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => {
+                                        tracing::error!("[Images] Task {} returned invalid image: {}", idx, e);
+                                        errors.push(e);
                                     }
-                                    tracing::debug!("[Images] Task {} succeeded", idx);
                                 }
                             }
                         }
@@ -2214,12 +3657,14 @@ pub async fn handle_images_edits(
                 }
                 Err(e) => {
                     tracing::error!("[Images] Task {} failed: {}", idx, e);
+                    state.metrics.record_image_generation(false);
                     errors.push(e);
                 }
             },
             Err(e) => {
                 let err_msg = format!("Task join error: {}", e);
                 tracing::error!("[Images] Task {} join error: {}", idx, e);
+                state.metrics.record_image_generation(false);
                 errors.push(err_msg);
             }
         }
@@ -2270,3 +3715,1254 @@ pub async fn handle_images_edits(
     )
         .into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::{proxy_state, TestPool};
+
+    #[test]
+    fn test_reference_images_payload_cap() {
+        let refs = vec!["a".repeat(400), "b".repeat(300), "c".repeat(500)];
+
+        // 单张均未超限，但总和 1200 超出 1000
+        let err = check_reference_images_payload(&refs, 1000).unwrap_err();
+        assert!(err.contains("1200 bytes total"), "{}", err);
+        assert!(err.contains("limit of 1000 bytes"), "{}", err);
+        assert!(err.contains("image1=400 bytes, image2=300 bytes, image3=500 bytes"), "{}", err);
+
+        assert!(check_reference_images_payload(&refs, 1200).is_ok());
+        // 0 表示不限制
+        assert!(check_reference_images_payload(&refs, 0).is_ok());
+        assert!(check_reference_images_payload(&[], 1).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_image_fanout_bounded_by_max_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let config = crate::proxy::config::ImageConfig {
+            max_concurrency: 2,
+            max_n: 6,
+            ..Default::default()
+        };
+        let err = image_fanout_permits(7, &config).unwrap_err();
+        assert!(err.contains("less than or equal to 6"), "{}", err);
+        assert!(image_fanout_permits(0, &config).is_err());
+
+        let permits = image_fanout_permits(6, &config).unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::new();
+        for _ in 0..6 {
+            let (permits, running, peak) = (permits.clone(), running.clone(), peak.clone());
+            tasks.push(tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+        // 0 表示不限制
+        let unlimited = crate::proxy::config::ImageConfig {
+            max_concurrency: 0,
+            max_n: 0,
+            ..Default::default()
+        };
+        let permits = image_fanout_permits(50, &unlimited).unwrap();
+        assert_eq!(permits.available_permits(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_echo_mode_streaming_and_non_streaming() {
+        use futures::StreamExt;
+
+        assert!(is_echo_mode(ECHO_TEST_MODEL));
+        assert!(!is_echo_mode("gpt-4o"));
+        let request = |stream: bool| -> OpenAIRequest {
+            serde_json::from_value(json!({
+                "model": ECHO_TEST_MODEL,
+                "stream": stream,
+                "temperature": 0.3,
+                "messages": [
+                    { "role": "system", "content": "Be terse." },
+                    { "role": "user", "content": "ping" }
+                ]
+            }))
+            .unwrap()
+        };
+
+        let resp = echo_response(&request(false), "gemini-2.5-flash");
+        assert_eq!(resp.headers()["X-Echo-Mode"], "true");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let chat: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(chat["model"], ECHO_TEST_MODEL);
+        assert_eq!(chat["choices"][0]["finish_reason"], "stop");
+        let content = chat["choices"][0]["message"]["content"].as_str().unwrap().to_string();
+        let echoed: Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(echoed["mapped_model"], "gemini-2.5-flash");
+        assert_eq!(echoed["request"]["contents"][0]["parts"][0]["text"], "ping");
+        assert_eq!(echoed["request"]["generationConfig"]["temperature"], 0.3);
+        assert!(echoed["request"].get("systemInstruction").is_none());
+        assert!(!content.contains("Be terse."));
+
+        // 流式: 多个 SSE 分片拼接后与非流式内容一致，并以 [DONE] 结束
+        let resp = echo_response(&request(true), "gemini-2.5-flash");
+        assert_eq!(resp.headers()["Content-Type"], "text/event-stream");
+        let mut sse = String::new();
+        let mut body = resp.into_body().into_data_stream();
+        while let Some(chunk) = body.next().await {
+            sse.push_str(&String::from_utf8_lossy(&chunk.unwrap()));
+        }
+        assert!(sse.trim_end().ends_with("data: [DONE]"));
+        let chunks: Vec<Value> = sse
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .filter_map(|d| serde_json::from_str(d).ok())
+            .collect();
+        let streamed: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert!(chunks.len() > 3);
+        assert_eq!(streamed, content);
+    }
+
+    #[test]
+    fn test_responses_input_system_messages_merge() {
+        let body = json!({
+            "model": "gpt-4o",
+            "instructions": "You are a travel agent.",
+            "input": [
+                { "role": "system", "content": "Prices must be in JPY." },
+                { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": "Plan a trip to Kyoto." }] },
+                { "role": "developer", "content": "Keep it under 100 words." },
+                { "role": "user", "content": "Three days." }
+            ]
+        });
+        let messages = responses_input_to_messages(&body);
+        let roles: Vec<&str> = messages.iter().filter_map(|m| m["role"].as_str()).collect();
+        assert_eq!(roles, ["system", "system", "user", "developer", "user"]);
+
+        let req: OpenAIRequest =
+            serde_json::from_value(json!({ "model": "gpt-4o", "messages": messages })).unwrap();
+        let (gemini, _, _) = transform_openai_request(&req, "p", "gemini-2.5-flash");
+        let system = gemini["request"]["systemInstruction"].to_string();
+        let position = |needle: &str| system.find(needle).unwrap();
+        assert!(position("You are a travel agent.") < position("Prices must be in JPY."));
+        assert!(position("Prices must be in JPY.") < position("Keep it under 100 words."));
+        // 相邻 user 轮次合并为一条 content，系统文本不进入 contents
+        let contents = gemini["request"]["contents"].to_string();
+        assert!(contents.contains("Plan a trip to Kyoto.") && contents.contains("Three days."));
+        assert!(!contents.contains("JPY") && !contents.contains("100 words"));
+    }
+
+    #[tokio::test]
+    async fn test_responses_input_file_url_is_fetched_and_inlined() {
+        use crate::proxy::tests::harness::{spawn_upstream_with, upstream_client};
+        use axum::http::HeaderValue;
+        use base64::Engine as _;
+
+        const PDF: &[u8] = b"%PDF-1.7\n1 0 obj\n<<>>\nendobj\n";
+        // 同一本地服务: GET /paper.pdf 提供文档，其余请求模拟 v1internal 上游
+        let addr = spawn_upstream_with(|_| {
+            axum::Router::new()
+                .route(
+                    "/paper.pdf",
+                    axum::routing::get(|| async {
+                        ([("content-type", "application/octet-stream")], PDF)
+                    }),
+                )
+                .fallback(|Json(body): Json<Value>| async move {
+                    let part = &body["request"]["contents"][0]["parts"][1];
+                    assert!(part.get("fileData").is_none(), "{}", part);
+                    assert_eq!(part["inlineData"]["mimeType"], "application/pdf");
+                    assert_eq!(
+                        part["inlineData"]["data"],
+                        base64::engine::general_purpose::STANDARD.encode(PDF)
+                    );
+                    Json(json!({
+                        "response": {
+                            "candidates": [{
+                                "content": { "role": "model", "parts": [{ "text": "A short paper." }] },
+                                "finishReason": "STOP"
+                            }]
+                        }
+                    }))
+                })
+        })
+        .await;
+        let pool = TestPool::new(&[("acc1", "a@test.com")]);
+        let state = AppState::for_test(pool.token_manager().await, upstream_client(addr).await);
+        state.experimental.write().await.remote_image_allowed_hosts = vec!["127.0.0.1".to_string()];
+
+        let respond = |path: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-force-stream", HeaderValue::from_static("false"));
+            handle_completions(
+                State(state.clone()),
+                headers,
+                Json(json!({
+                    "model": "gemini-2.5-flash",
+                    "input": [{
+                        "role": "user",
+                        "content": [
+                            { "type": "input_text", "text": "Summarize this paper." },
+                            { "type": "input_file", "file_url": format!("http://{}{}", addr, path) }
+                        ]
+                    }]
+                })),
+            )
+        };
+
+        let resp = respond("/paper.pdf").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // 无法下载的文档在请求阶段拒绝，不转发给上游
+        let resp = respond("/missing.pdf").await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("Invalid document") && body.contains("/missing.pdf"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_chat_batch_results_aligned_by_index() {
+        use axum::http::HeaderValue;
+
+        // 模拟上游: 提示词为 "bad" 时返回 400，其余回显提示词；记录上游收到的请求路径
+        let seen_paths = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = {
+            let seen_paths = seen_paths.clone();
+            axum::Router::new().fallback(move |uri: axum::http::Uri, Json(body): Json<Value>| {
+                let seen_paths = seen_paths.clone();
+                async move {
+                    seen_paths.lock().unwrap().push(uri.to_string());
+                    let prompt = body["request"]["contents"][0]["parts"][0]["text"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string();
+                    if prompt == "bad" {
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({ "error": { "code": 400, "message": "Invalid argument: bad prompt", "status": "INVALID_ARGUMENT" } })),
+                        )
+                            .into_response();
+                    }
+                    Json(json!({
+                        "response": {
+                            "candidates": [{
+                                "content": { "role": "model", "parts": [{ "text": format!("echo: {}", prompt) }] },
+                                "finishReason": "STOP"
+                            }]
+                        }
+                    }))
+                    .into_response()
+                }
+            })
+        };
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+        state.experimental.write().await.batch_max_concurrency = 1;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-force-stream", HeaderValue::from_static("false"));
+        let batch = |body: Value| handle_chat_batch(State(state.clone()), headers.clone(), Json(body));
+
+        let resp = batch(json!({
+            "requests": [
+                { "model": "gemini-2.5-flash", "messages": [{ "role": "user", "content": "bad" }] },
+                { "model": "gemini-2.5-flash", "stream": true, "messages": [{ "role": "user", "content": "ping" }] }
+            ]
+        }))
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["object"], "batch");
+        let responses = body["responses"].as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["error"]["status"], 400);
+        assert!(responses[0]["error"]["message"]
+            .to_string()
+            .contains("bad prompt"));
+        // 批量内逐项强制非流式: stream: true 的请求同样返回完整的 chat.completion
+        assert_eq!(responses[1]["object"], "chat.completion");
+        assert_eq!(responses[1]["choices"][0]["message"]["content"], "echo: ping");
+        assert_eq!(seen_paths.lock().unwrap().len(), 2);
+        assert!(seen_paths
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|p| !p.contains("streamGenerateContent")));
+
+        // 空批量在入口拒绝
+        let resp = batch(json!({ "requests": [] })).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // 非 JSON 的失败响应包装为 error 对象
+        let plain = batch_item_result(
+            (StatusCode::SERVICE_UNAVAILABLE, "no accounts configured").into_response(),
+        )
+        .await;
+        assert_eq!(plain["error"]["status"], 503);
+        assert_eq!(plain["error"]["message"], "no accounts configured");
+    }
+
+    #[test]
+    fn test_stored_codex_response_keeps_function_calls() {
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {"parts": [
+                    {"text": "Checking the weather."},
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}
+                ]},
+                "finishReason": "STOP"
+            }]
+        });
+        let chat_resp = transform_openai_response(&gemini_resp, None, 1);
+        let id = format!("resp-{}", uuid::Uuid::new_v4());
+        let mut legacy_resp = json!({ "id": id, "object": "text_completion" });
+
+        let opts = ResponseStoreOptions::from_body(&json!({ "store": true }), String::new());
+        finalize_codex_response(&mut legacy_resp, &chat_resp, &opts);
+
+        let stored = ResponseStore::global().get(&id, "").unwrap();
+        let output = stored["output"].as_array().unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0]["content"][0]["text"], "Checking the weather.");
+        assert_eq!(output[1]["type"], "function_call");
+        assert_eq!(output[1]["name"], "get_weather");
+        assert_eq!(output[1]["call_id"], chat_resp.choices[0].message.tool_calls.as_ref().unwrap()[0].id);
+        let args: Value = serde_json::from_str(output[1]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(args["city"], "Paris");
+    }
+
+    #[tokio::test]
+    async fn test_get_stored_response() {
+        let with_key = |key: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("authorization", format!("Bearer {}", key).parse().unwrap());
+            headers
+        };
+        let id = format!("resp-{}", uuid::Uuid::new_v4());
+        let resp = handle_get_response(Path(id.clone()), with_key("sk-owner")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let opts = ResponseStoreOptions::from_body(
+            &json!({ "store": true, "metadata": { "k": "v" } }),
+            key_owner(&with_key("sk-owner")),
+        );
+        let mut stored = json!({ "id": id, "object": "response", "status": "completed" });
+        opts.finalize(&mut stored);
+
+        // 其他 Key 或未携带 Key 时视为不存在
+        let resp = handle_get_response(Path(id.clone()), with_key("sk-other")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = handle_get_response(Path(id.clone()), HeaderMap::new()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = handle_get_response(Path(id), with_key("sk-owner")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, stored);
+        assert_eq!(body["metadata"]["k"], "v");
+    }
+
+    #[tokio::test]
+    async fn test_effective_params_only_in_debug_mode() {
+        use axum::http::HeaderValue;
+
+        // 模拟上游: 回传收到的 generationConfig 以便核对回写值
+        let upstream_config = std::sync::Arc::new(std::sync::Mutex::new(Value::Null));
+        let app = {
+            let upstream_config = upstream_config.clone();
+            axum::Router::new().fallback(move |Json(body): Json<Value>| {
+                let upstream_config = upstream_config.clone();
+                async move {
+                    *upstream_config.lock().unwrap() = body["request"]["generationConfig"].clone();
+                    Json(json!({
+                        "response": {
+                            "candidates": [{
+                                "content": { "role": "model", "parts": [{ "text": "hi" }] },
+                                "finishReason": "STOP"
+                            }]
+                        }
+                    }))
+                }
+            })
+        };
+        let (state, pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+
+        for enabled in [false, true] {
+            *state.debug_logging.write().await = crate::proxy::config::DebugLoggingConfig {
+                enabled,
+                output_dir: Some(pool.root().join("debug").to_string_lossy().into_owned()),
+            };
+            let mut headers = HeaderMap::new();
+            headers.insert("x-force-stream", HeaderValue::from_static("false"));
+            let resp = handle_chat_completions(
+                State(state.clone()),
+                headers,
+                Json(json!({
+                    "model": "gemini-2.5-flash",
+                    "messages": [{ "role": "user", "content": "hi" }],
+                    "temperature": 3.5,
+                    "top_p": 1.7,
+                    "max_tokens": 256
+                })),
+            )
+            .await
+            .map_err(|(status, message)| format!("{}: {}", status, message))
+            .unwrap()
+            .into_response();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["object"], "chat.completion");
+
+            if !enabled {
+                assert!(body.get("x_effective_params").is_none());
+                continue;
+            }
+            // 回写的是钳制之后实际发往上游的值
+            let params = &body["x_effective_params"];
+            let sent = upstream_config.lock().unwrap().clone();
+            assert_eq!(params["temperature"], 2.0);
+            assert_eq!(params["top_p"], 1.0);
+            assert_eq!(params["max_output_tokens"], 256);
+            assert_eq!(params["temperature"], sent["temperature"]);
+            assert_eq!(params["top_p"], sent["topP"]);
+            assert_eq!(params["max_output_tokens"], sent["maxOutputTokens"]);
+        }
+    }
+
+    #[test]
+    fn test_logit_bias_policy() {
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "logit_bias": { "50256": -100 }
+        });
+
+        // 默认策略: 忽略
+        assert_eq!(
+            crate::proxy::config::OpenAICompatConfig::default().logit_bias,
+            UnsupportedParamPolicy::Ignore
+        );
+        assert!(check_logit_bias(&body, UnsupportedParamPolicy::Ignore).is_ok());
+
+        // 拒绝策略: 400 并说明原因
+        let (status, message) = check_logit_bias(&body, UnsupportedParamPolicy::Reject).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("logit_bias is not supported"));
+
+        // 未携带或为空时任何策略都放行
+        let plain = json!({ "model": "gpt-4o", "logit_bias": {} });
+        assert!(check_logit_bias(&plain, UnsupportedParamPolicy::Reject).is_ok());
+        assert!(check_logit_bias(&json!({ "model": "gpt-4o" }), UnsupportedParamPolicy::Reject).is_ok());
+    }
+
+    #[test]
+    fn test_audio_modality_request_rejected_or_downgraded() {
+        let body = json!({
+            "model": "gpt-4o-audio-preview",
+            "modalities": ["text", "audio"],
+            "audio": { "voice": "alloy", "format": "wav" },
+            "messages": [{ "role": "user", "content": "hi" }]
+        });
+
+        // 请求体可正常反序列化
+        let req: OpenAIRequest = serde_json::from_value(body.clone()).unwrap();
+        assert!(req.wants_audio_output());
+
+        // 默认策略: 400 并说明如何修改请求
+        let policy = crate::proxy::config::OpenAICompatConfig::default().audio_output;
+        assert_eq!(policy, UnsupportedParamPolicy::Reject);
+        let (status, message) = check_audio_output(&req, policy).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("audio output is not supported"));
+
+        // 降级策略: 放行并通过 X-Unsupported-Params 告知
+        assert!(check_audio_output(&req, UnsupportedParamPolicy::Ignore).is_ok());
+        let resp = with_unsupported_params_header(Json(json!({})).into_response(), false, &req);
+        assert_eq!(resp.headers()["X-Unsupported-Params"], "audio");
+
+        // 仅文本 modalities 不受影响
+        let text_only: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "modalities": ["text"],
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+        assert!(check_audio_output(&text_only, UnsupportedParamPolicy::Reject).is_ok());
+    }
+
+    #[test]
+    fn test_fallback_chain_after_primary_budget_exhausted() {
+        use crate::proxy::config::{insert_model_profile, ModelProfile};
+
+        insert_model_profile(
+            "fallback-primary-model",
+            ModelProfile {
+                fallback_models: vec!["gemini-2.5-flash".to_string()],
+                ..Default::default()
+            },
+        );
+
+        let chain = resolve_model_profile("gpt-4o", "fallback-primary-model")
+            .map(|p| p.fallback_models)
+            .unwrap_or_default();
+        assert_eq!(chain, vec!["gemini-2.5-flash".to_string()]);
+
+        // 主模型先用完 3 次账号轮换预算，之后切换到降级模型
+        let models: Vec<Option<&str>> = (0..6)
+            .map(|i| fallback_model_for_attempt(&chain, 3, i))
+            .collect();
+        assert_eq!(
+            models,
+            vec![None, None, None, Some("gemini-2.5-flash"), Some("gemini-2.5-flash"), Some("gemini-2.5-flash")]
+        );
+
+        // 仅在发生降级时附加 X-Fallback-Model
+        let resp = with_fallback_header(StatusCode::OK.into_response(), Some("gemini-2.5-flash"));
+        assert_eq!(resp.headers()["X-Fallback-Model"], "gemini-2.5-flash");
+        let resp = with_fallback_header(StatusCode::OK.into_response(), None);
+        assert!(resp.headers().get("X-Fallback-Model").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_model_falls_back_to_next_in_chain() {
+        use crate::proxy::config::{insert_model_profile, ModelProfile};
+        use axum::http::HeaderValue;
+
+        assert!(is_model_unavailable(404, "Not Found"));
+        assert!(is_model_unavailable(400, "Model gemini-x is not supported"));
+        assert!(!is_model_unavailable(400, "Invalid argument"));
+        // 跳过主模型剩余的账号轮换预算，直接进入降级模型的第一次尝试
+        let chain = vec!["gemini-2.5-flash".to_string()];
+        assert_eq!(skip_to_next_fallback(&chain, 3, 0), Some(2));
+        assert_eq!(skip_to_next_fallback(&chain, 3, 3), None);
+
+        insert_model_profile(
+            "unavailable-primary-model",
+            ModelProfile {
+                fallback_models: chain.clone(),
+                ..Default::default()
+            },
+        );
+
+        // 模拟上游: 主模型返回 404，降级模型正常响应
+        let app = axum::Router::new().fallback(|Json(body): Json<Value>| async move {
+            if body["model"] == "unavailable-primary-model" {
+                return (StatusCode::NOT_FOUND, "Requested entity was not found.").into_response();
+            }
+            Json(json!({
+                "response": {
+                    "candidates": [{
+                        "content": { "role": "model", "parts": [{ "text": "served by fallback" }] },
+                        "finishReason": "STOP"
+                    }]
+                }
+            }))
+            .into_response()
+        });
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-force-stream", HeaderValue::from_static("false"));
+        let resp = handle_chat_completions(
+            State(state),
+            headers,
+            Json(json!({
+                "model": "unavailable-primary-model",
+                "messages": [{ "role": "user", "content": "hi" }]
+            })),
+        )
+        .await
+        .map_err(|(status, message)| format!("{}: {}", status, message))
+        .unwrap()
+        .into_response();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["X-Mapped-Model"], "gemini-2.5-flash");
+        assert_eq!(resp.headers()["X-Fallback-Model"], "gemini-2.5-flash");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], "served by fallback");
+
+    }
+
+    #[tokio::test]
+    async fn test_legacy_completions_map_logprobs() {
+        use axum::http::HeaderValue;
+
+        // 模拟上游: 提示词包含 "plain" 时不返回 logprobsResult
+        let app = axum::Router::new().fallback(|Json(body): Json<Value>| async move {
+            let config = &body["request"]["generationConfig"];
+            assert_eq!(config["responseLogprobs"], true);
+            assert_eq!(config["logprobs"], 2);
+            let mut candidate = json!({
+                "content": { "role": "model", "parts": [{ "text": "Hi!" }] },
+                "finishReason": "STOP",
+                "logprobsResult": {
+                    "topCandidates": [
+                        { "candidates": [
+                            { "token": "Hi", "logProbability": -0.05 },
+                            { "token": "Hello", "logProbability": -3.2 }
+                        ]},
+                        { "candidates": [{ "token": "!", "logProbability": -0.19 }] }
+                    ],
+                    "chosenCandidates": [
+                        { "token": "Hi", "logProbability": -0.05 },
+                        { "token": "!", "logProbability": -0.19 }
+                    ]
+                }
+            });
+            if body.to_string().contains("plain") {
+                candidate.as_object_mut().unwrap().remove("logprobsResult");
+            }
+            Json(json!({ "response": { "candidates": [candidate] } }))
+        });
+        let (state, _pool) = proxy_state(app, &[("acc1", "a@test.com")]).await;
+
+        let complete = |prompt: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-force-stream", HeaderValue::from_static("false"));
+            handle_completions(
+                State(state.clone()),
+                headers,
+                Json(json!({
+                    "model": "gemini-2.5-flash",
+                    "prompt": prompt,
+                    "logprobs": 2
+                })),
+            )
+        };
+
+        let resp = complete("say hi").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("X-Unsupported-Params").is_none());
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let logprobs = &body["choices"][0]["logprobs"];
+        assert_eq!(logprobs["tokens"], json!(["Hi", "!"]));
+        assert_eq!(logprobs["token_logprobs"], json!([-0.05, -0.19]));
+        assert_eq!(logprobs["top_logprobs"][0]["Hello"], -3.2);
+        assert_eq!(logprobs["text_offset"], json!([0, 2]));
+
+        // 上游未返回逐 token 数据: logprobs 为 null 并通过 X-Unsupported-Params 告知
+        let resp = complete("plain").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["X-Unsupported-Params"], "logprobs");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["choices"][0]["logprobs"].is_null());
+
+    }
+
+    #[tokio::test]
+    async fn test_model_specific_timeouts_override_defaults() {
+        use crate::proxy::config::{insert_model_profile, ModelProfile, ModelTimeouts};
+
+        insert_model_profile(
+            "timeout-reasoning-*",
+            ModelProfile {
+                timeouts: Some(ModelTimeouts {
+                    peek_timeout_secs: Some(300),
+                    total_timeout_secs: Some(900),
+                    collector_timeout_secs: None,
+                }),
+                ..Default::default()
+            },
+        );
+
+        let default_peek = Duration::from_secs(60);
+        let tuned = resolve_model_timeouts("gpt-4o", "timeout-reasoning-pro", default_peek);
+        assert_eq!(tuned.peek, Duration::from_secs(300));
+        assert_eq!(tuned.total, Some(Duration::from_secs(900)));
+        assert_eq!(tuned.collector, None);
+
+        // 未配置的模型沿用全局默认值
+        let plain = resolve_model_timeouts("gpt-4o", "timeout-fast-flash", default_peek);
+        assert_eq!(plain.peek, default_peek);
+        assert_eq!(plain.total, None);
+
+        // 超时转为带说明的错误
+        let err = with_optional_timeout(
+            Some(Duration::from_millis(10)),
+            "Stream collection",
+            async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok::<(), String>(())
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(err.starts_with("Stream collection timed out"));
+        assert_eq!(
+            with_optional_timeout(None, "Upstream request", async { Ok::<_, String>(1) }).await,
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_count_tokens_body_and_response() {
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "How many tokens is this?" }
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+                }
+            }],
+            "parallel_tool_calls": false
+        }))
+        .unwrap();
+        let (gemini_body, _, _) = transform_openai_request(&req, "test-project", "gemini-2.5-flash");
+
+        let body = build_count_tokens_body(&gemini_body, "gemini-2.5-flash");
+        assert_eq!(body["request"]["model"], "models/gemini-2.5-flash");
+        let contents = body["request"]["contents"].as_array().unwrap();
+        assert!(!contents.is_empty());
+        assert!(body["request"]["contents"].to_string().contains("How many tokens is this?"));
+        // 系统提示词与工具声明一并计数
+        assert!(body["request"]["systemInstruction"]
+            .to_string()
+            .contains("Be brief."));
+        assert_eq!(
+            body["request"]["tools"][0]["functionDeclarations"][0]["name"],
+            "get_weather"
+        );
+        assert_eq!(
+            body["request"]["toolConfig"]["functionCallingConfig"]["mode"],
+            "AUTO"
+        );
+        assert!(body["request"].get("generationConfig").is_none());
+
+        assert_eq!(parse_count_tokens_response(&json!({ "totalTokens": 42 })), Some(42));
+        assert_eq!(
+            parse_count_tokens_response(&json!({ "response": { "totalTokens": 7 } })),
+            Some(7)
+        );
+        assert_eq!(parse_count_tokens_response(&json!({ "error": {} })), None);
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_skips_account_after_upstream_429() {
+        // 模拟上游: acc1 被限流，其余账号正常计数
+        let app = axum::Router::new().fallback(|headers: HeaderMap| async move {
+            if headers["authorization"] == "Bearer atk-acc1" {
+                return (StatusCode::TOO_MANY_REQUESTS, "Resource has been exhausted")
+                    .into_response();
+            }
+            Json(json!({ "totalTokens": 5 })).into_response()
+        });
+        let pool = TestPool::new(&[]);
+        for (id, email, percentage) in [("acc1", "a@test.com", 90), ("acc2", "b@test.com", 10)] {
+            pool.add_account_with(
+                id,
+                email,
+                json!({
+                    "quota": { "models": [{ "name": "gemini-2.5-flash", "percentage": percentage }] }
+                }),
+            );
+        }
+        let upstream = crate::proxy::tests::harness::spawn_upstream_client(app).await;
+        let state = AppState::for_test(pool.token_manager().await, upstream);
+
+        let count = || {
+            handle_count_tokens(
+                State(state.clone()),
+                HeaderMap::new(),
+                Json(json!({
+                    "model": "gemini-2.5-flash",
+                    "messages": [{ "role": "user", "content": "How many tokens is this?" }]
+                })),
+            )
+        };
+
+        // 首次按配额选中 acc1 并绑定会话；上游 429 记入冷却
+        let resp = count().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["X-Account-Email"], "a@test.com");
+
+        // 同一会话再次计数: 跳过冷却中的绑定账号
+        let resp = count().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["X-Account-Email"], "b@test.com");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["input_tokens"], 5);
+    }
+
+    #[test]
+    fn test_debug_transform_with_tools() {
+        let req = normalize_chat_request(json!({
+            "model": "gemini-2.5-flash",
+            "messages": [
+                { "role": "user", "content": "What's the weather in Paris?" }
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "description": "Look up the weather",
+                    "parameters": {
+                        "type": "object",
+                        "properties": { "city": { "type": "string" } },
+                        "required": ["city"]
+                    }
+                }
+            }]
+        }))
+        .unwrap();
+
+        let out = build_debug_transform(&req, "gemini-2.5-flash");
+        assert_eq!(out["mapped_model"], "gemini-2.5-flash");
+        assert_eq!(out["request_config"]["request_type"], "agent");
+
+        let request = &out["gemini_body"]["request"];
+        let decls = request["tools"][0]["functionDeclarations"].as_array().unwrap();
+        assert_eq!(decls.len(), 1);
+        assert_eq!(decls[0]["name"], "get_weather");
+        assert_eq!(decls[0]["parameters"]["properties"]["city"]["type"].as_str().map(|t| t.to_lowercase()), Some("string".to_string()));
+
+        let contents = request["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 1);
+        assert_eq!(contents[0]["role"], "user");
+        assert_eq!(contents[0]["parts"][0]["text"], "What's the weather in Paris?");
+    }
+
+    #[test]
+    fn test_safety_blocked_response_respects_policy() {
+        let response = transform_openai_response(
+            &json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [{ "text": "Partial answer" }] },
+                    "finishReason": "SAFETY"
+                }]
+            }),
+            None,
+            1,
+        );
+        assert!(safety_blocked_response(
+            &response,
+            SafetyPartialPolicy::ContentFilter,
+            "a@test.com",
+            "gemini-2.5-flash"
+        )
+        .is_none());
+
+        let blocked = safety_blocked_response(
+            &response,
+            SafetyPartialPolicy::Error,
+            "a@test.com",
+            "gemini-2.5-flash",
+        )
+        .expect("error mode should replace the response");
+        assert_eq!(blocked.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(blocked.headers()["X-Mapped-Model"], "gemini-2.5-flash");
+    }
+
+    #[test]
+    fn test_non_stream_response_echoes_requested_model() {
+        let mut response = transform_openai_response(
+            &json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [{ "text": "Hi" }] },
+                    "finishReason": "STOP"
+                }],
+                "modelVersion": "gemini-2.5-flash"
+            }),
+            None,
+            1,
+        );
+        assert_eq!(response.model, "gemini-2.5-flash");
+
+        echo_requested_model(&mut response, "gpt-4o-mini");
+        assert_eq!(response.model, "gpt-4o-mini");
+    }
+
+    #[test]
+    fn test_force_stream_header_overrides_default() {
+        let mut headers = HeaderMap::new();
+        // 默认: 非流式请求内部转为流式，流式请求无需转换
+        assert!(resolve_force_stream(&headers, false, true));
+        assert!(!resolve_force_stream(&headers, true, true));
+        assert!(!resolve_force_stream(&headers, false, false));
+
+        headers.insert("X-Force-Stream", "false".parse().unwrap());
+        assert!(!resolve_force_stream(&headers, false, true));
+
+        headers.insert("X-Force-Stream", "TRUE".parse().unwrap());
+        assert!(resolve_force_stream(&headers, false, false));
+
+        headers.insert("X-Force-Stream", "maybe".parse().unwrap());
+        assert!(resolve_force_stream(&headers, false, true));
+    }
+
+    #[test]
+    fn test_prediction_accepted_and_flagged_unsupported() {
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Rename foo to bar in this file." }],
+            "prediction": { "type": "content", "content": "fn bar() {}" }
+        });
+        let req = parse_openai_request(body).unwrap();
+        assert_eq!(req.prediction_text().as_deref(), Some("fn bar() {}"));
+        let (gemini, _, _) = transform_openai_request(&req, "p", "gemini-2.5-flash");
+        assert!(!gemini.to_string().contains("prediction"));
+
+        let resp = with_unsupported_params_header(Json(json!({})).into_response(), false, &req);
+        assert_eq!(resp.headers()["X-Unsupported-Params"], "prediction");
+        let resp = with_unsupported_params_header(Json(json!({})).into_response(), true, &req);
+        assert_eq!(
+            resp.headers()["X-Unsupported-Params"],
+            "logprobs, prediction"
+        );
+
+        // 未携带 prediction 时不返回该头
+        let mut plain = req.clone();
+        plain.prediction = None;
+        let resp = with_unsupported_params_header(Json(json!({})).into_response(), false, &plain);
+        assert!(resp.headers().get("X-Unsupported-Params").is_none());
+    }
+
+    #[test]
+    fn test_image_model_rejected_on_chat_endpoint() {
+        let mut config = crate::proxy::config::ImageConfig::default();
+        // 默认不拦截，聊天接口仍可使用生图模型
+        assert!(
+            check_image_model_in_chat(&config, "gemini-3-pro-image", "gemini-3-pro-image").is_ok()
+        );
+
+        config.chat_blocked_models =
+            vec!["gemini-3-pro-image*".to_string(), "dall-e-3".to_string()];
+        let (status, message) = check_image_model_in_chat(
+            &config,
+            "gemini-3-pro-image-16x9",
+            "gemini-3-pro-image-16x9",
+        )
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("/v1/images/generations"));
+        // 按映射后的模型匹配
+        assert!(check_image_model_in_chat(&config, "my-painter", "gemini-3-pro-image").is_err());
+        assert!(check_image_model_in_chat(&config, "dall-e-3", "gemini-3-pro-image").is_err());
+        assert!(check_image_model_in_chat(&config, "gpt-4o", "gemini-2.5-flash").is_ok());
+    }
+
+    #[test]
+    fn test_preferred_language_from_accept_language() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(preferred_language(&headers), None);
+        headers.insert("accept-language", "zh-CN,zh;q=0.9,en;q=0.8".parse().unwrap());
+        assert_eq!(preferred_language(&headers).as_deref(), Some("zh-CN"));
+        headers.insert("accept-language", "*, fr;q=0.5".parse().unwrap());
+        assert_eq!(preferred_language(&headers).as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn test_empty_account_pool_returns_no_accounts_error() {
+        let pool = TestPool::new(&[]);
+        let token_manager = crate::proxy::TokenManager::new(pool.root().to_path_buf());
+        assert_eq!(token_manager.len(), 0);
+
+        let (status, message) = ensure_account_pool_not_empty(&token_manager).unwrap_err();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(message, "no accounts configured");
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_error_lists_attempts_in_debug_mode() {
+        let pool = TestPool::new(&[]);
+        let token_manager = pool.token_manager().await;
+        token_manager
+            .mark_rate_limited("a@test.com", 429, Some("120"), "")
+            .await;
+
+        let attempts = vec![
+            AccountAttempt::new("a@test.com", "gemini-2.5-flash", Some(429), "RESOURCE_EXHAUSTED"),
+            AccountAttempt::new("b@test.com", "gemini-2.5-flash", Some(401), "UNAUTHENTICATED"),
+            AccountAttempt::new("c@test.com", "gemini-2.5-flash", None, "connection reset"),
+        ];
+
+        // 调试模式: 结构化 JSON
+        let detail = describe_attempts(&attempts, &token_manager);
+        let resp = exhausted_response(
+            "connection reset",
+            Some("c@test.com".to_string()),
+            Some("gemini-2.5-flash"),
+            Some(detail),
+            exhausted_retry_after(&token_manager, Some("gemini-2.5-flash")),
+        );
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        // 账号池中没有冷却中的账号时使用默认 Retry-After
+        assert_eq!(
+            resp.headers()["Retry-After"],
+            super::super::common::DEFAULT_RETRY_AFTER_SECS.to_string()
+        );
+        assert_eq!(resp.headers()["X-Account-Email"], "c@test.com");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "all_accounts_exhausted");
+        let items = body["error"]["attempts"].as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["category"], "rate_limited");
+        assert!(items[0]["cooldown_until"].is_string());
+        assert_eq!(items[1]["category"], "auth");
+        assert!(items[1]["cooldown_until"].is_null());
+        assert_eq!(items[2]["category"], "network");
+        assert!(items[2]["status"].is_null());
+
+        // 非调试模式: 保持原有纯文本，不暴露账号信息
+        let resp = exhausted_response(
+            "connection reset",
+            None,
+            Some("gemini-2.5-flash"),
+            None,
+            42,
+        );
+        assert_eq!(resp.headers()["Retry-After"], "42");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8_lossy(&body);
+        assert_eq!(text, "All accounts exhausted. Last error: connection reset");
+        assert!(!text.contains("a@test.com"));
+    }
+
+    #[test]
+    fn test_rotation_trace_header_records_decisions() {
+        let mut attempts = vec![
+            AccountAttempt::new(
+                "a@test.com",
+                "gemini-2.5-flash",
+                Some(429),
+                "RESOURCE_EXHAUSTED",
+            ),
+            AccountAttempt::new("b@test.com", "gemini-2.5-flash", Some(503), "UNAVAILABLE"),
+            AccountAttempt::new("b@test.com", "gemini-2.5-flash", None, "connection reset"),
+        ];
+        attempts[0].strategy = Some(RetryStrategy::FixedDelay(Duration::from_secs(1)).name());
+        attempts[1].strategy = Some(
+            RetryStrategy::ExponentialBackoff {
+                base_ms: 1000,
+                max_ms: 8000,
+            }
+            .name(),
+        );
+
+        // 未开启 expose_rotation_trace 时不附加
+        let resp = with_rotation_trace(
+            StatusCode::OK.into_response(),
+            &attempts,
+            Some("c@test.com"),
+            false,
+        );
+        assert!(resp.headers().get(super::super::common::ROTATION_TRACE_HEADER).is_none());
+
+        let decode_trace = |resp: &Response| -> Vec<Value> {
+            let encoded = resp.headers()["X-Rotation-Trace"].to_str().unwrap();
+            let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+            assert!(!String::from_utf8_lossy(&decoded).contains("@test.com"));
+            serde_json::from_slice(&decoded).unwrap()
+        };
+        let resp = with_rotation_trace(
+            StatusCode::OK.into_response(),
+            &attempts,
+            Some("c@test.com"),
+            true,
+        );
+        let items = decode_trace(&resp);
+        assert_eq!(items.len(), 4);
+        // 邮箱脱敏
+        assert_eq!(items[0]["account"], "a***@te***");
+        assert_eq!(items[0]["status"], 429);
+        assert_eq!(items[0]["strategy"], "fixed_delay");
+        assert_eq!(items[0]["rotated"], true);
+        // 503 保持同一账号重试
+        assert_eq!(items[1]["strategy"], "exponential_backoff");
+        assert_eq!(items[1]["rotated"], false);
+        assert!(items[2]["strategy"].is_null());
+        assert_eq!(items[2]["category"], "network");
+        assert_eq!(items[2]["rotated"], true);
+        assert_eq!(items[3], json!({ "account": "c***@te***", "status": 200 }));
+
+        // 所有尝试均失败: 耗尽错误同样附带，末项不再有成功账号
+        let resp = with_rotation_trace(
+            StatusCode::TOO_MANY_REQUESTS.into_response(),
+            &attempts,
+            None,
+            true,
+        );
+        let items = decode_trace(&resp);
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[2]["category"], "network");
+        assert_eq!(items[2]["rotated"], false);
+    }
+
+    #[tokio::test]
+    async fn test_account_override_pins_specific_account() {
+        use axum::http::HeaderValue;
+
+        let pool = TestPool::new(&[("acc1", "a@test.com"), ("acc2", "b@test.com")]);
+        let token_manager = pool.token_manager().await;
+
+        let security = crate::proxy::ProxySecurityConfig {
+            auth_mode: crate::proxy::ProxyAuthMode::Strict,
+            api_key: "sk-api".to_string(),
+            admin_password: Some("admin123".to_string()),
+            api_keys: Vec::new(),
+            allow_lan_access: false,
+            port: 8045,
+            security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-account-override", HeaderValue::from_static("b@test.com"));
+
+        // 普通 API key 不允许指定账号
+        headers.insert("authorization", HeaderValue::from_static("Bearer sk-api"));
+        let (status, _) = resolve_account_override(&headers, &security, &token_manager).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        headers.insert("x-admin-token", HeaderValue::from_static("wrong"));
+        let (status, _) = resolve_account_override(&headers, &security, &token_manager).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // 代理 key + X-Admin-Token 管理凭据 -> 允许
+        headers.insert("x-admin-token", HeaderValue::from_static("admin123"));
+        assert!(resolve_account_override(&headers, &security, &token_manager).is_ok());
+        headers.remove("x-admin-token");
+
+        // 管理凭据 -> 固定到指定账号，X-Account-Email 即返回的 email
+        headers.insert("authorization", HeaderValue::from_static("Bearer admin123"));
+        let account_override = resolve_account_override(&headers, &security, &token_manager).unwrap();
+        assert_eq!(account_override.as_deref(), Some("b@test.com"));
+        for attempt in 0..3 {
+            let (_, project_id, email, account_id, _) = token_manager
+                .get_token_with_override(
+                    account_override.as_deref(),
+                    "gemini",
+                    attempt > 0,
+                    Some("sid1"),
+                    "gemini-2.5-flash",
+                )
+                .await
+                .unwrap();
+            assert_eq!(email, "b@test.com");
+            assert_eq!(account_id, "acc2");
+            assert_eq!(project_id, "pid-acc2");
+        }
+
+        // 不在池中的账号 -> 400
+        headers.insert("x-account-override", HeaderValue::from_static("nobody@test.com"));
+        let (status, message) = resolve_account_override(&headers, &security, &token_manager).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("nobody@test.com"));
+
+        // 未携带请求头 -> 正常轮换
+        assert!(resolve_account_override(&HeaderMap::new(), &security, &token_manager)
+            .unwrap()
+            .is_none());
+
+    }
+
+    #[tokio::test]
+    async fn test_images_edits_rejects_private_image_urls() {
+        use tower::ServiceExt;
+
+        let pool = TestPool::new(&[]);
+        let token_manager = pool.token_manager().await;
+        let upstream = std::sync::Arc::new(crate::proxy::upstream::client::UpstreamClient::new(
+            None, None,
+        ));
+        let app = axum::Router::new()
+            .route(
+                "/v1/images/edits",
+                axum::routing::post(handle_images_edits),
+            )
+            .with_state(AppState::for_test(token_manager, upstream));
+
+        // 云元数据地址 / 内网地址: 主图与仅有的参考图都在下载前被拒绝
+        for (field, url) in [
+            ("image_url", "http://169.254.169.254/latest/meta-data/"),
+            ("image_urls", "http://127.0.0.1:1/secret.png"),
+        ] {
+            let boundary = "x-edits-boundary";
+            let body = format!(
+                "--{b}\r\nContent-Disposition: form-data; name=\"prompt\"\r\n\r\nedit\r\n\
+                 --{b}\r\nContent-Disposition: form-data; name=\"{field}\"\r\n\r\n{url}\r\n\
+                 --{b}--\r\n",
+                b = boundary,
+                field = field,
+                url = url
+            );
+            let req = axum::http::Request::builder()
+                .method("POST")
+                .uri("/v1/images/edits")
+                .header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", boundary),
+                )
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", field);
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let message = String::from_utf8_lossy(&body);
+            assert!(message.contains("not a public address"), "{}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_end_user_rate_limit_applies_to_responses_and_completions() {
+        let pool = TestPool::new(&[]);
+        let state = AppState::for_test(
+            pool.token_manager().await,
+            std::sync::Arc::new(crate::proxy::upstream::client::UpstreamClient::new(
+                None, None,
+            )),
+        );
+        *state.user_rate_limit.write().await = crate::proxy::config::UserRateLimitConfig {
+            requests_per_minute: 1,
+            tokens_per_minute: 0,
+        };
+        let send =
+            |body: Value| handle_completions(State(state.clone()), HeaderMap::new(), Json(body));
+
+        // /v1/responses: 第一次通过限流 (账号池为空，返回 503)，第二次被限流
+        let responses_body = json!({ "model": "gpt-4o", "input": "hi", "user": "tenant-r" });
+        let resp = send(responses_body.clone()).await;
+        assert_ne!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = send(responses_body).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["X-RateLimit-Limit"], "1");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "user_rate_limit_exceeded");
+
+        // /v1/completions 共用同一计数
+        let resp = send(json!({ "model": "gpt-4o", "prompt": "hi", "user": "tenant-r" })).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // 其他 user 不受影响
+        let resp = send(json!({ "model": "gpt-4o", "prompt": "hi", "user": "tenant-c" })).await;
+        assert_ne!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(state.user_usage.get("tenant-c").is_some());
+    }
+}
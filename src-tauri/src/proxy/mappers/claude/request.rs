@@ -52,17 +52,13 @@ impl SafetyThreshold {
 }
 
 /// Build safety settings based on configuration
-fn build_safety_settings() -> Value {
+/// [NEW] 不支持 CIVIC_INTEGRITY 的模型省略该类别
+fn build_safety_settings(original_model: &str, mapped_model: &str) -> Value {
     let threshold = SafetyThreshold::from_env();
-    let threshold_str = threshold.to_gemini_threshold();
-
-    json!([
-        { "category": "HARM_CATEGORY_HARASSMENT", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": threshold_str },
-        { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": threshold_str },
-    ])
+    crate::proxy::mappers::common_utils::build_safety_settings(
+        threshold.to_gemini_threshold(),
+        crate::proxy::mappers::common_utils::supports_civic_integrity(original_model, mapped_model),
+    )
 }
 
 /// 清理消息中的 cache_control 字段
@@ -558,7 +554,7 @@ pub fn transform_claude_request_in(
     let tools = build_tools(&claude_req.tools, has_web_search_tool)?;
 
     // 5. Safety Settings (configurable via GEMINI_SAFETY_THRESHOLD env var)
-    let safety_settings = build_safety_settings();
+    let safety_settings = build_safety_settings(&claude_req.model, &mapped_model);
 
     // Build inner request
     let mut inner_request = json!({
@@ -820,8 +816,8 @@ fn build_system_instruction(
 
     // [NEW] 注入全局系统提示词 (紧跟 Antigravity 身份之后)
     let global_prompt_config = crate::proxy::config::get_global_system_prompt();
-    if global_prompt_config.enabled && !global_prompt_config.content.trim().is_empty() {
-        parts.push(json!({"text": global_prompt_config.content}));
+    if let Some(prefix) = global_prompt_config.prefix() {
+        parts.push(json!({"text": prefix}));
     }
 
     // 添加用户的系统提示词
@@ -843,6 +839,11 @@ fn build_system_instruction(
         }
     }
 
+    // [NEW] 全局追加内容 (位于用户系统提示词之后)
+    if let Some(suffix) = global_prompt_config.suffix() {
+        parts.push(json!({"text": suffix}));
+    }
+
     // [NEW] MCP XML Bridge: 如果存在 mcp__ 开头的工具，注入专用的调用协议
     // 这能有效规避部分 MCP 链路在标准的 tool_use 协议下解析不稳的问题
     if has_mcp_tools {
@@ -671,4 +671,94 @@ mod tests {
             _ => panic!("Expected Text block"),
         }
     }
+
+    #[test]
+    fn test_tool_use_round_trip() {
+        use crate::proxy::mappers::claude::request::transform_claude_request_in;
+
+        // 请求: 工具定义 + 上一轮 tool_use / tool_result 历史
+        let req: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "system": "You are a weather bot.",
+            "tools": [{
+                "name": "get_weather",
+                "description": "Get the weather",
+                "input_schema": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"]
+                }
+            }],
+            "messages": [
+                { "role": "user", "content": "Weather in Paris?" },
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1", "content": "18C, sunny" }
+                ]}
+            ]
+        }))
+        .unwrap();
+        let body = transform_claude_request_in(&req, "test-project", false).unwrap();
+        let request = &body["request"];
+        assert_eq!(
+            request["tools"][0]["functionDeclarations"][0]["name"],
+            "get_weather"
+        );
+        let contents = request["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["parts"][0]["functionCall"]["name"], "get_weather");
+        assert_eq!(contents[1]["parts"][0]["functionCall"]["args"]["city"], "Paris");
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"]["name"],
+            "get_weather"
+        );
+
+        // 响应: functionCall -> tool_use 内容块
+        let gemini_resp = GeminiResponse {
+            candidates: Some(vec![Candidate {
+                content: Some(GeminiContent {
+                    role: "model".to_string(),
+                    parts: vec![GeminiPart {
+                        text: None,
+                        thought: None,
+                        thought_signature: None,
+                        function_call: Some(FunctionCall {
+                            name: "get_weather".to_string(),
+                            id: None,
+                            args: Some(serde_json::json!({ "city": "Berlin" })),
+                        }),
+                        function_response: None,
+                        inline_data: None,
+                    }],
+                }),
+                finish_reason: Some("STOP".to_string()),
+                index: Some(0),
+                grounding_metadata: None,
+            }]),
+            usage_metadata: None,
+            model_version: Some("gemini-2.5-flash".to_string()),
+            response_id: Some("resp_789".to_string()),
+        };
+        let claude_resp = transform_response(
+            &gemini_resp,
+            false,
+            1_000_000,
+            None,
+            "gemini-2.5-flash".to_string(),
+            3,
+        )
+        .unwrap();
+        assert_eq!(claude_resp.stop_reason, "tool_use");
+        match &claude_resp.content[0] {
+            ContentBlock::ToolUse { id, name, input, .. } => {
+                assert!(!id.is_empty());
+                assert_eq!(name, "get_weather");
+                assert_eq!(input["city"], "Berlin");
+            }
+            other => panic!("Expected ToolUse block, got {:?}", other),
+        }
+    }
 }
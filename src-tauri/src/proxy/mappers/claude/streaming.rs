@@ -1110,4 +1110,46 @@ mod tests {
         // 3. content_block_stop
         assert!(output.contains(r#""type":"content_block_stop""#));
     }
+
+    #[test]
+    fn test_text_stream_event_sequence() {
+        let mut state = StreamingState::new();
+        let mut chunks = vec![state.emit_message_start(&json!({
+            "responseId": "resp_1",
+            "modelVersion": "gemini-2.5-flash"
+        }))];
+
+        let part = GeminiPart {
+            text: Some("Hello".to_string()),
+            function_call: None,
+            inline_data: None,
+            thought: None,
+            thought_signature: None,
+            function_response: None,
+        };
+        chunks.extend(PartProcessor::new(&mut state).process(&part));
+        chunks.extend(state.emit_finish(Some("STOP"), None));
+
+        let output = chunks
+            .iter()
+            .map(|b| String::from_utf8(b.to_vec()).unwrap())
+            .collect::<String>();
+        let events: Vec<&str> = output
+            .lines()
+            .filter_map(|l| l.strip_prefix("event: "))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert!(output.contains(r#""text":"Hello""#));
+        assert!(output.contains(r#""stop_reason":"end_turn""#));
+    }
 }
@@ -1,7 +1,11 @@
 // Common utilities for request mapping across all protocols
 // Provides unified grounding/networking logic
 
+use super::model_capabilities::{resolve_model_capabilities, ModelCapabilities};
 use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 
 /// Request configuration after grounding resolution
 #[derive(Debug, Clone)]
@@ -14,8 +18,36 @@ pub struct RequestConfig {
     pub final_model: String,
     /// Image generation configuration (if request_type is image_gen)
     pub image_config: Option<Value>,
+    /// [NEW] 账号路由用的工作负载分类: "code" / "chat" / "image_gen" (不发往上游)
+    pub workload: String,
+    /// [NEW] 映射后模型的能力 (见 model_capabilities)，用于特性开关
+    pub capabilities: ModelCapabilities,
 }
 
+/// 代码类工作负载 (Codex / 编码助手)
+pub const WORKLOAD_CODE: &str = "code";
+/// 普通对话工作负载
+pub const WORKLOAD_CHAT: &str = "chat";
+
+// 视为代码助手的工具名 (Codex / Claude Code / Gemini CLI 等常见本地执行与编辑工具)
+const CODE_TOOL_NAMES: &[&str] = &[
+    "shell",
+    "bash",
+    "local_shell",
+    "exec_command",
+    "run_shell_command",
+    "apply_patch",
+    "str_replace_editor",
+    "str_replace_based_edit_tool",
+    "text_editor",
+    "edit_file",
+    "write_file",
+    "read_file",
+];
+
+// 请求体超过该长度且包含代码块时视为携带大段文件上下文
+const LARGE_CODE_CONTEXT_CHARS: usize = 32 * 1024;
+
 pub fn resolve_request_config(
     original_model: &str,
     mapped_model: &str,
@@ -23,6 +55,151 @@ pub fn resolve_request_config(
     size: Option<&str>,    // [NEW] Image size parameter
     quality: Option<&str>, // [NEW] Image quality parameter
     body: Option<&Value>,  // [NEW] Request body for Gemini native imageConfig
+) -> RequestConfig {
+    let mut config = detect_request_config(original_model, mapped_model, tools, size, quality, body);
+
+    // [NEW] 按模型配置中的 request_type 强制路由分组 (仅影响账号调度，不改变上游 requestType)
+    let forced = crate::proxy::config::resolve_model_profile(original_model, mapped_model)
+        .and_then(|p| p.request_type)
+        .filter(|t| !t.is_empty());
+
+    // [NEW] 细分工作负载，供 get_token 按分类路由到指定账号
+    if let Some(forced) = forced {
+        tracing::info!(
+            "[Common-Utils] request_type override for {}: routing as {} (upstream type: {})",
+            original_model,
+            forced,
+            config.request_type
+        );
+        config.workload = forced;
+    } else if config.request_type == "image_gen" {
+        config.workload = "image_gen".to_string();
+    } else {
+        let (workload, reason) = classify_workload(tools, body);
+        tracing::debug!(
+            "[Common-Utils] Workload for {}: {} ({})",
+            original_model,
+            workload,
+            reason
+        );
+        config.workload = workload.to_string();
+    }
+
+    config.capabilities = resolve_model_capabilities(original_model, mapped_model);
+    tracing::debug!(
+        "[Common-Utils] Capabilities for {}: {:?}",
+        mapped_model,
+        config.capabilities
+    );
+    config
+}
+
+/// 按工具定义与请求体区分代码助手与普通对话，返回 (分类, 判定依据)
+pub fn classify_workload(
+    tools: &Option<Vec<Value>>,
+    body: Option<&Value>,
+) -> (&'static str, String) {
+    if let Some(name) = find_code_tool(tools) {
+        return (WORKLOAD_CODE, format!("code tool `{}`", name));
+    }
+    if let Some(body) = body {
+        // Codex (Responses API) 风格: instructions + input
+        if body.get("instructions").is_some() && body.get("input").is_some() {
+            return (WORKLOAD_CODE, "codex-style input".to_string());
+        }
+        // [FIX] 只统计消息正文，避免 base64 图片、工具 schema 等撑大长度
+        let texts = message_texts(body);
+        let chars: usize = texts.iter().map(|t| t.chars().count()).sum();
+        if chars > LARGE_CODE_CONTEXT_CHARS && texts.iter().any(|t| t.contains("```")) {
+            return (
+                WORKLOAD_CODE,
+                format!("large file context ({} chars)", chars),
+            );
+        }
+    }
+    (WORKLOAD_CHAT, "no code signals".to_string())
+}
+
+/// 提取请求中的消息文本: OpenAI / Claude 的 messages、Gemini 的 contents (含 request 包装)、Responses 的 input
+fn message_texts(body: &Value) -> Vec<&str> {
+    fn push_content<'a>(content: &'a Value, out: &mut Vec<&'a str>) {
+        match content {
+            Value::String(text) => out.push(text),
+            Value::Array(items) => {
+                for item in items {
+                    if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
+                        out.push(text);
+                    } else if let Some(inner) = item.get("content") {
+                        // Responses input item: { role, content: [...] }
+                        push_content(inner, out);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    for msg in body
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .into_iter()
+        .flatten()
+    {
+        if let Some(content) = msg.get("content") {
+            push_content(content, &mut out);
+        }
+    }
+    let contents = body
+        .get("contents")
+        .or_else(|| body.get("request").and_then(|r| r.get("contents")))
+        .and_then(|c| c.as_array());
+    for content in contents.into_iter().flatten() {
+        if let Some(parts) = content.get("parts") {
+            push_content(parts, &mut out);
+        }
+    }
+    if let Some(input) = body.get("input") {
+        push_content(input, &mut out);
+    }
+    out
+}
+
+/// 查找代码类工具，兼容 OpenAI / Claude / Gemini 原生的工具声明格式
+fn find_code_tool(tools: &Option<Vec<Value>>) -> Option<String> {
+    let is_code = |name: &str| CODE_TOOL_NAMES.contains(&name.to_ascii_lowercase().as_str());
+    for tool in tools.as_deref().unwrap_or_default() {
+        let names = [
+            tool.get("name"),
+            tool.get("type"),
+            tool.get("function").and_then(|f| f.get("name")),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(
+            tool.get("functionDeclarations")
+                .and_then(|v| v.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|decl| decl.get("name")),
+        );
+        for name in names.filter_map(|v| v.as_str()) {
+            if is_code(name) {
+                return Some(name.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// 根据模型名、工具定义及图片参数自动识别请求类型
+fn detect_request_config(
+    original_model: &str,
+    mapped_model: &str,
+    tools: &Option<Vec<Value>>,
+    size: Option<&str>,
+    quality: Option<&str>,
+    body: Option<&Value>,
 ) -> RequestConfig {
     // 1. Image Generation Check (Priority)
     if mapped_model.starts_with("gemini-3-pro-image") {
@@ -43,6 +220,8 @@ pub fn resolve_request_config(
                         inject_google_search: false,
                         final_model: parsed_base_model,
                         image_config: Some(image_config.clone()),
+                        workload: "image_gen".to_string(),
+                        capabilities: ModelCapabilities::default(),
                     };
                 }
             }
@@ -57,6 +236,8 @@ pub fn resolve_request_config(
             inject_google_search: false,
             final_model: parsed_base_model,
             image_config: Some(image_config),
+            workload: "image_gen".to_string(),
+            capabilities: ModelCapabilities::default(),
         };
     }
 
@@ -120,6 +301,8 @@ pub fn resolve_request_config(
         inject_google_search: enable_networking,
         final_model,
         image_config: None,
+        workload: WORKLOAD_CHAT.to_string(),
+        capabilities: ModelCapabilities::default(),
     }
 }
 
@@ -344,6 +527,101 @@ pub fn deep_clean_undefined(value: &mut Value) {
     }
 }
 
+const CIVIC_INTEGRITY_CATEGORY: &str = "HARM_CATEGORY_CIVIC_INTEGRITY";
+
+// 运行时记录的"不支持"在该时长后失效，上游为模型开放该类别后无需重启即可恢复
+const CIVIC_INTEGRITY_REJECTION_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// 运行时返回过 CIVIC_INTEGRITY 相关 400 的模型 (映射后的上游模型名) -> 记录时间
+fn civic_integrity_rejected_models() -> &'static Mutex<HashMap<String, SystemTime>> {
+    static MODELS: OnceLock<Mutex<HashMap<String, SystemTime>>> = OnceLock::new();
+    MODELS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// 模型是否接受 HARM_CATEGORY_CIVIC_INTEGRITY 安全类别
+/// 能力表 / model_profiles.omit_civic_integrity 标记的模型，以及最近 CIVIC_INTEGRITY_REJECTION_TTL 内
+/// 曾因该类别返回 400 的模型视为不支持
+pub fn supports_civic_integrity(original_model: &str, mapped_model: &str) -> bool {
+    if !resolve_model_capabilities(original_model, mapped_model).civic_integrity {
+        return false;
+    }
+    let Ok(mut models) = civic_integrity_rejected_models().lock() else {
+        return true;
+    };
+    match models.get(mapped_model) {
+        Some(marked_at)
+            if marked_at.elapsed().unwrap_or(Duration::ZERO) < CIVIC_INTEGRITY_REJECTION_TTL =>
+        {
+            false
+        }
+        Some(_) => {
+            models.remove(mapped_model);
+            true
+        }
+        None => true,
+    }
+}
+
+/// 上游 400 是否由不支持的 CIVIC_INTEGRITY 安全类别引起
+pub fn is_civic_integrity_rejection(status: u16, error_text: &str) -> bool {
+    status == 400 && error_text.contains("CIVIC_INTEGRITY")
+}
+
+/// 记录模型不支持 CIVIC_INTEGRITY，之后的请求 (含本次重试) 在 TTL 内不再携带该类别
+pub fn mark_civic_integrity_unsupported(mapped_model: &str) {
+    if let Ok(mut models) = civic_integrity_rejected_models().lock() {
+        if models
+            .insert(mapped_model.to_string(), SystemTime::now())
+            .is_none()
+        {
+            tracing::warn!(
+                "[Common-Utils] {} rejected {}, omitting it from safetySettings",
+                mapped_model,
+                CIVIC_INTEGRITY_CATEGORY
+            );
+        }
+    }
+}
+
+/// 请求体 (裸请求或 v1internal 包装) 的 safetySettings 是否包含 CIVIC_INTEGRITY 类别
+pub fn has_civic_integrity_setting(body: &Value) -> bool {
+    [&body["safetySettings"], &body["request"]["safetySettings"]]
+        .into_iter()
+        .filter_map(|settings| settings.as_array())
+        .flatten()
+        .any(|setting| setting["category"] == CIVIC_INTEGRITY_CATEGORY)
+}
+
+/// 从请求体 (裸请求或 v1internal 包装) 的 safetySettings 中移除 CIVIC_INTEGRITY 类别
+pub fn strip_civic_integrity_setting(body: &mut Value) {
+    let strip = |settings: Option<&mut Value>| {
+        if let Some(list) = settings.and_then(|s| s.as_array_mut()) {
+            list.retain(|setting| setting["category"] != CIVIC_INTEGRITY_CATEGORY);
+        }
+    };
+    strip(body.get_mut("safetySettings"));
+    strip(body.get_mut("request").and_then(|r| r.get_mut("safetySettings")));
+}
+
+/// 构建 safetySettings，按模型能力决定是否包含 CIVIC_INTEGRITY 类别
+pub fn build_safety_settings(threshold: &str, include_civic_integrity: bool) -> Value {
+    let mut categories = vec![
+        "HARM_CATEGORY_HARASSMENT",
+        "HARM_CATEGORY_HATE_SPEECH",
+        "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+        "HARM_CATEGORY_DANGEROUS_CONTENT",
+    ];
+    if include_civic_integrity {
+        categories.push(CIVIC_INTEGRITY_CATEGORY);
+    }
+    Value::Array(
+        categories
+            .into_iter()
+            .map(|category| json!({ "category": category, "threshold": threshold }))
+            .collect(),
+    )
+}
+
 /// Detects if the tool list contains a request for networking/web search.
 /// Supported keywords: "web_search", "google_search", "web_search_20250305"
 pub fn detects_networking_tool(tools: &Option<Vec<Value>>) -> bool {
@@ -477,6 +755,38 @@ mod tests {
         assert!(!config.inject_google_search);
     }
 
+    #[test]
+    fn test_model_profile_request_type_override() {
+        use crate::proxy::config::{insert_model_profile, ModelProfile};
+
+        // 合并到现有配置，避免与其他并行测试互相覆盖
+        insert_model_profile(
+            "gpt-4o-realtime*",
+            ModelProfile {
+                request_type: Some("realtime".to_string()),
+                ..Default::default()
+            },
+        );
+
+        // 路由分组强制为 realtime，上游 requestType 仍为自动识别的 agent
+        let config = resolve_request_config(
+            "gpt-4o-realtime-preview",
+            "gemini-2.5-flash",
+            &None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(config.workload, "realtime");
+        assert_eq!(config.request_type, "agent");
+        assert_eq!(config.final_model, "gemini-2.5-flash");
+
+        // 未命中配置的模型仍走自动识别
+        let config = resolve_request_config("gpt-4o", "gemini-2.5-flash", &None, None, None, None);
+        assert_eq!(config.workload, WORKLOAD_CHAT);
+        assert_eq!(config.request_type, "agent");
+    }
+
     #[test]
     fn test_gemini_native_tool_detection() {
         let tools = Some(vec![json!({
@@ -487,6 +797,54 @@ mod tests {
         assert!(detects_networking_tool(&tools));
     }
 
+    #[test]
+    fn test_shell_tool_request_classified_as_code() {
+        let tools = Some(vec![json!({
+            "type": "function",
+            "function": { "name": "shell", "parameters": { "type": "object" } }
+        })]);
+        let config =
+            resolve_request_config("gpt-5-codex", "gemini-3-flash", &tools, None, None, None);
+        assert_eq!(config.workload, WORKLOAD_CODE);
+        // 发往上游的 request_type 不受影响
+        assert_eq!(config.request_type, "agent");
+
+        // Gemini 原生声明与 Codex 内置工具
+        let gemini_tools = Some(vec![json!({
+            "functionDeclarations": [{ "name": "run_shell_command" }]
+        })]);
+        assert_eq!(classify_workload(&gemini_tools, None).0, WORKLOAD_CODE);
+        let codex_tools = Some(vec![json!({ "type": "local_shell" })]);
+        assert_eq!(classify_workload(&codex_tools, None).0, WORKLOAD_CODE);
+
+        // Codex 风格输入
+        let body = json!({ "instructions": "You are a coding agent", "input": "fix the bug" });
+        assert_eq!(classify_workload(&None, Some(&body)).0, WORKLOAD_CODE);
+
+        // 大段文件上下文只按消息正文计算: 体积来自图片 base64 时不视为代码
+        let fence = "```rust\nfn main() {}\n```";
+        let image = format!(
+            "data:image/png;base64,{}",
+            "A".repeat(LARGE_CODE_CONTEXT_CHARS * 2)
+        );
+        let body = json!({ "messages": [{ "role": "user", "content": [
+            { "type": "text", "text": fence },
+            { "type": "image_url", "image_url": { "url": image } }
+        ]}]});
+        assert_eq!(classify_workload(&None, Some(&body)).0, WORKLOAD_CHAT);
+        let source = format!("{}\n{}", fence, "x".repeat(LARGE_CODE_CONTEXT_CHARS));
+        let body = json!({ "messages": [{ "role": "user", "content": source }] });
+        assert_eq!(classify_workload(&None, Some(&body)).0, WORKLOAD_CODE);
+        let body = json!({ "contents": [{ "role": "user", "parts": [{ "text": source }] }] });
+        assert_eq!(classify_workload(&None, Some(&body)).0, WORKLOAD_CODE);
+
+        // 普通对话工具不视为代码
+        let chat_tools = Some(vec![json!({ "name": "get_weather" })]);
+        let config =
+            resolve_request_config("gpt-4o", "gemini-3-flash", &chat_tools, None, None, None);
+        assert_eq!(config.workload, WORKLOAD_CHAT);
+    }
+
     #[test]
     fn test_online_suffix_force_grounding() {
         let config =
@@ -617,4 +975,24 @@ mod tests {
         assert_eq!(calculate_aspect_ratio_from_size("0x1080"), "1:1");
         assert_eq!(calculate_aspect_ratio_from_size("abc x def"), "1:1");
     }
+
+    #[test]
+    fn test_civic_integrity_rejection_expires() {
+        let model = "gemini-civic-ttl-test-model";
+        assert!(supports_civic_integrity(model, model));
+        mark_civic_integrity_unsupported(model);
+        assert!(!supports_civic_integrity(model, model));
+
+        // 超过 TTL 后恢复携带该类别
+        let expired = SystemTime::now() - CIVIC_INTEGRITY_REJECTION_TTL - Duration::from_secs(1);
+        civic_integrity_rejected_models()
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), expired);
+        assert!(supports_civic_integrity(model, model));
+        assert!(!civic_integrity_rejected_models()
+            .lock()
+            .unwrap()
+            .contains_key(model));
+    }
 }
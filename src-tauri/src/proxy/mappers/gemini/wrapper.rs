@@ -1,12 +1,31 @@
 // Gemini v1internal 包装/解包
 use serde_json::{json, Value};
 
+use crate::proxy::config::TransformConfig;
+
 /// 包装请求体为 v1internal 格式
 pub fn wrap_request(
     body: &Value,
     project_id: &str,
     mapped_model: &str,
     session_id: Option<&str>,
+) -> Value {
+    wrap_request_with_config(
+        body,
+        project_id,
+        mapped_model,
+        session_id,
+        &TransformConfig::current(),
+    )
+}
+
+/// 与 wrap_request 相同，Thinking Budget / 全局系统提示词使用传入的配置
+pub fn wrap_request_with_config(
+    body: &Value,
+    project_id: &str,
+    mapped_model: &str,
+    session_id: Option<&str>,
+    transform_config: &TransformConfig,
 ) -> Value {
     // 优先使用传入的 mapped_model，其次尝试从 body 获取
     let original_model = body
@@ -153,7 +172,7 @@ pub fn wrap_request(
         if let Some(thinking_config) = gen_config.get_mut("thinkingConfig") {
             if let Some(budget_val) = thinking_config.get("thinkingBudget") {
                 if let Some(budget) = budget_val.as_u64() {
-                    let tb_config = crate::proxy::config::get_thinking_budget_config();
+                    let tb_config = &transform_config.thinking_budget;
                     let final_budget = match tb_config.mode {
                         crate::proxy::config::ThinkingBudgetMode::Passthrough => {
                             // 透传模式：不做任何修改，完全使用上游传入值
@@ -354,29 +373,33 @@ pub fn wrap_request(
                     }
 
                     // [NEW] 注入全局系统提示词 (紧跟 Antigravity 身份之后，用户指令之前)
-                    let global_prompt_config = crate::proxy::config::get_global_system_prompt();
-                    if global_prompt_config.enabled
-                        && !global_prompt_config.content.trim().is_empty()
-                    {
+                    let global_prompt_config = &transform_config.global_system_prompt;
+                    if let Some(prefix) = global_prompt_config.prefix() {
                         // 插入位置：Antigravity 身份之后 (index 1)
                         let insert_pos = if has_antigravity { 1 } else { 1 };
                         if insert_pos <= parts_array.len() {
-                            parts_array
-                                .insert(insert_pos, json!({"text": global_prompt_config.content}));
+                            parts_array.insert(insert_pos, json!({"text": prefix}));
                         } else {
-                            parts_array.push(json!({"text": global_prompt_config.content}));
+                            parts_array.push(json!({"text": prefix}));
                         }
                     }
+                    // [NEW] 全局追加内容位于用户指令之后
+                    if let Some(suffix) = global_prompt_config.suffix() {
+                        parts_array.push(json!({"text": suffix}));
+                    }
                 }
             }
         } else {
             // 没有 systemInstruction,创建一个新的
             let mut parts = vec![json!({"text": antigravity_identity})];
             // [NEW] 注入全局系统提示词
-            let global_prompt_config = crate::proxy::config::get_global_system_prompt();
-            if global_prompt_config.enabled && !global_prompt_config.content.trim().is_empty() {
-                parts.push(json!({"text": global_prompt_config.content}));
-            }
+            let global_prompt_config = &transform_config.global_system_prompt;
+            parts.extend(
+                [global_prompt_config.prefix(), global_prompt_config.suffix()]
+                    .into_iter()
+                    .flatten()
+                    .map(|text| json!({"text": text})),
+            );
             inner_request["systemInstruction"] = json!({
                 "role": "user",
                 "parts": parts
@@ -396,6 +419,39 @@ pub fn wrap_request(
     final_request
 }
 
+/// [NEW] 原生透传: 仅注入 project，不做任何内容转换，返回 (model, v1internal 请求体)
+/// 接受原生 generateContent 请求体 (顶层附带 model) 或完整的 v1internal 信封 ({ model, request })
+pub fn wrap_raw_request(body: &Value, project_id: &str) -> Result<(String, Value), String> {
+    if !body.is_object() {
+        return Err("Request body must be a JSON object".to_string());
+    }
+    let model = body
+        .get("model")
+        .and_then(|v| v.as_str())
+        .map(|m| m.trim_start_matches("models/"))
+        .filter(|m| !m.is_empty())
+        .ok_or_else(|| "Missing \"model\" in passthrough request body".to_string())?
+        .to_string();
+
+    let mut envelope = if body.get("request").is_some_and(|r| r.is_object()) {
+        body.clone()
+    } else {
+        let mut inner_request = body.clone();
+        if let Some(obj) = inner_request.as_object_mut() {
+            obj.remove("model");
+        }
+        json!({
+            "requestId": format!("agent-{}", uuid::Uuid::new_v4()),
+            "request": inner_request,
+            "userAgent": "antigravity",
+            "requestType": "agent"
+        })
+    };
+    envelope["project"] = json!(project_id);
+    envelope["model"] = json!(model);
+    Ok((model, envelope))
+}
+
 #[cfg(test)]
 mod test_fixes {
     use super::*;
@@ -651,16 +707,16 @@ mod tests {
 
     #[test]
     fn test_gemini_pro_thinking_budget_processing() {
-        // Update global config to Custom mode to verify logic execution
-        use crate::proxy::config::{
-            update_thinking_budget_config, ThinkingBudgetConfig, ThinkingBudgetMode,
-        };
+        // Use Custom mode to verify logic execution
+        use crate::proxy::config::{ThinkingBudgetConfig, ThinkingBudgetMode};
 
-        // Save old config (optional, but good practice if tests ran in parallel, but here it's fine)
-        update_thinking_budget_config(ThinkingBudgetConfig {
-            mode: ThinkingBudgetMode::Custom,
-            custom_value: 1024, // Distinct value
-        });
+        let config = TransformConfig {
+            thinking_budget: ThinkingBudgetConfig {
+                mode: ThinkingBudgetMode::Custom,
+                custom_value: 1024, // Distinct value
+            },
+            ..Default::default()
+        };
 
         let body = json!({
             "model": "gemini-3-pro-preview",
@@ -673,7 +729,8 @@ mod tests {
         });
 
         // Test with Pro model
-        let result = wrap_request(&body, "test-proj", "gemini-3-pro-preview", None);
+        let result =
+            wrap_request_with_config(&body, "test-proj", "gemini-3-pro-preview", None, &config);
         let req = result.get("request").unwrap();
         let gen_config = req.get("generationConfig").unwrap();
 
@@ -687,20 +744,18 @@ mod tests {
             budget, 1024,
             "Budget should be overridden to 1024 by custom config, proving logic execution"
         );
-
-        // Restore default (Auto 24576)
-        update_thinking_budget_config(ThinkingBudgetConfig::default());
     }
 
     #[test]
     fn test_gemini_pro_auto_inject_thinking() {
-        // Reset thinking budget to auto mode at the start to avoid interference from parallel tests
-        crate::proxy::config::update_thinking_budget_config(
-            crate::proxy::config::ThinkingBudgetConfig {
+        // Thinking budget in auto mode
+        let config = TransformConfig {
+            thinking_budget: crate::proxy::config::ThinkingBudgetConfig {
                 mode: crate::proxy::config::ThinkingBudgetMode::Auto,
                 custom_value: 24576,
             },
-        );
+            ..Default::default()
+        };
 
         // Request WITHOUT thinkingConfig
         let body = json!({
@@ -710,7 +765,8 @@ mod tests {
         });
 
         // Test with Pro model
-        let result = wrap_request(&body, "test-proj", "gemini-3-pro-preview", None);
+        let result =
+            wrap_request_with_config(&body, "test-proj", "gemini-3-pro-preview", None, &config);
         let req = result.get("request").unwrap();
         let gen_config = req.get("generationConfig").unwrap();
 
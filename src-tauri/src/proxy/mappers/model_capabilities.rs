@@ -0,0 +1,196 @@
+// 模型能力表 (Model Capability Registry)
+// 按上游模型名集中描述能力 (思维链、结构化输出、重复惩罚、安全类别、上下文窗口)
+// resolve_request_config / transform_openai_request 据此做特性开关，新增模型只需修改 CAPABILITY_TABLE
+
+use crate::proxy::common::model_mapping::wildcard_match;
+
+/// Gemini 类模型可接受的 thinkingBudget 上限
+pub const THINKING_BUDGET_CAP: i64 = 24576;
+
+const CONTEXT_1M: u32 = 1_048_576;
+const CONTEXT_2M: u32 = 2_097_152;
+
+/// 模型家族 (决定思维签名哨兵值等协议细节)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFamily {
+    Gemini,
+    Claude,
+    Other,
+}
+
+/// 单个模型的能力描述
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    pub family: ModelFamily,
+    /// 支持并默认开启 thinkingConfig (模型名包含 -thinking 时总是开启)
+    pub thinking: bool,
+    /// thinkingBudget 上限，None 表示不限制
+    pub thinking_budget_cap: Option<i64>,
+    /// 支持 responseMimeType = application/json 结构化输出
+    pub structured_output: bool,
+    /// 接受 frequencyPenalty / presencePenalty
+    pub penalties: bool,
+    /// 接受 HARM_CATEGORY_CIVIC_INTEGRITY 安全类别
+    pub civic_integrity: bool,
+    /// 上下文窗口 (token)
+    pub max_context_tokens: u32,
+}
+
+impl ModelCapabilities {
+    const fn new(family: ModelFamily, max_context_tokens: u32) -> Self {
+        Self {
+            family,
+            thinking: false,
+            thinking_budget_cap: None,
+            structured_output: true,
+            penalties: true,
+            civic_integrity: true,
+            max_context_tokens,
+        }
+    }
+
+    const fn with_thinking(mut self) -> Self {
+        self.thinking = true;
+        self
+    }
+
+    const fn with_budget_cap(mut self) -> Self {
+        self.thinking_budget_cap = Some(THINKING_BUDGET_CAP);
+        self
+    }
+
+    const fn without_structured_output(mut self) -> Self {
+        self.structured_output = false;
+        self
+    }
+}
+
+impl Default for ModelCapabilities {
+    fn default() -> Self {
+        Self::new(ModelFamily::Other, CONTEXT_1M)
+    }
+}
+
+/// 能力表: 按顺序匹配小写的上游模型名 (支持 * 通配符)，首个命中者生效
+const CAPABILITY_TABLE: &[(&str, ModelCapabilities)] = &[
+    (
+        "*claude*",
+        ModelCapabilities::new(ModelFamily::Claude, CONTEXT_1M).with_budget_cap(),
+    ),
+    // 图像模型: 不限制 budget，不支持 JSON 输出
+    (
+        "*gemini-3-pro-image*",
+        ModelCapabilities::new(ModelFamily::Gemini, CONTEXT_2M)
+            .with_thinking()
+            .without_structured_output(),
+    ),
+    (
+        "*gemini*-image*",
+        ModelCapabilities::new(ModelFamily::Gemini, CONTEXT_1M).without_structured_output(),
+    ),
+    // [FIX #1557] pro 模型不带 -thinking 后缀也支持思维链
+    (
+        "*gemini-3-pro*",
+        ModelCapabilities::new(ModelFamily::Gemini, CONTEXT_2M)
+            .with_thinking()
+            .with_budget_cap(),
+    ),
+    (
+        "*gemini-2.0-pro*",
+        ModelCapabilities::new(ModelFamily::Gemini, CONTEXT_2M)
+            .with_thinking()
+            .with_budget_cap(),
+    ),
+    (
+        "*gemini*pro*",
+        ModelCapabilities::new(ModelFamily::Gemini, CONTEXT_2M).with_budget_cap(),
+    ),
+    (
+        "*gemini*",
+        ModelCapabilities::new(ModelFamily::Gemini, CONTEXT_1M).with_budget_cap(),
+    ),
+    (
+        "*pro*",
+        ModelCapabilities::new(ModelFamily::Other, CONTEXT_2M),
+    ),
+];
+
+/// 仅按能力表查找 (不含按模型配置覆盖)
+pub fn lookup_model_capabilities(model: &str) -> ModelCapabilities {
+    let model = model.to_lowercase();
+    let mut caps = CAPABILITY_TABLE
+        .iter()
+        .find(|(pattern, _)| wildcard_match(pattern, &model))
+        .map(|(_, caps)| *caps)
+        .unwrap_or_default();
+
+    // 显式的 -thinking 变体总是开启思维链并限制 budget
+    if model.contains("-thinking") {
+        caps.thinking = true;
+        caps.thinking_budget_cap = caps.thinking_budget_cap.or(Some(THINKING_BUDGET_CAP));
+    }
+    caps
+}
+
+/// 查找模型能力，并应用 model_profiles 中的覆盖项 (omit_penalties / omit_civic_integrity)
+pub fn resolve_model_capabilities(original_model: &str, mapped_model: &str) -> ModelCapabilities {
+    let mut caps = lookup_model_capabilities(mapped_model);
+    if let Some(profile) = crate::proxy::config::resolve_model_profile(original_model, mapped_model)
+    {
+        caps.penalties &= !profile.omit_penalties;
+        caps.civic_integrity &= !profile.omit_civic_integrity;
+    }
+    caps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_table_lookup() {
+        let pro = lookup_model_capabilities("gemini-3-pro-high");
+        assert_eq!(pro.family, ModelFamily::Gemini);
+        assert!(pro.thinking);
+        assert_eq!(pro.thinking_budget_cap, Some(THINKING_BUDGET_CAP));
+        assert_eq!(pro.max_context_tokens, CONTEXT_2M);
+
+        let flash = lookup_model_capabilities("gemini-2.5-flash");
+        assert!(!flash.thinking);
+        assert_eq!(flash.thinking_budget_cap, Some(THINKING_BUDGET_CAP));
+        assert_eq!(flash.max_context_tokens, CONTEXT_1M);
+        assert!(lookup_model_capabilities("gemini-2.0-flash-thinking-exp").thinking);
+
+        let claude = lookup_model_capabilities("claude-sonnet-4-5");
+        assert_eq!(claude.family, ModelFamily::Claude);
+        assert!(!claude.thinking);
+        assert!(lookup_model_capabilities("claude-opus-4-5-thinking").thinking);
+
+        let image = lookup_model_capabilities("gemini-3-pro-image");
+        assert!(image.thinking);
+        assert_eq!(image.thinking_budget_cap, None);
+        assert!(!image.structured_output);
+
+        let unknown = lookup_model_capabilities("some-new-model");
+        assert_eq!(unknown, ModelCapabilities::default());
+    }
+
+    #[test]
+    fn test_model_profile_overrides_capabilities() {
+        use crate::proxy::config::{insert_model_profile, ModelProfile};
+
+        insert_model_profile(
+            "capability-probe-model",
+            ModelProfile {
+                omit_penalties: true,
+                omit_civic_integrity: true,
+                ..Default::default()
+            },
+        );
+
+        let caps = resolve_model_capabilities("capability-probe-model", "gemini-2.5-flash");
+        assert!(!caps.penalties);
+        assert!(!caps.civic_integrity);
+        assert!(resolve_model_capabilities("capability-plain-model", "gemini-2.5-flash").penalties);
+    }
+}
@@ -5,12 +5,14 @@ use super::models::*;
 use bytes::Bytes;
 use futures::StreamExt;
 use serde_json::{json, Value};
-use std::collections::HashMap;
 use std::io;
 
 /// Collects an OpenAI SSE stream into a complete OpenAIResponse
+/// 累计内容 (正文 + 思维链 + 工具参数) 超过 max_bytes 时停止读取，返回已收集的内容并将
+/// finish_reason 置为 "length"。max_bytes = 0 表示不限制
 pub async fn collect_stream_to_json<S, E>(
     mut stream: S,
+    max_bytes: usize,
 ) -> Result<OpenAIResponse, String>
 where
     S: futures::Stream<Item = Result<Bytes, E>> + Unpin,
@@ -28,13 +30,42 @@ where
     let mut role: Option<String> = None;
     let mut content_parts: Vec<String> = Vec::new();
     let mut reasoning_parts: Vec<String> = Vec::new();
+    let mut annotations: Vec<Value> = Vec::new();
+    let mut logprobs_content: Vec<Value> = Vec::new();
     let mut finish_reason: Option<String> = None;
-    // Tool calls aggregation: index -> (id, type, name, arguments_parts)
-    let mut tool_calls_map: HashMap<u32, (String, String, String, Vec<String>)> = HashMap::new();
+    let mut content_filter_categories: Option<Vec<String>> = None;
+    let mut tool_calls = ToolCallAccumulator::default();
+    let mut collected_bytes: usize = 0;
+    let mut truncated = false;
+    // 跨 chunk 的未完整行 (SSE 行可能被拆分到多个网络分片，多字节字符也可能被截断)
+    let mut pending: Vec<u8> = Vec::new();
+    let mut stream_ended = false;
 
-    while let Some(chunk_result) = stream.next().await {
-        let chunk = chunk_result.map_err(|e| format!("Stream error: {}", e))?;
-        let text = String::from_utf8_lossy(&chunk);
+    while !stream_ended {
+        if max_bytes > 0 && collected_bytes > max_bytes {
+            tracing::warn!(
+                "[OpenAI-Collector] Collected content exceeded {} bytes, truncating response",
+                max_bytes
+            );
+            truncated = true;
+            break;
+        }
+        match stream.next().await {
+            Some(chunk_result) => {
+                let chunk = chunk_result.map_err(|e| format!("Stream error: {}", e))?;
+                pending.extend_from_slice(&chunk);
+            }
+            None => {
+                // 流结束: 处理末尾没有换行的最后一行
+                stream_ended = true;
+                pending.push(b'\n');
+            }
+        }
+        let Some(last_newline) = pending.iter().rposition(|b| *b == b'\n') else {
+            continue;
+        };
+        let complete: Vec<u8> = pending.drain(..=last_newline).collect();
+        let text = String::from_utf8_lossy(&complete);
 
         for line in text.lines() {
             let line = line.trim();
@@ -74,52 +105,44 @@ where
                                 
                                 // Content
                                 if let Some(c) = delta.get("content").and_then(|v| v.as_str()) {
+                                    collected_bytes += c.len();
                                     content_parts.push(c.to_string());
                                 }
 
                                 // Reasoning Content
                                 if let Some(rc) = delta.get("reasoning_content").and_then(|v| v.as_str()) {
+                                    collected_bytes += rc.len();
                                     reasoning_parts.push(rc.to_string());
                                 }
 
+                                // Grounding citations
+                                if let Some(anns) = delta.get("annotations").and_then(|v| v.as_array()) {
+                                    annotations.extend(anns.iter().cloned());
+                                }
+
                                 // Tool Calls aggregation by index
                                 if let Some(tcs) = delta.get("tool_calls").and_then(|v| v.as_array()) {
                                     for tc in tcs {
-                                        let index = tc.get("index").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
-                                        
-                                        let entry = tool_calls_map.entry(index).or_insert_with(|| {
-                                            (String::new(), String::from("function"), String::new(), Vec::new())
-                                        });
-                                        
-                                        if let Some(id) = tc.get("id").and_then(|v| v.as_str()) {
-                                            if !id.is_empty() {
-                                                entry.0 = id.to_string();
-                                            }
-                                        }
-                                        
-                                        if let Some(tc_type) = tc.get("type").and_then(|v| v.as_str()) {
-                                            if !tc_type.is_empty() {
-                                                entry.1 = tc_type.to_string();
-                                            }
-                                        }
-                                        
-                                        if let Some(func) = tc.get("function") {
-                                            if let Some(name) = func.get("name").and_then(|v| v.as_str()) {
-                                                if !name.is_empty() {
-                                                    entry.2 = name.to_string();
-                                                }
-                                            }
-                                            if let Some(args) = func.get("arguments").and_then(|v| v.as_str()) {
-                                                entry.3.push(args.to_string());
-                                            }
-                                        }
+                                        collected_bytes += tool_calls.apply_delta(tc);
                                     }
                                 }
                             }
 
+                            // 逐 chunk 的对数概率按顺序拼接
+                            if let Some(lp) = choice
+                                .get("logprobs")
+                                .and_then(|v| v.get("content"))
+                                .and_then(|v| v.as_array())
+                            {
+                                logprobs_content.extend(lp.iter().cloned());
+                            }
+
                             if let Some(fr) = choice.get("finish_reason").and_then(|v| v.as_str()) {
                                 finish_reason = Some(fr.to_string());
                             }
+                            if let Some(categories) = choice.get("content_filter_categories") {
+                                content_filter_categories = serde_json::from_value(categories.clone()).ok();
+                            }
                         }
                     }
                 }
@@ -135,26 +158,7 @@ where
         Some(reasoning_parts.join(""))
     };
 
-    // Build aggregated tool_calls
-    let final_tool_calls: Option<Vec<ToolCall>> = if tool_calls_map.is_empty() {
-        None
-    } else {
-        let mut calls: Vec<(u32, ToolCall)> = tool_calls_map
-            .into_iter()
-            .map(|(index, (id, tc_type, name, args_parts))| {
-                (index, ToolCall {
-                    id,
-                    r#type: tc_type,
-                    function: ToolFunction {
-                        name,
-                        arguments: args_parts.join(""),
-                    },
-                })
-            })
-            .collect();
-        calls.sort_by_key(|(index, _)| *index);
-        Some(calls.into_iter().map(|(_, tc)| tc).collect())
-    };
+    let final_tool_calls = tool_calls.finish(&response.id);
 
     let message = OpenAIMessage {
         role: role.unwrap_or("assistant".to_string()),
@@ -163,13 +167,291 @@ where
         tool_calls: final_tool_calls,
         tool_call_id: None,
         name: None,
+        annotations: if annotations.is_empty() { None } else { Some(annotations) },
     };
 
     response.choices.push(Choice {
         index: 0,
         message,
-        finish_reason: finish_reason.or(Some("stop".to_string())),
+        finish_reason: if truncated {
+            Some("length".to_string())
+        } else {
+            finish_reason.or(Some("stop".to_string()))
+        },
+        logprobs: if logprobs_content.is_empty() {
+            None
+        } else {
+            Some(json!({ "content": logprobs_content }))
+        },
+        content_filter_categories,
     });
 
     Ok(response)
 }
+
+/// 流式工具调用的累积状态 (按 index 合并)
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    r#type: String,
+    name: String,
+    arguments: String,
+}
+
+/// 合并 tool_calls 增量: 同一 index 的 arguments 片段按到达顺序拼接，id / name 以首次出现为准
+#[derive(Default)]
+struct ToolCallAccumulator {
+    calls: Vec<(u64, PartialToolCall)>,
+}
+
+impl ToolCallAccumulator {
+    /// 应用一个 delta.tool_calls[i]，返回新增的参数字节数
+    fn apply_delta(&mut self, tc: &Value) -> usize {
+        let id = tc
+            .get("id")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty());
+        let slot = match tc.get("index").and_then(|v| v.as_u64()) {
+            // 同一 index 出现不同的 id: 上游重复使用了 index，作为新的调用追加
+            Some(index) => match self.calls.iter().position(|(i, _)| *i == index) {
+                Some(pos)
+                    if id.is_some_and(|id| {
+                        let existing = &self.calls[pos].1.id;
+                        !existing.is_empty() && existing != id
+                    }) =>
+                {
+                    self.push_new()
+                }
+                Some(pos) => pos,
+                None => {
+                    self.calls.push((index, PartialToolCall::default()));
+                    self.calls.len() - 1
+                }
+            },
+            // 缺少 index: 按 id 匹配，否则视为最后一个调用的续片 (无 id) 或新调用
+            None => match id {
+                Some(id) => self
+                    .calls
+                    .iter()
+                    .position(|(_, c)| c.id == id)
+                    .unwrap_or_else(|| self.push_new()),
+                None if !self.calls.is_empty() => self.calls.len() - 1,
+                None => self.push_new(),
+            },
+        };
+
+        let call = &mut self.calls[slot].1;
+        if let Some(id) = id {
+            if call.id.is_empty() {
+                call.id = id.to_string();
+            }
+        }
+        if let Some(tc_type) = tc.get("type").and_then(|v| v.as_str()) {
+            if call.r#type.is_empty() && !tc_type.is_empty() {
+                call.r#type = tc_type.to_string();
+            }
+        }
+        let Some(func) = tc.get("function") else {
+            return 0;
+        };
+        if let Some(name) = func.get("name").and_then(|v| v.as_str()) {
+            if call.name.is_empty() && !name.is_empty() {
+                call.name = name.to_string();
+            }
+        }
+        match func.get("arguments") {
+            Some(Value::String(args)) => {
+                call.arguments.push_str(args);
+                args.len()
+            }
+            // 少数上游直接给出 JSON 对象而非字符串
+            Some(args @ Value::Object(_)) => {
+                let args = args.to_string();
+                call.arguments.push_str(&args);
+                args.len()
+            }
+            _ => 0,
+        }
+    }
+
+    fn push_new(&mut self) -> usize {
+        let next = self.calls.iter().map(|(i, _)| i + 1).max().unwrap_or(0);
+        self.calls.push((next, PartialToolCall::default()));
+        self.calls.len() - 1
+    }
+
+    /// response_id 用于为缺少 id 的调用生成 ID (与流式路径同一方案，按位置加盐)
+    fn finish(mut self, response_id: &str) -> Option<Vec<ToolCall>> {
+        if self.calls.is_empty() {
+            return None;
+        }
+        self.calls.sort_by_key(|(index, _)| *index);
+        Some(
+            self.calls
+                .into_iter()
+                .enumerate()
+                .map(|(position, (_, call))| {
+                    // 上游分片未携带 id 时按内容生成，与流式路径使用同一方案
+                    let id = if call.id.is_empty() {
+                        super::tool_call_ids::stable_tool_call_id(
+                            &json!({ "name": call.name, "args": call.arguments }),
+                            response_id,
+                            position,
+                        )
+                    } else {
+                        call.id
+                    };
+                    ToolCall {
+                        id,
+                        r#type: if call.r#type.is_empty() {
+                            "function".to_string()
+                        } else {
+                            call.r#type
+                        },
+                        function: ToolFunction {
+                            name: call.name,
+                            arguments: call.arguments,
+                        },
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sse_chunk(content: &str) -> Result<Bytes, String> {
+        let event = json!({
+            "id": "chatcmpl-big",
+            "model": "gemini-2.5-flash",
+            "choices": [{ "index": 0, "delta": { "content": content } }]
+        });
+        Ok(Bytes::from(format!("data: {}\n\n", event)))
+    }
+
+    #[tokio::test]
+    async fn test_collection_budget_truncates_runaway_stream() {
+        let piece = "x".repeat(1024);
+        let chunks: Vec<Result<Bytes, String>> = (0..10_000).map(|_| sse_chunk(&piece)).collect();
+
+        let response = collect_stream_to_json(futures::stream::iter(chunks), 64 * 1024)
+            .await
+            .unwrap();
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("length"));
+        let content = match &choice.message.content {
+            Some(OpenAIContent::String(s)) => s.len(),
+            _ => unreachable!(),
+        };
+        assert!(content > 64 * 1024 && content <= 65 * 1024 + 1024);
+
+        // 未超出预算时正常结束
+        let small: Vec<Result<Bytes, String>> = (0..4).map(|_| sse_chunk(&piece)).collect();
+        let response = collect_stream_to_json(futures::stream::iter(small), 64 * 1024)
+            .await
+            .unwrap();
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+
+    fn tool_delta(tool_calls: Value) -> String {
+        let event = json!({
+            "id": "chatcmpl-tools",
+            "choices": [{ "index": 0, "delta": { "tool_calls": tool_calls } }]
+        });
+        format!("data: {}\n\n", event)
+    }
+
+    fn tool_calls_of(response: &OpenAIResponse) -> &Vec<ToolCall> {
+        response.choices[0].message.tool_calls.as_ref().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_multi_tool_argument_deltas_merged_by_index() {
+        let events = [
+            tool_delta(json!([
+                { "index": 0, "id": "call_a", "type": "function", "function": { "name": "get_weather", "arguments": "" } }
+            ])),
+            tool_delta(json!([{ "index": 0, "function": { "arguments": "{\"city\":" } }])),
+            // 第二个调用开始，与第一个交错
+            tool_delta(json!([
+                { "index": 1, "id": "call_b", "type": "function", "function": { "name": "get_time", "arguments": "{\"tz\"" } }
+            ])),
+            tool_delta(json!([{ "index": 0, "function": { "arguments": "\"Paris\"}" } }])),
+            // 后续分片重复 id / 携带不同 name 时保留首次出现的值
+            tool_delta(json!([
+                { "index": 1, "id": "call_b", "function": { "name": "ignored", "arguments": ":\"UTC\"}" } }
+            ])),
+            "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\ndata: [DONE]\n\n".to_string(),
+        ];
+        // 将整个 SSE 文本按 7 字节切分，模拟行与 JSON 被拆到多个网络分片
+        let raw = events.concat().into_bytes();
+        let chunks: Vec<Result<Bytes, String>> = raw
+            .chunks(7)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+
+        let response = collect_stream_to_json(futures::stream::iter(chunks), 0)
+            .await
+            .unwrap();
+        let calls = tool_calls_of(&response);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_a");
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(calls[1].id, "call_b");
+        assert_eq!(calls[1].function.name, "get_time");
+        assert_eq!(calls[1].function.arguments, r#"{"tz":"UTC"}"#);
+        assert_eq!(
+            response.choices[0].finish_reason.as_deref(),
+            Some("tool_calls")
+        );
+        for call in calls {
+            serde_json::from_str::<Value>(&call.function.arguments).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_deltas_without_index_or_with_reused_index() {
+        let events = [
+            // 无 index: 按 id 区分调用，无 id 的分片续接上一个调用
+            tool_delta(
+                json!([{ "id": "call_1", "function": { "name": "a", "arguments": "{\"x\":" } }]),
+            ),
+            tool_delta(json!([{ "function": { "arguments": "1}" } }])),
+            tool_delta(json!([{ "id": "call_2", "function": { "name": "b", "arguments": "{}" } }])),
+            // 上游重复使用 index 0 但 id 不同: 追加为新调用而不是拼接
+            tool_delta(
+                json!([{ "index": 0, "id": "call_3", "function": { "name": "c", "arguments": "{\"y\":2}" } }]),
+            ),
+        ];
+        let chunks: Vec<Result<Bytes, String>> =
+            events.iter().map(|e| Ok(Bytes::from(e.clone()))).collect();
+
+        let response = collect_stream_to_json(futures::stream::iter(chunks), 0)
+            .await
+            .unwrap();
+        let calls = tool_calls_of(&response);
+        let summary: Vec<(&str, &str, &str)> = calls
+            .iter()
+            .map(|c| {
+                (
+                    c.id.as_str(),
+                    c.function.name.as_str(),
+                    c.function.arguments.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("call_1", "a", r#"{"x":1}"#),
+                ("call_2", "b", "{}"),
+                ("call_3", "c", r#"{"y":2}"#),
+            ]
+        );
+        assert!(calls.iter().all(|c| c.r#type == "function"));
+    }
+}
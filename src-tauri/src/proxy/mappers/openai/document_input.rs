@@ -0,0 +1,358 @@
+// 文档输入 (PDF 等)
+// Chat 的 file 内容块 / Responses API 的 input_file 转为 Gemini inlineData (base64)
+// file_url 由 handler 在转换前下载并内联 (Gemini 无法读取任意 http(s) 地址的 fileUri)
+// 仅接受 Gemini 可直接读取的文档类型，超出大小上限、类型不支持或下载失败时返回 400
+use base64::Engine as _;
+use serde_json::{json, Value};
+
+use super::image_fetch::{fetch_remote_bytes, is_remote_url, ImageClient};
+use super::models::*;
+
+/// 单个内联文档的解码后大小上限 (Gemini 内联请求总大小上限为 20MB)
+pub const MAX_INLINE_DOCUMENT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Gemini 可直接读取的文档类型
+const SUPPORTED_DOCUMENT_MIME_TYPES: &[&str] = &[
+    "application/pdf",
+    "text/plain",
+    "text/markdown",
+    "text/csv",
+    "text/html",
+    "text/xml",
+    "text/rtf",
+    "application/json",
+];
+
+/// 按扩展名推断文档类型
+fn mime_from_name(name: &str) -> Option<&'static str> {
+    let name = name.split(['?', '#']).next().unwrap_or(name);
+    let ext = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "xml" => "text/xml",
+        "rtf" => "text/rtf",
+        "json" => "application/json",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        _ => return None,
+    })
+}
+
+/// 解析 file_data: data URL ("data:<mime>;base64,<data>") 或裸 base64，返回 (声明的类型, base64 数据)
+fn split_file_data(file_data: &str) -> (Option<&str>, &str) {
+    match file_data
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+    {
+        Some((meta, data)) => {
+            let mime = meta.split(';').next().filter(|m| !m.is_empty());
+            (mime, data)
+        }
+        None => (None, file_data),
+    }
+}
+
+fn ensure_supported(mime: &str) -> Result<(), String> {
+    if SUPPORTED_DOCUMENT_MIME_TYPES.contains(&mime) {
+        Ok(())
+    } else {
+        Err(format!(
+            "unsupported document type '{}' (supported: {})",
+            mime,
+            SUPPORTED_DOCUMENT_MIME_TYPES.join(", ")
+        ))
+    }
+}
+
+/// 将文档内容块转换为 Gemini part
+pub fn document_part(file: &OpenAIFileContent) -> Result<Value, String> {
+    let filename = file.filename.as_deref().unwrap_or("");
+
+    if let Some(file_data) = file.file_data.as_deref().filter(|d| !d.is_empty()) {
+        let (declared, data) = split_file_data(file_data);
+        let data = data.trim();
+        let decoded_len = data.len() / 4 * 3;
+        if decoded_len > MAX_INLINE_DOCUMENT_BYTES {
+            return Err(format!(
+                "document '{}' exceeds the {} byte limit",
+                filename, MAX_INLINE_DOCUMENT_BYTES
+            ));
+        }
+        // 优先识别 PDF 文件头，其次为声明的类型与文件扩展名
+        let head = base64::engine::general_purpose::STANDARD
+            .decode(&data[..data.len().min(8)])
+            .unwrap_or_default();
+        let mime = if head.starts_with(b"%PDF-") {
+            "application/pdf"
+        } else {
+            declared
+                .or_else(|| mime_from_name(filename))
+                .ok_or_else(|| format!("cannot determine the type of document '{}'", filename))?
+        };
+        ensure_supported(mime)?;
+        return Ok(json!({ "inlineData": { "mimeType": mime, "data": data } }));
+    }
+
+    // file_url 应已由 inline_remote_documents 替换为 file_data
+    check_file_reference(file)?;
+    Err(format!(
+        "document at '{}' was not fetched",
+        file.file_url.as_deref().unwrap_or_default()
+    ))
+}
+
+/// 无 file_data 时的引用校验: file_url 须为 http(s) 且扩展名可识别时类型受支持
+fn check_file_reference(file: &OpenAIFileContent) -> Result<(), String> {
+    if let Some(url) = file.file_url.as_deref().filter(|u| !u.is_empty()) {
+        if !is_remote_url(url) {
+            return Err(format!("file_url must be an http(s) URL, got '{}'", url));
+        }
+        // 无扩展名时以下载后的内容为准
+        if let Some(mime) = file
+            .filename
+            .as_deref()
+            .and_then(mime_from_name)
+            .or_else(|| mime_from_name(url))
+        {
+            ensure_supported(mime)?;
+        }
+        return Ok(());
+    }
+
+    if file.file_id.is_some() {
+        return Err("file_id references are not supported; send the document as file_data (base64) or file_url".to_string());
+    }
+    Err("file content block requires file_data or file_url".to_string())
+}
+
+/// 识别下载到的文档类型: PDF 文件头 > 受支持的 Content-Type > 文件名 / URL 扩展名
+fn remote_document_mime(
+    bytes: &[u8],
+    content_type: Option<&str>,
+    filename: &str,
+    url: &str,
+) -> Result<&'static str, String> {
+    if bytes.starts_with(b"%PDF-") {
+        return Ok("application/pdf");
+    }
+    let declared = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| ct.trim().to_ascii_lowercase())
+        .filter(|ct| !ct.is_empty() && ct != "application/octet-stream");
+    if let Some(mime) = declared
+        .as_deref()
+        .and_then(|ct| SUPPORTED_DOCUMENT_MIME_TYPES.iter().find(|m| **m == ct))
+    {
+        return Ok(mime);
+    }
+    match mime_from_name(filename).or_else(|| mime_from_name(url)) {
+        Some(mime) => ensure_supported(mime).map(|_| mime),
+        None => match declared {
+            Some(ct) => Err(ensure_supported(&ct).unwrap_err()),
+            None => Err(format!("cannot determine the type of document '{}'", url)),
+        },
+    }
+}
+
+/// 请求中是否包含需要下载的远程文档
+pub fn has_remote_documents(request: &OpenAIRequest) -> bool {
+    request.messages.iter().any(|msg| match msg.content.as_ref() {
+        Some(OpenAIContent::Array(blocks)) => blocks.iter().any(|b| {
+            matches!(b, OpenAIContentBlock::File { file } if needs_fetch(file))
+        }),
+        _ => false,
+    })
+}
+
+fn needs_fetch(file: &OpenAIFileContent) -> bool {
+    file.file_data.as_deref().is_none_or(str::is_empty)
+        && file.file_url.as_deref().is_some_and(is_remote_url)
+}
+
+/// 将请求中所有 file_url 文档下载并替换为 file_data (data URL)，返回被内联的文档数量
+pub async fn inline_remote_documents(
+    request: &mut OpenAIRequest,
+    client: &ImageClient,
+    max_bytes: usize,
+) -> Result<usize, String> {
+    let mut inlined = 0;
+    for msg in request.messages.iter_mut() {
+        let Some(OpenAIContent::Array(blocks)) = msg.content.as_mut() else {
+            continue;
+        };
+        for block in blocks.iter_mut() {
+            let OpenAIContentBlock::File { file } = block else {
+                continue;
+            };
+            if !needs_fetch(file) {
+                continue;
+            }
+            let url = file.file_url.take().unwrap_or_default();
+            tracing::debug!("[OpenAI-Request] Fetching remote document: {}", url);
+            let (bytes, content_type) =
+                fetch_remote_bytes(client, &url, max_bytes, "document").await?;
+            let filename = file.filename.as_deref().unwrap_or("");
+            let mime = remote_document_mime(&bytes, content_type.as_deref(), filename, &url)?;
+            file.file_data = Some(format!(
+                "data:{};base64,{}",
+                mime,
+                base64::engine::general_purpose::STANDARD.encode(&bytes)
+            ));
+            inlined += 1;
+        }
+    }
+    Ok(inlined)
+}
+
+/// 请求阶段校验所有文档内容块，返回带消息位置的错误
+pub fn validate_request_documents(request: &OpenAIRequest) -> Result<(), String> {
+    for (i, msg) in request.messages.iter().enumerate() {
+        let Some(OpenAIContent::Array(blocks)) = msg.content.as_ref() else {
+            continue;
+        };
+        for (j, block) in blocks.iter().enumerate() {
+            if let OpenAIContentBlock::File { file } = block {
+                let has_data = file.file_data.as_deref().is_some_and(|d| !d.is_empty());
+                let checked = if has_data {
+                    document_part(file).map(|_| ())
+                } else {
+                    check_file_reference(file)
+                };
+                checked.map_err(|e| format!("messages[{}].content[{}]: {}", i, j, e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Responses API input_file (字段平铺) -> Chat file 内容块
+pub fn input_file_to_chat_block(part: &Value) -> Value {
+    let mut file = serde_json::Map::new();
+    for key in ["file_data", "file_url", "file_id", "filename"] {
+        if let Some(v) = part.get(key) {
+            file.insert(key.to_string(), v.clone());
+        }
+    }
+    json!({ "type": "file", "file": file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_types_and_limits() {
+        let file = |data: &str, name: &str| OpenAIFileContent {
+            file_data: Some(data.to_string()),
+            filename: Some(name.to_string()),
+            ..Default::default()
+        };
+        let b64 = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+
+        // 裸 base64 PDF: 按文件头识别
+        let part = document_part(&file(&b64(b"%PDF-1.7\n..."), "report")).unwrap();
+        assert_eq!(part["inlineData"]["mimeType"], "application/pdf");
+
+        // 文本文档: 按扩展名识别
+        let part = document_part(&file(&b64(b"a,b\n1,2\n"), "data.csv")).unwrap();
+        assert_eq!(part["inlineData"]["mimeType"], "text/csv");
+
+        // 不支持的类型
+        let data = format!("data:application/zip;base64,{}", b64(b"PK\x03\x04"));
+        let err = document_part(&file(&data, "a.zip")).unwrap_err();
+        assert!(err.contains("unsupported document type 'application/zip'"));
+        let err = document_part(&file(&b64(b"PK\x03\x04"), "slides.pptx")).unwrap_err();
+        assert!(err.contains("unsupported document type"));
+
+        // 超出大小上限
+        let huge = "A".repeat(MAX_INLINE_DOCUMENT_BYTES / 3 * 4 + 8);
+        assert!(document_part(&file(&huge, "big.pdf"))
+            .unwrap_err()
+            .contains("exceeds"));
+
+        // 未下载的 URL 不转为 fileData；file_id 不支持
+        let url = OpenAIFileContent {
+            file_url: Some("https://example.com/paper.pdf".to_string()),
+            ..Default::default()
+        };
+        assert!(document_part(&url).unwrap_err().contains("not fetched"));
+        let id = OpenAIFileContent {
+            file_id: Some("file-abc".to_string()),
+            ..Default::default()
+        };
+        assert!(document_part(&id).unwrap_err().contains("file_id"));
+    }
+
+    #[tokio::test]
+    async fn test_remote_documents_are_fetched_and_inlined() {
+        use crate::proxy::config::UpstreamProxyConfig;
+        use crate::proxy::mappers::openai::image_fetch::build_image_client;
+        use crate::proxy::tests::harness::spawn_upstream;
+        use axum::routing::get;
+
+        let app = axum::Router::new()
+            .route("/report", get(|| async { b"%PDF-1.4\n%%EOF".to_vec() }))
+            .route(
+                "/notes",
+                get(|| async { ([("content-type", "text/csv; charset=utf-8")], "a,b\n1,2\n") }),
+            )
+            .route(
+                "/archive",
+                get(|| async { ([("content-type", "application/zip")], b"PK\x03\x04".to_vec()) }),
+            );
+        let base = format!("http://{}", spawn_upstream(app).await);
+        let client =
+            build_image_client(&UpstreamProxyConfig::default(), &["127.0.0.1".to_string()]).unwrap();
+        let request = |path: &str| -> OpenAIRequest {
+            serde_json::from_value(json!({
+                "model": "gemini-2.5-flash",
+                "messages": [{
+                    "role": "user",
+                    "content": [{ "type": "file", "file": { "file_url": format!("{}{}", base, path) } }]
+                }]
+            }))
+            .unwrap()
+        };
+        let file = |req: &OpenAIRequest| match req.messages[0].content.as_ref() {
+            Some(OpenAIContent::Array(blocks)) => match &blocks[0] {
+                OpenAIContentBlock::File { file } => file.clone(),
+                other => panic!("unexpected block {:?}", other),
+            },
+            _ => panic!("expected array content"),
+        };
+
+        // PDF 按文件头识别，文本文档按 Content-Type 识别；内联后不再保留 file_url
+        for (path, mime) in [("/report", "application/pdf"), ("/notes", "text/csv")] {
+            let mut req = request(path);
+            assert!(has_remote_documents(&req));
+            let count = inline_remote_documents(&mut req, &client, MAX_INLINE_DOCUMENT_BYTES)
+                .await
+                .unwrap();
+            assert_eq!(count, 1);
+            assert!(!has_remote_documents(&req));
+            let file = file(&req);
+            assert!(file.file_url.is_none());
+            let part = document_part(&file).unwrap();
+            assert_eq!(part["inlineData"]["mimeType"], mime);
+        }
+
+        // 类型不支持 / 下载失败 / 超出大小上限
+        for (path, max_bytes, expected) in [
+            ("/archive", MAX_INLINE_DOCUMENT_BYTES, "unsupported document type"),
+            ("/missing.pdf", MAX_INLINE_DOCUMENT_BYTES, "HTTP 404"),
+            ("/report", 4, "too large"),
+        ] {
+            let mut req = request(path);
+            let err = inline_remote_documents(&mut req, &client, max_bytes)
+                .await
+                .unwrap_err();
+            assert!(err.contains(expected), "{}: {}", path, err);
+        }
+    }
+}
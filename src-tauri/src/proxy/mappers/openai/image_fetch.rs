@@ -0,0 +1,651 @@
+// 远程图片预取
+// Gemini 无法拉取任意 http(s) 图片 / 文档 URL，需要在转换前下载并内联为 data URL
+// URL 由客户端提供: 下载前及每次重定向都解析目标地址，拒绝回环 / 内网 / 链路本地地址 (SSRF)
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use base64::Engine as _;
+use tokio::time::Duration;
+
+use super::models::*;
+use crate::proxy::config::UpstreamProxyConfig;
+
+/// 单张远程图片的最大字节数 (20MB，与 Gemini inlineData 上限一致)
+pub const MAX_REMOTE_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+const REMOTE_IMAGE_TIMEOUT_SECS: u64 = 30;
+const MAX_REMOTE_IMAGE_REDIRECTS: usize = 5;
+
+/// 远程图片 / 文档下载客户端 (禁用自动重定向，由 fetch_remote_bytes 逐跳校验)
+#[derive(Clone)]
+pub struct ImageClient {
+    client: reqwest::Client,
+    /// 允许访问非公网地址的主机 (experimental.remote_image_allowed_hosts)
+    allowed_hosts: Arc<Vec<String>>,
+}
+
+impl ImageClient {
+    fn is_allowed_host(&self, host: &str) -> bool {
+        is_allowed_host(&self.allowed_hosts, host)
+    }
+}
+
+fn is_allowed_host(allowed_hosts: &[String], host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    allowed_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+}
+
+/// DNS 解析时过滤非公网地址，防止校验后 DNS 记录被改指向内网 (DNS rebinding)
+struct PublicOnlyResolver {
+    allowed_hosts: Arc<Vec<String>>,
+}
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let allowed = is_allowed_host(&self.allowed_hosts, name.as_str());
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| allowed || is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+pub fn build_image_client(
+    upstream_proxy: &UpstreamProxyConfig,
+    allowed_hosts: &[String],
+) -> Result<ImageClient, String> {
+    let allowed_hosts = Arc::new(allowed_hosts.to_vec());
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(REMOTE_IMAGE_TIMEOUT_SECS))
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicOnlyResolver {
+            allowed_hosts: allowed_hosts.clone(),
+        }));
+
+    if upstream_proxy.enabled && !upstream_proxy.url.is_empty() {
+        let proxy = reqwest::Proxy::all(&upstream_proxy.url)
+            .map_err(|e| format!("Invalid upstream proxy url: {}", e))?;
+        builder = builder.proxy(proxy);
+    }
+
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+    Ok(ImageClient {
+        client,
+        allowed_hosts,
+    })
+}
+
+/// 是否为公网地址 (拒绝回环 / 私有 / 链路本地 / 运营商 NAT / 组播等)
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// 校验下载目标: 仅 http(s)，主机解析出的所有地址都必须是公网地址
+async fn check_remote_target(
+    client: &ImageClient,
+    url: &reqwest::Url,
+    kind: &str,
+) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Refusing to fetch {} from {}: bad scheme", kind, url));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("Refusing to fetch {} from {}: missing host", kind, url))?;
+    if client.is_allowed_host(host) {
+        return Ok(());
+    }
+    let addrs: Vec<IpAddr> = match url.host() {
+        Some(url::Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(url::Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        _ => {
+            let port = url.port_or_known_default().unwrap_or(80);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("Failed to resolve {} host {}: {}", kind, host, e))?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+    if addrs.is_empty() {
+        return Err(format!("Failed to resolve {} host {}", kind, host));
+    }
+    if let Some(ip) = addrs.into_iter().find(|ip| !is_public_ip(*ip)) {
+        return Err(format!(
+            "Refusing to fetch {} from {}: {} is not a public address",
+            kind, host, ip
+        ));
+    }
+    Ok(())
+}
+
+pub fn is_remote_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// 请求中是否包含需要下载的远程图片
+pub fn has_remote_images(request: &OpenAIRequest) -> bool {
+    request.messages.iter().any(|msg| match msg.content.as_ref() {
+        Some(OpenAIContent::Array(blocks)) => blocks.iter().any(|b| {
+            matches!(b, OpenAIContentBlock::ImageUrl { image_url } if is_remote_url(&image_url.url))
+        }),
+        _ => false,
+    })
+}
+
+/// 根据文件头魔数识别图片 MIME 类型 (不信任服务端返回的 Content-Type)
+pub fn sniff_image_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.len() >= 12
+        && &bytes[4..8] == b"ftyp"
+        && (&bytes[8..12] == b"heic" || &bytes[8..12] == b"heix")
+    {
+        Some("image/heic")
+    } else {
+        None
+    }
+}
+
+/// 下载远程资源，返回 (内容, 响应 Content-Type) (手动跟随重定向，每一跳都校验目标地址)
+/// kind 仅用于错误信息 ("image" / "document")
+pub async fn fetch_remote_bytes(
+    client: &ImageClient,
+    url: &str,
+    max_bytes: usize,
+    kind: &str,
+) -> Result<(Vec<u8>, Option<String>), String> {
+    let mut target =
+        reqwest::Url::parse(url).map_err(|e| format!("Invalid {} URL {}: {}", kind, url, e))?;
+    let mut redirects = 0;
+    let mut resp = loop {
+        check_remote_target(client, &target, kind).await?;
+        let resp = client
+            .client
+            .get(target.clone())
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch {} {}: {}", kind, url, e))?;
+        if !resp.status().is_redirection() {
+            break resp;
+        }
+        redirects += 1;
+        if redirects > MAX_REMOTE_IMAGE_REDIRECTS {
+            return Err(format!("Failed to fetch {} {}: too many redirects", kind, url));
+        }
+        let location = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| {
+                format!("Failed to fetch {} {}: redirect without Location", kind, url)
+            })?;
+        target = target.join(location).map_err(|e| {
+            format!("Failed to fetch {} {}: invalid redirect: {}", kind, url, e)
+        })?;
+        tracing::debug!("[OpenAI-Request] Remote {} redirect -> {}", kind, target);
+    };
+
+    if !resp.status().is_success() {
+        return Err(format!(
+            "Failed to fetch {} {}: HTTP {}",
+            kind,
+            url,
+            resp.status().as_u16()
+        ));
+    }
+
+    if let Some(len) = resp.content_length() {
+        if len as usize > max_bytes {
+            return Err(format!(
+                "Remote {} {} is too large ({} bytes, limit {} bytes)",
+                kind, url, len, max_bytes
+            ));
+        }
+    }
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    // 分块读取，防止无 Content-Length 的响应绕过大小限制
+    let mut buf: Vec<u8> = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to read {} {}: {}", kind, url, e))?
+    {
+        if buf.len() + chunk.len() > max_bytes {
+            return Err(format!(
+                "Remote {} {} is too large (exceeds limit {} bytes)",
+                kind, url, max_bytes
+            ));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok((buf, content_type))
+}
+
+/// 下载单张远程图片并返回 data URL
+pub async fn fetch_remote_image(
+    client: &ImageClient,
+    url: &str,
+    max_bytes: usize,
+) -> Result<String, String> {
+    let (buf, _) = fetch_remote_bytes(client, url, max_bytes, "image").await?;
+    let mime_type = sniff_image_mime(&buf)
+        .ok_or_else(|| format!("Content at {} is not a supported image", url))?;
+    let b64 = base64::engine::general_purpose::STANDARD.encode(&buf);
+
+    Ok(format!("data:{};base64,{}", mime_type, b64))
+}
+
+/// 根据 base64 数据开头识别图片 MIME 类型 (只解码前 12 字节)
+pub fn sniff_base64_image_mime(data: &str) -> Option<&'static str> {
+    let prefix = data.get(..16)?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(prefix)
+        .ok()?;
+    sniff_image_mime(&bytes)
+}
+
+/// 解析图片引用 (http(s) URL 下载，data: URL 直接解码)，返回 (mime_type, base64 数据)
+pub async fn load_image_reference(
+    client: &ImageClient,
+    url: &str,
+    max_bytes: usize,
+) -> Result<(String, String), String> {
+    let url = url.trim();
+    let data_url = if is_remote_url(url) {
+        fetch_remote_image(client, url, max_bytes).await?
+    } else if url.starts_with("data:") {
+        url.to_string()
+    } else {
+        return Err(format!(
+            "Unsupported image reference '{}': expected an http(s) or data: URL",
+            url.chars().take(64).collect::<String>()
+        ));
+    };
+
+    let (header, data) = data_url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+        .filter(|(header, _)| header.ends_with(";base64"))
+        .ok_or_else(|| "Invalid data URL: expected data:<mime>;base64,<data>".to_string())?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid data URL: {}", e))?;
+    if bytes.len() > max_bytes {
+        return Err(format!(
+            "Image is too large ({} bytes, limit {} bytes)",
+            bytes.len(),
+            max_bytes
+        ));
+    }
+    let mime_type = sniff_image_mime(&bytes)
+        .map(str::to_string)
+        .unwrap_or_else(|| header.trim_end_matches(";base64").to_string());
+    Ok((mime_type, data.to_string()))
+}
+
+/// 解析表单中的图片 URL 列表: 支持 JSON 数组或以逗号 / 空白分隔的 URL
+pub fn parse_image_url_list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    if value.starts_with('[') {
+        if let Ok(urls) = serde_json::from_str::<Vec<String>>(value) {
+            return urls.into_iter().filter(|u| !u.trim().is_empty()).collect();
+        }
+    }
+    // data: URL 本身包含逗号，整体作为单个引用
+    if value.starts_with("data:") {
+        return vec![value.to_string()];
+    }
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|u| !u.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 将请求中所有 http(s) 图片 URL 下载并替换为 data URL
+/// data URL 与本地路径保持不变，由 transform_openai_request 直接处理
+/// 返回被内联的图片数量
+pub async fn inline_remote_images(
+    request: &mut OpenAIRequest,
+    client: &ImageClient,
+    max_bytes: usize,
+) -> Result<usize, String> {
+    let mut inlined = 0;
+    for msg in request.messages.iter_mut() {
+        let Some(OpenAIContent::Array(blocks)) = msg.content.as_mut() else {
+            continue;
+        };
+        for block in blocks.iter_mut() {
+            if let OpenAIContentBlock::ImageUrl { image_url } = block {
+                if !is_remote_url(&image_url.url) {
+                    continue;
+                }
+                tracing::debug!("[OpenAI-Request] Fetching remote image: {}", image_url.url);
+                image_url.url = fetch_remote_image(client, &image_url.url, max_bytes).await?;
+                inlined += 1;
+            }
+        }
+    }
+    Ok(inlined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::spawn_upstream;
+
+    const PNG_1X1: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg==";
+
+    fn image_request(urls: &[&str]) -> OpenAIRequest {
+        let mut blocks = vec![OpenAIContentBlock::Text {
+            text: "Compare these images".to_string(),
+        }];
+        for url in urls {
+            blocks.push(OpenAIContentBlock::ImageUrl {
+                image_url: OpenAIImageUrl {
+                    url: url.to_string(),
+                    detail: None,
+                    mime_type: None,
+                },
+            });
+        }
+        serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": blocks }]
+        }))
+        .unwrap()
+    }
+
+    fn image_urls(req: &OpenAIRequest) -> Vec<String> {
+        match req.messages[0].content.as_ref() {
+            Some(OpenAIContent::Array(blocks)) => blocks
+                .iter()
+                .filter_map(|b| match b {
+                    OpenAIContentBlock::ImageUrl { image_url } => Some(image_url.url.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// 测试服务监听在 127.0.0.1，需显式放行
+    fn test_client() -> ImageClient {
+        build_image_client(&UpstreamProxyConfig::default(), &["127.0.0.1".to_string()]).unwrap()
+    }
+
+    /// 启动一个本地 HTTP 服务: /img.png 返回 1x1 PNG，/text 返回纯文本，其余路径 404
+    async fn spawn_image_server() -> String {
+        use axum::{routing::get, Router};
+        let png = base64::engine::general_purpose::STANDARD
+            .decode(PNG_1X1)
+            .unwrap();
+        let app = Router::new()
+            .route(
+                "/img.png",
+                // 故意返回错误的 Content-Type，验证 MIME 嗅探
+                get(move || async move { ([("content-type", "application/octet-stream")], png) }),
+            )
+            .route("/text", get(|| async { "not an image" }))
+            .route(
+                "/redirect",
+                get(|| async { (axum::http::StatusCode::FOUND, [("location", "/img.png")]) }),
+            )
+            .route(
+                "/redirect-metadata",
+                get(|| async {
+                    (
+                        axum::http::StatusCode::FOUND,
+                        [("location", "http://169.254.169.254/latest/meta-data/")],
+                    )
+                }),
+            );
+        format!("http://{}", spawn_upstream(app).await)
+    }
+
+    #[test]
+    fn test_sniff_image_mime() {
+        let png = base64::engine::general_purpose::STANDARD
+            .decode(PNG_1X1)
+            .unwrap();
+        assert_eq!(sniff_image_mime(&png), Some("image/png"));
+        assert_eq!(sniff_image_mime(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(sniff_image_mime(b"GIF89a...."), Some("image/gif"));
+        assert_eq!(sniff_image_mime(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_image_mime(b"<html></html>"), None);
+    }
+
+    #[tokio::test]
+    async fn test_data_url_passes_through_untouched() {
+        let data_url = format!("data:image/png;base64,{}", PNG_1X1);
+        let mut req = image_request(&[&data_url]);
+        let client = test_client();
+
+        let inlined = inline_remote_images(&mut req, &client, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(inlined, 0);
+        assert_eq!(image_urls(&req), vec![data_url]);
+    }
+
+    #[tokio::test]
+    async fn test_remote_image_is_fetched_and_inlined() {
+        let base = spawn_image_server().await;
+        let mut req = image_request(&[&format!("{}/img.png", base)]);
+        let client = test_client();
+
+        let inlined = inline_remote_images(&mut req, &client, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(inlined, 1);
+        assert_eq!(
+            image_urls(&req),
+            vec![format!("data:image/png;base64,{}", PNG_1X1)]
+        );
+
+        let (body, _, _) =
+            crate::proxy::mappers::openai::transform_openai_request(&req, "test-p", "gemini-2.5-flash");
+        let parts = &body["request"]["contents"][0]["parts"];
+        assert_eq!(parts[1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(parts[1]["inlineData"]["data"], PNG_1X1);
+        assert!(parts[1].get("fileData").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_two_images_in_one_message() {
+        let base = spawn_image_server().await;
+        let data_url = format!("data:image/png;base64,{}", PNG_1X1);
+        let mut req = image_request(&[&data_url, &format!("{}/img.png", base)]);
+        let client = test_client();
+
+        inline_remote_images(&mut req, &client, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap();
+
+        let (body, _, _) =
+            crate::proxy::mappers::openai::transform_openai_request(&req, "test-p", "gemini-2.5-flash");
+        let parts = body["request"]["contents"][0]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0]["text"], "Compare these images");
+        assert_eq!(parts[1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(parts[2]["inlineData"]["mimeType"], "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_remote_image_failures() {
+        let base = spawn_image_server().await;
+        let client = test_client();
+
+        // 404
+        let mut req = image_request(&[&format!("{}/missing.png", base)]);
+        let err = inline_remote_images(&mut req, &client, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap_err();
+        assert!(err.contains("HTTP 404"), "{}", err);
+
+        // 非图片内容
+        let mut req = image_request(&[&format!("{}/text", base)]);
+        let err = inline_remote_images(&mut req, &client, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap_err();
+        assert!(err.contains("not a supported image"), "{}", err);
+
+        // 超出大小限制
+        let mut req = image_request(&[&format!("{}/img.png", base)]);
+        let err = inline_remote_images(&mut req, &client, 16).await.unwrap_err();
+        assert!(err.contains("too large"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_private_addresses_are_rejected() {
+        let base = spawn_image_server().await;
+        let strict = build_image_client(&UpstreamProxyConfig::default(), &[]).unwrap();
+
+        // 未放行的回环地址 / 云元数据地址
+        for url in [
+            format!("{}/img.png", base),
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+            "http://[::1]/img.png".to_string(),
+            "http://localhost/img.png".to_string(),
+        ] {
+            let err = fetch_remote_image(&strict, &url, MAX_REMOTE_IMAGE_BYTES)
+                .await
+                .unwrap_err();
+            assert!(err.contains("not a public address"), "{}: {}", url, err);
+        }
+
+        // 重定向逐跳校验: 放行主机内的跳转正常，跳到内网地址被拒绝
+        let client = test_client();
+        let data_url = fetch_remote_image(&client, &format!("{}/redirect", base), 1024)
+            .await
+            .unwrap();
+        assert_eq!(data_url, format!("data:image/png;base64,{}", PNG_1X1));
+        let err = fetch_remote_image(
+            &client,
+            &format!("{}/redirect-metadata", base),
+            MAX_REMOTE_IMAGE_BYTES,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.contains("169.254.169.254"), "{}", err);
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_image_reference() {
+        let base = spawn_image_server().await;
+        let client = test_client();
+
+        let data_url = format!("data:image/png;base64,{}", PNG_1X1);
+        let (mime, data) = load_image_reference(&client, &data_url, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap();
+        assert_eq!((mime.as_str(), data.as_str()), ("image/png", PNG_1X1));
+
+        let url = format!("{}/img.png", base);
+        let (mime, data) = load_image_reference(&client, &url, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap();
+        assert_eq!((mime.as_str(), data.as_str()), ("image/png", PNG_1X1));
+        assert_eq!(sniff_base64_image_mime(&data), Some("image/png"));
+
+        let missing = format!("{}/missing.png", base);
+        assert!(
+            load_image_reference(&client, &missing, MAX_REMOTE_IMAGE_BYTES)
+                .await
+                .is_err()
+        );
+        assert!(
+            load_image_reference(&client, "file:///etc/passwd", MAX_REMOTE_IMAGE_BYTES)
+                .await
+                .is_err()
+        );
+        assert!(load_image_reference(&client, &data_url, 16).await.is_err());
+
+        // 图像编辑的 URL 引用同样拒绝非公网地址
+        let strict = build_image_client(&UpstreamProxyConfig::default(), &[]).unwrap();
+        let err = load_image_reference(&strict, &url, MAX_REMOTE_IMAGE_BYTES)
+            .await
+            .unwrap_err();
+        assert!(err.contains("not a public address"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_image_url_list() {
+        assert_eq!(
+            parse_image_url_list(r#"["https://a/1.png", "https://a/2.png"]"#),
+            vec!["https://a/1.png", "https://a/2.png"]
+        );
+        assert_eq!(
+            parse_image_url_list("https://a/1.png, https://a/2.png\nhttps://a/3.png"),
+            vec!["https://a/1.png", "https://a/2.png", "https://a/3.png"]
+        );
+        let data_url = format!("data:image/png;base64,{}", PNG_1X1);
+        assert_eq!(parse_image_url_list(&data_url), vec![data_url.clone()]);
+    }
+}
@@ -0,0 +1,260 @@
+// 上传图片尺寸适配
+// 超过 Gemini 单张图片字节上限或最大边长的图片会被上游拒绝，发送前按比例缩小并重新编码
+// 注意: JPEG 按 JPEG 重新编码，其余格式 (png / webp) 编码为 PNG；无法解码的图片保持原样
+use std::io::Cursor;
+
+use base64::Engine as _;
+use image::{imageops::FilterType, GenericImageView, ImageFormat};
+
+use super::models::*;
+
+/// 逐步缩小的最大轮数 (每轮边长 ×0.75)
+const MAX_SHRINK_ROUNDS: usize = 8;
+
+/// 上传图片限制 (experimental.input_image_max_dimension / input_image_max_bytes，0 表示不限制)
+#[derive(Debug, Clone, Copy)]
+pub struct InputImageLimits {
+    pub max_dimension: u32,
+    pub max_bytes: usize,
+}
+
+impl InputImageLimits {
+    pub fn from_config(config: &crate::proxy::config::ExperimentalConfig) -> Self {
+        Self {
+            max_dimension: config.input_image_max_dimension,
+            max_bytes: config.input_image_max_bytes,
+        }
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.max_dimension == 0 && self.max_bytes == 0
+    }
+
+    fn exceeds(&self, width: u32, height: u32, len: usize) -> bool {
+        (self.max_dimension > 0 && width.max(height) > self.max_dimension)
+            || (self.max_bytes > 0 && len > self.max_bytes)
+    }
+}
+
+/// 缩小后的图片 (JPEG 源图为 JPEG 编码，其余为 PNG)
+#[derive(Debug, Clone)]
+pub struct FittedImage {
+    pub bytes: Vec<u8>,
+    pub mime_type: &'static str,
+}
+
+/// 检查图片是否超出限制，超出时保持宽高比缩小并重新编码；未超出返回 Ok(None)
+pub fn fit_image(bytes: &[u8], limits: &InputImageLimits) -> Result<Option<FittedImage>, String> {
+    if limits.is_disabled() {
+        return Ok(None);
+    }
+    // 先读取图片头部获取尺寸，小图无需完整解码
+    let (width, height) = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("cannot read image dimensions: {}", e))?;
+    if !limits.exceeds(width, height, bytes.len()) {
+        return Ok(None);
+    }
+
+    let format = image::guess_format(bytes).map_err(|e| format!("cannot detect format: {}", e))?;
+    // 照片类 JPEG 重新编码为 PNG 体积会成倍增加
+    let target = if format == ImageFormat::Jpeg {
+        ImageFormat::Jpeg
+    } else {
+        ImageFormat::Png
+    };
+    let img = image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| format!("cannot decode image: {}", e))?;
+    let mut img = match limits.max_dimension {
+        max if max > 0 && width.max(height) > max => img.resize(max, max, FilterType::Lanczos3),
+        _ => img,
+    };
+
+    for _ in 0..MAX_SHRINK_ROUNDS {
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, target)
+            .map_err(|e| format!("cannot encode image: {}", e))?;
+        let encoded = out.into_inner();
+        if limits.max_bytes == 0 || encoded.len() <= limits.max_bytes {
+            let (w, h) = img.dimensions();
+            tracing::info!(
+                "[OpenAI-Request] Downscaled input image {}x{} ({} bytes) -> {}x{} ({} bytes)",
+                width,
+                height,
+                bytes.len(),
+                w,
+                h,
+                encoded.len()
+            );
+            return Ok(Some(FittedImage {
+                bytes: encoded,
+                mime_type: target.to_mime_type(),
+            }));
+        }
+        let (w, h) = img.dimensions();
+        img = img.resize(
+            (w * 3 / 4).max(1),
+            (h * 3 / 4).max(1),
+            FilterType::Lanczos3,
+        );
+    }
+    Err(format!(
+        "image still exceeds {} bytes after downscaling",
+        limits.max_bytes
+    ))
+}
+
+/// base64 版本: 处理失败时记录警告并返回 None (保持原图，由上游决定是否接受)
+/// 解码 / 缩放 / 编码在阻塞线程池中执行，避免占用异步运行时
+pub async fn fit_base64_image(
+    data: &str,
+    limits: &InputImageLimits,
+) -> Option<(String, &'static str)> {
+    if limits.is_disabled() {
+        return None;
+    }
+    let (data, limits) = (data.to_string(), *limits);
+    tokio::task::spawn_blocking(move || fit_base64_image_blocking(&data, &limits))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("[OpenAI-Request] Input image task failed: {}", e);
+            None
+        })
+}
+
+fn fit_base64_image_blocking(
+    data: &str,
+    limits: &InputImageLimits,
+) -> Option<(String, &'static str)> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .ok()?;
+    match fit_image(&bytes, limits) {
+        Ok(Some(fitted)) => Some((
+            base64::engine::general_purpose::STANDARD.encode(fitted.bytes),
+            fitted.mime_type,
+        )),
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!("[OpenAI-Request] Input image left unchanged: {}", e);
+            None
+        }
+    }
+}
+
+/// 缩小请求中超出限制的 data URL 图片，返回被处理的图片数量
+pub async fn fit_request_images(request: &mut OpenAIRequest, limits: &InputImageLimits) -> usize {
+    if limits.is_disabled() {
+        return 0;
+    }
+    let mut fitted = 0;
+    for msg in request.messages.iter_mut() {
+        let Some(OpenAIContent::Array(blocks)) = msg.content.as_mut() else {
+            continue;
+        };
+        for block in blocks.iter_mut() {
+            let OpenAIContentBlock::ImageUrl { image_url } = block else {
+                continue;
+            };
+            let Some(data) = image_url
+                .url
+                .strip_prefix("data:")
+                .and_then(|rest| rest.split_once(',').map(|(_, data)| data))
+            else {
+                continue;
+            };
+            if let Some((data, mime_type)) = fit_base64_image(data, limits).await {
+                image_url.url = format!("data:{};base64,{}", mime_type, data);
+                fitted += 1;
+            }
+        }
+    }
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noisy_png(width: u32, height: u32) -> Vec<u8> {
+        // 伪随机噪声，保证 PNG 无法高效压缩
+        let mut seed: u32 = 0x1234_5678;
+        let img = image::RgbImage::from_fn(width, height, |_, _| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            image::Rgb([seed as u8, (seed >> 8) as u8, (seed >> 16) as u8])
+        });
+        let mut out = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(&mut out, ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_oversized_input_image_is_downscaled() {
+        let original = noisy_png(600, 300);
+        let limits = InputImageLimits {
+            max_dimension: 400,
+            max_bytes: 100 * 1024,
+        };
+        assert!(original.len() > limits.max_bytes);
+
+        let b64 = base64::engine::general_purpose::STANDARD.encode(&original);
+        let mut request: OpenAIRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "describe" },
+                    { "type": "image_url", "image_url": { "url": format!("data:image/png;base64,{}", b64) } }
+                ]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(fit_request_images(&mut request, &limits).await, 1);
+
+        let url = match request.messages[0].content.as_ref() {
+            Some(OpenAIContent::Array(blocks)) => match &blocks[1] {
+                OpenAIContentBlock::ImageUrl { image_url } => image_url.url.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let data = url.strip_prefix("data:image/png;base64,").unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data).unwrap();
+        assert!(bytes.len() <= limits.max_bytes);
+        let out = image::load_from_memory(&bytes).unwrap();
+        assert!(out.width() <= 400 && out.height() <= 400);
+        // 保持宽高比 (2:1)
+        assert_eq!(out.width() / out.height(), 2);
+
+        // 小图不处理
+        let small = noisy_png(16, 16);
+        assert!(fit_image(&small, &limits).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_oversized_jpeg_input_is_downscaled_as_jpeg() {
+        let img = image::load_from_memory(&noisy_png(600, 300)).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, ImageFormat::Jpeg).unwrap();
+        let original = out.into_inner();
+        let limits = InputImageLimits {
+            max_dimension: 400,
+            max_bytes: 0,
+        };
+
+        let fitted = fit_image(&original, &limits).unwrap().unwrap();
+        assert_eq!(fitted.mime_type, "image/jpeg");
+        assert_eq!(
+            image::guess_format(&fitted.bytes).unwrap(),
+            ImageFormat::Jpeg
+        );
+        let out = image::load_from_memory(&fitted.bytes).unwrap();
+        assert_eq!((out.width(), out.height()), (400, 200));
+    }
+}
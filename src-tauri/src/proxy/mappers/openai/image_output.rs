@@ -0,0 +1,233 @@
+// 图像生成结果后处理
+// Gemini 返回的 inlineData 格式不固定 (PNG / JPEG / WebP)，mimeType 也不一定与实际数据一致
+// 始终校验 base64 可解码并按实际数据修正 data: URL 的 mime；开启后处理时按请求转码并缩小到请求尺寸
+// 注意: 转码能力取决于 image crate 启用的格式 (当前为 png / jpeg / webp)，不支持的格式保持原样输出
+use std::io::Cursor;
+
+use base64::Engine as _;
+use image::{imageops::FilterType, ImageFormat};
+use serde_json::{json, Value};
+
+/// 图像输出后处理选项 (experimental.enable_image_postprocess 开启时生效)
+#[derive(Debug, Clone, Default)]
+pub struct ImageOutputOptions {
+    /// 目标格式，None 表示保持原格式
+    pub format: Option<ImageFormat>,
+    /// 最大宽高，仅缩小不放大 (保持宽高比)
+    pub max_size: Option<(u32, u32)>,
+}
+
+impl ImageOutputOptions {
+    /// 由请求参数构造: output_format 缺省时输出 PNG (b64_json 客户端普遍按 PNG 处理)
+    pub fn from_request(output_format: Option<&str>, size: Option<&str>) -> Self {
+        Self {
+            format: Some(
+                output_format
+                    .and_then(parse_output_format)
+                    .unwrap_or(ImageFormat::Png),
+            ),
+            max_size: size.and_then(parse_size),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub data: String,
+    pub mime_type: String,
+}
+
+pub fn parse_output_format(format: &str) -> Option<ImageFormat> {
+    match format.trim().to_ascii_lowercase().as_str() {
+        "png" => Some(ImageFormat::Png),
+        "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+        "webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// 解析 "1024x1024" 形式的尺寸
+pub fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (w, h) = size.trim().split_once(['x', 'X'])?;
+    let (w, h) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+/// 校验并处理单张图片: base64 无法解码或无法识别格式时返回错误
+/// 转码 / 缩放失败 (如格式未启用) 时记录警告并保持原图
+pub fn process_inline_image(
+    data: &str,
+    declared_mime: Option<&str>,
+    options: Option<&ImageOutputOptions>,
+) -> Result<ProcessedImage, String> {
+    let data = data.trim();
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid base64 image data: {}", e))?;
+    let actual = image::guess_format(&bytes)
+        .map_err(|_| "Upstream returned unrecognized image data".to_string())?;
+
+    if let Some(declared) = declared_mime.filter(|m| *m != actual.to_mime_type()) {
+        tracing::warn!(
+            "[Images] Upstream declared {} but data is {}, using actual type",
+            declared,
+            actual.to_mime_type()
+        );
+    }
+
+    let original = ProcessedImage {
+        data: data.to_string(),
+        mime_type: actual.to_mime_type().to_string(),
+    };
+    let Some(options) = options else {
+        return Ok(original);
+    };
+    match transcode(&bytes, actual, options) {
+        Ok(Some(processed)) => Ok(processed),
+        Ok(None) => Ok(original),
+        Err(e) => {
+            tracing::warn!("[Images] Post-processing skipped, returning original image: {}", e);
+            Ok(original)
+        }
+    }
+}
+
+fn transcode(
+    bytes: &[u8],
+    actual: ImageFormat,
+    options: &ImageOutputOptions,
+) -> Result<Option<ProcessedImage>, String> {
+    let target = options.format.unwrap_or(actual);
+    if target == actual && options.max_size.is_none() {
+        return Ok(None);
+    }
+
+    let img = image::load_from_memory_with_format(bytes, actual)
+        .map_err(|e| format!("cannot decode {}: {}", actual.to_mime_type(), e))?;
+    let resized = match options.max_size {
+        Some((w, h)) if img.width() > w || img.height() > h => {
+            Some(img.resize(w, h, FilterType::Lanczos3))
+        }
+        _ => None,
+    };
+    if target == actual && resized.is_none() {
+        return Ok(None);
+    }
+
+    let mut img = resized.unwrap_or(img);
+    // JPEG 不支持透明通道，编码前去掉 alpha
+    if target == ImageFormat::Jpeg && img.color().has_alpha() {
+        img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, target)
+        .map_err(|e| format!("cannot encode {}: {}", target.to_mime_type(), e))?;
+    Ok(Some(ProcessedImage {
+        data: base64::engine::general_purpose::STANDARD.encode(out.into_inner()),
+        mime_type: target.to_mime_type().to_string(),
+    }))
+}
+
+/// 将 Gemini inlineData 转为 OpenAI images 响应项 (url 为 data: URL，否则 b64_json)
+/// data 为空时返回 Ok(None)；解码 / 转码在阻塞线程池中执行，避免占用异步运行时
+pub async fn image_response_item(
+    inline_data: &Value,
+    response_format: &str,
+    options: Option<&ImageOutputOptions>,
+) -> Result<Option<Value>, String> {
+    let data = inline_data.get("data").and_then(|v| v.as_str()).unwrap_or("");
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let data = data.to_string();
+    let declared = inline_data
+        .get("mimeType")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    let options = options.cloned();
+    let image = tokio::task::spawn_blocking(move || {
+        process_inline_image(&data, declared.as_deref(), options.as_ref())
+    })
+    .await
+    .map_err(|e| format!("Image processing task failed: {}", e))??;
+    Ok(Some(if response_format == "url" {
+        json!({ "url": format!("data:{};base64,{}", image.mime_type, image.data) })
+    } else {
+        json!({ "b64_json": image.data })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(img: &image::DynamicImage, format: ImageFormat) -> String {
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, format).unwrap();
+        base64::engine::general_purpose::STANDARD.encode(out.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_image_output_validation_and_mime_correction() {
+        let img = image::DynamicImage::new_rgba8(64, 32);
+        let png = encode(&img, ImageFormat::Png);
+
+        // 上游声明 JPEG 但实际为 PNG: data URL 使用实际类型
+        let item = image_response_item(
+            &json!({ "mimeType": "image/jpeg", "data": png }),
+            "url",
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let url = item["url"].as_str().unwrap();
+        assert!(url.starts_with("data:image/png;base64,"));
+        assert!(url.ends_with(&png));
+
+        // 无法解码的 base64 / 非图片数据
+        for data in ["not base64!!", "aGVsbG8="] {
+            let item = json!({ "data": data });
+            assert!(image_response_item(&item, "b64_json", None).await.is_err());
+        }
+        let item = json!({ "data": "" });
+        assert!(image_response_item(&item, "b64_json", None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_image_output_transcode_and_downscale() {
+        let webp = encode(&image::DynamicImage::new_rgba8(64, 32), ImageFormat::WebP);
+
+        let options = ImageOutputOptions::from_request(None, Some("16x16"));
+        let processed = process_inline_image(&webp, Some("image/webp"), Some(&options)).unwrap();
+        assert_eq!(processed.mime_type, "image/png");
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&processed.data)
+            .unwrap();
+        let out = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+        assert_eq!((out.width(), out.height()), (16, 8));
+
+        // 已是目标格式且不超过尺寸时原样返回
+        let png = encode(&image::DynamicImage::new_rgba8(8, 8), ImageFormat::Png);
+        let processed = process_inline_image(&png, None, Some(&options)).unwrap();
+        assert_eq!(processed.data, png);
+
+        // 转为 JPEG (含透明通道的源图)
+        let options = ImageOutputOptions::from_request(Some("jpeg"), None);
+        let processed = process_inline_image(&webp, None, Some(&options)).unwrap();
+        assert_eq!(processed.mime_type, "image/jpeg");
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&processed.data)
+            .unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Jpeg);
+        let out = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg).unwrap();
+        assert_eq!((out.width(), out.height()), (64, 32));
+
+        assert_eq!(parse_size("1792x1024"), Some((1792, 1024)));
+        assert_eq!(parse_size("auto"), None);
+        assert_eq!(parse_output_format("JPG"), Some(ImageFormat::Jpeg));
+    }
+}
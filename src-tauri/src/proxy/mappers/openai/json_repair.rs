@@ -0,0 +1,188 @@
+// 工具调用参数 JSON 修复
+// 流式输出被截断 (限流 / max_tokens) 时 function.arguments 可能是不完整的 JSON，客户端解析会直接崩溃
+// 尽力补全未闭合的字符串 / 括号；无法修复时替换为 {} 并附加说明注解 (由 experimental.enable_tool_args_repair 开启)
+use serde_json::{json, Value};
+
+use super::models::OpenAIResponse;
+
+/// 修复结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepairOutcome {
+    /// 原本就是合法 JSON
+    Valid,
+    /// 补全截断部分后成为合法 JSON
+    Repaired(String),
+    /// 无法修复，替换为空对象
+    Replaced,
+}
+
+/// 尝试将截断的 JSON 补全为合法 JSON
+pub fn repair_truncated_json(input: &str) -> Option<String> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    let mut closers: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for ch in trimmed.chars() {
+        if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            // 括号不匹配说明不是单纯的截断，放弃修复 (匹配时 pop 已生效)
+            '}' | ']' if closers.pop() != Some(ch) => return None,
+            _ => {}
+        }
+    }
+
+    let mut base = trimmed.to_string();
+    if in_string {
+        if escaped {
+            base.pop();
+        }
+        base.push('"');
+    }
+    let suffix: String = closers.iter().rev().collect();
+
+    // 截断位置可能落在 "key" 之后、":" 之后或 "," 之后，依次尝试补齐
+    let without_comma = base.trim_end().trim_end_matches(',').to_string();
+    let candidates = [
+        format!("{}{}", base, suffix),
+        format!("{}{}", without_comma, suffix),
+        format!("{}null{}", base, suffix),
+        format!("{}:null{}", base, suffix),
+    ];
+    candidates
+        .into_iter()
+        .find(|c| serde_json::from_str::<Value>(c).is_ok())
+}
+
+/// 校验并修复单个参数字符串
+pub fn repair_arguments(arguments: &str) -> RepairOutcome {
+    if serde_json::from_str::<Value>(arguments).is_ok() {
+        return RepairOutcome::Valid;
+    }
+    match repair_truncated_json(arguments) {
+        Some(fixed) => RepairOutcome::Repaired(fixed),
+        None => RepairOutcome::Replaced,
+    }
+}
+
+/// 修复响应中所有工具调用的参数，返回修改的数量
+/// 每处修改都会在 message.annotations 中追加 tool_arguments_repair 注解，便于客户端识别
+pub fn repair_tool_call_arguments(response: &mut OpenAIResponse) -> usize {
+    let mut changed = 0;
+    for choice in response.choices.iter_mut() {
+        let message = &mut choice.message;
+        let Some(calls) = message.tool_calls.as_mut() else {
+            continue;
+        };
+        let mut notes = Vec::new();
+        for call in calls.iter_mut() {
+            let action = match repair_arguments(&call.function.arguments) {
+                RepairOutcome::Valid => continue,
+                RepairOutcome::Repaired(fixed) => {
+                    call.function.arguments = fixed;
+                    "closed_truncated_json"
+                }
+                RepairOutcome::Replaced => {
+                    call.function.arguments = "{}".to_string();
+                    "replaced_with_empty_object"
+                }
+            };
+            tracing::warn!(
+                "[OpenAI] Repaired invalid tool_call arguments for {} ({}): {}",
+                call.function.name,
+                call.id,
+                action
+            );
+            notes.push(json!({
+                "type": "tool_arguments_repair",
+                "tool_call_id": call.id,
+                "action": action,
+                "finish_reason": choice.finish_reason,
+            }));
+        }
+        if !notes.is_empty() {
+            changed += notes.len();
+            message.annotations.get_or_insert_with(Vec::new).extend(notes);
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_arguments_become_valid_json() {
+        let fixed = repair_truncated_json(r#"{"path": "src/ma"#).unwrap();
+        assert_eq!(fixed, r#"{"path": "src/ma"}"#);
+        assert!(serde_json::from_str::<Value>(&fixed).is_ok());
+
+        for truncated in [
+            r#"{"path": "src/main.rs", "#,
+            r#"{"path": "src/main.rs", "content":"#,
+            r#"{"edits": [{"old": "a\"#,
+            r#"{"pa"#,
+            r#"{"items": [1, 2"#,
+        ] {
+            let fixed = repair_truncated_json(truncated)
+                .unwrap_or_else(|| panic!("should repair {}", truncated));
+            assert!(serde_json::from_str::<Value>(&fixed).is_ok(), "{}", fixed);
+        }
+
+        // 无法修复时替换为 {}
+        assert_eq!(repair_arguments(r#"{"a": tru"#), RepairOutcome::Replaced);
+        assert_eq!(repair_arguments(r#"{"a": 1}"#), RepairOutcome::Valid);
+    }
+
+    #[test]
+    fn test_repair_tool_call_arguments_in_response() {
+        let mut response: OpenAIResponse = serde_json::from_value(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gemini-2.5-flash",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "tool_calls": [
+                        { "id": "call_1", "type": "function", "function": { "name": "read_file", "arguments": "{\"path\": \"src/ma" } },
+                        { "id": "call_2", "type": "function", "function": { "name": "list", "arguments": "{}" } },
+                        { "id": "call_3", "type": "function", "function": { "name": "run", "arguments": "{\"x\": nul" } }
+                    ]
+                },
+                "finish_reason": "length"
+            }]
+        }))
+        .unwrap();
+
+        assert_eq!(repair_tool_call_arguments(&mut response), 2);
+        let message = &response.choices[0].message;
+        let calls = message.tool_calls.as_ref().unwrap();
+        let args: Value = serde_json::from_str(&calls[0].function.arguments).unwrap();
+        assert_eq!(args["path"], "src/ma");
+        assert_eq!(calls[1].function.arguments, "{}");
+        assert_eq!(calls[2].function.arguments, "{}");
+
+        let notes = message.annotations.as_ref().unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0]["action"], "closed_truncated_json");
+        assert_eq!(notes[1]["tool_call_id"], "call_3");
+        assert_eq!(notes[1]["action"], "replaced_with_empty_object");
+    }
+}
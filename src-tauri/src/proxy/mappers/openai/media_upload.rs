@@ -0,0 +1,254 @@
+// 大体积媒体上传
+// 内联 base64 超过 experimental.media_upload_threshold_bytes 的媒体 (视频 / PDF 等) 改为上传至 Gemini Files API，
+// 转换时以 fileData.fileUri 引用，避免触发上游请求体大小限制；小图片仍以 inlineData 内联
+use base64::Engine as _;
+
+use super::models::*;
+use crate::proxy::upstream::client::UpstreamClient;
+
+/// 拆分 base64 data URL，返回 (mime_type, data)
+fn split_data_url(url: &str) -> Option<(&str, &str)> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime_type = header.strip_suffix(";base64")?;
+    Some((mime_type, data))
+}
+
+/// base64 数据解码后的近似字节数
+fn decoded_len(data: &str) -> usize {
+    data.len() / 4 * 3
+}
+
+fn is_large_media(url: &str, threshold: usize) -> bool {
+    split_data_url(url).is_some_and(|(_, data)| decoded_len(data) > threshold)
+}
+
+/// 请求中是否包含超过阈值的内联媒体，threshold 为 0 时关闭
+pub fn has_large_inline_media(request: &OpenAIRequest, threshold: usize) -> bool {
+    threshold > 0
+        && request.messages.iter().any(|msg| match msg.content.as_ref() {
+            Some(OpenAIContent::Array(blocks)) => blocks.iter().any(|b| {
+                matches!(b, OpenAIContentBlock::ImageUrl { image_url } if is_large_media(&image_url.url, threshold))
+            }),
+            _ => false,
+        })
+}
+
+/// 将超过阈值的内联媒体上传至 Files API 并替换为文件 URI，返回替换后的请求副本
+/// 无需上传时返回 None；单个文件上传失败时保留原内联数据，由上游决定是否接受
+pub async fn upload_large_media(
+    upstream: &UpstreamClient,
+    access_token: &str,
+    account_id: Option<&str>,
+    request: &OpenAIRequest,
+    threshold: usize,
+) -> Option<OpenAIRequest> {
+    if !has_large_inline_media(request, threshold) {
+        return None;
+    }
+    let mut request = request.clone();
+    let mut uploaded = 0;
+    for msg in request.messages.iter_mut() {
+        let Some(OpenAIContent::Array(blocks)) = msg.content.as_mut() else {
+            continue;
+        };
+        for block in blocks.iter_mut() {
+            let OpenAIContentBlock::ImageUrl { image_url } = block else {
+                continue;
+            };
+            if !is_large_media(&image_url.url, threshold) {
+                continue;
+            }
+            let Some((mime_type, data)) = split_data_url(&image_url.url) else {
+                continue;
+            };
+            let mime_type = mime_type.to_string();
+            let bytes = match base64::engine::general_purpose::STANDARD.decode(data) {
+                Ok(b) => b,
+                Err(e) => {
+                    tracing::warn!("[Media-Upload] Skipping invalid base64 media: {}", e);
+                    continue;
+                }
+            };
+            let display_name = format!("upload-{}", uuid::Uuid::new_v4().simple());
+            match upstream
+                .upload_file(access_token, account_id, bytes, &mime_type, &display_name)
+                .await
+            {
+                Ok(uri) => {
+                    image_url.url = uri;
+                    image_url.mime_type = Some(mime_type);
+                    uploaded += 1;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "[Media-Upload] Upload failed, keeping inline data ({}): {}",
+                        mime_type,
+                        e
+                    );
+                }
+            }
+        }
+    }
+    if uploaded > 0 {
+        tracing::info!(
+            "[Media-Upload] Uploaded {} large media file(s) to Files API",
+            uploaded
+        );
+    }
+    Some(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::{spawn_upstream, spawn_upstream_with};
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::post,
+        Json, Router,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// 模拟 Files API: 发起会话返回上传地址，上传后返回文件 URI
+    async fn spawn_files_api(received: Arc<Mutex<Vec<Vec<u8>>>>) -> String {
+        let addr = spawn_upstream_with(move |addr| {
+            let session_url = format!("http://{}/session/1", addr);
+            Router::new()
+                .route(
+                    "/upload/v1beta/files",
+                    post(move |headers: HeaderMap| async move {
+                        assert_eq!(headers["x-goog-upload-command"], "start");
+                        assert_eq!(headers["authorization"], "Bearer token-a");
+                        assert_eq!(headers["x-goog-upload-header-content-type"], "video/mp4");
+                        (
+                            StatusCode::OK,
+                            [("x-goog-upload-url", session_url)],
+                            "",
+                        )
+                            .into_response()
+                    }),
+                )
+                .route(
+                    "/session/1",
+                    post(move |headers: HeaderMap, body: Bytes| async move {
+                        assert_eq!(headers["x-goog-upload-command"], "upload, finalize");
+                        received.lock().unwrap().push(body.to_vec());
+                        Json(serde_json::json!({
+                            "file": { "uri": "https://files.example/v1beta/files/abc123", "mimeType": "video/mp4" }
+                        }))
+                    }),
+                )
+        })
+        .await;
+        format!("http://{}/upload/v1beta/files", addr)
+    }
+
+    #[tokio::test]
+    async fn test_large_media_uploaded_as_file_data() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let upstream = UpstreamClient::new(None, None);
+        upstream
+            .set_files_upload_url(&spawn_files_api(received.clone()).await)
+            .await;
+
+        let video = vec![7u8; 600];
+        let video_b64 = base64::engine::general_purpose::STANDARD.encode(&video);
+        let small_png = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg==";
+        let request: OpenAIRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": [
+                { "type": "text", "text": "Summarize this clip" },
+                { "type": "image_url", "image_url": { "url": format!("data:video/mp4;base64,{}", video_b64) } },
+                { "type": "image_url", "image_url": { "url": format!("data:image/png;base64,{}", small_png) } }
+            ]}]
+        }))
+        .unwrap();
+
+        // 阈值关闭或未超限时不上传
+        assert!(upload_large_media(&upstream, "token-a", None, &request, 0)
+            .await
+            .is_none());
+        assert!(
+            upload_large_media(&upstream, "token-a", None, &request, 4096)
+                .await
+                .is_none()
+        );
+
+        let uploaded = upload_large_media(&upstream, "token-a", None, &request, 256)
+            .await
+            .unwrap();
+        assert_eq!(received.lock().unwrap().as_slice(), &[video]);
+
+        let (body, _, _) = super::super::request::transform_openai_request(
+            &uploaded,
+            "test-project",
+            "gemini-2.5-flash",
+        );
+        let parts = body["request"]["contents"][0]["parts"].as_array().unwrap();
+        assert_eq!(
+            parts[1]["fileData"],
+            serde_json::json!({ "fileUri": "https://files.example/v1beta/files/abc123", "mimeType": "video/mp4" })
+        );
+        assert_eq!(parts[2]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(parts[2]["inlineData"]["data"], small_png);
+
+        // 同一账号重试 / 重复请求复用已上传的 URI，不再上传
+        let again = upload_large_media(&upstream, "token-a", None, &request, 256)
+            .await
+            .unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(
+            serde_json::to_value(&again.messages).unwrap(),
+            serde_json::to_value(&uploaded.messages).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_denied_token_falls_back_to_inline_without_retrying() {
+        // 模拟 Files API 拒绝该账号的 token
+        let starts = Arc::new(Mutex::new(0));
+        let addr = spawn_upstream({
+            let starts = starts.clone();
+            Router::new().route(
+                "/upload/v1beta/files",
+                post(move || async move {
+                    *starts.lock().unwrap() += 1;
+                    StatusCode::FORBIDDEN
+                }),
+            )
+        })
+        .await;
+        let upstream = UpstreamClient::new(None, None);
+        upstream
+            .set_files_upload_url(&format!("http://{}/upload/v1beta/files", addr))
+            .await;
+
+        let video_url = format!(
+            "data:video/mp4;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(vec![7u8; 600])
+        );
+        let request: OpenAIRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": [
+                { "type": "image_url", "image_url": { "url": video_url } }
+            ]}]
+        }))
+        .unwrap();
+
+        for _ in 0..2 {
+            let result = upload_large_media(&upstream, "token-a", Some("acc-1"), &request, 256)
+                .await
+                .unwrap();
+            let Some(OpenAIContent::Array(blocks)) = result.messages[0].content.as_ref() else {
+                panic!("expected content blocks");
+            };
+            assert!(matches!(
+                &blocks[0],
+                OpenAIContentBlock::ImageUrl { image_url } if image_url.url == video_url
+            ));
+        }
+        assert_eq!(*starts.lock().unwrap(), 1);
+    }
+}
@@ -6,7 +6,15 @@ pub mod request;
 pub mod response;
 pub mod streaming;
 pub mod collector; // [NEW]
+pub mod document_input; // PDF 等文档内容块
+pub mod image_fetch;
+pub mod image_input; // 上传图片超限时缩小
+pub mod image_output; // 生成图片的校验 / 转码 / 缩放
+pub mod media_upload; // 大体积媒体上传至 Files API
+pub mod json_repair; // 截断的工具调用参数修复
 pub mod thinking_recovery;
+pub mod tool_call_ids; // 工具调用 ID 生成与回传解析
+pub mod validation; // 请求体校验 (定位出错字段)
 
 pub use models::*;
 pub use request::*;
@@ -16,9 +16,22 @@ pub struct OpenAIRequest {
     pub n: Option<u32>, // [NEW] 支持多候选结果数量
     #[serde(rename = "max_tokens")]
     pub max_tokens: Option<u32>,
+    // [NEW] 新版 OpenAI 客户端 (推理模型) 使用 max_completion_tokens 替代 max_tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
     pub temperature: Option<f64>,
     #[serde(rename = "top_p")]
     pub top_p: Option<f64>,
+    // [NEW] 重复惩罚 (映射到 Gemini frequencyPenalty / presencePenalty)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    // [NEW] 对数概率: chat 接口为布尔值，旧版 completions 接口为整数 (等同于 top_logprobs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
     pub stop: Option<Value>,
     pub response_format: Option<ResponseFormat>,
     #[serde(default)]
@@ -40,6 +53,100 @@ pub struct OpenAIRequest {
     // [NEW] Thinking/Extended Thinking 支持 (兼容 Anthropic/Claude 协议)
     #[serde(default)]
     pub thinking: Option<ThinkingConfig>,
+    // [NEW] 终端用户标识 (用于粘性会话区分与按用户限流)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    // [NEW] json_schema 模式下字符串值使用的自然语言 (未提供时取 Accept-Language)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_language: Option<String>,
+    // [NEW] 丢弃思维链 (thought) 内容，仅保留最终回答 (覆盖 openai_compat.strip_reasoning)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_reasoning: Option<bool>,
+    // [NEW] Predicted Outputs ({ type: "content", content })，Gemini 无对应能力，转换时忽略
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prediction: Option<Value>,
+    // [NEW] 音频输出 (gpt-4o-audio 的 audio 参数与 modalities: ["text", "audio"])，上游仅输出文本
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Value>,
+    // [NEW] 单次请求的思考预算: OpenAI reasoning_effort (low/medium/high) 或自定义 thinking_budget (token 数)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    /// -1 表示由模型动态决定思考预算 (Gemini 动态思考)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<i64>,
+}
+
+impl OpenAIRequest {
+    /// 是否请求了音频输出 (audio 参数或 modalities 包含 "audio")
+    pub fn wants_audio_output(&self) -> bool {
+        let audio_modality = self
+            .modalities
+            .as_ref()
+            .and_then(|m| m.as_array())
+            .map(|m| m.iter().any(|v| v.as_str() == Some("audio")))
+            .unwrap_or(false);
+        audio_modality || self.audio.as_ref().map(|a| !a.is_null()).unwrap_or(false)
+    }
+
+    /// 输出 token 上限: 同时提供时以 max_completion_tokens 为准
+    pub fn output_token_limit(&self) -> Option<u32> {
+        self.max_completion_tokens.or(self.max_tokens)
+    }
+
+    pub fn has_penalties(&self) -> bool {
+        self.frequency_penalty.is_some() || self.presence_penalty.is_some()
+    }
+
+    /// 移除重复惩罚参数，返回是否有参数被移除
+    pub fn strip_penalties(&mut self) -> bool {
+        let had = self.has_penalties();
+        self.frequency_penalty = None;
+        self.presence_penalty = None;
+        had
+    }
+
+    /// prediction.content 中的预测文本 (字符串或文本块数组)
+    pub fn prediction_text(&self) -> Option<String> {
+        let content = self.prediction.as_ref()?.get("content")?;
+        let text = match content {
+            Value::String(s) => s.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+                .collect::<Vec<_>>()
+                .join(""),
+            _ => return None,
+        };
+        Some(text).filter(|t| !t.trim().is_empty())
+    }
+
+    pub fn logprobs_requested(&self) -> bool {
+        match &self.logprobs {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::Number(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// 每个位置返回的候选数量: top_logprobs 优先，否则取旧版 completions 的整数 logprobs
+    pub fn requested_top_logprobs(&self) -> Option<u32> {
+        self.top_logprobs.or_else(|| {
+            self.logprobs
+                .as_ref()
+                .and_then(|v| v.as_u64())
+                .map(|n| n as u32)
+        })
+    }
+
+    /// 移除对数概率参数，返回是否有参数被移除
+    pub fn strip_logprobs(&mut self) -> bool {
+        let had = self.logprobs_requested();
+        self.logprobs = None;
+        self.top_logprobs = None;
+        had
+    }
 }
 
 /// Thinking 配置 (兼容 Anthropic 和 OpenAI 扩展协议)
@@ -72,6 +179,9 @@ pub enum OpenAIContentBlock {
     ImageUrl { image_url: OpenAIImageUrl },
     #[serde(rename = "audio_url")]
     AudioUrl { audio_url: AudioUrlContent },
+    // [NEW] 文档 (PDF 等)，见 document_input
+    #[serde(rename = "file")]
+    File { file: OpenAIFileContent },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -79,6 +189,22 @@ pub struct OpenAIImageUrl {
     pub url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub detail: Option<String>,
+    /// [NEW] url 为 Gemini Files API 文件 URI 时的媒体类型 (上传大体积媒体后填充)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// file 内容块: file_data 为 data URL 或裸 base64；file_url 为扩展字段 (http(s) 文档地址)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct OpenAIFileContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -99,6 +225,9 @@ pub struct OpenAIMessage {
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// 联网搜索引文 (url_citation)，由 Gemini groundingMetadata 转换而来
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Value>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -130,6 +259,11 @@ pub struct Choice {
     pub index: u32,
     pub message: OpenAIMessage,
     pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Value>,
+    /// [NEW] 扩展字段: finish_reason 为 content_filter 时触发拦截的 Gemini 安全类别
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_filter_categories: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
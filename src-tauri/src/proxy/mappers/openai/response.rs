@@ -1,12 +1,154 @@
 // OpenAI 协议响应转换模块
 use super::models::*;
-use serde_json::Value;
+use serde_json::{json, Value};
+
+/// 将 Gemini groundingMetadata 转换为 OpenAI url_citation 注解 (按来源 URL 去重)
+/// start_index / end_index 取自 groundingSupports 中首个引用该来源的文本片段，缺失时为 0
+/// Gemini 的 segment 索引是 text 中的 UTF-8 字节偏移，这里换算为 OpenAI 使用的字符偏移
+pub fn grounding_annotations(grounding: &Value, text: &str) -> Vec<Value> {
+    let Some(chunks) = grounding.get("groundingChunks").and_then(|c| c.as_array()) else {
+        return Vec::new();
+    };
+    let supports = grounding
+        .get("groundingSupports")
+        .and_then(|s| s.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    let mut seen = std::collections::HashSet::new();
+    let mut annotations = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let Some(web) = chunk.get("web") else { continue };
+        let Some(uri) = web.get("uri").and_then(|v| v.as_str()) else { continue };
+        if !seen.insert(uri.to_string()) {
+            continue;
+        }
+        let segment = supports
+            .iter()
+            .find(|s| {
+                s.get("groundingChunkIndices")
+                    .and_then(|v| v.as_array())
+                    .map(|idx| idx.iter().any(|v| v.as_u64() == Some(i as u64)))
+                    .unwrap_or(false)
+            })
+            .and_then(|s| s.get("segment"));
+        let index_of = |key: &str| {
+            segment
+                .and_then(|seg| seg.get(key))
+                .and_then(|v| v.as_u64())
+                .map(|offset| byte_to_char_offset(text, offset as usize))
+                .unwrap_or(0)
+        };
+        annotations.push(json!({
+            "type": "url_citation",
+            "url_citation": {
+                "url": uri,
+                "title": web.get("title").and_then(|v| v.as_str()).unwrap_or(""),
+                "start_index": index_of("startIndex"),
+                "end_index": index_of("endIndex"),
+            }
+        }));
+    }
+    annotations
+}
+
+/// 字节偏移换算为字符偏移；落在多字节字符中间时计入该字符，超出文本时取文本末尾
+fn byte_to_char_offset(text: &str, byte_offset: usize) -> usize {
+    text.char_indices()
+        .take_while(|(i, _)| *i < byte_offset)
+        .count()
+}
+
+fn logprob_entry(candidate: &Value) -> Value {
+    let token = candidate.get("token").and_then(|v| v.as_str()).unwrap_or("");
+    json!({
+        "token": token,
+        "logprob": candidate.get("logProbability").and_then(|v| v.as_f64()).unwrap_or(0.0),
+        "bytes": token.as_bytes(),
+    })
+}
+
+/// 将 Gemini logprobsResult 转换为 OpenAI choices[].logprobs 结构
+/// chosenCandidates[i] 对应 content[i]，topCandidates[i].candidates 对应其 top_logprobs
+/// 仅有 avgLogprobs (无逐 token 数据) 时无法还原，返回 None
+pub fn map_logprobs(candidate: &Value) -> Option<Value> {
+    let result = candidate.get("logprobsResult")?;
+    let chosen = result.get("chosenCandidates").and_then(|v| v.as_array())?;
+    let top = result
+        .get("topCandidates")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    let content: Vec<Value> = chosen
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let mut entry = logprob_entry(c);
+            let top_logprobs: Vec<Value> = top
+                .get(i)
+                .and_then(|t| t.get("candidates"))
+                .and_then(|v| v.as_array())
+                .map(|list| list.iter().map(logprob_entry).collect())
+                .unwrap_or_default();
+            entry["top_logprobs"] = json!(top_logprobs);
+            entry
+        })
+        .collect();
+    Some(json!({ "content": content }))
+}
+
+/// [FIX] 将 chat 形式的 logprobs 转换为旧版 /v1/completions 的结构:
+/// { tokens, token_logprobs, top_logprobs: [{token: logprob}], text_offset }
+/// text_offset 为字符偏移，流式场景由调用方传入已输出文本的长度
+pub fn legacy_completion_logprobs(chat_logprobs: &Value, text_offset: usize) -> Value {
+    let content = chat_logprobs
+        .get("content")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    let mut tokens = Vec::with_capacity(content.len());
+    let mut token_logprobs = Vec::with_capacity(content.len());
+    let mut top_logprobs = Vec::with_capacity(content.len());
+    let mut offsets = Vec::with_capacity(content.len());
+    let mut offset = text_offset;
+    for entry in &content {
+        let token = entry.get("token").and_then(|v| v.as_str()).unwrap_or("");
+        tokens.push(json!(token));
+        token_logprobs.push(entry.get("logprob").cloned().unwrap_or(Value::Null));
+        let top: serde_json::Map<String, Value> = entry
+            .get("top_logprobs")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|t| {
+                let token = t.get("token").and_then(|v| v.as_str())?;
+                Some((token.to_string(), t.get("logprob").cloned()?))
+            })
+            .collect();
+        top_logprobs.push(Value::Object(top));
+        offsets.push(json!(offset));
+        offset += token.chars().count();
+    }
+    json!({
+        "tokens": tokens,
+        "token_logprobs": token_logprobs,
+        "top_logprobs": top_logprobs,
+        "text_offset": offsets,
+    })
+}
 
 pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&str>, message_count: usize) -> OpenAIResponse {
     // 解包 response 字段
     let raw = gemini_response.get("response").unwrap_or(gemini_response);
 
     let mut choices = Vec::new();
+    // 工具调用 ID 的盐: 与流式路径一致使用 responseId，缺失时为本次响应随机生成
+    let tool_call_salt = raw
+        .get("responseId")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
 
     // 支持多候选结果 (n > 1)
     if let Some(candidates) = raw.get("candidates").and_then(|c| c.as_array()) {
@@ -14,6 +156,8 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
             let mut content_out = String::new();
             let mut thought_out = String::new();
             let mut tool_calls = Vec::new();
+            let mut tool_call_ids = super::tool_call_ids::ToolCallIds::new(&tool_call_salt, idx);
+            let mut annotations = Vec::new();
 
             // 提取 content 和 tool_calls
             if let Some(parts) = candidate
@@ -57,11 +201,8 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
                             .get("args")
                             .map(|v| v.to_string())
                             .unwrap_or_else(|| "{}".to_string());
-                        let id = fc
-                            .get("id")
-                            .and_then(|v| v.as_str())
-                            .map(|s| s.to_string())
-                            .unwrap_or_else(|| format!("{}-{}", name, uuid::Uuid::new_v4()));
+                        // [FIX] 同一轮中参数完全相同的并行调用会得到相同的哈希 ID，分配器追加序号保证唯一
+                        let id = tool_call_ids.assign(fc);
 
                         tool_calls.push(ToolCall {
                             id,
@@ -90,6 +231,7 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
 
             // 提取并处理该候选结果的联网搜索引文 (Grounding Metadata)
             if let Some(grounding) = candidate.get("groundingMetadata") {
+                annotations = grounding_annotations(grounding, &content_out);
                 let mut grounding_text = String::new();
 
                 // 1. 处理搜索词
@@ -131,14 +273,30 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
             let finish_reason = candidate
                 .get("finishReason")
                 .and_then(|f| f.as_str())
-                .map(|f| match f {
-                    "STOP" => "stop",
-                    "MAX_TOKENS" => "length",
-                    "SAFETY" => "content_filter",
-                    "RECITATION" => "content_filter",
-                    _ => "stop",
-                })
+                .and_then(map_finish_reason)
                 .unwrap_or("stop");
+            // 与流式路径一致 (FIX #1575): 返回了工具调用时 finish_reason 为 tool_calls
+            let finish_reason = if !tool_calls.is_empty() && finish_reason == "stop" {
+                "tool_calls"
+            } else {
+                finish_reason
+            };
+
+            // 被 SAFETY / RECITATION 拦截且没有任何输出时，补充说明文本，避免客户端收到空回复
+            if content_out.is_empty() && tool_calls.is_empty() {
+                if let Some(notice) = candidate
+                    .get("finishReason")
+                    .and_then(|f| f.as_str())
+                    .and_then(blocked_finish_notice)
+                {
+                    content_out.push_str(notice);
+                }
+            }
+
+            // [NEW] 被安全策略拦截时附带触发的安全类别
+            let content_filter_categories = (finish_reason == "content_filter")
+                .then(|| blocked_safety_categories(candidate.get("safetyRatings")))
+                .filter(|c| !c.is_empty());
 
             choices.push(Choice {
                 index: idx as u32,
@@ -161,12 +319,38 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
                     },
                     tool_call_id: None,
                     name: None,
+                    annotations: if annotations.is_empty() {
+                        None
+                    } else {
+                        Some(annotations)
+                    },
                 },
                 finish_reason: Some(finish_reason.to_string()),
+                logprobs: map_logprobs(candidate),
+                content_filter_categories,
             });
         }
     }
 
+    // [NEW] 输入被拦截 (promptFeedback.blockReason) 时上游不返回候选，补充一个 content_filter 结果
+    if let Some((notice, categories)) = prompt_block(raw) {
+        choices.push(Choice {
+            index: 0,
+            message: OpenAIMessage {
+                role: "assistant".to_string(),
+                content: Some(OpenAIContent::String(notice)),
+                reasoning_content: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                annotations: None,
+            },
+            finish_reason: Some("content_filter".to_string()),
+            logprobs: None,
+            content_filter_categories: (!categories.is_empty()).then_some(categories),
+        });
+    }
+
     // Extract and map usage metadata from Gemini to OpenAI format
     let usage = raw.get("usageMetadata").and_then(|u| {
         let prompt_tokens = u
@@ -215,10 +399,160 @@ pub fn transform_openai_response(gemini_response: &Value, session_id: Option<&st
     }
 }
 
+/// parallel_tool_calls: false 时仅保留每个 choice 的第一个工具调用，返回被丢弃的数量
+pub fn limit_to_single_tool_call(response: &mut OpenAIResponse) -> usize {
+    let mut suppressed = 0;
+    for choice in response.choices.iter_mut() {
+        if let Some(calls) = choice.message.tool_calls.as_mut() {
+            if calls.len() > 1 {
+                suppressed += calls.len() - 1;
+                calls.truncate(1);
+            }
+        }
+    }
+    if suppressed > 0 {
+        tracing::info!(
+            "[OpenAI] parallel_tool_calls=false: suppressed {} additional tool call(s)",
+            suppressed
+        );
+    }
+    suppressed
+}
+
+/// strip_reasoning: 丢弃各 choice 的思维链内容，仅保留最终回答，返回被丢弃的 choice 数量
+pub fn strip_reasoning_content(response: &mut OpenAIResponse) -> usize {
+    response
+        .choices
+        .iter_mut()
+        .filter_map(|choice| choice.message.reasoning_content.take())
+        .count()
+}
+
+/// 被安全策略截断时返回给客户端的错误体 (流式错误事件与非流式响应共用)
+pub fn content_filter_error() -> Value {
+    json!({
+        "error": {
+            "message": "The response was blocked by the upstream safety filter",
+            "type": "invalid_request_error",
+            "code": "content_filter"
+        }
+    })
+}
+
+/// Gemini finishReason -> OpenAI finish_reason，未知值返回 None 由调用方兜底
+/// RECITATION (引用受版权保护内容) 没有对应的 OpenAI 值，使用自定义的 "recitation"
+pub fn map_finish_reason(reason: &str) -> Option<&'static str> {
+    match reason {
+        "STOP" => Some("stop"),
+        "MAX_TOKENS" => Some("length"),
+        "SAFETY" | "PROHIBITED_CONTENT" | "BLOCKLIST" | "SPII" | "IMAGE_SAFETY" => {
+            Some("content_filter")
+        }
+        "RECITATION" => Some("recitation"),
+        _ => None,
+    }
+}
+
+/// 生成被拦截时的说明文本 (仅在候选没有任何输出时使用)
+pub fn blocked_finish_notice(reason: &str) -> Option<&'static str> {
+    match map_finish_reason(reason)? {
+        "content_filter" => Some("[Response blocked by the upstream safety filter]"),
+        "recitation" => Some(
+            "[Response stopped: the model output closely matched existing material (RECITATION)]",
+        ),
+        _ => None,
+    }
+}
+
+/// 触发拦截的 Gemini 安全类别: 取 safetyRatings 中 blocked = true 的项，
+/// 上游未标记 blocked 时退而取 MEDIUM / HIGH 概率的项
+pub fn blocked_safety_categories(ratings: Option<&Value>) -> Vec<String> {
+    let ratings = ratings
+        .and_then(|r| r.as_array())
+        .cloned()
+        .unwrap_or_default();
+    let category = |r: &Value| r.get("category").and_then(|c| c.as_str()).map(String::from);
+    let blocked: Vec<String> = ratings
+        .iter()
+        .filter(|r| r.get("blocked").and_then(|b| b.as_bool()) == Some(true))
+        .filter_map(category)
+        .collect();
+    if !blocked.is_empty() {
+        return blocked;
+    }
+    ratings
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.get("probability").and_then(|p| p.as_str()),
+                Some("MEDIUM") | Some("HIGH")
+            )
+        })
+        .filter_map(category)
+        .collect()
+}
+
+/// 输入被拦截且没有任何候选时返回 (说明文本, 触发的安全类别)
+pub fn prompt_block(raw: &Value) -> Option<(String, Vec<String>)> {
+    let has_candidates = raw
+        .get("candidates")
+        .and_then(|c| c.as_array())
+        .is_some_and(|c| !c.is_empty());
+    if has_candidates {
+        return None;
+    }
+    let feedback = raw.get("promptFeedback")?;
+    let reason = feedback.get("blockReason").and_then(|r| r.as_str())?;
+    Some((
+        format!("[Prompt blocked by the upstream safety filter ({})]", reason),
+        blocked_safety_categories(feedback.get("safetyRatings")),
+    ))
+}
+
+/// 是否存在被安全策略截断的候选 (映射后的 finish_reason 为 content_filter)
+pub fn has_content_filter_finish(response: &OpenAIResponse) -> bool {
+    response
+        .choices
+        .iter()
+        .any(|c| c.finish_reason.as_deref() == Some("content_filter"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
+    use futures::{Stream, StreamExt};
     use serde_json::json;
+    use std::pin::Pin;
+
+    type UpstreamStream = Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>;
+    type ProxyStream = Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>>;
+
+    /// 每个 Gemini 响应编码为一个 SSE data 分片，模拟上游字节流
+    fn sse_chunks<'a>(chunks: impl IntoIterator<Item = &'a Value>) -> UpstreamStream {
+        let chunks: Vec<Result<Bytes, reqwest::Error>> = chunks
+            .into_iter()
+            .map(|c| Ok(Bytes::from(format!("data: {}\n\n", c))))
+            .collect();
+        Box::pin(futures::stream::iter(chunks))
+    }
+
+    /// 按分片收集输出流的原始文本
+    async fn collect_sse_text(stream: ProxyStream) -> Vec<String> {
+        stream
+            .map(|c| String::from_utf8_lossy(&c.unwrap()).to_string())
+            .collect()
+            .await
+    }
+
+    /// 解析输出流中的 data 事件 JSON，跳过 [DONE] 等非 JSON 分片
+    async fn collect_sse(stream: ProxyStream) -> Vec<Value> {
+        collect_sse_text(stream)
+            .await
+            .iter()
+            .filter_map(|c| serde_json::from_str(c.trim().strip_prefix("data: ")?).ok())
+            .collect()
+    }
 
     #[test]
     fn test_transform_openai_response() {
@@ -271,6 +605,731 @@ mod tests {
         assert_eq!(usage.prompt_tokens_details.unwrap().cached_tokens, Some(25));
     }
 
+    fn grounded_gemini_response() -> Value {
+        json!({
+            "candidates": [{
+                "content": {"parts": [{"text": "Rust 1.80 was released in July 2024."}]},
+                "finishReason": "STOP",
+                "groundingMetadata": {
+                    "webSearchQueries": ["rust 1.80 release date"],
+                    "groundingChunks": [
+                        {"web": {"uri": "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html", "title": "rust-lang.org"}},
+                        {"web": {"uri": "https://example.com/news", "title": "example.com"}},
+                        {"web": {"uri": "https://example.com/news", "title": "example.com"}}
+                    ],
+                    "groundingSupports": [{
+                        "segment": {"startIndex": 0, "endIndex": 35},
+                        "groundingChunkIndices": [0]
+                    }]
+                }
+            }]
+        })
+    }
+
+    #[test]
+    fn test_grounding_metadata_mapped_to_url_citations() {
+        let result = transform_openai_response(&grounded_gemini_response(), None, 1);
+        let annotations = result.choices[0].message.annotations.as_ref().unwrap();
+
+        // 重复来源去重
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0]["type"], "url_citation");
+        let first = &annotations[0]["url_citation"];
+        assert_eq!(first["url"], "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html");
+        assert_eq!(first["title"], "rust-lang.org");
+        assert_eq!(first["start_index"], 0);
+        assert_eq!(first["end_index"], 35);
+        // 无 groundingSupports 引用时索引为 0
+        assert_eq!(annotations[1]["url_citation"]["url"], "https://example.com/news");
+        assert_eq!(annotations[1]["url_citation"]["end_index"], 0);
+
+        // 序列化到 message.annotations
+        let body = serde_json::to_value(&result).unwrap();
+        assert_eq!(body["choices"][0]["message"]["annotations"][0]["type"], "url_citation");
+    }
+
+    #[test]
+    fn test_grounding_indices_converted_to_char_offsets() {
+        // "Rust 1.80 " 占 10 字节，"已于七月发布。" 每个汉字 3 字节
+        let text = "Rust 1.80 已于七月发布。";
+        let grounding = json!({
+            "groundingChunks": [{"web": {"uri": "https://blog.rust-lang.org", "title": "rust-lang.org"}}],
+            "groundingSupports": [{
+                "segment": {"startIndex": 10, "endIndex": text.len()},
+                "groundingChunkIndices": [0]
+            }]
+        });
+        let annotations = grounding_annotations(&grounding, text);
+        let citation = &annotations[0]["url_citation"];
+        assert_eq!(citation["start_index"], 10);
+        assert_eq!(citation["end_index"], text.chars().count());
+
+        let start = citation["start_index"].as_u64().unwrap() as usize;
+        let end = citation["end_index"].as_u64().unwrap() as usize;
+        let cited: String = text.chars().skip(start).take(end - start).collect();
+        assert_eq!(cited, "已于七月发布。");
+
+        // 落在多字节字符中间或超出文本时不越界
+        assert_eq!(byte_to_char_offset(text, 11), 11);
+        assert_eq!(byte_to_char_offset(text, 1000), text.chars().count());
+    }
+
+    #[tokio::test]
+    async fn test_grounding_annotations_survive_stream_collection() {
+
+        let openai_stream = super::super::streaming::create_openai_sse_stream(
+            sse_chunks([&grounded_gemini_response()]),
+            "gemini-2.5-flash".to_string(),
+            "session-grounding".to_string(),
+            1,
+        );
+        let result = super::super::collector::collect_stream_to_json(openai_stream, 0)
+            .await
+            .unwrap();
+        let annotations = result.choices[0].message.annotations.as_ref().unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(
+            annotations[0]["url_citation"]["url"],
+            "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gemini_logprobs_mapped_to_openai_shape() {
+
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": "Hi!" }] },
+                "finishReason": "STOP",
+                "avgLogprobs": -0.12,
+                "logprobsResult": {
+                    "topCandidates": [
+                        { "candidates": [
+                            { "token": "Hi", "logProbability": -0.05 },
+                            { "token": "Hello", "logProbability": -3.2 }
+                        ]},
+                        { "candidates": [
+                            { "token": "!", "logProbability": -0.19 }
+                        ]}
+                    ],
+                    "chosenCandidates": [
+                        { "token": "Hi", "logProbability": -0.05 },
+                        { "token": "!", "logProbability": -0.19 }
+                    ]
+                }
+            }]
+        });
+
+        let result = transform_openai_response(&gemini_resp, None, 1);
+        let logprobs = result.choices[0].logprobs.as_ref().unwrap();
+        let content = logprobs["content"].as_array().unwrap();
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[0]["token"], "Hi");
+        assert_eq!(content[0]["logprob"], -0.05);
+        assert_eq!(content[0]["bytes"], json!([72, 105]));
+        assert_eq!(content[0]["top_logprobs"][1]["token"], "Hello");
+        assert_eq!(content[0]["top_logprobs"][1]["logprob"], -3.2);
+        assert_eq!(content[1]["top_logprobs"].as_array().unwrap().len(), 1);
+
+        // 流式收集后得到相同结构
+        let openai_stream = super::super::streaming::create_openai_sse_stream(
+            sse_chunks([&gemini_resp]),
+            "gemini-2.5-flash".to_string(),
+            "session-logprobs".to_string(),
+            1,
+        );
+        let collected = super::super::collector::collect_stream_to_json(openai_stream, 0)
+            .await
+            .unwrap();
+        assert_eq!(collected.choices[0].logprobs.as_ref(), Some(logprobs));
+
+        // 模型未返回 logprobsResult: 序列化结果中不包含 logprobs
+        let plain = transform_openai_response(&grounded_gemini_response(), None, 1);
+        assert!(plain.choices[0].logprobs.is_none());
+        let body = serde_json::to_value(&plain).unwrap();
+        assert!(body["choices"][0].get("logprobs").is_none());
+
+        // 旧版 completions 结构
+        let legacy = legacy_completion_logprobs(logprobs, 3);
+        assert_eq!(legacy["tokens"], json!(["Hi", "!"]));
+        assert_eq!(legacy["token_logprobs"][0], -0.05);
+        assert_eq!(legacy["top_logprobs"][0]["Hello"], -3.2);
+        assert_eq!(legacy["top_logprobs"][1].as_object().unwrap().len(), 1);
+        assert_eq!(legacy["text_offset"], json!([3, 5]));
+    }
+
+    #[tokio::test]
+    async fn test_first_stream_chunks_match_openai_opening() {
+
+        let gemini_chunks = [
+            json!({ "candidates": [{ "content": { "parts": [{ "text": "Hel" }] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": "lo" },
+                { "functionCall": { "name": "lookup", "args": { "q": "x" } } }
+            ] }, "finishReason": "STOP" }] }),
+        ];
+        let chunks = collect_sse(super::super::streaming::create_openai_sse_stream(
+            sse_chunks(&gemini_chunks),
+            "gemini-2.5-flash".to_string(),
+            "session-role-first".to_string(),
+            1,
+        ))
+        .await;
+
+        // 首个分片仅包含 role，后续分片不再携带 role
+        assert_eq!(
+            chunks[0]["choices"][0]["delta"],
+            json!({ "role": "assistant", "content": "" })
+        );
+        assert!(chunks[0]["choices"][0]["finish_reason"].is_null());
+        assert!(chunks[1..]
+            .iter()
+            .all(|c| c["choices"][0]["delta"].get("role").is_none()));
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hel");
+
+        // Responses API: response.created / response.in_progress 先于任何输出
+        let events = collect_sse(super::super::streaming::create_codex_sse_stream(
+            sse_chunks([&gemini_chunks[0]]),
+            "gemini-2.5-flash".to_string(),
+            "session-role-first".to_string(),
+            1,
+            Default::default(),
+            false,
+        ))
+        .await;
+        assert_eq!(events[0]["type"], "response.created");
+        assert_eq!(events[0]["response"]["status"], "in_progress");
+        assert_eq!(events[1]["type"], "response.in_progress");
+        assert_eq!(events[2]["type"], "response.output_text.delta");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_function_call_emits_argument_deltas() {
+        let tool_call_deltas = |chunks: &[Value]| -> Vec<Value> {
+            chunks
+                .iter()
+                .filter_map(|c| c["choices"][0]["delta"]["tool_calls"].get(0).cloned())
+                .collect()
+        };
+        let joined_arguments = |deltas: &[Value]| -> String {
+            deltas[1..]
+                .iter()
+                .map(|d| d["function"]["arguments"].as_str().unwrap())
+                .collect()
+        };
+
+        // 完整参数在一个上游分片中到达: 起始分片后原样转发为一个参数增量，不做人为切分
+        let args = json!({ "path": "src/main.rs", "content": "fn main() {}\n" });
+        let complete = json!({ "candidates": [{ "content": { "parts": [
+            { "functionCall": { "name": "write_file", "args": args } }
+        ] }, "finishReason": "STOP" }] });
+        let chunks = collect_sse(super::super::streaming::create_openai_sse_stream(
+            sse_chunks([&complete]),
+            "gemini-2.5-flash".to_string(),
+            "session-tool-deltas".to_string(),
+            1,
+        ))
+        .await;
+        let deltas = tool_call_deltas(&chunks);
+        assert_eq!(deltas.len(), 2);
+        assert!(deltas[0]["id"].as_str().unwrap().starts_with("call_"));
+        assert_eq!(deltas[0]["function"]["name"], "write_file");
+        assert_eq!(deltas[0]["function"]["arguments"], "");
+        assert_eq!(deltas[1]["function"]["arguments"], args.to_string());
+
+        // 上游跨分片流式输出参数 (partialArgs): 每个上游分片对应一个参数增量
+        let streamed = [
+            json!({ "candidates": [{ "content": { "parts": [
+                { "functionCall": { "name": "write_file", "willContinue": true } }
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "functionCall": { "partialArgs": [
+                    { "jsonPath": "$.path", "stringValue": "src/main.rs" },
+                    { "jsonPath": "$.content", "stringValue": "fn main() {\n", "willContinue": true }
+                ], "willContinue": true } }
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "functionCall": { "partialArgs": [
+                    { "jsonPath": "$.content", "stringValue": "    println!(\"hi\");\n}\n" }
+                ], "willContinue": true } }
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "functionCall": { "partialArgs": [
+                    { "jsonPath": "$.options.overwrite", "boolValue": true },
+                    { "jsonPath": "$.options.mode", "numberValue": 420 },
+                    { "jsonPath": "$.tags[0]", "stringValue": "a" },
+                    { "jsonPath": "$.tags[1]", "stringValue": "b" }
+                ] } }
+            ] }, "finishReason": "STOP" }] }),
+        ];
+        let expected = json!({
+            "path": "src/main.rs",
+            "content": "fn main() {\n    println!(\"hi\");\n}\n",
+            "options": { "overwrite": true, "mode": 420 },
+            "tags": ["a", "b"]
+        });
+        let chunks = collect_sse(super::super::streaming::create_openai_sse_stream(
+            sse_chunks(&streamed),
+            "gemini-2.5-flash".to_string(),
+            "session-tool-partial-args".to_string(),
+            1,
+        ))
+        .await;
+        let deltas = tool_call_deltas(&chunks);
+        // 起始分片 + 3 个携带参数的上游分片
+        assert_eq!(deltas.len(), 4);
+        assert_eq!(deltas[0]["function"]["name"], "write_file");
+        assert!(deltas[1..]
+            .iter()
+            .all(|d| d.get("id").is_none() && d["index"] == 0));
+        assert_eq!(
+            serde_json::from_str::<Value>(&joined_arguments(&deltas)).unwrap(),
+            expected
+        );
+        // 以 tool_calls 结束
+        assert_eq!(
+            chunks.last().unwrap()["choices"][0]["finish_reason"],
+            "tool_calls"
+        );
+
+        // 收集器按 index 聚合回完整调用
+        let collected = super::super::collector::collect_stream_to_json(
+            super::super::streaming::create_openai_sse_stream(
+                sse_chunks(&streamed),
+                "gemini-2.5-flash".to_string(),
+                "session-tool-partial-args".to_string(),
+                1,
+            ),
+            0,
+        )
+        .await
+        .unwrap();
+        let calls = collected.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "write_file");
+        assert_eq!(
+            serde_json::from_str::<Value>(&calls[0].function.arguments).unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streamed_tool_calls_indexed_per_choice() {
+
+        // n = 3: 两个候选各自调用工具，第三个候选仅输出文本
+        let gemini_chunk = json!({ "candidates": [
+            { "content": { "parts": [
+                { "functionCall": { "name": "read_file", "args": { "path": "a.rs" } } }
+            ] }, "finishReason": "STOP" },
+            { "content": { "parts": [
+                { "functionCall": { "name": "read_file", "args": { "path": "b.rs" } } }
+            ] }, "finishReason": "STOP" },
+            { "content": { "parts": [{ "text": "No tools needed." }] }, "finishReason": "STOP" }
+        ] });
+        let chunks = collect_sse(super::super::streaming::create_openai_sse_stream(
+            sse_chunks([&gemini_chunk]),
+            "gemini-2.5-flash".to_string(),
+            "session-tool-choices".to_string(),
+            1,
+        ))
+        .await;
+
+        for choice in 0..2 {
+            let starts: Vec<&Value> = chunks
+                .iter()
+                .filter(|c| c["choices"][0]["index"] == choice)
+                .filter_map(|c| c["choices"][0]["delta"]["tool_calls"].get(0))
+                .filter(|d| d.get("id").is_some())
+                .collect();
+            assert_eq!(starts.len(), 1);
+            assert_eq!(starts[0]["index"], 0);
+        }
+        let finish = |choice: u64| {
+            chunks
+                .iter()
+                .find(|c| {
+                    c["choices"][0]["index"] == choice
+                        && !c["choices"][0]["finish_reason"].is_null()
+                })
+                .map(|c| c["choices"][0]["finish_reason"].clone())
+                .unwrap()
+        };
+        assert_eq!(finish(0), "tool_calls");
+        assert_eq!(finish(1), "tool_calls");
+        assert_eq!(finish(2), "stop");
+    }
+
+    #[tokio::test]
+    async fn test_thought_parts_stream_as_reasoning_content() {
+
+        let gemini_chunks = [
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": "Compare the two numbers.", "thought": true }
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": " 9.11 < 9.9", "thought": true },
+                { "text": "9.9 is larger." }
+            ] }, "finishReason": "STOP" }] }),
+        ];
+        let stream = || {
+            super::super::streaming::create_openai_sse_stream(
+                sse_chunks(&gemini_chunks),
+                "gemini-2.5-pro".to_string(),
+                "session-reasoning".to_string(),
+                1,
+            )
+        };
+
+        // 思考内容仅出现在 delta.reasoning_content，不混入 delta.content
+        let deltas: Vec<Value> = collect_sse(stream())
+            .await
+            .into_iter()
+            .map(|chunk| chunk["choices"][0]["delta"].clone())
+            .collect();
+        let reasoning: String = deltas
+            .iter()
+            .filter_map(|d| d["reasoning_content"].as_str())
+            .collect();
+        let content: String = deltas.iter().filter_map(|d| d["content"].as_str()).collect();
+        assert_eq!(reasoning, "Compare the two numbers. 9.11 < 9.9");
+        assert_eq!(content, "9.9 is larger.");
+
+        // 内部收集与非流式转换均填充 message.reasoning_content
+        let collected = super::super::collector::collect_stream_to_json(stream(), 0)
+            .await
+            .unwrap();
+        let message = &collected.choices[0].message;
+        assert_eq!(
+            message.reasoning_content.as_deref(),
+            Some("Compare the two numbers. 9.11 < 9.9")
+        );
+        assert!(matches!(
+            &message.content,
+            Some(OpenAIContent::String(text)) if text == "9.9 is larger."
+        ));
+
+        let non_stream = transform_openai_response(&gemini_chunks[1], None, 1);
+        assert_eq!(
+            non_stream.choices[0].message.reasoning_content.as_deref(),
+            Some(" 9.11 < 9.9")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strip_reasoning_removes_thought_parts() {
+
+        let gemini_chunks = [
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": "Let me think about this.", "thought": true }
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": " Checking units.", "thought": true },
+                { "text": "The answer is 42." }
+            ] }, "finishReason": "STOP" }] }),
+        ];
+        let stream = || {
+            super::super::streaming::strip_reasoning_deltas(
+                super::super::streaming::create_openai_sse_stream(
+                    sse_chunks(&gemini_chunks),
+                    "gemini-2.5-pro".to_string(),
+                    "session-strip-reasoning".to_string(),
+                    1,
+                ),
+            )
+        };
+
+        let raw = collect_sse_text(stream()).await;
+        assert!(raw.iter().all(|c| !c.contains("reasoning_content")));
+        assert!(raw.last().unwrap().contains("[DONE]"));
+        let content: String = collect_sse(stream())
+            .await
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str().map(str::to_string))
+            .collect();
+        assert_eq!(content, "The answer is 42.");
+
+        // 内部收集与非流式转换
+        let collected = super::super::collector::collect_stream_to_json(stream(), 0)
+            .await
+            .unwrap();
+        assert_eq!(collected.choices[0].message.reasoning_content, None);
+        assert!(matches!(
+            &collected.choices[0].message.content,
+            Some(OpenAIContent::String(text)) if text == "The answer is 42."
+        ));
+
+        let mut non_stream = transform_openai_response(&gemini_chunks[1], None, 1);
+        assert_eq!(strip_reasoning_content(&mut non_stream), 1);
+        assert_eq!(non_stream.choices[0].message.reasoning_content, None);
+        assert!(matches!(
+            &non_stream.choices[0].message.content,
+            Some(OpenAIContent::String(text)) if text == "The answer is 42."
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_ids_stable_across_stream_and_collection() {
+
+        let gemini_resp = json!({
+            "responseId": "resp-tool-ids",
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "functionCall": { "name": "get_weather", "args": { "city": "Paris" } } },
+                        { "functionCall": { "name": "get_time", "args": { "tz": "CET" } } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        async fn collect_ids(gemini_resp: &Value) -> Vec<String> {
+            let openai_stream = super::super::streaming::create_openai_sse_stream(
+                sse_chunks([gemini_resp]),
+                "gemini-2.5-flash".to_string(),
+                "session-tool-ids".to_string(),
+                1,
+            );
+            let result = super::super::collector::collect_stream_to_json(openai_stream, 0)
+                .await
+                .unwrap();
+            result.choices[0]
+                .message
+                .tool_calls
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|tc| tc.id.clone())
+                .collect()
+        }
+
+        // 同一输入两次收集得到相同 ID，且两个调用不会被合并
+        let first = collect_ids(&gemini_resp).await;
+        let second = collect_ids(&gemini_resp).await;
+        assert_eq!(first.len(), 2);
+        assert_ne!(first[0], first[1]);
+        assert_eq!(first, second);
+
+        // 与非流式转换路径一致
+        let non_stream = transform_openai_response(&gemini_resp, Some("session-tool-ids"), 1);
+        let non_stream_ids: Vec<String> = non_stream.choices[0]
+            .message
+            .tool_calls
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|tc| tc.id.clone())
+            .collect();
+        assert_eq!(first, non_stream_ids);
+    }
+
+    #[test]
+    fn test_simultaneous_function_calls_become_distinct_tool_calls() {
+        let gemini_resp = json!({
+            "responseId": "resp-parallel",
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "text": "Rolling twice." },
+                        { "functionCall": { "name": "roll_die", "args": { "sides": 6 } } },
+                        { "functionCall": { "name": "roll_die", "args": { "sides": 6 } } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        // parallel_tool_calls 未设置或为 true: 两个调用都保留，ID 互不相同
+        let result = transform_openai_response(&gemini_resp, Some("session-parallel"), 1);
+        let choice = &result.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_ne!(calls[0].id, calls[1].id);
+        assert!(calls.iter().all(|c| c.function.name == "roll_die"));
+        assert!(calls.iter().all(|c| c.function.arguments == r#"{"sides":6}"#));
+
+        // 相同输入得到相同 ID
+        let again = transform_openai_response(&gemini_resp, Some("session-parallel"), 1);
+        let again_ids: Vec<&str> = again.choices[0]
+            .message
+            .tool_calls
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(again_ids, vec![calls[0].id.as_str(), calls[1].id.as_str()]);
+
+        // 下一轮 (不同 responseId) 中相同的调用得到不同 ID，历史中的工具结果不会混淆
+        let mut next_turn = gemini_resp.clone();
+        next_turn["responseId"] = json!("resp-parallel-next");
+        let next = transform_openai_response(&next_turn, Some("session-parallel"), 3);
+        let next_calls = next.choices[0].message.tool_calls.as_ref().unwrap();
+        assert!(next_calls.iter().all(|c| c.id != calls[0].id && c.id != calls[1].id));
+    }
+
+    #[tokio::test]
+    async fn test_identical_streamed_function_calls_are_not_deduplicated() {
+
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "functionCall": { "name": "roll_die", "args": { "sides": 6 } } },
+                        { "functionCall": { "name": "roll_die", "args": { "sides": 6 } } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        // Chat 流式 + 收集器: 两个相同的调用各自占用一个 index
+        let openai_stream = super::super::streaming::create_openai_sse_stream(
+            sse_chunks([&gemini_resp]),
+            "gemini-2.5-flash".to_string(),
+            "session-identical-calls".to_string(),
+            1,
+        );
+        let collected = super::super::collector::collect_stream_to_json(openai_stream, 0)
+            .await
+            .unwrap();
+        let calls = collected.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_ne!(calls[0].id, calls[1].id);
+        assert!(calls.iter().all(|c| c.function.arguments == r#"{"sides":6}"#));
+
+        // Responses 流式: 两个 function_call 输出项
+        let events = collect_sse_text(super::super::streaming::create_codex_sse_stream(
+            sse_chunks([&gemini_resp]),
+            "gemini-2.5-flash".to_string(),
+            "session-identical-calls".to_string(),
+            1,
+            crate::proxy::response_store::ResponseStoreOptions::default(),
+            false,
+        ))
+        .await;
+        let function_call_items = events
+            .iter()
+            .filter(|e| e.contains("response.output_item.done") && e.contains("\"function_call\""))
+            .count();
+        assert_eq!(function_call_items, 2);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_tool_calls_false_keeps_single_tool_call() {
+
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "functionCall": { "name": "read_file", "args": { "path": "a.rs" } } },
+                        { "functionCall": { "name": "read_file", "args": { "path": "b.rs" } } },
+                        { "functionCall": { "name": "read_file", "args": { "path": "c.rs" } } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        // 非流式
+        let mut result = transform_openai_response(&gemini_resp, Some("session-single-call"), 1);
+        assert_eq!(result.choices[0].message.tool_calls.as_ref().unwrap().len(), 3);
+        assert_eq!(limit_to_single_tool_call(&mut result), 2);
+        let calls = result.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.arguments, r#"{"path":"a.rs"}"#);
+
+        // 流式 (及内部收集)
+        let chunks = collect_sse_text(super::super::streaming::limit_stream_to_single_tool_call(
+            super::super::streaming::create_openai_sse_stream(
+                sse_chunks([&gemini_resp]),
+                "gemini-2.5-flash".to_string(),
+                "session-single-call".to_string(),
+                1,
+            ),
+        ))
+        .await;
+        // 每个调用以携带 id 的首个分片开始，其后为参数增量分片
+        let tool_chunks = chunks.iter().filter(|c| c.contains("\"tool_calls\":[")).count();
+        let started_calls = chunks
+            .iter()
+            .filter(|c| c.contains("\"tool_calls\":[") && c.contains("\"type\":\"function\""))
+            .count();
+        assert_eq!(started_calls, 1);
+        assert_eq!(tool_chunks, 2);
+        let collected = super::super::collector::collect_stream_to_json(
+            futures::stream::iter(chunks.into_iter().map(|c| Ok::<Bytes, String>(Bytes::from(c)))),
+            0,
+        )
+        .await
+        .unwrap();
+        let calls = collected.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.arguments, r#"{"path":"a.rs"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_whitespace_deltas_coalesced_when_enabled() {
+
+        let texts = ["Hello", " ", "\n", "world", "  "];
+        let mut events: Vec<Value> = texts
+            .iter()
+            .map(|t| json!({ "candidates": [{ "content": { "parts": [{ "text": t }] } }] }))
+            .collect();
+        events.push(json!({ "candidates": [{ "content": { "parts": [] }, "finishReason": "STOP" }] }));
+
+        let run = |coalesce: bool| {
+            let gemini_stream = sse_chunks(&events);
+            async move {
+                let mut stream = super::super::streaming::create_openai_sse_stream(
+                    gemini_stream,
+                    "gemini-2.5-flash".to_string(),
+                    "session-whitespace".to_string(),
+                    1,
+                );
+                if coalesce {
+                    stream = super::super::streaming::coalesce_whitespace_deltas(stream);
+                }
+                collect_sse(stream)
+                    .await
+                    .iter()
+                    .map(|c| {
+                        (
+                            c["choices"][0]["delta"]["content"].as_str().unwrap_or("").to_string(),
+                            c["choices"][0]["finish_reason"].as_str().map(str::to_string),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // 关闭时空白分片逐个输出
+        let plain = run(false).await;
+        assert!(plain.iter().any(|(c, _)| c == " "));
+
+        let coalesced = run(true).await;
+        assert_eq!(
+            coalesced,
+            vec![
+                // 起始 role 分片不参与合并
+                ("".to_string(), None),
+                ("Hello".to_string(), None),
+                (" \nworld".to_string(), None),
+                ("  ".to_string(), Some("stop".to_string())),
+            ]
+        );
+        // 内容不丢失
+        let joined = |v: &Vec<(String, Option<String>)>| {
+            v.iter().map(|(c, _)| c.as_str()).collect::<String>()
+        };
+        assert_eq!(joined(&plain), joined(&coalesced));
+    }
+
     #[test]
     fn test_response_without_usage_metadata() {
         let gemini_resp = json!({
@@ -285,4 +1344,412 @@ mod tests {
         let result = transform_openai_response(&gemini_resp, Some("session-123"), 1);
         assert!(result.usage.is_none());
     }
+
+    #[tokio::test]
+    async fn test_partial_then_safety_block_policies() {
+        use crate::proxy::config::SafetyPartialPolicy;
+
+        let partial = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Step one: mix the" }] } }]
+        });
+        let blocked = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": " chemicals" }] }, "finishReason": "SAFETY" }]
+        });
+
+        // 非流式: 部分内容保留，finish_reason 为 content_filter
+        let result = transform_openai_response(
+            &json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [{ "text": "Step one: mix the" }] },
+                    "finishReason": "SAFETY"
+                }]
+            }),
+            None,
+            1,
+        );
+        assert_eq!(result.choices[0].finish_reason.as_deref(), Some("content_filter"));
+        assert!(has_content_filter_finish(&result));
+        assert_eq!(content_filter_error()["error"]["code"], "content_filter");
+
+        async fn run(
+            partial: &Value,
+            blocked: &Value,
+            policy: SafetyPartialPolicy,
+            max_buffer_bytes: usize,
+        ) -> String {
+            collect_sse_text(super::super::streaming::apply_stream_safety_policy(
+                super::super::streaming::create_openai_sse_stream(
+                    sse_chunks([partial, blocked]),
+                    "gemini-2.5-flash".to_string(),
+                    "session-safety".to_string(),
+                    1,
+                ),
+                policy,
+                max_buffer_bytes,
+            ))
+            .await
+            .concat()
+        }
+
+        // content_filter 模式: 透传部分内容
+        let out = run(&partial, &blocked, SafetyPartialPolicy::ContentFilter, 0).await;
+        assert!(out.contains("Step one: mix the"));
+        assert!(out.contains("\"finish_reason\":\"content_filter\""));
+        assert!(!out.contains("\"error\""));
+
+        // error 模式: 部分内容被丢弃，仅输出错误事件
+        let out = run(&partial, &blocked, SafetyPartialPolicy::Error, 0).await;
+        assert!(!out.contains("Step one"));
+        assert!(!out.contains("chemicals"));
+        assert!(out.contains("\"code\":\"content_filter\""));
+        assert!(out.trim_end().ends_with("data: [DONE]"));
+
+        // error 模式下正常结束的流原样输出
+        let ok = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": " water" }] }, "finishReason": "STOP" }]
+        });
+        let out = run(&partial, &ok, SafetyPartialPolicy::Error, 0).await;
+        assert!(out.contains("Step one: mix the"));
+        assert!(out.contains(" water"));
+        assert!(!out.contains("\"error\""));
+
+        // error 模式下缓冲超过上限: 不输出部分内容，以错误事件结束
+        let out = run(&partial, &ok, SafetyPartialPolicy::Error, 64).await;
+        assert!(!out.contains("Step one"));
+        assert!(out.contains("\"code\":\"response_too_large\""));
+        assert!(out.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[tokio::test]
+    async fn test_responses_output_includes_generated_image() {
+
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "text": "Here is your cat." },
+                        { "inlineData": { "mimeType": "image/png", "data": "iVBORw0KGgo=" } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+        let events = collect_sse(super::super::streaming::create_codex_sse_stream(
+            sse_chunks([&gemini_resp]),
+            "gemini-3-pro-image".to_string(),
+            "session-codex-image".to_string(),
+            1,
+            Default::default(),
+            false,
+        ))
+        .await;
+
+        let item_done = events
+            .iter()
+            .find(|e| e["type"] == "response.output_item.done")
+            .expect("image output item event");
+        assert_eq!(item_done["output_index"], 1);
+        assert_eq!(item_done["item"]["type"], "image_generation_call");
+
+        let completed = events
+            .iter()
+            .find(|e| e["type"] == "response.completed")
+            .unwrap();
+        let output = completed["response"]["output"].as_array().unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0]["type"], "message");
+        assert_eq!(output[0]["content"][0]["text"], "Here is your cat.");
+        assert_eq!(output[1]["type"], "image_generation_call");
+        assert_eq!(output[1]["status"], "completed");
+        assert_eq!(output[1]["output_format"], "png");
+        assert_eq!(output[1]["result"], "iVBORw0KGgo=");
+        assert_eq!(output[1]["id"], item_done["item"]["id"]);
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_round_trip_preserves_association() {
+
+        let gemini_resp = json!({
+            "responseId": "resp-round-trip",
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "functionCall": { "name": "lookup_order", "args": { "order": "A-1042" } } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        // Codex (Responses) 流输出 function_call 项，call_id 与 Chat 路径一致
+        let events = collect_sse(super::super::streaming::create_codex_sse_stream(
+            sse_chunks([&gemini_resp]),
+            "gemini-2.5-flash".to_string(),
+            "session-round-trip".to_string(),
+            1,
+            Default::default(),
+            false,
+        ))
+        .await;
+        // 事件顺序: output_item.added -> arguments.delta -> arguments.done -> output_item.done
+        let item_events: Vec<&Value> = events
+            .iter()
+            .filter(|e| e["output_index"] == 1)
+            .collect();
+        let types: Vec<&str> = item_events.iter().filter_map(|e| e["type"].as_str()).collect();
+        assert_eq!(
+            types,
+            [
+                "response.output_item.added",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.done",
+                "response.output_item.done"
+            ]
+        );
+        let item = &item_events[3]["item"];
+        let added = &item_events[0]["item"];
+        assert_eq!(added["id"], item["id"]);
+        assert_eq!(added["status"], "in_progress");
+        assert_eq!(added["arguments"], "");
+        assert_eq!(item_events[1]["item_id"], item["id"]);
+        assert_eq!(item_events[1]["delta"], r#"{"order":"A-1042"}"#);
+        assert_eq!(item_events[2]["arguments"], r#"{"order":"A-1042"}"#);
+        assert_eq!(item["status"], "completed");
+        assert_eq!(item["type"], "function_call");
+        assert_eq!(item["name"], "lookup_order");
+        assert_eq!(item["arguments"], r#"{"order":"A-1042"}"#);
+        let call_id = item["call_id"].as_str().unwrap().to_string();
+
+        let chat = transform_openai_response(&gemini_resp, Some("session-round-trip"), 1);
+        assert_eq!(chat.choices[0].message.tool_calls.as_ref().unwrap()[0].id, call_id);
+
+        // 客户端只回传工具结果 (未携带原调用与函数名)，仍能解析出函数名并保持 ID 关联
+        let follow_up: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "user", "content": "Where is my order?" },
+                { "role": "tool", "tool_call_id": call_id, "content": "shipped" }
+            ]
+        }))
+        .unwrap();
+        let (body, _, _) = super::super::request::transform_openai_request(
+            &follow_up,
+            "test-project",
+            "gemini-2.5-flash",
+        );
+        let response_part = body["request"]["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|c| c["parts"].as_array().unwrap().iter())
+            .find(|p| p.get("functionResponse").is_some())
+            .expect("functionResponse part")
+            .clone();
+        assert_eq!(response_part["functionResponse"]["name"], "lookup_order");
+        assert_eq!(response_part["functionResponse"]["id"], call_id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_responses_reasoning_summary_output() {
+
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [
+                    { "text": "Rayleigh scattering...", "thought": true },
+                    { "text": "Because of Rayleigh scattering." }
+                ]},
+                "finishReason": "STOP"
+            }]
+        });
+        async fn run(gemini_resp: &Value, summary: bool) -> Vec<Value> {
+            collect_sse(super::super::streaming::create_codex_sse_stream(
+                sse_chunks([gemini_resp]),
+                "gemini-2.5-flash".to_string(),
+                "session-codex-reasoning".to_string(),
+                1,
+                Default::default(),
+                summary,
+            ))
+            .await
+        }
+        let completed_output = |events: &[Value]| {
+            events
+                .iter()
+                .find(|e| e["type"] == "response.completed")
+                .unwrap()["response"]["output"]
+                .clone()
+        };
+
+        let events = run(&gemini_resp, true).await;
+        assert!(events
+            .iter()
+            .any(|e| e["type"] == "response.reasoning_summary_text.delta"));
+        let output = completed_output(&events);
+        assert_eq!(output[0]["content"][0]["text"], "Because of Rayleigh scattering.");
+        assert_eq!(output[1]["type"], "reasoning");
+        assert_eq!(output[1]["summary"][0]["text"], "Rayleigh scattering...");
+
+        // 未请求摘要: 思考内容既不进入正文也不返回
+        let events = run(&gemini_resp, false).await;
+        let output = completed_output(&events);
+        assert_eq!(output.as_array().unwrap().len(), 1);
+        assert_eq!(output[0]["content"][0]["text"], "Because of Rayleigh scattering.");
+    }
+
+    #[tokio::test]
+    async fn test_content_filter_reports_blocked_categories() {
+
+        let safety = json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [] },
+                "finishReason": "SAFETY",
+                "safetyRatings": [
+                    { "category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE" },
+                    { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true }
+                ]
+            }]
+        });
+        let prompt_blocked = json!({
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "safetyRatings": [
+                    { "category": "HARM_CATEGORY_HATE_SPEECH", "probability": "HIGH" },
+                    { "category": "HARM_CATEGORY_HARASSMENT", "probability": "LOW" }
+                ]
+            },
+            "usageMetadata": { "promptTokenCount": 12, "totalTokenCount": 12 }
+        });
+
+        // 非流式: 候选被拦截
+        let result = transform_openai_response(&safety, None, 1);
+        assert_eq!(
+            result.choices[0].content_filter_categories,
+            Some(vec!["HARM_CATEGORY_DANGEROUS_CONTENT".to_string()])
+        );
+        let body = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            body["choices"][0]["content_filter_categories"],
+            json!(["HARM_CATEGORY_DANGEROUS_CONTENT"])
+        );
+
+        // 非流式: 输入被拦截 (无候选)
+        let result = transform_openai_response(&prompt_blocked, None, 1);
+        assert_eq!(result.choices.len(), 1);
+        assert_eq!(
+            result.choices[0].finish_reason.as_deref(),
+            Some("content_filter")
+        );
+        assert_eq!(
+            result.choices[0].content_filter_categories,
+            Some(vec!["HARM_CATEGORY_HATE_SPEECH".to_string()])
+        );
+        match &result.choices[0].message.content {
+            Some(OpenAIContent::String(s)) => assert!(s.contains("Prompt blocked")),
+            other => panic!("unexpected content: {:?}", other),
+        }
+
+        // 正常完成的响应不带该字段
+        let plain = transform_openai_response(&grounded_gemini_response(), None, 1);
+        let body = serde_json::to_value(&plain).unwrap();
+        assert!(body["choices"][0]
+            .get("content_filter_categories")
+            .is_none());
+
+        // 流式及内部收集
+        for (gemini, expected) in [
+            (&safety, "HARM_CATEGORY_DANGEROUS_CONTENT"),
+            (&prompt_blocked, "HARM_CATEGORY_HATE_SPEECH"),
+        ] {
+            let collected = super::super::collector::collect_stream_to_json(
+                super::super::streaming::create_openai_sse_stream(
+                    sse_chunks([gemini]),
+                    "gemini-2.5-flash".to_string(),
+                    "session-content-filter".to_string(),
+                    1,
+                ),
+                0,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                collected.choices[0].finish_reason.as_deref(),
+                Some("content_filter")
+            );
+            assert_eq!(
+                collected.choices[0].content_filter_categories,
+                Some(vec![expected.to_string()])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_safety_and_recitation_finish_reasons() {
+
+        let safety = json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [] },
+                "finishReason": "SAFETY",
+                "safetyRatings": [{ "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true }]
+            }]
+        });
+        let recitation = json!({
+            "candidates": [{ "finishReason": "RECITATION", "index": 0 }]
+        });
+
+        // 非流式: 空候选补充说明文本
+        let result = transform_openai_response(&safety, None, 1);
+        assert_eq!(result.choices[0].finish_reason.as_deref(), Some("content_filter"));
+        match &result.choices[0].message.content {
+            Some(OpenAIContent::String(s)) => assert!(s.contains("safety filter")),
+            other => panic!("unexpected content: {:?}", other),
+        }
+
+        let result = transform_openai_response(&recitation, None, 1);
+        assert_eq!(result.choices[0].finish_reason.as_deref(), Some("recitation"));
+        assert!(!has_content_filter_finish(&result));
+        match &result.choices[0].message.content {
+            Some(OpenAIContent::String(s)) => assert!(s.contains("RECITATION")),
+            other => panic!("unexpected content: {:?}", other),
+        }
+
+        // 已有部分输出时不追加说明
+        let partial = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Once upon" }] }, "finishReason": "RECITATION" }]
+        });
+        let result = transform_openai_response(&partial, None, 1);
+        match &result.choices[0].message.content {
+            Some(OpenAIContent::String(s)) => assert_eq!(s, "Once upon"),
+            other => panic!("unexpected content: {:?}", other),
+        }
+
+        // 流式: 同样映射并补充说明；此前已输出内容时不补充
+        async fn run(chunks: Vec<Value>) -> String {
+            collect_sse_text(super::super::streaming::create_openai_sse_stream(
+                sse_chunks(&chunks),
+                "gemini-2.5-flash".to_string(),
+                "session-recitation".to_string(),
+                1,
+            ))
+            .await
+            .concat()
+        }
+
+        let out = run(vec![safety.clone()]).await;
+        assert!(out.contains("\"finish_reason\":\"content_filter\""));
+        assert!(out.contains("safety filter"));
+
+        let out = run(vec![recitation.clone()]).await;
+        assert!(out.contains("\"finish_reason\":\"recitation\""));
+        assert!(out.contains("RECITATION"));
+
+        let first = json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Once upon" }] } }]
+        });
+        let out = run(vec![first, recitation]).await;
+        assert!(out.contains("Once upon"));
+        assert!(out.contains("\"finish_reason\":\"recitation\""));
+        assert!(!out.contains("closely matched"));
+    }
 }
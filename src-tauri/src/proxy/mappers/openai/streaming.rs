@@ -9,7 +9,13 @@ use std::sync::{Mutex, OnceLock};
 use tracing::debug;
 use uuid::Uuid;
 
-
+use super::response::{
+    blocked_finish_notice, blocked_safety_categories, content_filter_error,
+    legacy_completion_logprobs, map_finish_reason, map_logprobs, prompt_block,
+};
+use super::tool_call_ids::ToolCallIds;
+use crate::proxy::config::SafetyPartialPolicy;
+use crate::proxy::response_store::ResponseStoreOptions;
 
 /// 保存 thoughtSignature 到会话缓存
 pub fn store_thought_signature(sig: &str, session_id: &str, message_count: usize) {
@@ -64,6 +70,435 @@ fn extract_usage_metadata(u: &Value) -> Option<super::models::OpenAIUsage> {
     })
 }
 
+/// parallel_tool_calls: false 时丢弃第一个之后的工具调用分片
+/// create_openai_sse_stream 中工具调用的各增量分片均携带所属调用的 index，按 index 过滤即可
+pub fn limit_stream_to_single_tool_call(
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>>,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
+    Box::pin(stream.filter(|item| {
+        let keep = match item {
+            Ok(bytes) => !is_secondary_tool_call_chunk(bytes),
+            Err(_) => true,
+        };
+        if !keep {
+            tracing::info!("[OpenAI-Stream] parallel_tool_calls=false: suppressed additional tool call");
+        }
+        futures::future::ready(keep)
+    }))
+}
+
+/// strip_reasoning: 移除 delta.reasoning_content，仅含思维链的分片整体丢弃
+pub fn strip_reasoning_deltas(
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>>,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
+    Box::pin(stream.filter_map(|item| {
+        let item = match item {
+            Ok(bytes) => without_reasoning(bytes).map(Ok),
+            Err(e) => Some(Err(e)),
+        };
+        futures::future::ready(item)
+    }))
+}
+
+fn without_reasoning(bytes: Bytes) -> Option<Bytes> {
+    let text = String::from_utf8_lossy(&bytes);
+    let Some(mut chunk) = text
+        .trim()
+        .strip_prefix("data: ")
+        .and_then(|payload| serde_json::from_str::<Value>(payload).ok())
+    else {
+        return Some(bytes);
+    };
+    let Some(choices) = chunk.get_mut("choices").and_then(|c| c.as_array_mut()) else {
+        return Some(bytes);
+    };
+    let mut stripped = false;
+    let mut has_output = false;
+    for choice in choices.iter_mut() {
+        let finished = choice.get("finish_reason").is_some_and(|f| !f.is_null());
+        let Some(delta) = choice.get_mut("delta").and_then(|d| d.as_object_mut()) else {
+            has_output = true;
+            continue;
+        };
+        stripped |= delta.remove("reasoning_content").is_some();
+        has_output |= finished || delta.iter().any(|(_, v)| !v.is_null());
+    }
+    if !stripped {
+        return Some(bytes);
+    }
+    let has_usage = chunk.get("usage").is_some_and(|u| !u.is_null());
+    (has_output || has_usage).then(|| Bytes::from(format!("data: {}\n\n", chunk)))
+}
+
+/// streaming.suppress_whitespace_deltas: 不单独输出空白 content 分片，合并到同一 choice 的下一个分片
+/// (有正文、结束或流末尾时补发)，避免逐分片渲染的客户端闪烁。工具调用 / 思维链分片原样透传
+pub fn coalesce_whitespace_deltas(
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>>,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
+    Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        // choice index -> 暂存的空白内容
+        let mut pending: std::collections::BTreeMap<u64, String> = std::collections::BTreeMap::new();
+        let mut last_chunk: Option<Value> = None;
+        while let Some(item) = stream.next().await {
+            let bytes = match item {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    yield Err(e);
+                    continue;
+                }
+            };
+            let text = String::from_utf8_lossy(&bytes);
+            let Some(mut chunk) = text
+                .trim()
+                .strip_prefix("data: ")
+                .and_then(|payload| serde_json::from_str::<Value>(payload).ok())
+            else {
+                // 心跳、[DONE] 等: 流结束前补发剩余空白
+                if text.contains("[DONE]") {
+                    if let Some(template) = last_chunk.take() {
+                        for (index, content) in std::mem::take(&mut pending) {
+                            yield Ok(whitespace_chunk(&template, index, content));
+                        }
+                    }
+                }
+                yield Ok(bytes);
+                continue;
+            };
+
+            let has_usage = chunk.get("usage").is_some_and(|u| !u.is_null());
+            let Some(choice) = chunk
+                .get_mut("choices")
+                .and_then(|c| c.as_array_mut())
+                .filter(|c| c.len() == 1)
+                .and_then(|c| c.first_mut())
+            else {
+                yield Ok(bytes);
+                continue;
+            };
+            let index = choice.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            let finished = choice.get("finish_reason").is_some_and(|f| !f.is_null());
+            let Some(delta) = choice.get_mut("delta").and_then(|d| d.as_object_mut()) else {
+                yield Ok(bytes);
+                continue;
+            };
+            let content = delta.get("content").and_then(|c| c.as_str()).map(str::to_string);
+            // 起始 role 分片 (content 为空) 原样透传，不参与合并
+            let content_only = delta.keys().all(|k| k == "content");
+
+            match content {
+                Some(content)
+                    if content.trim().is_empty() && content_only && !finished && !has_usage =>
+                {
+                    pending.entry(index).or_default().push_str(&content);
+                    last_chunk = Some(chunk);
+                }
+                Some(content) if pending.contains_key(&index) => {
+                    let merged = pending.remove(&index).unwrap_or_default() + &content;
+                    delta.insert("content".to_string(), Value::String(merged));
+                    last_chunk = Some(chunk.clone());
+                    yield Ok(Bytes::from(format!("data: {}\n\n", chunk)));
+                }
+                None if finished && pending.contains_key(&index) => {
+                    let merged = pending.remove(&index).unwrap_or_default();
+                    delta.insert("content".to_string(), Value::String(merged));
+                    yield Ok(Bytes::from(format!("data: {}\n\n", chunk)));
+                }
+                _ => {
+                    last_chunk = Some(chunk);
+                    yield Ok(bytes);
+                }
+            }
+        }
+    })
+}
+
+fn whitespace_chunk(template: &Value, index: u64, content: String) -> Bytes {
+    let chunk = json!({
+        "id": template["id"],
+        "object": "chat.completion.chunk",
+        "created": template["created"],
+        "model": template["model"],
+        "choices": [{
+            "index": index,
+            "delta": { "content": content },
+            "finish_reason": Value::Null
+        }]
+    });
+    Bytes::from(format!("data: {}\n\n", chunk))
+}
+
+/// safety_partial = error 时缓冲数据分片直到流结束 (心跳照常透传)
+/// 遇到 finish_reason = content_filter 时丢弃已缓冲的部分内容，改为输出错误事件
+/// 缓冲超过 max_buffer_bytes 时同样丢弃已缓冲内容并以错误事件结束 (不输出部分内容)，0 表示不限制
+pub fn apply_stream_safety_policy(
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>>,
+    policy: SafetyPartialPolicy,
+    max_buffer_bytes: usize,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
+    if policy == SafetyPartialPolicy::ContentFilter {
+        return stream;
+    }
+    Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        let mut buffered: Vec<Bytes> = Vec::new();
+        let mut buffered_bytes = 0usize;
+        let mut blocked = false;
+        while let Some(item) = stream.next().await {
+            let bytes = match item {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    yield Err(e);
+                    continue;
+                }
+            };
+            if bytes.starts_with(b":") {
+                yield Ok(bytes);
+                continue;
+            }
+            if let Some(chunk) = content_filter_chunk(&bytes) {
+                tracing::warn!(
+                    "[OpenAI-Stream] Response blocked by safety filter, discarding {} buffered chunk(s)",
+                    buffered.len()
+                );
+                let mut error_chunk = json!({
+                    "id": chunk["id"],
+                    "object": "chat.completion.chunk",
+                    "created": chunk["created"],
+                    "model": chunk["model"],
+                    "choices": [],
+                });
+                error_chunk["error"] = content_filter_error()["error"].clone();
+                yield Ok(Bytes::from(format!("data: {}\n\n", error_chunk)));
+                yield Ok(Bytes::from("data: [DONE]\n\n"));
+                blocked = true;
+                break;
+            }
+            buffered_bytes += bytes.len();
+            if max_buffer_bytes > 0 && buffered_bytes > max_buffer_bytes {
+                tracing::warn!(
+                    "[OpenAI-Stream] Safety buffer exceeded {} bytes, discarding {} buffered chunk(s)",
+                    max_buffer_bytes,
+                    buffered.len()
+                );
+                let error_chunk = json!({
+                    "object": "chat.completion.chunk",
+                    "choices": [],
+                    "error": {
+                        "message": format!(
+                            "The response exceeded {} bytes while being held for the safety check",
+                            max_buffer_bytes
+                        ),
+                        "type": "server_error",
+                        "code": "response_too_large"
+                    }
+                });
+                yield Ok(Bytes::from(format!("data: {}\n\n", error_chunk)));
+                yield Ok(Bytes::from("data: [DONE]\n\n"));
+                blocked = true;
+                break;
+            }
+            buffered.push(bytes);
+        }
+        if !blocked {
+            for bytes in buffered {
+                yield Ok(bytes);
+            }
+        }
+    })
+}
+
+fn content_filter_chunk(bytes: &Bytes) -> Option<Value> {
+    let text = String::from_utf8_lossy(bytes);
+    let payload = text.trim().strip_prefix("data: ")?;
+    let chunk = serde_json::from_str::<Value>(payload).ok()?;
+    let filtered = chunk
+        .get("choices")?
+        .as_array()?
+        .iter()
+        .any(|c| c.get("finish_reason").and_then(|f| f.as_str()) == Some("content_filter"));
+    filtered.then_some(chunk)
+}
+
+fn is_secondary_tool_call_chunk(bytes: &Bytes) -> bool {
+    let text = String::from_utf8_lossy(bytes);
+    let Some(payload) = text.trim().strip_prefix("data: ") else {
+        return false;
+    };
+    let Ok(chunk) = serde_json::from_str::<Value>(payload) else {
+        return false;
+    };
+    chunk
+        .get("choices")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|choice| choice.get("delta")?.get("tool_calls")?.as_array())
+        .flatten()
+        .any(|tc| tc.get("index").and_then(|i| i.as_u64()).unwrap_or(0) > 0)
+}
+
+/// 工具调用起始分片: 携带 index / id / type / name，arguments 为空 (OpenAI 增量格式)
+fn tool_call_start_delta(call_index: usize, call_id: &str, name: &str) -> Value {
+    json!({
+        "index": call_index,
+        "id": call_id,
+        "type": "function",
+        "function": { "name": name, "arguments": "" }
+    })
+}
+
+/// 工具调用参数分片: 仅携带 index 与 arguments 片段
+fn tool_call_args_delta(call_index: usize, arguments: &str) -> Value {
+    json!({ "index": call_index, "function": { "arguments": arguments } })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum JsonPathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// 解析 partialArgs 的 jsonPath ("$.a.b[0]" / "$['a b']")
+fn parse_json_path(path: &str) -> Vec<JsonPathSegment> {
+    let mut segments = Vec::new();
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").unwrap_or(after.len());
+            segments.push(JsonPathSegment::Key(after[..end].to_string()));
+            rest = after.get(end + 2..).unwrap_or("");
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').unwrap_or(after.len());
+            segments.push(JsonPathSegment::Index(after[..end].trim().parse().unwrap_or(0)));
+            rest = after.get(end + 1..).unwrap_or("");
+        } else {
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            segments.push(JsonPathSegment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        }
+    }
+    segments
+}
+
+/// 将 Gemini 流式 functionCall 的 partialArgs (jsonPath + 值片段) 还原为 JSON 参数文本的增量
+/// 每个上游分片产出一段文本，全部片段拼接后为完整的参数 JSON
+#[derive(Default)]
+struct PartialArgsWriter {
+    started: bool,
+    root_has_members: bool,
+    /// 根对象之下已打开的容器: (所在路径段, 是否为数组, 是否已有成员)
+    open: Vec<(JsonPathSegment, bool, bool)>,
+    /// 尚未结束的字符串值所在的 jsonPath
+    open_string: Option<String>,
+}
+
+impl PartialArgsWriter {
+    fn write(&mut self, arg: &Value) -> String {
+        let mut out = String::new();
+        if !self.started {
+            self.started = true;
+            out.push('{');
+        }
+        let path = arg.get("jsonPath").and_then(|v| v.as_str()).unwrap_or("$");
+        let will_continue = arg.get("willContinue").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        // 同一字符串值的后续片段直接续写
+        if self.open_string.as_deref() == Some(path) {
+            if let Some(s) = arg.get("stringValue").and_then(|v| v.as_str()) {
+                out.push_str(&escape_json_fragment(s));
+            }
+            if !will_continue {
+                out.push('"');
+                self.open_string = None;
+            }
+            return out;
+        }
+        if self.open_string.take().is_some() {
+            out.push('"');
+        }
+
+        let segments = parse_json_path(path);
+        let Some((leaf, parents)) = segments.split_last() else {
+            return out;
+        };
+        // 关闭与新路径不再共享的容器，再依次打开缺少的容器
+        let common = self
+            .open
+            .iter()
+            .zip(parents)
+            .take_while(|((seg, _, _), parent)| seg == *parent)
+            .count();
+        while self.open.len() > common {
+            let (_, is_array, _) = self.open.pop().unwrap();
+            out.push(if is_array { ']' } else { '}' });
+        }
+        for (i, segment) in parents.iter().enumerate().skip(common) {
+            self.push_member(segment, &mut out);
+            let is_array = matches!(segments[i + 1], JsonPathSegment::Index(_));
+            out.push(if is_array { '[' } else { '{' });
+            self.open.push((segment.clone(), is_array, false));
+        }
+        self.push_member(leaf, &mut out);
+
+        if let Some(s) = arg.get("stringValue").and_then(|v| v.as_str()) {
+            out.push('"');
+            out.push_str(&escape_json_fragment(s));
+            if will_continue {
+                self.open_string = Some(path.to_string());
+            } else {
+                out.push('"');
+            }
+        } else if let Some(n) = arg.get("numberValue") {
+            out.push_str(&n.to_string());
+        } else if let Some(b) = arg.get("boolValue") {
+            out.push_str(&b.to_string());
+        } else {
+            out.push_str("null");
+        }
+        out
+    }
+
+    /// 成员前缀: 逗号分隔符，对象成员另加 "key":
+    fn push_member(&mut self, segment: &JsonPathSegment, out: &mut String) {
+        let has_members = match self.open.last_mut() {
+            Some((_, _, has_members)) => has_members,
+            None => &mut self.root_has_members,
+        };
+        if *has_members {
+            out.push(',');
+        }
+        *has_members = true;
+        if let JsonPathSegment::Key(key) = segment {
+            out.push_str(&serde_json::to_string(key).unwrap_or_default());
+            out.push(':');
+        }
+    }
+
+    /// 调用结束: 补齐未关闭的字符串与容器
+    fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if !self.started {
+            return "{}".to_string();
+        }
+        if self.open_string.take().is_some() {
+            out.push('"');
+        }
+        while let Some((_, is_array, _)) = self.open.pop() {
+            out.push(if is_array { ']' } else { '}' });
+        }
+        out.push('}');
+        out
+    }
+}
+
+/// 字符串片段按 JSON 转义 (不含两侧引号)
+fn escape_json_fragment(s: &str) -> String {
+    let quoted = serde_json::to_string(s).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
 pub fn create_openai_sse_stream(
     mut gemini_stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
     model: String,
@@ -75,7 +510,17 @@ pub fn create_openai_sse_stream(
     let created_ts = Utc::now().timestamp();
 
     let stream = async_stream::stream! {
-        let mut emitted_tool_calls = std::collections::HashSet::new();
+        // [FIX] 按 choice 记录已发送的工具调用数: 每个 choice 的 tool_calls index 从 0 开始，finish_reason 互不影响
+        let mut emitted_tool_calls: std::collections::HashMap<usize, usize> = std::collections::HashMap::new();
+        // [NEW] 跨分片流式输出参数 (partialArgs / willContinue) 的进行中调用: choice -> (调用 index, 参数还原器)
+        let mut streaming_calls: std::collections::HashMap<usize, (usize, PartialArgsWriter)> = std::collections::HashMap::new();
+        // 每个 choice 独立的工具调用 ID 分配器 (以 responseId 加盐，与非流式路径一致)
+        let mut tool_call_ids: std::collections::HashMap<usize, ToolCallIds> = std::collections::HashMap::new();
+        let mut emitted_content: std::collections::HashSet<usize> = std::collections::HashSet::new();
+        // 每个 choice 已输出的正文，用于将 grounding 引文的字节偏移换算为字符偏移
+        let mut streamed_text: std::collections::HashMap<usize, String> = std::collections::HashMap::new();
+        // [NEW] 已发送起始 role 分片的 choice (与 OpenAI 一致: 每个 choice 的首个分片仅包含 role)
+        let mut role_sent: std::collections::HashSet<usize> = std::collections::HashSet::new();
         let mut final_usage: Option<super::models::OpenAIUsage> = None;
         let mut error_occurred = false;
 
@@ -104,6 +549,20 @@ pub fn create_openai_sse_stream(
 
                                             if let Some(candidates) = actual_data.get("candidates").and_then(|c| c.as_array()) {
                                                 for (idx, candidate) in candidates.iter().enumerate() {
+                                                    if role_sent.insert(idx) {
+                                                        let role_chunk = json!({
+                                                            "id": &stream_id,
+                                                            "object": "chat.completion.chunk",
+                                                            "created": created_ts,
+                                                            "model": &model,
+                                                            "choices": [{
+                                                                "index": idx as u32,
+                                                                "delta": { "role": "assistant", "content": "" },
+                                                                "finish_reason": serde_json::Value::Null
+                                                            }]
+                                                        });
+                                                        yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&role_chunk).unwrap_or_default())));
+                                                    }
                                                     let parts = candidate.get("content").and_then(|c| c.get("parts")).and_then(|p| p.as_array());
                                                     let mut content_out = String::new();
                                                     let mut thought_out = String::new();
@@ -126,34 +585,68 @@ pub fn create_openai_sse_stream(
                                                                 }
                                                             }
                                                             if let Some(func_call) = part.get("functionCall") {
-                                                                let call_key = serde_json::to_string(func_call).unwrap_or_default();
-                                                                if !emitted_tool_calls.contains(&call_key) {
-                                                                    emitted_tool_calls.insert(call_key);
+                                                                // [NEW] 上游跨分片流式输出参数 (partialArgs + willContinue) 时，每个上游分片转发为一个参数增量
+                                                                let continues = func_call.get("willContinue").and_then(|v| v.as_bool()).unwrap_or(false);
+                                                                let is_partial = continues || func_call.get("partialArgs").is_some() || streaming_calls.contains_key(&idx);
+                                                                let mut tool_call_deltas = Vec::new();
+                                                                if let std::collections::hash_map::Entry::Vacant(pending) = streaming_calls.entry(idx) {
+                                                                    // 按出现顺序分配 index: 同名同参的并行调用也是独立调用，不按内容去重
+                                                                    let choice_calls = emitted_tool_calls.entry(idx).or_insert(0);
+                                                                    let call_index = *choice_calls;
+                                                                    *choice_calls += 1;
                                                                     let name = func_call.get("name").and_then(|v| v.as_str()).unwrap_or("unknown");
-                                                                    let mut args = func_call.get("args").unwrap_or(&json!({})).clone();
-                                                                    
-                                                                    // [FIX #1575] 标准化 shell 工具参数名称
-                                                                    // Gemini 可能使用 cmd/code/script 等替代参数名，统一为 command
-                                                                    if name == "shell" || name == "bash" || name == "local_shell" {
-                                                                        if let Some(obj) = args.as_object_mut() {
-                                                                            if !obj.contains_key("command") {
-                                                                                for alt_key in &["cmd", "code", "script", "shell_command"] {
-                                                                                    if let Some(val) = obj.remove(*alt_key) {
-                                                                                        obj.insert("command".to_string(), val);
-                                                                                        debug!("[OpenAI-Stream] Normalized shell arg '{}' -> 'command'", alt_key);
-                                                                                        break;
+                                                                    let call_id = tool_call_ids
+                                                                        .entry(idx)
+                                                                        .or_insert_with(|| ToolCallIds::new(actual_data.get("responseId").and_then(|v| v.as_str()).unwrap_or(&stream_id), idx))
+                                                                        .assign(func_call);
+                                                                    // 按 OpenAI 方式增量输出: 首个分片携带 id / name，随后为 arguments
+                                                                    tool_call_deltas.push(tool_call_start_delta(call_index, &call_id, name));
+
+                                                                    if is_partial {
+                                                                        pending.insert((call_index, PartialArgsWriter::default()));
+                                                                    } else {
+                                                                        let mut args = func_call.get("args").unwrap_or(&json!({})).clone();
+
+                                                                        // [FIX #1575] 标准化 shell 工具参数名称
+                                                                        // Gemini 可能使用 cmd/code/script 等替代参数名，统一为 command
+                                                                        if name == "shell" || name == "bash" || name == "local_shell" {
+                                                                            if let Some(obj) = args.as_object_mut() {
+                                                                                if !obj.contains_key("command") {
+                                                                                    for alt_key in &["cmd", "code", "script", "shell_command"] {
+                                                                                        if let Some(val) = obj.remove(*alt_key) {
+                                                                                            obj.insert("command".to_string(), val);
+                                                                                            debug!("[OpenAI-Stream] Normalized shell arg '{}' -> 'command'", alt_key);
+                                                                                            break;
+                                                                                        }
                                                                                     }
                                                                                 }
                                                                             }
                                                                         }
+
+                                                                        // 完整参数在同一上游分片中到达，原样作为一个增量转发
+                                                                        let args_str = serde_json::to_string(&args).unwrap_or_default();
+                                                                        tool_call_deltas.push(tool_call_args_delta(call_index, &args_str));
+                                                                    }
+                                                                }
+                                                                if let Some((call_index, writer)) = streaming_calls.get_mut(&idx) {
+                                                                    let call_index = *call_index;
+                                                                    let mut fragment: String = func_call
+                                                                        .get("partialArgs")
+                                                                        .and_then(|v| v.as_array())
+                                                                        .into_iter()
+                                                                        .flatten()
+                                                                        .map(|arg| writer.write(arg))
+                                                                        .collect();
+                                                                    if !continues {
+                                                                        fragment.push_str(&writer.finish());
+                                                                        streaming_calls.remove(&idx);
                                                                     }
-                                                                    
-                                                                    let args_str = serde_json::to_string(&args).unwrap_or_default();
-                                                                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-                                                                    use std::hash::{Hash, Hasher};
-                                                                    serde_json::to_string(func_call).unwrap_or_default().hash(&mut hasher);
-                                                                    let call_id = format!("call_{:x}", hasher.finish());
+                                                                    if !fragment.is_empty() {
+                                                                        tool_call_deltas.push(tool_call_args_delta(call_index, &fragment));
+                                                                    }
+                                                                }
 
+                                                                for tool_call_delta in tool_call_deltas {
                                                                     let tool_call_chunk = json!({
                                                                         "id": &stream_id,
                                                                         "object": "chat.completion.chunk",
@@ -161,15 +654,7 @@ pub fn create_openai_sse_stream(
                                                                         "model": &model,
                                                                         "choices": [{
                                                                             "index": idx as u32,
-                                                                            "delta": {
-                                                                                "role": "assistant",
-                                                                                "tool_calls": [{
-                                                                                    "index": 0,
-                                                                                    "id": call_id,
-                                                                                    "type": "function",
-                                                                                    "function": { "name": name, "arguments": args_str }
-                                                                                }]
-                                                                            },
+                                                                            "delta": { "tool_calls": [tool_call_delta] },
                                                                             "finish_reason": serde_json::Value::Null
                                                                         }]
                                                                     });
@@ -180,7 +665,11 @@ pub fn create_openai_sse_stream(
                                                         }
                                                     }
 
+                                                    let choice_text = streamed_text.entry(idx).or_default();
+                                                    choice_text.push_str(&content_out);
+                                                    let mut annotations = Vec::new();
                                                     if let Some(grounding) = candidate.get("groundingMetadata") {
+                                                        annotations = super::response::grounding_annotations(grounding, choice_text);
                                                         let mut grounding_text = String::new();
                                                         if let Some(queries) = grounding.get("webSearchQueries").and_then(|q| q.as_array()) {
                                                             let query_list: Vec<&str> = queries.iter().filter_map(|v| v.as_str()).collect();
@@ -206,17 +695,20 @@ pub fn create_openai_sse_stream(
                                                         if !grounding_text.is_empty() { content_out.push_str(&grounding_text); }
                                                     }
 
-                                                    let gemini_finish_reason = candidate.get("finishReason").and_then(|f| f.as_str()).map(|f| match f {
-                                                        "STOP" => "stop",
-                                                        "MAX_TOKENS" => "length",
-                                                        "SAFETY" => "content_filter",
-                                                        "RECITATION" => "content_filter",
-                                                        _ => f,
-                                                    });
+                                                    let has_tool_calls = emitted_tool_calls.get(&idx).is_some_and(|count| *count > 0);
+                                                    let gemini_finish_reason = candidate.get("finishReason").and_then(|f| f.as_str()).map(|f| map_finish_reason(f).unwrap_or(f));
+
+                                                    // 被 SAFETY / RECITATION 拦截且此前没有任何输出时，补充说明文本
+                                                    if content_out.is_empty() && !has_tool_calls && !emitted_content.contains(&idx) {
+                                                        if let Some(notice) = candidate.get("finishReason").and_then(|f| f.as_str()).and_then(blocked_finish_notice) {
+                                                            content_out.push_str(notice);
+                                                        }
+                                                    }
+                                                    if !content_out.is_empty() { emitted_content.insert(idx); }
 
                                                     // [FIX #1575] 如果发射了工具调用，强制设置为 tool_calls
                                                     // 解决 Gemini 返回 STOP 但有工具调用时，OpenAI 客户端认为对话已结束的问题
-                                                    let finish_reason = if !emitted_tool_calls.is_empty() && gemini_finish_reason.is_some() {
+                                                    let finish_reason = if has_tool_calls && gemini_finish_reason.is_some() {
                                                         Some("tool_calls")
                                                     } else {
                                                         gemini_finish_reason
@@ -230,7 +722,7 @@ pub fn create_openai_sse_stream(
                                                             "model": &model,
                                                             "choices": [{
                                                                 "index": idx as u32,
-                                                                "delta": { "role": "assistant", "content": serde_json::Value::Null, "reasoning_content": thought_out },
+                                                                "delta": { "content": serde_json::Value::Null, "reasoning_content": thought_out },
                                                                 "finish_reason": serde_json::Value::Null
                                                             }]
                                                         });
@@ -250,6 +742,18 @@ pub fn create_openai_sse_stream(
                                                                 "finish_reason": finish_reason
                                                             }]
                                                         });
+                                                        if !annotations.is_empty() {
+                                                            openai_chunk["choices"][0]["delta"]["annotations"] = json!(annotations);
+                                                        }
+                                                        if let Some(logprobs) = map_logprobs(candidate) {
+                                                            openai_chunk["choices"][0]["logprobs"] = logprobs;
+                                                        }
+                                                        if finish_reason == Some("content_filter") {
+                                                            let categories = blocked_safety_categories(candidate.get("safetyRatings"));
+                                                            if !categories.is_empty() {
+                                                                openai_chunk["choices"][0]["content_filter_categories"] = json!(categories);
+                                                            }
+                                                        }
                                                         if let Some(ref usage) = final_usage {
                                                             openai_chunk["usage"] = serde_json::to_value(usage).unwrap();
                                                         }
@@ -259,6 +763,37 @@ pub fn create_openai_sse_stream(
                                                     }
                                                 }
                                             }
+                                            // [NEW] 输入被拦截 (promptFeedback.blockReason): 没有候选，直接以 content_filter 结束
+                                            if let Some((notice, categories)) = prompt_block(&actual_data) {
+                                                if role_sent.insert(0) {
+                                                    let role_chunk = json!({
+                                                        "id": &stream_id,
+                                                        "object": "chat.completion.chunk",
+                                                        "created": created_ts,
+                                                        "model": &model,
+                                                        "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "" }, "finish_reason": serde_json::Value::Null }]
+                                                    });
+                                                    yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&role_chunk).unwrap_or_default())));
+                                                }
+                                                let mut blocked_chunk = json!({
+                                                    "id": &stream_id,
+                                                    "object": "chat.completion.chunk",
+                                                    "created": created_ts,
+                                                    "model": &model,
+                                                    "choices": [{
+                                                        "index": 0,
+                                                        "delta": { "content": notice },
+                                                        "finish_reason": "content_filter"
+                                                    }]
+                                                });
+                                                if !categories.is_empty() {
+                                                    blocked_chunk["choices"][0]["content_filter_categories"] = json!(categories);
+                                                }
+                                                if let Some(usage) = final_usage.take() {
+                                                    blocked_chunk["usage"] = serde_json::to_value(usage).unwrap();
+                                                }
+                                                yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&blocked_chunk).unwrap_or_default())));
+                                            }
                                         }
                                     }
                                 }
@@ -311,6 +846,8 @@ pub fn create_legacy_sse_stream(
     let stream = async_stream::stream! {
         let mut final_usage: Option<super::models::OpenAIUsage> = None;
         let mut error_occurred = false;
+        // [FIX] 已输出文本的字符数，作为 logprobs.text_offset 的起点
+        let mut text_offset = 0usize;
         let mut heartbeat_interval = tokio::time::interval(std::time::Duration::from_secs(15));
         heartbeat_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
@@ -333,8 +870,12 @@ pub fn create_legacy_sse_stream(
                                             if let Some(u) = actual_data.get("usageMetadata") { final_usage = extract_usage_metadata(u); }
 
                                             let mut content_out = String::new();
+                                            let mut logprobs = Value::Null;
                                             if let Some(candidates) = actual_data.get("candidates").and_then(|c| c.as_array()) {
                                                 if let Some(candidate) = candidates.get(0) {
+                                                    if let Some(chat_logprobs) = map_logprobs(candidate) {
+                                                        logprobs = legacy_completion_logprobs(&chat_logprobs, text_offset);
+                                                    }
                                                     if let Some(parts) = candidate.get("content").and_then(|c| c.get("parts")).and_then(|p| p.as_array()) {
                                                         for part in parts {
                                                             if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
@@ -348,13 +889,12 @@ pub fn create_legacy_sse_stream(
                                                 }
                                             }
 
-                                            let finish_reason = actual_data.get("candidates").and_then(|c| c.as_array()).and_then(|c| c.get(0)).and_then(|c| c.get("finishReason")).and_then(|f| f.as_str()).map(|f| match f {
-                                                "STOP" => "stop", "MAX_TOKENS" => "length", "SAFETY" => "content_filter", _ => f,
-                                            });
+                                            let finish_reason = actual_data.get("candidates").and_then(|c| c.as_array()).and_then(|c| c.get(0)).and_then(|c| c.get("finishReason")).and_then(|f| f.as_str()).map(|f| map_finish_reason(f).unwrap_or(f));
 
+                                            text_offset += content_out.chars().count();
                                             let mut legacy_chunk = json!({
                                                 "id": &stream_id, "object": "text_completion", "created": created_ts, "model": &model,
-                                                "choices": [{ "text": content_out, "index": 0, "logprobs": null, "finish_reason": finish_reason }]
+                                                "choices": [{ "text": content_out, "index": 0, "logprobs": logprobs, "finish_reason": finish_reason }]
                                             });
                                             if let Some(ref usage) = final_usage { legacy_chunk["usage"] = serde_json::to_value(usage).unwrap(); }
                                             if finish_reason.is_some() { final_usage = None; }
@@ -390,11 +930,57 @@ pub fn create_legacy_sse_stream(
     Box::pin(stream)
 }
 
+/// Gemini inlineData 图像 -> Responses API image_generation_call 输出项
+pub fn responses_image_output_item(inline_data: &Value) -> Option<Value> {
+    let data = inline_data.get("data").and_then(|v| v.as_str()).filter(|d| !d.is_empty())?;
+    let mime_type = inline_data
+        .get("mimeType")
+        .and_then(|v| v.as_str())
+        .unwrap_or("image/png");
+    let output_format = mime_type.strip_prefix("image/").unwrap_or("png");
+    Some(json!({
+        "type": "image_generation_call",
+        "id": format!("ig_{}", Uuid::new_v4().simple()),
+        "status": "completed",
+        "output_format": output_format,
+        "result": data,
+    }))
+}
+
+/// Gemini functionCall -> Responses API function_call 输出项
+pub fn responses_function_call_item(func_call: &Value, call_id: String) -> Value {
+    let name = func_call.get("name").and_then(|v| v.as_str()).unwrap_or("unknown");
+    let args = func_call.get("args").cloned().unwrap_or_else(|| json!({}));
+    json!({
+        "type": "function_call",
+        "id": format!("fc_{}", Uuid::new_v4().simple()),
+        "call_id": call_id,
+        "name": name,
+        "arguments": serde_json::to_string(&args).unwrap_or_default(),
+        "status": "completed",
+    })
+}
+
+/// 组装 Responses API 的 output 列表: 助手文本消息固定位于 0 号位置，生成的图像 / 工具调用按顺序追加
+/// (流式 response.output_item.done 事件的 output_index 与此一致)
+pub fn build_responses_output(output_text: &str, images: Vec<Value>) -> Vec<Value> {
+    let mut output = Vec::with_capacity(images.len() + 1);
+    output.push(json!({
+        "type": "message",
+        "role": "assistant",
+        "content": [{ "type": "output_text", "text": output_text }]
+    }));
+    output.extend(images);
+    output
+}
+
 pub fn create_codex_sse_stream(
     mut gemini_stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
-    _model: String,
+    model: String,
     session_id: String,
     message_count: usize,
+    store_options: ResponseStoreOptions,
+    reasoning_summary: bool,
 ) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
     let mut buffer = BytesMut::new();
     let charset = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
@@ -406,10 +992,18 @@ pub fn create_codex_sse_stream(
     let response_id = format!("resp-{}", random_str);
 
     let stream = async_stream::stream! {
-        let created_ev = json!({ "type": "response.created", "response": { "id": &response_id, "object": "response" } });
+        // 起始事件: response.created 与 response.in_progress (严格的 Responses API 客户端要求二者先于任何输出)
+        let opening_response = json!({ "id": &response_id, "object": "response", "created_at": Utc::now().timestamp(), "status": "in_progress", "model": &model, "output": [], "metadata": store_options.metadata.clone().unwrap_or_else(|| json!({})) });
+        let created_ev = json!({ "type": "response.created", "response": &opening_response });
         yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&created_ev).unwrap())));
+        let in_progress_ev = json!({ "type": "response.in_progress", "response": &opening_response });
+        yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&in_progress_ev).unwrap())));
 
-        let mut emitted_tool_calls = std::collections::HashSet::new();
+        let mut tool_call_ids: Option<ToolCallIds> = None;
+        let mut output_text = String::new();
+        // 文本消息之后的输出项 (生成的图像 / 工具调用)，按发出顺序排列
+        let mut output_items: Vec<Value> = Vec::new();
+        let mut reasoning_text = String::new();
         let mut heartbeat_interval = tokio::time::interval(std::time::Duration::from_secs(15));
         heartbeat_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
@@ -433,19 +1027,54 @@ pub fn create_codex_sse_stream(
                                             if let Some(candidate) = candidates.get(0) {
                                                 if let Some(parts) = candidate.get("content").and_then(|c| c.get("parts")).and_then(|p| p.as_array()) {
                                                     for part in parts {
+                                                        let is_thought_part = part.get("thought").and_then(|v| v.as_bool()).unwrap_or(false);
                                                         if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
-                                                            let delta_ev = json!({ "type": "response.output_text.delta", "delta": text });
-                                                            yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&delta_ev).unwrap())));
+                                                            if is_thought_part {
+                                                                // 思考内容不混入正文，仅在请求 reasoning.summary 时以推理摘要返回
+                                                                if reasoning_summary {
+                                                                    reasoning_text.push_str(text);
+                                                                    let delta_ev = json!({ "type": "response.reasoning_summary_text.delta", "summary_index": 0, "delta": text });
+                                                                    yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&delta_ev).unwrap())));
+                                                                }
+                                                            } else {
+                                                                output_text.push_str(text);
+                                                                let delta_ev = json!({ "type": "response.output_text.delta", "delta": text });
+                                                                yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&delta_ev).unwrap())));
+                                                            }
                                                         }
                                                         if let Some(sig) = part.get("thoughtSignature").or(part.get("thought_signature")).and_then(|s| s.as_str()) {
                                                             store_thought_signature(sig, &session_id, message_count);
                                                         }
+                                                        // [NEW] 生成的图像映射为 image_generation_call 输出项
+                                                        if let Some(item) = part.get("inlineData").and_then(responses_image_output_item) {
+                                                            let done_ev = json!({ "type": "response.output_item.done", "output_index": output_items.len() + 1, "item": &item });
+                                                            yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&done_ev).unwrap())));
+                                                            output_items.push(item);
+                                                        }
                                                         if let Some(func_call) = part.get("functionCall") {
-                                                            let call_key = serde_json::to_string(func_call).unwrap_or_default();
-                                                            if !emitted_tool_calls.contains(&call_key) {
-                                                                emitted_tool_calls.insert(call_key);
-                                                                // (Codex tool call mapping logic omitted for brevity, keeping it simple but valid)
+                                                            // [NEW] 工具调用映射为 function_call 输出项，call_id 与 Chat 路径使用同一方案
+                                                            // 同名同参的并行调用按出现位置各自输出，不按内容去重
+                                                            let call_id = tool_call_ids
+                                                                .get_or_insert_with(|| ToolCallIds::new(actual_data.get("responseId").and_then(|v| v.as_str()).unwrap_or(&response_id), 0))
+                                                                .assign(func_call);
+                                                            let item = responses_function_call_item(func_call, call_id);
+                                                            // 事件顺序与 OpenAI 一致: output_item.added -> arguments.delta -> arguments.done -> output_item.done
+                                                            // Gemini 一次性给出完整参数，delta 即为全部参数
+                                                            let output_index = output_items.len() + 1;
+                                                            let item_id = item["id"].clone();
+                                                            let arguments = item["arguments"].clone();
+                                                            let mut added_item = item.clone();
+                                                            added_item["arguments"] = json!("");
+                                                            added_item["status"] = json!("in_progress");
+                                                            for ev in [
+                                                                json!({ "type": "response.output_item.added", "output_index": output_index, "item": added_item }),
+                                                                json!({ "type": "response.function_call_arguments.delta", "item_id": &item_id, "output_index": output_index, "delta": &arguments }),
+                                                                json!({ "type": "response.function_call_arguments.done", "item_id": &item_id, "output_index": output_index, "arguments": &arguments }),
+                                                                json!({ "type": "response.output_item.done", "output_index": output_index, "item": &item }),
+                                                            ] {
+                                                                yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&ev).unwrap())));
                                                             }
+                                                            output_items.push(item);
                                                         }
                                                     }
                                                 }
@@ -462,6 +1091,28 @@ pub fn create_codex_sse_stream(
                 _ = heartbeat_interval.tick() => { yield Ok::<Bytes, String>(Bytes::from(": ping\n\n")); }
             }
         }
+
+        // 最终响应对象: 回写 metadata，store: true 时保存供 GET /v1/responses/{id} 读取
+        let mut output = build_responses_output(&output_text, output_items);
+        if !reasoning_text.is_empty() {
+            // 推理摘要追加在末尾，不影响流式事件中已发出的 output_index
+            output.push(json!({
+                "type": "reasoning",
+                "id": format!("rs_{}", Uuid::new_v4().simple()),
+                "summary": [{ "type": "summary_text", "text": reasoning_text }]
+            }));
+        }
+        let mut final_response = json!({
+            "id": &response_id,
+            "object": "response",
+            "created_at": chrono::Utc::now().timestamp(),
+            "status": "completed",
+            "model": &model,
+            "output": output
+        });
+        store_options.finalize(&mut final_response);
+        let completed_ev = json!({ "type": "response.completed", "response": final_response });
+        yield Ok::<Bytes, String>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&completed_ev).unwrap())));
     };
     Box::pin(stream)
 }
@@ -0,0 +1,171 @@
+// 工具调用 ID 统一方案
+// Gemini functionCall 通常不携带 id，由代理生成。流式输出、内部收集、非流式转换与 Codex Responses 流共用同一方案:
+//   - 上游 functionCall.id 存在时原样使用
+//   - 否则按 响应 ID + 候选序号 + 调用位置 + 调用内容 哈希生成 call_<hex>
+//     (不同轮次中完全相同的调用也得到不同 ID，避免历史中的工具结果互相混淆)
+// 已发出的 ID -> 函数名 记录在进程级注册表中，客户端回传工具结果时即使省略了原调用也能解析出函数名
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+
+// 注册表容量上限，超出后淘汰最早写入的记录
+const MAX_REMEMBERED_CALLS: usize = 4096;
+
+/// 生成稳定的工具调用 ID
+/// 优先使用上游返回的 functionCall.id；否则按 salt (所属响应) 与调用位置加调用内容哈希生成，
+/// 保证流式输出、内部收集 (collect_stream_to_json) 与非流式转换三条路径对同一响应中的同一调用得到相同 ID
+pub fn stable_tool_call_id(func_call: &Value, salt: &str, position: usize) -> String {
+    if let Some(id) = func_call
+        .get("id")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+    {
+        return id.to_string();
+    }
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update([0]);
+    hasher.update(position.to_le_bytes());
+    hasher.update(
+        serde_json::to_string(func_call)
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    let digest = hasher.finalize();
+    let hex: String = digest
+        .iter()
+        .take(12)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("call_{}", hex)
+}
+
+/// 单个响应 (候选) 内的工具调用 ID 分配器
+pub struct ToolCallIds {
+    salt: String,
+    count: usize,
+}
+
+impl ToolCallIds {
+    /// response_id 优先使用 Gemini responseId (流式各分片一致，流式与非流式路径得到相同 ID)，
+    /// 缺失时由调用方传入本次响应生成的 ID
+    pub fn new(response_id: &str, candidate_index: usize) -> Self {
+        Self {
+            salt: format!("{}:{}", response_id, candidate_index),
+            count: 0,
+        }
+    }
+
+    /// 为 functionCall 分配 ID 并登记函数名；按调用位置加盐，参数完全相同的并行调用也互不相同
+    pub fn assign(&mut self, func_call: &Value) -> String {
+        let id = stable_tool_call_id(func_call, &self.salt, self.count);
+        self.count += 1;
+        if let Some(name) = func_call.get("name").and_then(|v| v.as_str()) {
+            remember_tool_call(&id, name);
+        }
+        id
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    names: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(Registry::default()))
+}
+
+/// 记录已发出的工具调用 ID 对应的函数名
+pub fn remember_tool_call(id: &str, name: &str) {
+    if id.is_empty() || name.is_empty() {
+        return;
+    }
+    if let Ok(mut reg) = registry().lock() {
+        if reg.names.insert(id.to_string(), name.to_string()).is_none() {
+            reg.order.push_back(id.to_string());
+        }
+        while reg.order.len() > MAX_REMEMBERED_CALLS {
+            if let Some(oldest) = reg.order.pop_front() {
+                reg.names.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// 按代理此前发出的工具调用 ID 查找函数名 (客户端回传的历史中缺少原调用时使用)
+pub fn resolve_tool_call_name(id: &str) -> Option<String> {
+    registry().lock().ok()?.names.get(id).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_call_ids_stable_and_unique() {
+        let weather = json!({ "name": "get_weather", "args": { "city": "Paris" } });
+        let roll = json!({ "name": "roll_die", "args": { "sides": 6 } });
+
+        let mut first = ToolCallIds::new("resp-turn-1", 0);
+        let ids: Vec<String> = [&weather, &roll, &roll]
+            .iter()
+            .map(|fc| first.assign(fc))
+            .collect();
+        assert!(ids.iter().all(|id| id.starts_with("call_")));
+        assert_ne!(ids[1], ids[2]);
+
+        // 同一响应 (流式 / 非流式各自转换) 得到相同 ID
+        let mut same_response = ToolCallIds::new("resp-turn-1", 0);
+        let again: Vec<String> = [&weather, &roll, &roll]
+            .iter()
+            .map(|fc| same_response.assign(fc))
+            .collect();
+        assert_eq!(ids, again);
+
+        // 上游自带 id 时原样使用
+        let mut upstream = ToolCallIds::new("resp-upstream", 0);
+        assert_eq!(
+            upstream.assign(&json!({ "id": "fc-upstream-1", "name": "get_time", "args": {} })),
+            "fc-upstream-1"
+        );
+
+        assert_eq!(
+            resolve_tool_call_name(&ids[0]).as_deref(),
+            Some("get_weather")
+        );
+        assert_eq!(resolve_tool_call_name(&ids[2]).as_deref(), Some("roll_die"));
+        assert_eq!(
+            resolve_tool_call_name("fc-upstream-1").as_deref(),
+            Some("get_time")
+        );
+        assert_eq!(resolve_tool_call_name("call_unknown"), None);
+    }
+
+    #[test]
+    fn test_same_call_in_different_turns_gets_distinct_ids() {
+        let weather = json!({ "name": "get_weather", "args": { "city": "Paris" } });
+
+        let turn_one = ToolCallIds::new("resp-turn-1", 0).assign(&weather);
+        let turn_two = ToolCallIds::new("resp-turn-2", 0).assign(&weather);
+        assert_ne!(turn_one, turn_two);
+
+        // 同一响应的不同候选也互不相同
+        let other_choice = ToolCallIds::new("resp-turn-1", 1).assign(&weather);
+        assert_ne!(turn_one, other_choice);
+
+        // 两轮的工具结果都能解析回函数名
+        assert_eq!(
+            resolve_tool_call_name(&turn_one).as_deref(),
+            Some("get_weather")
+        );
+        assert_eq!(
+            resolve_tool_call_name(&turn_two).as_deref(),
+            Some("get_weather")
+        );
+    }
+}
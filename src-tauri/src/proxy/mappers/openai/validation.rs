@@ -0,0 +1,303 @@
+// OpenAI 请求体校验
+// serde 反序列化失败时只给出 "invalid type ... at line 1 column 87"，无法定位字段
+// 这里针对常见错误 (messages 非数组、content 类型错误、tool_calls 格式错误等) 给出带字段路径的提示
+use serde_json::Value;
+
+use super::models::OpenAIRequest;
+
+/// 解析 OpenAI 请求体，失败时返回指明出错字段路径的错误信息
+pub fn parse_openai_request(body: Value) -> Result<OpenAIRequest, String> {
+    match serde_json::from_value::<OpenAIRequest>(body.clone()) {
+        Ok(req) => {
+            for (key, value) in [
+                ("max_tokens", req.max_tokens),
+                ("max_completion_tokens", req.max_completion_tokens),
+            ] {
+                if value == Some(0) {
+                    return Err(format!("Invalid request: {}: must be a positive integer", key));
+                }
+            }
+            // [NEW] 文档内容块: 类型不支持或超出大小上限时直接拒绝
+            super::document_input::validate_request_documents(&req)
+                .map_err(|e| format!("Invalid request: {}", e))?;
+            Ok(req)
+        }
+        Err(e) => Err(format!("Invalid request: {}", describe_request_error(&body, &e))),
+    }
+}
+
+/// 定位请求体中的具体错误字段，无法识别时回退为 serde 原始错误
+pub fn describe_request_error(body: &Value, err: &serde_json::Error) -> String {
+    find_request_problem(body).unwrap_or_else(|| err.to_string())
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn expected(path: &str, what: &str, got: &Value) -> String {
+    format!("{}: expected {}, got {}", path, what, type_name(got))
+}
+
+fn find_request_problem(body: &Value) -> Option<String> {
+    let Some(obj) = body.as_object() else {
+        return Some(expected("body", "a JSON object", body));
+    };
+
+    match obj.get("model") {
+        None => return Some("model: missing required field".to_string()),
+        Some(v) if !v.is_string() => return Some(expected("model", "a string", v)),
+        _ => {}
+    }
+
+    if let Some(v) = obj.get("stream").filter(|v| !v.is_null() && !v.is_boolean()) {
+        return Some(expected("stream", "a boolean", v));
+    }
+    for key in ["temperature", "top_p", "frequency_penalty", "presence_penalty"] {
+        if let Some(v) = obj.get(key).filter(|v| !v.is_null() && !v.is_number()) {
+            return Some(expected(key, "a number", v));
+        }
+    }
+    for key in ["max_tokens", "max_completion_tokens", "n"] {
+        if let Some(v) = obj.get(key).filter(|v| !v.is_null() && !v.is_u64()) {
+            return Some(expected(key, "a non-negative integer", v));
+        }
+    }
+    if let Some(v) = obj.get("tools").filter(|v| !v.is_null() && !v.is_array()) {
+        return Some(expected("tools", "an array", v));
+    }
+
+    if let Some(messages) = obj.get("messages") {
+        let Some(messages) = messages.as_array() else {
+            return Some(expected("messages", "an array", messages));
+        };
+        for (i, msg) in messages.iter().enumerate() {
+            if let Some(problem) = find_message_problem(&format!("messages[{}]", i), msg) {
+                return Some(problem);
+            }
+        }
+    }
+
+    None
+}
+
+fn find_message_problem(path: &str, msg: &Value) -> Option<String> {
+    let Some(obj) = msg.as_object() else {
+        return Some(expected(path, "a message object", msg));
+    };
+
+    match obj.get("role") {
+        None => return Some(format!("{}.role: missing required field", path)),
+        Some(v) if !v.is_string() => return Some(expected(&format!("{}.role", path), "a string", v)),
+        _ => {}
+    }
+
+    match obj.get("content") {
+        None | Some(Value::Null) | Some(Value::String(_)) => {}
+        Some(Value::Array(parts)) => {
+            for (j, part) in parts.iter().enumerate() {
+                if let Some(problem) = find_content_part_problem(&format!("{}.content[{}]", path, j), part) {
+                    return Some(problem);
+                }
+            }
+        }
+        Some(v) => {
+            return Some(expected(
+                &format!("{}.content", path),
+                "a string or an array of content parts",
+                v,
+            ))
+        }
+    }
+
+    for key in ["tool_call_id", "name", "reasoning_content"] {
+        if let Some(v) = obj.get(key).filter(|v| !v.is_null() && !v.is_string()) {
+            return Some(expected(&format!("{}.{}", path, key), "a string", v));
+        }
+    }
+
+    if let Some(tool_calls) = obj.get("tool_calls").filter(|v| !v.is_null()) {
+        let tc_path = format!("{}.tool_calls", path);
+        let Some(tool_calls) = tool_calls.as_array() else {
+            return Some(expected(&tc_path, "an array", tool_calls));
+        };
+        for (j, call) in tool_calls.iter().enumerate() {
+            if let Some(problem) = find_tool_call_problem(&format!("{}[{}]", tc_path, j), call) {
+                return Some(problem);
+            }
+        }
+    }
+
+    None
+}
+
+fn find_content_part_problem(path: &str, part: &Value) -> Option<String> {
+    let Some(obj) = part.as_object() else {
+        return Some(expected(path, "a content part object", part));
+    };
+    let part_type = match obj.get("type") {
+        None => return Some(format!("{}.type: missing required field", path)),
+        Some(Value::String(t)) => t.as_str(),
+        Some(v) => return Some(expected(&format!("{}.type", path), "a string", v)),
+    };
+
+    let require_string = |field: &str, holder: Option<&Value>, field_path: String| -> Option<String> {
+        match holder.and_then(|h| h.get(field)) {
+            None => Some(format!("{}: missing required field", field_path)),
+            Some(v) if !v.is_string() => Some(expected(&field_path, "a string", v)),
+            _ => None,
+        }
+    };
+
+    match part_type {
+        "text" | "input_text" => require_string("text", Some(part), format!("{}.text", path)),
+        "image_url" | "audio_url" => match obj.get(part_type) {
+            None => Some(format!("{}.{}: missing required field", path, part_type)),
+            Some(inner) if !inner.is_object() => Some(expected(
+                &format!("{}.{}", path, part_type),
+                "an object with a url field",
+                inner,
+            )),
+            inner => require_string("url", inner, format!("{}.{}.url", path, part_type)),
+        },
+        // file 块的字段均为可选 (file_data / file_url / file_id)，只校验结构
+        "file" => match obj.get("file") {
+            None => Some(format!("{}.file: missing required field", path)),
+            Some(Value::Object(file)) => ["file_data", "file_url", "file_id", "filename"]
+                .into_iter()
+                .find_map(|key| match file.get(key) {
+                    Some(v) if !v.is_string() && !v.is_null() => {
+                        Some(expected(&format!("{}.file.{}", path, key), "a string", v))
+                    }
+                    _ => None,
+                }),
+            Some(inner) => Some(expected(&format!("{}.file", path), "an object", inner)),
+        },
+        other => Some(format!(
+            "{}.type: unsupported content part type '{}' (expected text, image_url, audio_url or file)",
+            path, other
+        )),
+    }
+}
+
+fn find_tool_call_problem(path: &str, call: &Value) -> Option<String> {
+    let Some(obj) = call.as_object() else {
+        return Some(expected(path, "a tool call object", call));
+    };
+    for key in ["id", "type"] {
+        match obj.get(key) {
+            None => return Some(format!("{}.{}: missing required field", path, key)),
+            Some(v) if !v.is_string() => return Some(expected(&format!("{}.{}", path, key), "a string", v)),
+            _ => {}
+        }
+    }
+    let function = match obj.get("function") {
+        None => return Some(format!("{}.function: missing required field", path)),
+        Some(f) if !f.is_object() => return Some(expected(&format!("{}.function", path), "an object", f)),
+        Some(f) => f,
+    };
+    match function.get("name") {
+        None => return Some(format!("{}.function.name: missing required field", path)),
+        Some(v) if !v.is_string() => {
+            return Some(expected(&format!("{}.function.name", path), "a string", v))
+        }
+        _ => {}
+    }
+    match function.get("arguments") {
+        None => Some(format!("{}.function.arguments: missing required field", path)),
+        // 常见错误: 直接传入对象而非 JSON 字符串
+        Some(v) if !v.is_string() => Some(expected(
+            &format!("{}.function.arguments", path),
+            "a JSON-encoded string",
+            v,
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error_for(body: Value) -> String {
+        parse_openai_request(body).expect_err("payload should be rejected")
+    }
+
+    #[test]
+    fn test_malformed_payloads_name_offending_field() {
+        let cases = vec![
+            (
+                json!({ "model": "gpt-4o", "messages": { "role": "user", "content": "hi" } }),
+                "messages: expected an array, got object",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [{ "role": "user", "content": "hi" }, { "role": "user", "content": 42 }] }),
+                "messages[1].content: expected a string or an array of content parts, got number",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [{ "role": "user", "content": [{ "type": "image_url", "image_url": "https://x/y.png" }] }] }),
+                "messages[0].content[0].image_url: expected an object with a url field, got string",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [{ "role": "assistant", "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "f", "arguments": { "a": 1 } } }] }] }),
+                "messages[0].tool_calls[0].function.arguments: expected a JSON-encoded string, got object",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [{ "role": "assistant", "tool_calls": { "id": "call_1" } }] }),
+                "messages[0].tool_calls: expected an array, got object",
+            ),
+            (
+                json!({ "messages": [] }),
+                "model: missing required field",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [{ "content": "hi" }] }),
+                "messages[0].role: missing required field",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [], "max_completion_tokens": -5 }),
+                "max_completion_tokens: expected a non-negative integer, got number",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [], "max_completion_tokens": 0 }),
+                "max_completion_tokens: must be a positive integer",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [], "max_tokens": 0 }),
+                "max_tokens: must be a positive integer",
+            ),
+            (
+                json!({ "model": "gpt-4o", "messages": [{ "role": "user", "content": [{ "type": "file", "file": { "file_data": 7 } }] }] }),
+                "messages[0].content[0].file.file_data: expected a string, got number",
+            ),
+        ];
+
+        for (body, expected_message) in cases {
+            let err = error_for(body);
+            assert!(err.starts_with("Invalid request: "), "{}", err);
+            assert!(err.contains(expected_message), "got: {}", err);
+        }
+    }
+
+    #[test]
+    fn test_valid_payload_still_parses() {
+        let req = parse_openai_request(json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "user", "content": [{ "type": "text", "text": "hi" }] },
+                { "role": "user", "content": [{ "type": "file", "file": { "filename": "a.pdf", "file_data": "data:application/pdf;base64,JVBERi0=" } }] },
+                { "role": "assistant", "content": null, "tool_calls": [{ "id": "c1", "type": "function", "function": { "name": "f", "arguments": "{}" } }] }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(req.messages.len(), 3);
+    }
+}
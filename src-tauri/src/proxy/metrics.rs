@@ -0,0 +1,362 @@
+// Prometheus 指标
+// 由 AppState 持有，在请求中间件、重试循环、限流标记等已有挂载点累加，GET /metrics 以文本格式导出
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::proxy::TokenManager;
+
+/// 带标签的计数器: 标签值 -> 计数 (BTreeMap 保证导出顺序稳定)
+#[derive(Default)]
+struct LabeledCounter {
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl LabeledCounter {
+    fn inc(&self, labels: &[&str]) {
+        if let Ok(mut values) = self.values.lock() {
+            *values
+                .entry(labels.iter().map(|s| s.to_string()).collect())
+                .or_insert(0) += 1;
+        }
+    }
+
+    fn snapshot(&self) -> Vec<(Vec<String>, u64)> {
+        self.values
+            .lock()
+            .map(|v| v.iter().map(|(k, n)| (k.clone(), *n)).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// 带标签的延迟汇总: 标签值 -> (总和秒数, 次数)
+#[derive(Default)]
+struct LabeledSummary {
+    values: Mutex<BTreeMap<Vec<String>, (f64, u64)>>,
+}
+
+impl LabeledSummary {
+    fn observe(&self, labels: &[&str], seconds: f64) {
+        if let Ok(mut values) = self.values.lock() {
+            let entry = values
+                .entry(labels.iter().map(|s| s.to_string()).collect())
+                .or_insert((0.0, 0));
+            entry.0 += seconds;
+            entry.1 += 1;
+        }
+    }
+
+    fn snapshot(&self) -> Vec<(Vec<String>, (f64, u64))> {
+        self.values
+            .lock()
+            .map(|v| v.iter().map(|(k, n)| (k.clone(), *n)).collect())
+            .unwrap_or_default()
+    }
+}
+
+pub struct ProxyMetrics {
+    token_manager: Arc<TokenManager>,
+    /// model, status
+    requests: LabeledCounter,
+    /// protocol
+    retries: LabeledCounter,
+    /// protocol
+    account_rotations: LabeledCounter,
+    /// protocol
+    rate_limit_events: LabeledCounter,
+    /// protocol
+    stream_peek_timeouts: LabeledCounter,
+    /// result (success / failure)
+    image_generations: LabeledCounter,
+    /// protocol
+    first_token_latency: LabeledSummary,
+}
+
+impl ProxyMetrics {
+    pub fn new(token_manager: Arc<TokenManager>) -> Self {
+        Self {
+            token_manager,
+            requests: LabeledCounter::default(),
+            retries: LabeledCounter::default(),
+            account_rotations: LabeledCounter::default(),
+            rate_limit_events: LabeledCounter::default(),
+            stream_peek_timeouts: LabeledCounter::default(),
+            image_generations: LabeledCounter::default(),
+            first_token_latency: LabeledSummary::default(),
+        }
+    }
+
+    pub fn record_request(&self, model: &str, status: u16) {
+        self.requests.inc(&[model, &status.to_string()]);
+    }
+
+    /// 在重试循环中获取到账号后调用: attempt > 0 计为一次重试，账号变化计为一次轮换
+    pub fn record_attempt(
+        &self,
+        protocol: &str,
+        attempt: usize,
+        previous_email: Option<&str>,
+        email: &str,
+    ) {
+        if attempt == 0 {
+            return;
+        }
+        self.retries.inc(&[protocol]);
+        if previous_email.is_some_and(|prev| prev != email) {
+            self.account_rotations.inc(&[protocol]);
+        }
+    }
+
+    pub fn record_rate_limited(&self, protocol: &str) {
+        self.rate_limit_events.inc(&[protocol]);
+    }
+
+    pub fn record_peek_timeout(&self, protocol: &str) {
+        self.stream_peek_timeouts.inc(&[protocol]);
+    }
+
+    /// peek 到首个有效数据块时调用 (time-to-first-token)
+    pub fn record_first_token_latency(&self, protocol: &str, latency: std::time::Duration) {
+        self.first_token_latency
+            .observe(&[protocol], latency.as_secs_f64());
+    }
+
+    pub fn record_image_generation(&self, success: bool) {
+        self.image_generations
+            .inc(&[if success { "success" } else { "failure" }]);
+    }
+
+    /// 以 Prometheus 文本格式导出
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_counter(
+            &mut out,
+            "antigravity_requests_total",
+            "Proxied API requests by mapped model and HTTP status",
+            &["model", "status"],
+            &self.requests.snapshot(),
+        );
+        write_counter(
+            &mut out,
+            "antigravity_retry_attempts_total",
+            "Upstream retry attempts",
+            &["protocol"],
+            &self.retries.snapshot(),
+        );
+        write_counter(
+            &mut out,
+            "antigravity_account_rotations_total",
+            "Retries that switched to a different account",
+            &["protocol"],
+            &self.account_rotations.snapshot(),
+        );
+        write_counter(
+            &mut out,
+            "antigravity_rate_limit_events_total",
+            "Upstream rate limit responses that locked an account",
+            &["protocol"],
+            &self.rate_limit_events.snapshot(),
+        );
+        write_counter(
+            &mut out,
+            "antigravity_stream_peek_timeouts_total",
+            "Streams that produced no data before the peek timeout",
+            &["protocol"],
+            &self.stream_peek_timeouts.snapshot(),
+        );
+        write_counter(
+            &mut out,
+            "antigravity_image_generations_total",
+            "Image generation requests by result",
+            &["result"],
+            &self.image_generations.snapshot(),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP antigravity_time_to_first_token_seconds Time from upstream request to the first streamed data chunk"
+        );
+        let _ = writeln!(out, "# TYPE antigravity_time_to_first_token_seconds summary");
+        for (labels, (sum, count)) in self.first_token_latency.snapshot() {
+            let protocol = escape_label(labels.first().map(String::as_str).unwrap_or(""));
+            let _ = writeln!(
+                out,
+                "antigravity_time_to_first_token_seconds_sum{{protocol=\"{}\"}} {}",
+                protocol, sum
+            );
+            let _ = writeln!(
+                out,
+                "antigravity_time_to_first_token_seconds_count{{protocol=\"{}\"}} {}",
+                protocol, count
+            );
+        }
+
+        // 账号池状态在导出时实时计算，限流到期后自动恢复为 0
+        let accounts = self.token_manager.account_rate_limit_states();
+        let _ = writeln!(out, "# HELP antigravity_accounts Accounts in the pool");
+        let _ = writeln!(out, "# TYPE antigravity_accounts gauge");
+        let _ = writeln!(out, "antigravity_accounts {}", accounts.len());
+        let _ = writeln!(
+            out,
+            "# HELP antigravity_account_rate_limited Whether the account is currently rate limited (1) or available (0)"
+        );
+        let _ = writeln!(out, "# TYPE antigravity_account_rate_limited gauge");
+        for (account_id, limited) in accounts {
+            let _ = writeln!(
+                out,
+                "antigravity_account_rate_limited{{account=\"{}\"}} {}",
+                escape_label(&account_id),
+                limited as u8
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP antigravity_account_time_to_first_token_seconds Average time to first token over the account's recent streams"
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE antigravity_account_time_to_first_token_seconds gauge"
+        );
+        for (account_id, avg_ms) in self.token_manager.account_first_token_latencies() {
+            let _ = writeln!(
+                out,
+                "antigravity_account_time_to_first_token_seconds{{account=\"{}\"}} {}",
+                escape_label(&account_id),
+                avg_ms as f64 / 1000.0
+            );
+        }
+
+        out
+    }
+}
+
+fn write_counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label_names: &[&str],
+    samples: &[(Vec<String>, u64)],
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, value) in samples {
+        let labels = label_names
+            .iter()
+            .zip(labels)
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 记录请求数 (按 X-Mapped-Model 响应头与状态码)
+pub async fn metrics_middleware(
+    State(metrics): State<Arc<ProxyMetrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let model = response
+        .headers()
+        .get("X-Mapped-Model")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+    metrics.record_request(&model, response.status().as_u16());
+    response
+}
+
+/// GET /metrics
+pub async fn handle_metrics(State(metrics): State<Arc<ProxyMetrics>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        metrics.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::TestPool;
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn scrape(app: &Router) -> String {
+        let resp = app
+            .clone()
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_scrape_after_request() {
+        let pool = TestPool::new(&[]);
+        let metrics = Arc::new(ProxyMetrics::new(pool.token_manager().await));
+
+        let proxied = Router::new()
+            .route(
+                "/v1/chat/completions",
+                get(|| async { ([("X-Mapped-Model", "gemini-2.5-flash")], "ok") }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                metrics.clone(),
+                metrics_middleware,
+            ));
+        let app = Router::new()
+            .route("/metrics", get(handle_metrics))
+            .merge(proxied)
+            .with_state(metrics.clone());
+
+        let before = scrape(&app).await;
+        assert!(before.contains("# TYPE antigravity_requests_total counter"));
+        assert!(!before.contains("antigravity_requests_total{"));
+        assert!(before.contains("antigravity_accounts 0"));
+
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/chat/completions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        metrics.record_attempt("openai", 0, None, "a@test.com");
+        metrics.record_attempt("openai", 1, Some("a@test.com"), "b@test.com");
+        metrics.record_peek_timeout("openai");
+        metrics.record_image_generation(false);
+
+        let after = scrape(&app).await;
+        assert!(after.contains(
+            "antigravity_requests_total{model=\"gemini-2.5-flash\",status=\"200\"} 1"
+        ));
+        assert!(after.contains("antigravity_retry_attempts_total{protocol=\"openai\"} 1"));
+        assert!(after.contains("antigravity_account_rotations_total{protocol=\"openai\"} 1"));
+        assert!(after.contains("antigravity_stream_peek_timeouts_total{protocol=\"openai\"} 1"));
+        assert!(after.contains("antigravity_image_generations_total{result=\"failure\"} 1"));
+    }
+}
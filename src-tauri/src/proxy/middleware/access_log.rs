@@ -0,0 +1,331 @@
+// 访问日志中间件
+// 每个请求结束时输出一行 INFO 级别的结构化访问日志 (target = "access_log")，格式为 JSON 或 logfmt，
+// 便于接入日志采集。只记录请求元数据，不记录 prompt / 响应正文；path 不含 query (可能携带 key=)
+// 请求携带 OpenAI `user` 字段时额外记录 user，便于多租户部署追查滥用
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{body::Body, extract::Request, middleware::Next, response::Response};
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::proxy::config::{get_access_log_config, AccessLogConfig, AccessLogFormat};
+
+tokio::task_local! {
+    static ACCESS_LOG_FIELDS: Arc<Mutex<AccessLogFields>>;
+}
+
+/// 由下游 (monitor / handler) 在请求处理过程中补充的字段
+#[derive(Debug, Clone, Default)]
+struct AccessLogFields {
+    model: Option<String>,
+    mapped_model: Option<String>,
+    account_email: Option<String>,
+    user: Option<String>,
+    attempts: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    pub method: String,
+    pub path: String,
+    pub model: Option<String>,
+    pub mapped_model: Option<String>,
+    pub account: Option<String>,
+    pub user: Option<String>,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub attempts: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl AccessLogEntry {
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+            AccessLogFormat::Logfmt => {
+                let mut pairs: Vec<(&str, String)> =
+                    vec![("method", self.method.clone()), ("path", self.path.clone())];
+                let optional = [
+                    ("model", &self.model),
+                    ("mapped_model", &self.mapped_model),
+                    ("account", &self.account),
+                    ("user", &self.user),
+                ];
+                for (key, value) in optional {
+                    if let Some(value) = value {
+                        pairs.push((key, value.clone()));
+                    }
+                }
+                pairs.extend([
+                    ("status", self.status.to_string()),
+                    ("latency_ms", self.latency_ms.to_string()),
+                    ("attempts", self.attempts.to_string()),
+                    ("bytes_in", self.bytes_in.to_string()),
+                    ("bytes_out", self.bytes_out.to_string()),
+                ]);
+                pairs
+                    .into_iter()
+                    .map(|(key, value)| format!("{}={}", key, logfmt_value(&value)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+        }
+    }
+}
+
+/// logfmt 值: 含空格 / 引号 / 等号时加引号并转义
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=' || c == '\\')
+    {
+        return value.to_string();
+    }
+    format!("{:?}", value)
+}
+
+/// 记录客户端请求的模型名 (monitor 解析请求体后调用)
+/// 在 access_log_middleware 之外调用时为空操作
+pub fn record_requested_model(model: &str) {
+    let _ = ACCESS_LOG_FIELDS.try_with(|fields| {
+        if let Ok(mut fields) = fields.lock() {
+            fields.model = Some(model.to_string());
+        }
+    });
+}
+
+/// 记录请求中的终端用户 (OpenAI `user` 字段)
+pub fn record_end_user(user: &str) {
+    let _ = ACCESS_LOG_FIELDS.try_with(|fields| {
+        if let Ok(mut fields) = fields.lock() {
+            fields.user = Some(user.to_string());
+        }
+    });
+}
+
+/// 记录当前上游尝试的账号 / 映射模型 / 次数 (由 request_span::record_attempt 转发)
+pub fn record_upstream_attempt(attempt: usize, account_email: &str, mapped_model: &str) {
+    let _ = ACCESS_LOG_FIELDS.try_with(|fields| {
+        if let Ok(mut fields) = fields.lock() {
+            fields.attempts = attempt;
+            fields.account_email = Some(account_email.to_string());
+            fields.mapped_model = Some(mapped_model.to_string());
+        }
+    });
+}
+
+/// 响应正文全部发送 (或客户端断开) 时输出日志，流式响应的延迟包含整个流
+struct AccessLogGuard {
+    format: AccessLogFormat,
+    entry: AccessLogEntry,
+    start: Instant,
+    bytes_in: Arc<AtomicU64>,
+}
+
+impl AccessLogGuard {
+    fn add_bytes_out(&mut self, len: usize) {
+        self.entry.bytes_out += len as u64;
+    }
+}
+
+impl Drop for AccessLogGuard {
+    fn drop(&mut self) {
+        self.entry.latency_ms = self.start.elapsed().as_millis() as u64;
+        self.entry.bytes_in = self.bytes_in.load(Ordering::Relaxed);
+        tracing::info!(target: "access_log", "{}", self.entry.format(self.format));
+    }
+}
+
+pub async fn access_log_middleware(request: Request, next: Next) -> Response {
+    log_access(get_access_log_config(), request, next).await
+}
+
+/// 按给定配置记录访问日志 (未启用时直接放行)
+async fn log_access(config: AccessLogConfig, request: Request, next: Next) -> Response {
+    if !config.enabled {
+        return next.run(request).await;
+    }
+
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    // 按实际读取的请求体字节计数 (Content-Length 可能缺失，如 chunked 上传)
+    let bytes_in = Arc::new(AtomicU64::new(0));
+    let (parts, body) = request.into_parts();
+    let counter = bytes_in.clone();
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            counter.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        }
+        chunk
+    });
+    let request = Request::from_parts(parts, Body::from_stream(body));
+
+    let fields = Arc::new(Mutex::new(AccessLogFields::default()));
+    let response = ACCESS_LOG_FIELDS
+        .scope(fields.clone(), next.run(request))
+        .await;
+
+    let fields = fields.lock().map(|f| f.clone()).unwrap_or_default();
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+    };
+    let entry = AccessLogEntry {
+        method,
+        path,
+        model: fields.model,
+        mapped_model: fields.mapped_model.or_else(|| header("X-Mapped-Model")),
+        account: fields.account_email.or_else(|| header("X-Account-Email")),
+        user: fields.user,
+        status: response.status().as_u16(),
+        latency_ms: 0,
+        attempts: fields.attempts,
+        bytes_in: 0,
+        bytes_out: 0,
+    };
+
+    let mut guard = AccessLogGuard {
+        format: config.format,
+        entry,
+        start,
+        bytes_in,
+    };
+    let (parts, body) = response.into_parts();
+    let stream = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            guard.add_bytes_out(bytes.len());
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+    use std::sync::Mutex as StdMutex;
+    use tower::ServiceExt;
+
+    #[derive(Clone, Default)]
+    struct CaptureWriter(Arc<StdMutex<Vec<u8>>>);
+
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_logfmt_quotes_values() {
+        let entry = AccessLogEntry {
+            method: "POST".to_string(),
+            path: "/v1/chat/completions".to_string(),
+            model: Some("my model".to_string()),
+            mapped_model: None,
+            account: Some("a@test.com".to_string()),
+            user: Some("tenant-1".to_string()),
+            status: 200,
+            latency_ms: 12,
+            attempts: 1,
+            bytes_in: 10,
+            bytes_out: 20,
+        };
+        assert_eq!(
+            entry.format(AccessLogFormat::Logfmt),
+            "method=POST path=/v1/chat/completions model=\"my model\" account=a@test.com \
+             user=tenant-1 status=200 latency_ms=12 attempts=1 bytes_in=10 bytes_out=20"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!(json["mapped_model"], serde_json::Value::Null);
+        assert_eq!(json["bytes_out"], 20);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_access_log_line_emitted_without_prompt() {
+        let writer = CaptureWriter::default();
+        let make_writer = {
+            let writer = writer.clone();
+            move || writer.clone()
+        };
+        let subscriber = tracing_subscriber::fmt()
+            .without_time()
+            .with_ansi(false)
+            .with_max_level(tracing::Level::INFO)
+            .with_writer(make_writer)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/v1/chat/completions",
+                post(|_body: String| async {
+                    record_requested_model("gpt-4o");
+                    record_end_user("tenant-1");
+                    crate::proxy::middleware::request_span::record_attempt(
+                        2,
+                        "a@test.com",
+                        "gemini-2.5-flash",
+                    );
+                    "hello"
+                }),
+            )
+            .layer(axum::middleware::from_fn(|request, next| {
+                let config = AccessLogConfig {
+                    enabled: true,
+                    format: AccessLogFormat::Json,
+                };
+                log_access(config, request, next)
+            }));
+
+        let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"secret prompt"}]}"#;
+        // 分块上传且不带 Content-Length，bytes_in 按实际读取的字节计数
+        let chunks: Vec<Result<&str, std::io::Error>> = vec![Ok(&body[..20]), Ok(&body[20..])];
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/chat/completions?key=sk-secret")
+                    .body(Body::from_stream(futures::stream::iter(chunks)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"hello");
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let line = output
+            .lines()
+            .find(|l| l.contains("access_log"))
+            .expect("access log line");
+        assert!(!line.contains("secret"));
+        let json: serde_json::Value =
+            serde_json::from_str(&line[line.find('{').unwrap()..]).unwrap();
+        assert_eq!(json["method"], "POST");
+        assert_eq!(json["path"], "/v1/chat/completions");
+        assert_eq!(json["model"], "gpt-4o");
+        assert_eq!(json["mapped_model"], "gemini-2.5-flash");
+        assert_eq!(json["account"], "a@test.com");
+        assert_eq!(json["user"], "tenant-1");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["attempts"], 2);
+        assert_eq!(json["bytes_in"], body.len());
+        assert_eq!(json["bytes_out"], 5);
+    }
+}
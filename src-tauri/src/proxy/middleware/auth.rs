@@ -4,7 +4,8 @@ use axum::{
     extract::Request,
     http::{header, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
 };
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -29,6 +30,48 @@ pub async fn admin_auth_middleware(
     auth_middleware_internal(state, request, next, true).await
 }
 
+/// 判断请求携带的 key 是否为管理凭据
+/// 优先使用独立的 admin_password，如果没有则回退使用 api_key
+pub fn is_admin_credential(security: &ProxySecurityConfig, api_key: Option<&str>) -> bool {
+    let Some(key) = api_key.filter(|k| !k.is_empty()) else {
+        return false;
+    };
+    match &security.admin_password {
+        Some(pwd) if !pwd.is_empty() => key == pwd,
+        _ => !security.api_key.is_empty() && key == security.api_key,
+    }
+}
+
+/// [NEW] 代理接口鉴权失败时返回 OpenAI 格式的 401，便于 SDK 给出明确提示
+fn openai_unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "invalid_request_error",
+                "param": null,
+                "code": "invalid_api_key"
+            }
+        })),
+    )
+        .into_response()
+}
+
+const MISSING_API_KEY_MESSAGE: &str =
+    "You didn't provide an API key. Provide it in the Authorization header as 'Bearer <key>'.";
+const INVALID_API_KEY_MESSAGE: &str = "Incorrect API key provided.";
+
+/// 从请求头中提取 API key (Authorization / x-api-key / x-goog-api-key)
+pub fn extract_api_key(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer ").or(Some(s)))
+        .or_else(|| headers.get("x-api-key").and_then(|h| h.to_str().ok()))
+        .or_else(|| headers.get("x-goog-api-key").and_then(|h| h.to_str().ok()))
+}
+
 /// 内部认证逻辑
 async fn auth_middleware_internal(
     State(security): State<Arc<RwLock<ProxySecurityConfig>>>,
@@ -40,7 +83,8 @@ async fn auth_middleware_internal(
     let path = request.uri().path().to_string();
 
     // 过滤心跳和健康检查请求,避免日志噪音
-    let is_health_check = path == "/healthz" || path == "/api/health" || path == "/health";
+    let is_health_check =
+        path == "/healthz" || path == "/readyz" || path == "/api/health" || path == "/health";
     let is_internal_endpoint = path.starts_with("/internal/");
     if !path.contains("event_logging") && !is_health_check {
         tracing::info!("Request: {} {}", method, path);
@@ -116,48 +160,34 @@ async fn auth_middleware_internal(
     }
     
     // 从 header 中提取 API key
-    let api_key = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|s| s.strip_prefix("Bearer ").or(Some(s)))
-        .or_else(|| {
-            request
-                .headers()
-                .get("x-api-key")
-                .and_then(|h| h.to_str().ok())
-        })
-        .or_else(|| {
-            request
-                .headers()
-                .get("x-goog-api-key")
-                .and_then(|h| h.to_str().ok())
-        });
+    let api_key = extract_api_key(request.headers());
 
-    if security.api_key.is_empty() && (security.admin_password.is_none() || security.admin_password.as_ref().unwrap().is_empty()) {
-        if force_strict {
-             tracing::error!("Admin auth is required but both api_key and admin_password are empty; denying request");
-             return Err(StatusCode::UNAUTHORIZED);
-        }
-        tracing::error!("Proxy auth is enabled but api_key is empty; denying request");
+    if force_strict
+        && security.api_key.is_empty()
+        && security.admin_password.as_deref().is_none_or(str::is_empty)
+    {
+        tracing::error!(
+            "Admin auth is required but both api_key and admin_password are empty; denying request"
+        );
         return Err(StatusCode::UNAUTHORIZED);
     }
+    if !force_strict && !security.has_proxy_keys() {
+        tracing::error!("Proxy auth is enabled but no api_key is configured; denying request");
+        return Ok(openai_unauthorized(INVALID_API_KEY_MESSAGE));
+    }
 
     // 认证逻辑
     let authorized = if force_strict {
-        // 管理接口：优先使用独立的 admin_password，如果没有则回退使用 api_key
-        match &security.admin_password {
-            Some(pwd) if !pwd.is_empty() => {
-                api_key.map(|k| k == pwd).unwrap_or(false)
-            }
-            _ => {
-                // 回退使用 api_key
-                api_key.map(|k| k == security.api_key).unwrap_or(false)
+        is_admin_credential(&security, api_key)
+    } else {
+        // AI 代理接口：主 api_key 或已启用的附加 key
+        match api_key.and_then(|k| security.match_proxy_key(k)) {
+            Some(name) => {
+                tracing::debug!("Proxy request authorized with API key '{}'", name);
+                true
             }
+            None => false,
         }
-    } else {
-        // AI 代理接口：仅允许使用 api_key
-        api_key.map(|k| k == security.api_key).unwrap_or(false)
     };
 
     if authorized {
@@ -206,18 +236,20 @@ async fn auth_middleware_internal(
                     
                     Ok(response)
                 } else {
-                    Err(StatusCode::UNAUTHORIZED)
+                    Ok(openai_unauthorized(INVALID_API_KEY_MESSAGE))
                 }
             }
             Ok((false, reason)) => {
                 tracing::warn!("UserToken rejected: {:?}", reason);
-                Err(StatusCode::UNAUTHORIZED)
+                Ok(openai_unauthorized(INVALID_API_KEY_MESSAGE))
             }
             Err(e) => {
                 tracing::error!("UserToken validation error: {}", e);
                 Err(StatusCode::INTERNAL_SERVER_ERROR)
             }
         }
+    } else if !force_strict {
+        Ok(openai_unauthorized(MISSING_API_KEY_MESSAGE))
     } else {
         Err(StatusCode::UNAUTHORIZED)
     }
@@ -242,6 +274,7 @@ mod tests {
             auth_mode: ProxyAuthMode::Strict,
             api_key: "sk-api".to_string(),
             admin_password: Some("admin123".to_string()),
+            api_keys: Vec::new(),
             allow_lan_access: true,
             port: 8045,
             security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
@@ -258,6 +291,89 @@ mod tests {
         // 我们在 auth_middleware_internal 基础上做了逻辑校验即可
     }
 
+    #[test]
+    fn test_is_admin_credential() {
+        let mut security = ProxySecurityConfig {
+            auth_mode: ProxyAuthMode::Strict,
+            api_key: "sk-api".to_string(),
+            admin_password: Some("admin123".to_string()),
+            api_keys: Vec::new(),
+            allow_lan_access: true,
+            port: 8045,
+            security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
+        };
+        assert!(is_admin_credential(&security, Some("admin123")));
+        assert!(!is_admin_credential(&security, Some("sk-api")));
+        assert!(!is_admin_credential(&security, None));
+
+        // 未设置管理密码时回退到 api_key
+        security.admin_password = None;
+        assert!(is_admin_credential(&security, Some("sk-api")));
+        security.api_key = String::new();
+        assert!(!is_admin_credential(&security, Some("")));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_api_keys_and_openai_401() {
+        use axum::{routing::get, Router};
+        use tower::ServiceExt;
+
+        let security = ProxySecurityConfig {
+            auth_mode: ProxyAuthMode::Strict,
+            api_key: "sk-api".to_string(),
+            admin_password: None,
+            api_keys: vec![
+                crate::proxy::config::ApiKeyEntry {
+                    key: "sk-team-alice".to_string(),
+                    name: "alice".to_string(),
+                    enabled: true,
+                },
+                crate::proxy::config::ApiKeyEntry {
+                    key: "sk-team-bob".to_string(),
+                    name: "bob".to_string(),
+                    enabled: false,
+                },
+            ],
+            allow_lan_access: true,
+            port: 8045,
+            security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
+        };
+        assert_eq!(security.match_proxy_key("sk-api"), Some("default"));
+        assert_eq!(security.match_proxy_key("sk-team-alice"), Some("alice"));
+        assert_eq!(security.match_proxy_key("sk-team-bob"), None);
+        assert_eq!(security.match_proxy_key(""), None);
+
+        let app = Router::new()
+            .route("/v1/models", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(RwLock::new(security)),
+                auth_middleware,
+            ));
+        let send = |key: Option<&str>| {
+            let mut req = Request::builder().uri("/v1/models");
+            if let Some(key) = key {
+                req = req.header("Authorization", format!("Bearer {}", key));
+            }
+            app.clone().oneshot(req.body(axum::body::Body::empty()).unwrap())
+        };
+
+        assert_eq!(
+            send(Some("sk-team-alice")).await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(send(Some("sk-api")).await.unwrap().status(), StatusCode::OK);
+
+        // 缺少 key: OpenAI 格式 401
+        let resp = send(None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "invalid_api_key");
+        assert_eq!(json["error"]["type"], "invalid_request_error");
+    }
+
     #[test]
     fn test_auth_placeholder() {
         assert!(true);
@@ -0,0 +1,168 @@
+// OpenAI 接口请求体大小限制
+// 在 handler 解析 JSON 之前检查请求体大小，超出 openai_compat.max_request_body_bytes 时
+// 直接返回 OpenAI 格式的 413 错误，避免误传超大 base64 图片数组时占用大量内存
+// 作为代理路由的全局层挂在 monitor 之外 (monitor / idempotency / dedup 都会缓冲整个请求体)，仅对 OpenAI 接口生效
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::proxy::config::get_openai_compat_config;
+
+/// 受请求体大小限制的 OpenAI 接口 (POST)
+const LIMITED_PATHS: &[&str] = &[
+    "/v1/chat/completions",
+    "/v1/chat/batch",
+    "/v1/completions",
+    "/v1/responses",
+];
+
+pub(crate) fn payload_too_large(limit: usize) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(json!({
+            "error": {
+                "message": format!(
+                    "Request body exceeds the maximum allowed size of {} bytes",
+                    limit
+                ),
+                "type": "invalid_request_error",
+                "code": "request_too_large"
+            }
+        })),
+    )
+        .into_response()
+}
+
+pub async fn openai_body_limit_middleware(request: Request, next: Next) -> Response {
+    if request.method() != axum::http::Method::POST
+        || !LIMITED_PATHS.contains(&request.uri().path())
+    {
+        return next.run(request).await;
+    }
+    let limit = get_openai_compat_config().max_request_body_bytes;
+    if limit == 0 {
+        return next.run(request).await;
+    }
+
+    // 声明了 Content-Length 时无需读取即可拒绝
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return payload_too_large(limit);
+    }
+
+    // chunked 等未声明长度的请求: 读取时超出上限立即停止
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, limit).await {
+        Ok(bytes) => bytes,
+        Err(_) => return payload_too_large(limit),
+    };
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::OpenAICompatConfig;
+    use crate::proxy::tests::harness::TestPool;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// 与 server.rs 相同的代理路由 (含 monitor / idempotency / dedup 等全部中间件层)
+    fn app(token_manager: Arc<crate::proxy::TokenManager>) -> axum::Router {
+        let upstream = Arc::new(crate::proxy::upstream::client::UpstreamClient::new(
+            None, None,
+        ));
+        let state = crate::proxy::server::AppState::for_test(token_manager, upstream);
+        crate::proxy::server::build_proxy_router(&state).with_state(state)
+    }
+
+    /// 不断输出数据、永不结束的请求体: 若在限制之前被完整缓冲，请求将一直挂起
+    fn endless_body() -> Body {
+        let chunk = bytes::Bytes::from(vec![b'A'; 1024 * 1024]);
+        Body::from_stream(futures::stream::repeat_with(move || {
+            Ok::<_, std::io::Error>(chunk.clone())
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_returns_openai_413() {
+        // 使用默认上限，不修改全局配置
+        let limit = OpenAICompatConfig::default().max_request_body_bytes;
+        let pool = TestPool::new(&[]);
+        let app = app(pool.token_manager().await);
+
+        // 未超限: 进入 handler (账号池为空，返回 503 而不是 413)
+        let small = r#"{"model":"gpt-4o"}"#;
+        let resp = app.clone()
+            .oneshot(
+                Request::post("/v1/chat/completions")
+                    .header("content-type", "application/json")
+                    .body(Body::from(small))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_ne!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // 未声明 Content-Length 且永不结束的请求体: 超过上限即返回 413，不会先被 monitor 缓冲
+        for path in ["/v1/chat/completions", "/v1/responses"] {
+            let resp = tokio::time::timeout(
+                std::time::Duration::from_secs(30),
+                app.clone().oneshot(
+                    Request::post(path)
+                        .header("content-type", "application/json")
+                        .body(endless_body())
+                        .unwrap(),
+                ),
+            )
+            .await
+            .expect("oversized body must be rejected before being buffered")
+            .unwrap();
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["error"]["type"], "invalid_request_error");
+            assert_eq!(json["error"]["code"], "request_too_large");
+        }
+
+        // 声明了超限 Content-Length 时无需读取请求体即拒绝
+        let small_body = r#"{"model":"gpt-4o","prompt":"hi"}"#;
+        let resp = app.clone()
+            .oneshot(
+                Request::post("/v1/completions")
+                    .header("content-type", "application/json")
+                    .header("content-length", limit + 1)
+                    .body(Body::from(small_body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // 非 OpenAI 接口不受该限制
+        let resp = app.clone()
+            .oneshot(
+                Request::post("/v1/messages")
+                    .header("content-type", "application/json")
+                    .header("content-length", limit + 1)
+                    .body(Body::from(small_body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_ne!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,154 @@
+// 响应压缩
+// 基于 tower-http 的 CompressionLayer，按客户端 Accept-Encoding (br / gzip / deflate) 协商编码
+// experimental.response_compression 可热更新，关闭时不压缩；text/event-stream 等流式响应始终原样透传
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+use crate::proxy::config::ExperimentalConfig;
+
+// 小于该大小的响应压缩收益有限，直接透传
+const MIN_COMPRESS_BYTES: u16 = 1024;
+
+/// 读取 experimental.response_compression 开关 (配置正在被写入时本次不压缩)
+#[derive(Clone)]
+pub struct CompressionEnabled(Arc<RwLock<ExperimentalConfig>>);
+
+impl Predicate for CompressionEnabled {
+    fn should_compress<B>(&self, _response: &axum::http::Response<B>) -> bool
+    where
+        B: axum::body::HttpBody,
+    {
+        self.0
+            .try_read()
+            .map(|config| config.response_compression)
+            .unwrap_or(false)
+    }
+}
+
+/// 构造响应压缩层: 开关 + 最小体积 + 排除 SSE / gRPC / 图片
+pub fn compression_layer(
+    experimental: Arc<RwLock<ExperimentalConfig>>,
+) -> CompressionLayer<impl Predicate> {
+    let predicate = CompressionEnabled(experimental)
+        .and(SizeAbove::new(MIN_COMPRESS_BYTES))
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES);
+    CompressionLayer::new().compress_when(predicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body, extract::Request, http::header, response::Response, routing::get, Json, Router,
+    };
+    use tower::ServiceExt;
+
+    fn large_json() -> serde_json::Value {
+        let data: Vec<_> = (0..200)
+            .map(|i| serde_json::json!({ "id": format!("model-{}", i), "object": "model" }))
+            .collect();
+        serde_json::json!({ "object": "list", "data": data })
+    }
+
+    fn app(experimental: Arc<RwLock<ExperimentalConfig>>) -> Router {
+        Router::new()
+            .route("/v1/models", get(|| async { Json(large_json()) }))
+            .route(
+                "/v1/stream",
+                get(|| async {
+                    Response::builder()
+                        .header(header::CONTENT_TYPE, "text/event-stream")
+                        .body(Body::from("data: {}\n\n".repeat(500)))
+                        .unwrap()
+                }),
+            )
+            .route(
+                "/v1/small",
+                get(|| async { Json(serde_json::json!({ "ok": true })) }),
+            )
+            .layer(compression_layer(experimental))
+    }
+
+    fn request(uri: &str, accept_encoding: &str) -> Request {
+        Request::builder()
+            .uri(uri)
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn body_bytes(resp: Response) -> Vec<u8> {
+        axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    /// 按 Content-Encoding 解码响应体
+    fn decode(encoding: &str, body: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+        let mut decoded = Vec::new();
+        match encoding {
+            "gzip" => flate2::read::GzDecoder::new(body).read_to_end(&mut decoded),
+            "deflate" => flate2::read::ZlibDecoder::new(body).read_to_end(&mut decoded),
+            "br" => brotli::Decompressor::new(body, 4096).read_to_end(&mut decoded),
+            other => panic!("unexpected content-encoding: {}", other),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[tokio::test]
+    async fn test_json_compressed_and_sse_passthrough() {
+        let experimental = Arc::new(RwLock::new(ExperimentalConfig {
+            response_compression: true,
+            ..Default::default()
+        }));
+        let plain = serde_json::to_vec(&large_json()).unwrap();
+
+        // 同权重时优先 br，q 值参与协商
+        for (accept, expected) in [
+            ("gzip, deflate, br", "br"),
+            ("br;q=0.5, gzip", "gzip"),
+            ("deflate", "deflate"),
+        ] {
+            let resp = app(experimental.clone())
+                .oneshot(request("/v1/models", accept))
+                .await
+                .unwrap();
+            assert_eq!(resp.headers()[header::CONTENT_ENCODING], expected);
+            let body = body_bytes(resp).await;
+            assert!(body.len() < plain.len());
+            // 解码后与原始响应逐字节一致
+            assert_eq!(decode(expected, &body), plain, "{}", expected);
+        }
+
+        // SSE 与过小的响应不压缩
+        let resp = app(experimental.clone())
+            .oneshot(request("/v1/stream", "gzip"))
+            .await
+            .unwrap();
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_bytes(resp).await, "data: {}\n\n".repeat(500).into_bytes());
+        let resp = app(experimental.clone())
+            .oneshot(request("/v1/small", "gzip"))
+            .await
+            .unwrap();
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_bytes(resp).await, br#"{"ok":true}"#);
+
+        // 关闭后 (热更新) 原样返回
+        experimental.write().await.response_compression = false;
+        let resp = app(experimental)
+            .oneshot(request("/v1/models", "gzip"))
+            .await
+            .unwrap();
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_bytes(resp).await, plain);
+    }
+}
@@ -0,0 +1,307 @@
+// 相同请求并发去重 (single-flight)
+// 客户端网络抖动重试时可能并发发出完全相同的非流式请求；开启 experimental.enable_request_dedup 后
+// 后到的请求等待并共享首个请求的响应，避免重复消耗上游配额。流式请求无法共享，直接放行
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
+
+use crate::proxy::server::AppState;
+
+/// 共享响应时附加的响应头
+pub const DEDUP_SHARED_HEADER: &str = "X-Dedup-Shared";
+
+/// 可在多个等待者之间共享的完整响应
+#[derive(Clone)]
+pub struct SharedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl SharedResponse {
+    fn into_response(self, shared: bool) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        if shared {
+            response
+                .headers_mut()
+                .insert(DEDUP_SHARED_HEADER, HeaderValue::from_static("true"));
+        }
+        response
+    }
+}
+
+/// 以请求哈希为 key 的 single-flight 表
+#[derive(Default)]
+pub struct RequestDeduplicator {
+    inflight: Mutex<HashMap<String, broadcast::Sender<SharedResponse>>>,
+}
+
+impl RequestDeduplicator {
+    /// 同一 key 仅执行一次 fut，期间到达的相同请求等待其结果；返回 (响应, 是否为共享结果)
+    /// 首个请求被取消 (如客户端断开) 时，等待者各自执行自身请求
+    pub async fn run<F>(&self, key: String, fut: F) -> (SharedResponse, bool)
+    where
+        F: Future<Output = SharedResponse>,
+    {
+        let waiter = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(tx) => Some(tx.subscribe()),
+                None => {
+                    inflight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+
+        if let Some(mut rx) = waiter {
+            if let Ok(result) = rx.recv().await {
+                return (result, true);
+            }
+            tracing::debug!("[Dedup] Leader request dropped, executing duplicate request itself");
+            return (fut.await, false);
+        }
+
+        // 首个请求: 无论正常完成还是被取消都移除 key (仅移除一次)
+        let guard = InflightGuard {
+            dedup: self,
+            key: &key,
+            removed: false,
+        };
+        let result = fut.await;
+        if let Some(tx) = guard.take() {
+            let _ = tx.send(result.clone());
+        }
+        (result, false)
+    }
+
+    pub fn inflight_count(&self) -> usize {
+        self.inflight.lock().map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+struct InflightGuard<'a> {
+    dedup: &'a RequestDeduplicator,
+    key: &'a str,
+    removed: bool,
+}
+
+impl InflightGuard<'_> {
+    /// 取出 sender 并标记已移除，Drop 时不再重复移除 (否则可能误删同 key 的新一轮请求)
+    fn take(mut self) -> Option<broadcast::Sender<SharedResponse>> {
+        self.removed = true;
+        self.dedup.inflight.lock().ok()?.remove(self.key)
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        if let Ok(mut inflight) = self.dedup.inflight.lock() {
+            inflight.remove(self.key);
+        }
+    }
+}
+
+/// 参与请求哈希的请求头: 客户端凭证、指定账号与语言 (任一不同的请求不会共享结果)
+const KEY_HEADERS: [&str; 6] = [
+    "authorization",
+    "x-api-key",
+    "x-goog-api-key",
+    "x-account-override",
+    "accept-language",
+    "content-language",
+];
+
+/// 请求哈希: 路径 (含查询参数) + 关键请求头 + 请求体
+pub fn request_key(path: &str, headers: &HeaderMap, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_bytes());
+    for name in KEY_HEADERS {
+        hasher.update([0u8]);
+        if let Some(v) = headers.get(name) {
+            hasher.update(v.as_bytes());
+        }
+    }
+    hasher.update([0u8]);
+    hasher.update(body);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn is_stream_request(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("stream").and_then(|s| s.as_bool()))
+        .unwrap_or(false)
+}
+
+pub async fn dedup_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.experimental.read().await.enable_request_dedup {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(b) => b,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+    if is_stream_request(&bytes) {
+        return next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    }
+
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_else(|| parts.uri.path());
+    let key = request_key(path, &parts.headers, &bytes);
+    let dedup = state.request_dedup.clone();
+    let (result, shared) = dedup
+        .run(key, async move {
+            let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+            let (parts, body) = response.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX)
+                .await
+                .unwrap_or_default();
+            SharedResponse {
+                status: parts.status,
+                headers: parts.headers,
+                body,
+            }
+        })
+        .await;
+    if shared {
+        tracing::info!("[Dedup] Shared in-flight response with identical concurrent request");
+    }
+    result.into_response(shared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn ok_response(text: &'static str) -> SharedResponse {
+        SharedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(text.as_bytes()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_concurrent_requests_share_one_call() {
+        let dedup = Arc::new(RequestDeduplicator::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let run = |key: &str| {
+            let dedup = dedup.clone();
+            let calls = calls.clone();
+            let key = key.to_string();
+            tokio::spawn(async move {
+                dedup
+                    .run(key, async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        ok_response("answer")
+                    })
+                    .await
+            })
+        };
+
+        let first = run("same");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let second = run("same");
+        let other = run("other");
+
+        let (a, a_shared) = first.await.unwrap();
+        let (b, b_shared) = second.await.unwrap();
+        let (_, other_shared) = other.await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(!a_shared && b_shared && !other_shared);
+        assert_eq!(a.body, b.body);
+        assert_eq!(dedup.inflight_count(), 0);
+
+        // 首个请求被取消: 等待者自行执行
+        let dedup = Arc::new(RequestDeduplicator::default());
+        let leader = {
+            let dedup = dedup.clone();
+            tokio::spawn(async move {
+                dedup
+                    .run("k".to_string(), async {
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                        ok_response("never")
+                    })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let follower = {
+            let dedup = dedup.clone();
+            tokio::spawn(async move {
+                dedup
+                    .run("k".to_string(), async { ok_response("own") })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+        let (resp, shared) = follower.await.unwrap();
+        assert!(!shared);
+        assert_eq!(&resp.body[..], b"own");
+        assert_eq!(dedup.inflight_count(), 0);
+    }
+
+    #[test]
+    fn test_request_key_and_stream_detection() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer a"));
+        let body = br#"{"model":"gpt-4o","messages":[]}"#;
+        let key_a = request_key("/v1/chat/completions", &headers, body);
+        assert_eq!(key_a, request_key("/v1/chat/completions", &headers, body));
+
+        // 不同凭证不共享
+        headers.insert("authorization", HeaderValue::from_static("Bearer b"));
+        assert_ne!(key_a, request_key("/v1/chat/completions", &headers, body));
+
+        // 指定账号或语言不同也不共享
+        headers.insert("authorization", HeaderValue::from_static("Bearer a"));
+        headers.insert("x-account-override", HeaderValue::from_static("b@test.com"));
+        let key_override = request_key("/v1/chat/completions", &headers, body);
+        assert_ne!(key_a, key_override);
+        headers.insert("accept-language", HeaderValue::from_static("ja-JP"));
+        assert_ne!(key_override, request_key("/v1/chat/completions", &headers, body));
+
+        assert!(is_stream_request(br#"{"stream":true}"#));
+        assert!(!is_stream_request(body));
+    }
+}
@@ -0,0 +1,421 @@
+// 客户端幂等键 (Idempotency-Key)
+// 携带相同 Idempotency-Key 且请求内容一致的重复提交，在 experimental.idempotency_ttl_secs 窗口内
+// 直接返回首次请求的结果；并发到达的重复提交等待首个请求完成 (复用 dedup 的 single-flight)
+// 仅缓存 2xx 非流式响应，失败的请求可以用同一个键重试
+// 作用于非流式对话 / completions / messages 以及图像生成与编辑接口 (避免客户端重试导致重复出图)
+// 默认关闭；缓存按条数与响应体总字节数做 LRU 淘汰，请求体读取受 openai_compat.max_request_body_bytes 限制
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+use super::body_limit::payload_too_large;
+use super::dedup::{is_stream_request, request_key, RequestDeduplicator, SharedResponse};
+use crate::proxy::config::get_openai_compat_config;
+use crate::proxy::server::AppState;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// 返回缓存结果时附加的响应头
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "Idempotent-Replayed";
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// 缓存容量限制 (来自 experimental.idempotency_*)
+#[derive(Debug, Clone, Copy)]
+pub struct IdempotencyLimits {
+    pub ttl: Duration,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+}
+
+struct CachedEntry {
+    stored_at: Instant,
+    last_used: Instant,
+    response: SharedResponse,
+}
+
+#[derive(Default)]
+struct CompletedResponses {
+    entries: HashMap<String, CachedEntry>,
+    total_bytes: usize,
+}
+
+impl CompletedResponses {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_bytes -= entry.response.body.len();
+        }
+    }
+
+    fn purge_expired(&mut self, ttl: Duration) {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.stored_at.elapsed() >= ttl)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+
+    /// 淘汰最久未使用的条目
+    fn evict_lru(&mut self) -> bool {
+        let Some(key) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(k, _)| k.clone())
+        else {
+            return false;
+        };
+        self.remove(&key);
+        true
+    }
+}
+
+#[derive(Default)]
+pub struct IdempotencyCache {
+    inflight: RequestDeduplicator,
+    completed: Mutex<CompletedResponses>,
+}
+
+impl IdempotencyCache {
+    fn get(&self, key: &str, ttl: Duration) -> Option<SharedResponse> {
+        let mut completed = self.completed.lock().ok()?;
+        let entry = completed.entries.get_mut(key)?;
+        if entry.stored_at.elapsed() >= ttl {
+            completed.remove(key);
+            return None;
+        }
+        entry.last_used = Instant::now();
+        Some(entry.response.clone())
+    }
+
+    fn insert(&self, key: String, response: SharedResponse, limits: IdempotencyLimits) {
+        let size = response.body.len();
+        if limits.max_entries == 0 || size > limits.max_bytes {
+            tracing::debug!(
+                "[Idempotency] Response of {} bytes exceeds cache limit, not cached",
+                size
+            );
+            return;
+        }
+        let Ok(mut completed) = self.completed.lock() else {
+            return;
+        };
+        // 写入时顺带清理过期条目，再按条数 / 字节数淘汰
+        completed.purge_expired(limits.ttl);
+        completed.remove(&key);
+        while completed.entries.len() >= limits.max_entries
+            || completed.total_bytes + size > limits.max_bytes
+        {
+            if !completed.evict_lru() {
+                break;
+            }
+        }
+        let now = Instant::now();
+        completed.total_bytes += size;
+        completed.entries.insert(
+            key,
+            CachedEntry {
+                stored_at: now,
+                last_used: now,
+                response,
+            },
+        );
+    }
+
+    /// 命中缓存或共享并发请求的结果时返回 (响应, true)
+    pub async fn run<F>(
+        &self,
+        key: String,
+        limits: IdempotencyLimits,
+        fut: F,
+    ) -> (SharedResponse, bool)
+    where
+        F: Future<Output = SharedResponse>,
+    {
+        if let Some(cached) = self.get(&key, limits.ttl) {
+            return (cached, true);
+        }
+        let (response, shared) = self.inflight.run(key.clone(), fut).await;
+        if !shared && response.status.is_success() {
+            self.insert(key, response.clone(), limits);
+        }
+        (response, shared)
+    }
+
+    pub fn cached_count(&self) -> usize {
+        self.completed.lock().map(|m| m.entries.len()).unwrap_or(0)
+    }
+
+    pub fn cached_bytes(&self) -> usize {
+        self.completed.lock().map(|m| m.total_bytes).unwrap_or(0)
+    }
+}
+
+pub async fn idempotency_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limits = {
+        let experimental = state.experimental.read().await;
+        IdempotencyLimits {
+            ttl: Duration::from_secs(experimental.idempotency_ttl_secs),
+            max_entries: experimental.idempotency_max_entries,
+            max_bytes: experimental.idempotency_max_bytes,
+        }
+    };
+    let idempotency_key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_IDEMPOTENCY_KEY_LEN);
+    let Some(idempotency_key) = idempotency_key.filter(|_| !limits.ttl.is_zero()) else {
+        return next.run(request).await;
+    };
+
+    // 请求体需整体读取以计算内容哈希，受与 body_limit 相同的上限约束 (/v1/messages 与图像接口没有单独的限制层)
+    let body_limit = match get_openai_compat_config().max_request_body_bytes {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, body_limit).await {
+        Ok(b) => b,
+        Err(_) => return payload_too_large(body_limit),
+    };
+    // 流式响应无法缓存，直接放行
+    if is_stream_request(&bytes) {
+        return next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await;
+    }
+
+    // 同一个键配合不同内容视为不同请求
+    let key = format!(
+        "{}:{}",
+        idempotency_key,
+        request_key(parts.uri.path(), &parts.headers, &bytes)
+    );
+    let cache = state.idempotency.clone();
+    let (result, replayed) = cache
+        .run(key, limits, async move {
+            let response = next
+                .run(Request::from_parts(parts, Body::from(bytes)))
+                .await;
+            let (parts, body) = response.into_parts();
+            match axum::body::to_bytes(body, body_limit).await {
+                Ok(body) => SharedResponse {
+                    status: parts.status,
+                    headers: parts.headers,
+                    body,
+                },
+                Err(e) => SharedResponse {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    headers: Default::default(),
+                    body: format!("Failed to buffer response: {}", e).into(),
+                },
+            }
+        })
+        .await;
+    if replayed {
+        tracing::info!(
+            "[Idempotency] Replayed prior result for Idempotency-Key {}",
+            idempotency_key
+        );
+    }
+
+    let mut response = Response::new(Body::from(result.body));
+    *response.status_mut() = result.status;
+    *response.headers_mut() = result.headers;
+    if replayed {
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAY_HEADER, HeaderValue::from_static("true"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::proxy_state;
+    use axum::body::Bytes;
+    use axum::http::HeaderMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn response(status: StatusCode, text: &'static str) -> SharedResponse {
+        SharedResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(text.as_bytes()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeated_image_generation_calls_upstream_once() {
+        use axum::{routing::post, Json, Router};
+        use std::sync::Arc;
+        use tower::ServiceExt;
+
+        // 模拟上游: 统计调用次数并返回一张图片
+        let upstream_calls = Arc::new(AtomicUsize::new(0));
+        let counter = upstream_calls.clone();
+        let upstream_app = Router::new().fallback(move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Json(serde_json::json!({
+                    "response": { "candidates": [{ "content": { "parts": [{ "inlineData": {
+                        "mimeType": "image/png",
+                        "data": "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg=="
+                    }}]}}]}
+                }))
+            }
+        });
+        let (state, _pool) = proxy_state(upstream_app, &[("acc1", "a@test.com")]).await;
+        // 默认关闭，测试中显式开启
+        state.experimental.write().await.idempotency_ttl_secs = 600;
+        let app = Router::new()
+            .route(
+                "/v1/images/generations",
+                post(crate::proxy::handlers::openai::handle_images_generations),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                idempotency_middleware,
+            ))
+            .with_state(state);
+        let request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/v1/images/generations")
+                .header("content-type", "application/json")
+                .header(IDEMPOTENCY_KEY_HEADER, "img-key-1")
+                .body(Body::from(r#"{"prompt":"a red apple","n":1}"#))
+                .unwrap()
+        };
+
+        let first = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert!(first.headers().get(IDEMPOTENT_REPLAY_HEADER).is_none());
+        let first_body = axum::body::to_bytes(first.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let second = app.oneshot(request()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(second.headers()[IDEMPOTENT_REPLAY_HEADER], "true");
+        let second_body = axum::body::to_bytes(second.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(first_body, second_body);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_submission_returns_cached_result() {
+        let cache = IdempotencyCache::default();
+        let calls = AtomicUsize::new(0);
+        let limits = IdempotencyLimits {
+            ttl: Duration::from_secs(60),
+            max_entries: 16,
+            max_bytes: 1024,
+        };
+        let call = |text: &'static str| {
+            let calls = &calls;
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                response(StatusCode::OK, text)
+            }
+        };
+
+        let (first, replayed) = cache
+            .run("key-1:hash".to_string(), limits, call("first"))
+            .await;
+        assert!(!replayed);
+        let (second, replayed) = cache
+            .run("key-1:hash".to_string(), limits, call("second"))
+            .await;
+        assert!(replayed);
+        assert_eq!(second.body, first.body);
+
+        // 不同的键 (或不同内容) 正常执行
+        let (other, replayed) = cache
+            .run("key-2:hash".to_string(), limits, call("other"))
+            .await;
+        assert!(!replayed);
+        assert_eq!(&other.body[..], b"other");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // 失败的响应不缓存，可用同一个键重试
+        let (_, _) = cache
+            .run("key-3:hash".to_string(), limits, async {
+                response(StatusCode::SERVICE_UNAVAILABLE, "busy")
+            })
+            .await;
+        let (retry, replayed) = cache
+            .run("key-3:hash".to_string(), limits, call("ok"))
+            .await;
+        assert!(!replayed);
+        assert_eq!(retry.status, StatusCode::OK);
+
+        // 超过 TTL 后重新执行
+        let (expired, replayed) = cache
+            .run(
+                "key-1:hash".to_string(),
+                IdempotencyLimits {
+                    ttl: Duration::ZERO,
+                    ..limits
+                },
+                call("fresh"),
+            )
+            .await;
+        assert!(!replayed);
+        assert_eq!(&expired.body[..], b"fresh");
+        assert_eq!(cache.cached_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_evicts_least_recently_used_entries() {
+        let cache = IdempotencyCache::default();
+        let limits = IdempotencyLimits {
+            ttl: Duration::from_secs(60),
+            max_entries: 2,
+            max_bytes: 10,
+        };
+        let ok = |text: &'static str| async move { response(StatusCode::OK, text) };
+
+        cache.run("a".to_string(), limits, ok("aaaa")).await;
+        cache.run("b".to_string(), limits, ok("bbbb")).await;
+        // 访问 a 后 b 成为最久未使用
+        assert!(cache.run("a".to_string(), limits, ok("x")).await.1);
+        cache.run("c".to_string(), limits, ok("cccc")).await;
+        assert_eq!(cache.cached_count(), 2);
+        assert!(cache.run("a".to_string(), limits, ok("x")).await.1);
+        assert!(!cache.run("b".to_string(), limits, ok("bbbb")).await.1);
+
+        // 字节上限: 写入 6 字节需要淘汰两个 4 字节条目中的一个
+        cache.run("d".to_string(), limits, ok("dddddd")).await;
+        assert!(cache.cached_bytes() <= limits.max_bytes);
+        assert_eq!(cache.cached_count(), 2);
+
+        // 单个响应超过字节上限时不缓存
+        let (_, replayed) = cache.run("e".to_string(), limits, ok("eeeeeeeeeeee")).await;
+        assert!(!replayed);
+        assert!(!cache.run("e".to_string(), limits, ok("e")).await.1);
+    }
+}
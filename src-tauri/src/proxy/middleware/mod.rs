@@ -1,15 +1,29 @@
 // Middleware 模块 - Axum 中间件
 
+pub mod access_log;
+pub mod compression;
+pub mod body_limit;
 pub mod auth;
 pub mod cors;
 pub mod logging;
 pub mod monitor;
 pub mod ip_filter;
+pub mod request_span;
+pub mod dedup;
+pub mod idempotency;
+pub mod upstream_latency;
 
 pub mod service_status;
 
+pub use access_log::access_log_middleware;
+pub use compression::compression_layer;
+pub use body_limit::openai_body_limit_middleware;
 pub use cors::cors_layer;
 pub use monitor::monitor_middleware;
 pub use service_status::service_status_middleware;
 pub use auth::{auth_middleware, admin_auth_middleware};
 pub use ip_filter::ip_filter_middleware;
+pub use request_span::request_span_middleware;
+pub use dedup::dedup_middleware;
+pub use idempotency::idempotency_middleware;
+pub use upstream_latency::upstream_latency_middleware;
@@ -14,6 +14,82 @@ use futures::StreamExt;
 const MAX_REQUEST_LOG_SIZE: usize = 100 * 1024 * 1024; // 100MB
 const MAX_RESPONSE_LOG_SIZE: usize = 100 * 1024 * 1024; // 100MB for image responses
 
+/// 累计账号窗口用量 (响应未携带 usage 时只计请求数)
+fn record_account_usage(token_manager: &crate::proxy::TokenManager, log: &ProxyRequestLog) {
+    if let Some(email) = &log.account_email {
+        token_manager.record_account_usage(
+            email,
+            log.input_tokens.unwrap_or(0),
+            log.output_tokens.unwrap_or(0),
+        );
+    }
+}
+
+/// 流式响应转发结果: 完整数据 (用于日志) / 末尾 8KB (用于兜底提取 usage)
+struct StreamCapture {
+    data: Vec<u8>,
+    tail: Vec<u8>,
+    client_disconnected: bool,
+}
+
+/// 将响应流转发给客户端并保留副本
+/// [FIX] 客户端断开 (接收端被丢弃) 时立即停止并丢弃上游流，中止上游请求以节省配额
+async fn forward_stream<S, E>(
+    stream: S,
+    tx: tokio::sync::mpsc::Sender<Result<bytes::Bytes, axum::Error>>,
+) -> StreamCapture
+where
+    S: futures::Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+    E: Into<axum::BoxError>,
+{
+    let mut stream = stream;
+    let mut capture = StreamCapture {
+        data: Vec::new(),
+        tail: Vec::new(),
+        client_disconnected: false,
+    };
+
+    loop {
+        let chunk_res = tokio::select! {
+            item = stream.next() => match item {
+                Some(item) => item,
+                None => break,
+            },
+            _ = tx.closed() => {
+                capture.client_disconnected = true;
+                break;
+            }
+        };
+        let sent = match chunk_res {
+            Ok(chunk) => {
+                capture.data.extend_from_slice(&chunk);
+                if chunk.len() > 8192 {
+                    capture.tail = chunk.slice(chunk.len() - 8192..).to_vec();
+                } else {
+                    capture.tail.extend_from_slice(&chunk);
+                    if capture.tail.len() > 8192 {
+                        capture.tail.drain(0..capture.tail.len() - 8192);
+                    }
+                }
+                tx.send(Ok(chunk)).await
+            }
+            Err(e) => tx.send(Err(axum::Error::new(e))).await,
+        };
+        if sent.is_err() {
+            capture.client_disconnected = true;
+            break;
+        }
+    }
+
+    if capture.client_disconnected {
+        tracing::info!(
+            "[Monitor] Client disconnected mid-stream after {} bytes, aborting upstream",
+            capture.data.len()
+        );
+    }
+    capture
+}
+
 /// Helper function to record User Token usage
 fn record_user_token_usage(
     user_token_identity: &Option<UserTokenIdentity>,
@@ -95,6 +171,9 @@ pub async fn monitor_middleware(
                         v.get("model").and_then(|m| m.as_str()).map(|s| s.to_string())
                     );
                 }
+                if let Some(m) = &model {
+                    super::access_log::record_requested_model(m);
+                }
                 request_body_str = if let Ok(s) = std::str::from_utf8(&bytes) {
                     Some(s.to_string())
                 } else {
@@ -155,6 +234,7 @@ pub async fn monitor_middleware(
     let username = user_token_identity.as_ref().map(|identity| identity.username.clone());
 
     let monitor = state.monitor.clone();
+    let token_manager = state.token_manager.clone();
     let mut log = ProxyRequestLog {
         id: uuid::Uuid::new_v4().to_string(),
         timestamp: chrono::Utc::now().timestamp_millis(),
@@ -178,30 +258,15 @@ pub async fn monitor_middleware(
 
     if content_type.contains("text/event-stream") {
         let (parts, body) = response.into_parts();
-        let mut stream = body.into_data_stream();
+        let stream = body.into_data_stream();
         let (tx, rx) = tokio::sync::mpsc::channel(64);
         
         tokio::spawn(async move {
-            let mut all_stream_data = Vec::new();
-            let mut last_few_bytes = Vec::new();
-            
-            while let Some(chunk_res) = stream.next().await {
-                if let Ok(chunk) = chunk_res {
-                    all_stream_data.extend_from_slice(&chunk);
-                    
-                    if chunk.len() > 8192 {
-                        last_few_bytes = chunk.slice(chunk.len()-8192..).to_vec();
-                    } else {
-                        last_few_bytes.extend_from_slice(&chunk);
-                        if last_few_bytes.len() > 8192 {
-                            last_few_bytes.drain(0..last_few_bytes.len()-8192);
-                        }
-                    }
-                    let _ = tx.send(Ok::<_, axum::Error>(chunk)).await;
-                } else if let Err(e) = chunk_res {
-                    let _ = tx.send(Err(axum::Error::new(e))).await;
-                }
-            }
+            let StreamCapture {
+                data: all_stream_data,
+                tail: last_few_bytes,
+                client_disconnected,
+            } = forward_stream(stream, tx).await;
             
             // Parse and consolidate stream data into readable format
             if let Ok(full_response) = std::str::from_utf8(&all_stream_data) {
@@ -428,10 +493,13 @@ pub async fn monitor_middleware(
             
             if log.status >= 400 {
                 log.error = Some("Stream Error or Failed".to_string());
+            } else if client_disconnected {
+                log.error = Some("Client disconnected".to_string());
             }
 
             // Record User Token Usage
             record_user_token_usage(&user_token_identity, &log, user_agent.clone());
+            record_account_usage(&token_manager, &log);
 
             monitor.log_request(log).await;
         });
@@ -475,6 +543,7 @@ pub async fn monitor_middleware(
 
                 // Record User Token Usage
                 record_user_token_usage(&user_token_identity, &log, user_agent.clone());
+                record_account_usage(&token_manager, &log);
 
                 monitor.log_request(log).await;
                 Response::from_parts(parts, Body::from(bytes))
@@ -484,6 +553,7 @@ pub async fn monitor_middleware(
 
                 // Record User Token Usage (even if too large)
                 record_user_token_usage(&user_token_identity, &log, user_agent.clone());
+                record_account_usage(&token_manager, &log);
 
                 monitor.log_request(log).await;
                 Response::from_parts(parts, Body::empty())
@@ -494,8 +564,59 @@ pub async fn monitor_middleware(
 
         // Record User Token Usage
         record_user_token_usage(&user_token_identity, &log, user_agent);
+        record_account_usage(&token_manager, &log);
 
         monitor.log_request(log).await;
         response
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// 被丢弃时置位的上游流
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_drops_upstream_stream() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(dropped.clone());
+        // 首个分片后上游永远挂起 (模拟仍在生成的长响应)
+        let upstream = futures::stream::once(async {
+            Ok::<_, std::io::Error>(Bytes::from_static(b"data: hi\n\n"))
+        })
+        .chain(futures::stream::pending())
+        .map(move |item| {
+            let _keep = &flag;
+            item
+        });
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        let task = tokio::spawn(forward_stream(Box::pin(upstream), tx));
+
+        let first = rx.recv().await.unwrap().unwrap();
+        assert_eq!(&first[..], b"data: hi\n\n");
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        // 客户端断开
+        drop(rx);
+        let capture = tokio::time::timeout(Duration::from_secs(2), task)
+            .await
+            .expect("forwarding should stop once the client is gone")
+            .unwrap();
+        assert!(capture.client_disconnected);
+        assert_eq!(capture.data, b"data: hi\n\n");
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
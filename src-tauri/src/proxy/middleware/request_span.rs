@@ -0,0 +1,220 @@
+// 请求关联 Span 中间件
+// 为每个 AI 请求创建 info_span，请求内所有日志自动携带 trace_id 等结构化字段
+// (JSON 日志模式下以字段形式输出，默认人类可读格式不受影响)
+// trace_id 优先沿用客户端的 X-Request-Id，并在所有响应 (包括错误响应) 中回显
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+use tracing::{field, Instrument, Span};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// 生成请求关联 ID
+pub fn generate_trace_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn client_request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(REQUEST_ID_HEADER)?
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+        })
+        .map(str::to_string)
+}
+
+/// 当前请求的关联 ID: 经过 request_span_middleware 时即为中间件写入的 X-Request-Id，否则生成新的
+pub fn request_id(headers: &HeaderMap) -> String {
+    client_request_id(headers).unwrap_or_else(generate_trace_id)
+}
+
+pub async fn request_span_middleware(mut request: Request, next: Next) -> Response {
+    let request_id = request_id(request.headers());
+    let header_value = HeaderValue::from_str(&request_id).ok();
+    if let Some(value) = &header_value {
+        // 写回请求头，handler 通过 request_id() 读取同一个 ID
+        request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+    }
+    let span = tracing::info_span!(
+        "request",
+        trace_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        account_email = field::Empty,
+        mapped_model = field::Empty,
+        attempt = field::Empty,
+        status_code = field::Empty,
+    );
+
+    let mut response = next.run(request).instrument(span.clone()).await;
+    if let Some(value) = header_value {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    span.record("status_code", response.status().as_u16());
+    span.in_scope(|| tracing::debug!("Request completed"));
+    response
+}
+
+/// 将当前重试的账号 / 模型 / 次数记录到请求 span
+/// 在 request_span_middleware 之外调用时为空操作
+pub fn record_attempt(attempt: usize, account_email: &str, mapped_model: &str) {
+    let span = Span::current();
+    span.record("attempt", attempt);
+    span.record("account_email", account_email);
+    span.record("mapped_model", mapped_model);
+    super::access_log::record_upstream_attempt(attempt, account_email, mapped_model);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    #[derive(Clone, Default)]
+    struct CaptureWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_request_span_fields_attached_to_json_logs() {
+        let writer = CaptureWriter::default();
+        let make_writer = {
+            let writer = writer.clone();
+            move || writer.clone()
+        };
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(make_writer)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/v1/chat/completions",
+                get(|| async {
+                    record_attempt(1, "a@test.com", "gemini-2.5-flash");
+                    tracing::info!("inside handler");
+                    "ok"
+                }),
+            )
+            .layer(axum::middleware::from_fn(request_span_middleware));
+
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/chat/completions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        let handler_line = lines
+            .iter()
+            .find(|l| l["fields"]["message"] == "inside handler")
+            .expect("handler log line");
+        let span = &handler_line["span"];
+        assert_eq!(span["name"], "request");
+        assert_eq!(span["trace_id"].as_str().unwrap().len(), 12);
+        assert_eq!(span["account_email"], "a@test.com");
+        assert_eq!(span["mapped_model"], "gemini-2.5-flash");
+        assert_eq!(span["attempt"], 1);
+
+        let done_line = lines
+            .iter()
+            .find(|l| l["fields"]["message"] == "Request completed")
+            .expect("completion log line");
+        assert_eq!(done_line["span"]["status_code"], 200);
+        assert_eq!(done_line["span"]["trace_id"], span["trace_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_echoed_on_every_response() {
+        use axum::http::StatusCode;
+
+        let app = Router::new()
+            .route(
+                "/v1/chat/completions",
+                get(|headers: HeaderMap| async move {
+                    (StatusCode::BAD_GATEWAY, request_id(&headers))
+                }),
+            )
+            .layer(axum::middleware::from_fn(request_span_middleware));
+
+        // 客户端提供的 ID 原样回显，handler 看到同一个 ID
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/chat/completions")
+                    .header("X-Request-Id", "client-req-42")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()["x-request-id"], "client-req-42");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"client-req-42");
+
+        // 未提供或格式非法时生成新的 ID
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let resp = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/v1/chat/completions")
+                        .header("X-Request-Id", "bad id\twith spaces")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let id = resp.headers()["x-request-id"].to_str().unwrap().to_string();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(body, id.as_bytes());
+            assert_eq!(id.len(), 12);
+            ids.push(id);
+        }
+        assert_ne!(ids[0], ids[1]);
+    }
+}
@@ -14,7 +14,12 @@ pub async fn service_status_middleware(
     let path = request.uri().path();
     
     // Always allow Admin API and Auth callback
-    if path.starts_with("/api/") || path == "/auth/callback" || path == "/health" {
+    // /healthz 为存活检查，代理服务停用时同样返回 200 (/readyz 则随服务状态返回 503)
+    if path.starts_with("/api/")
+        || path == "/auth/callback"
+        || path == "/health"
+        || path == "/healthz"
+    {
         return next.run(request).await;
     }
 
@@ -0,0 +1,127 @@
+// 上游延迟响应头中间件
+// experimental.expose_upstream_latency 开启时为响应附加 X-Upstream-Latency-Ms:
+// 最终被采用的那次上游调用 (call_v1_internal) 从发出请求到收到响应头的耗时，
+// 不含账号轮换 / 端点降级 / 重试等待的开销。流式响应只计算到响应头到达为止
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use tokio::sync::RwLock;
+
+use crate::proxy::config::ExperimentalConfig;
+
+pub const UPSTREAM_LATENCY_HEADER: &str = "X-Upstream-Latency-Ms";
+
+// u64::MAX 表示本次请求未调用上游
+const UNSET: u64 = u64::MAX;
+
+tokio::task_local! {
+    static UPSTREAM_LATENCY_MS: Arc<AtomicU64>;
+}
+
+/// 记录一次上游调用的耗时，后一次覆盖前一次 (即最终采用的那次尝试)
+/// 在 upstream_latency_middleware 之外调用时为空操作
+pub fn record_upstream_latency(latency: Duration) {
+    let _ = UPSTREAM_LATENCY_MS.try_with(|slot| {
+        slot.store(latency.as_millis() as u64, Ordering::Relaxed);
+    });
+}
+
+pub async fn upstream_latency_middleware(
+    State(experimental): State<Arc<RwLock<ExperimentalConfig>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !experimental.read().await.expose_upstream_latency {
+        return next.run(request).await;
+    }
+
+    let slot = Arc::new(AtomicU64::new(UNSET));
+    let mut response = UPSTREAM_LATENCY_MS
+        .scope(slot.clone(), next.run(request))
+        .await;
+
+    let latency_ms = slot.load(Ordering::Relaxed);
+    if latency_ms != UNSET {
+        response
+            .headers_mut()
+            .insert(UPSTREAM_LATENCY_HEADER, HeaderValue::from(latency_ms));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tests::harness::{spawn_upstream, v1internal_url};
+    use crate::proxy::upstream::client::UpstreamClient;
+    use axum::{body::Body, routing::post, Json, Router};
+    use tower::ServiceExt;
+
+    const UPSTREAM_DELAY_MS: u64 = 150;
+
+    /// 启动一个固定延迟后返回的模拟上游
+    async fn spawn_slow_upstream() -> String {
+        let app = Router::new().fallback(|| async {
+            tokio::time::sleep(Duration::from_millis(UPSTREAM_DELAY_MS)).await;
+            Json(serde_json::json!({ "ok": true }))
+        });
+        v1internal_url(spawn_upstream(app).await)
+    }
+
+    fn app(upstream: Arc<UpstreamClient>, expose: bool) -> Router {
+        let experimental = Arc::new(RwLock::new(ExperimentalConfig {
+            expose_upstream_latency: expose,
+            ..Default::default()
+        }));
+        Router::new()
+            .route(
+                "/v1/chat/completions",
+                post(move || async move {
+                    // 模拟代理自身的开销，不应计入上游延迟
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    let result = upstream
+                        .call_v1_internal("generateContent", "token", serde_json::json!({}), None, None)
+                        .await
+                        .unwrap();
+                    result.response.text().await.unwrap()
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                experimental,
+                upstream_latency_middleware,
+            ))
+    }
+
+    fn request() -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upstream_latency_header_reflects_upstream_delay() {
+        let upstream = Arc::new(UpstreamClient::new(None, None));
+        upstream.set_base_urls(vec![spawn_slow_upstream().await]).await;
+
+        let resp = app(upstream.clone(), true).oneshot(request()).await.unwrap();
+        let latency: u64 = resp.headers()[UPSTREAM_LATENCY_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(latency >= UPSTREAM_DELAY_MS, "latency {}ms", latency);
+        assert!(latency < 300 + UPSTREAM_DELAY_MS, "latency {}ms", latency);
+
+        let resp = app(upstream, false).oneshot(request()).await.unwrap();
+        assert!(resp.headers().get(UPSTREAM_LATENCY_HEADER).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+// 提示词屏蔽规则
+// 将配置中的子串 / 正则规则编译为一个不区分大小写的 RegexSet，在转发上游前检查用户消息
+// 命中时只返回通用的拒绝信息，命中的规则 id 仅写入日志
+use axum::http::StatusCode;
+use regex::{Regex, RegexSetBuilder};
+use serde_json::Value;
+
+use crate::proxy::config::PromptFilterConfig;
+use crate::proxy::mappers::claude::models::{ClaudeRequest, ContentBlock, MessageContent};
+use crate::proxy::mappers::openai::{OpenAIContent, OpenAIContentBlock, OpenAIRequest};
+
+pub const REJECTED_MESSAGE: &str = "Request rejected by policy";
+
+pub struct PromptFilter {
+    rule_ids: Vec<String>,
+    set: Option<regex::RegexSet>,
+    status: StatusCode,
+}
+
+impl Default for PromptFilter {
+    fn default() -> Self {
+        Self {
+            rule_ids: Vec::new(),
+            set: None,
+            status: StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl PromptFilter {
+    /// 编译配置中的规则；无效的正则记录警告后跳过，不影响其它规则
+    pub fn from_config(config: &PromptFilterConfig) -> Self {
+        let status = match config.status_code {
+            403 => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        if !config.enabled {
+            return Self {
+                status,
+                ..Default::default()
+            };
+        }
+
+        let mut rule_ids = Vec::new();
+        let mut patterns = Vec::new();
+        for rule in config.rules.iter().filter(|r| !r.pattern.is_empty()) {
+            let pattern = if rule.regex {
+                if let Err(e) = Regex::new(&rule.pattern) {
+                    tracing::warn!("[Prompt-Filter] Skipping invalid rule {}: {}", rule.id, e);
+                    continue;
+                }
+                rule.pattern.clone()
+            } else {
+                regex::escape(&rule.pattern)
+            };
+            rule_ids.push(rule.id.clone());
+            patterns.push(pattern);
+        }
+
+        let set = if patterns.is_empty() {
+            None
+        } else {
+            match RegexSetBuilder::new(&patterns)
+                .case_insensitive(true)
+                .build()
+            {
+                Ok(set) => Some(set),
+                Err(e) => {
+                    tracing::warn!("[Prompt-Filter] Failed to compile rules: {}", e);
+                    None
+                }
+            }
+        };
+        Self {
+            rule_ids,
+            set,
+            status,
+        }
+    }
+
+    /// 返回第一条命中的规则 id
+    pub fn check(&self, text: &str) -> Option<&str> {
+        let set = self.set.as_ref()?;
+        set.matches(text)
+            .iter()
+            .next()
+            .map(|idx| self.rule_ids[idx].as_str())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// 拼接 OpenAI 请求中所有 user 消息的文本
+pub fn openai_user_text(request: &OpenAIRequest) -> String {
+    let mut parts = Vec::new();
+    for msg in request.messages.iter().filter(|m| m.role == "user") {
+        match &msg.content {
+            Some(OpenAIContent::String(text)) => parts.push(text.as_str()),
+            Some(OpenAIContent::Array(blocks)) => {
+                for block in blocks {
+                    if let OpenAIContentBlock::Text { text } = block {
+                        parts.push(text.as_str());
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+    parts.join("\n")
+}
+
+/// 拼接 Claude 请求中所有 user 消息的文本
+pub fn claude_user_text(request: &ClaudeRequest) -> String {
+    let mut parts = Vec::new();
+    for msg in request.messages.iter().filter(|m| m.role == "user") {
+        match &msg.content {
+            MessageContent::String(text) => parts.push(text.as_str()),
+            MessageContent::Array(blocks) => {
+                for block in blocks {
+                    if let ContentBlock::Text { text } = block {
+                        parts.push(text.as_str());
+                    }
+                }
+            }
+        }
+    }
+    parts.join("\n")
+}
+
+/// 拼接 Gemini 请求中所有 user 内容的文本 (兼容原生透传的 request 包装；省略 role 视为 user)
+pub fn gemini_user_text(body: &Value) -> String {
+    let contents = body
+        .get("contents")
+        .or_else(|| body.get("request").and_then(|r| r.get("contents")))
+        .and_then(|c| c.as_array());
+    let mut parts = Vec::new();
+    for content in contents.into_iter().flatten() {
+        let role = content
+            .get("role")
+            .and_then(|r| r.as_str())
+            .unwrap_or("user");
+        if role != "user" {
+            continue;
+        }
+        let texts = content
+            .get("parts")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()));
+        parts.extend(texts);
+    }
+    parts.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::PromptFilterRule;
+
+    fn filter() -> PromptFilter {
+        PromptFilter::from_config(&PromptFilterConfig {
+            enabled: true,
+            rules: vec![
+                PromptFilterRule {
+                    id: "no-secrets".to_string(),
+                    pattern: "internal password".to_string(),
+                    regex: false,
+                },
+                PromptFilterRule {
+                    id: "card-number".to_string(),
+                    pattern: r"\b\d{4}-\d{4}-\d{4}-\d{4}\b".to_string(),
+                    regex: true,
+                },
+                PromptFilterRule {
+                    id: "broken".to_string(),
+                    pattern: "(unclosed".to_string(),
+                    regex: true,
+                },
+            ],
+            status_code: 403,
+        })
+    }
+
+    #[test]
+    fn test_blocked_and_allowed_prompts() {
+        let filter = filter();
+        assert_eq!(filter.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            filter.check("What is the INTERNAL Password?"),
+            Some("no-secrets")
+        );
+        assert_eq!(
+            filter.check("charge 1234-5678-9012-3456 please"),
+            Some("card-number")
+        );
+        // 子串规则中的正则元字符按字面匹配，无效正则被跳过
+        assert_eq!(filter.check("(unclosed paren"), None);
+        assert_eq!(filter.check("Write a haiku about autumn"), None);
+
+        let disabled = PromptFilter::from_config(&PromptFilterConfig::default());
+        assert_eq!(disabled.check("internal password"), None);
+    }
+
+    #[test]
+    fn test_user_text_extraction_covers_normalized_inputs() {
+        let request: OpenAIRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "system", "content": "internal password is fine here" },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "first part" },
+                    { "type": "text", "text": "second part" }
+                ]}
+            ]
+        }))
+        .unwrap();
+        let text = openai_user_text(&request);
+        assert_eq!(text, "first part\nsecond part");
+        assert_eq!(filter().check(&text), None);
+
+        let claude: ClaudeRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "my Internal Password is hunter2" }]
+        }))
+        .unwrap();
+        assert_eq!(
+            filter().check(&claude_user_text(&claude)),
+            Some("no-secrets")
+        );
+
+        // Gemini: 原生 contents 与透传的 request 包装，model 轮次不参与匹配
+        let gemini = serde_json::json!({
+            "contents": [
+                { "role": "model", "parts": [{ "text": "internal password" }] },
+                { "parts": [{ "text": "hello" }, { "text": "my internal password" }] }
+            ]
+        });
+        assert_eq!(gemini_user_text(&gemini), "hello\nmy internal password");
+        let wrapped = serde_json::json!({ "model": "gemini-2.5-flash", "request": gemini });
+        assert_eq!(
+            filter().check(&gemini_user_text(&wrapped)),
+            Some("no-secrets")
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Responses API 本地存储
+// 客户端以 store: true 调用 /v1/responses 时保存最终响应对象，供 GET /v1/responses/{id} 轮询读取
+use axum::http::HeaderMap;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+const RESPONSE_TTL: Duration = Duration::from_secs(60 * 60);
+const RESPONSE_STORE_LIMIT: usize = 1000;
+
+#[derive(Clone, Debug)]
+struct StoredResponse {
+    response: Value,
+    owner: String,
+    timestamp: SystemTime,
+}
+
+impl StoredResponse {
+    fn is_expired(&self) -> bool {
+        self.timestamp.elapsed().unwrap_or(Duration::ZERO) > RESPONSE_TTL
+    }
+}
+
+pub struct ResponseStore {
+    responses: Mutex<HashMap<String, StoredResponse>>,
+}
+
+impl ResponseStore {
+    fn new() -> Self {
+        Self {
+            responses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Global singleton instance
+    pub fn global() -> &'static ResponseStore {
+        static INSTANCE: OnceLock<ResponseStore> = OnceLock::new();
+        INSTANCE.get_or_init(ResponseStore::new)
+    }
+
+    /// 保存响应对象 (超出容量时先清理过期项，再淘汰最旧的一项)
+    pub fn put(&self, id: &str, owner: &str, response: Value) {
+        if let Ok(mut store) = self.responses.lock() {
+            store.insert(
+                id.to_string(),
+                StoredResponse {
+                    response,
+                    owner: owner.to_string(),
+                    timestamp: SystemTime::now(),
+                },
+            );
+            if store.len() > RESPONSE_STORE_LIMIT {
+                store.retain(|_, v| !v.is_expired());
+            }
+            while store.len() > RESPONSE_STORE_LIMIT {
+                let oldest = store
+                    .iter()
+                    .min_by_key(|(_, v)| v.timestamp)
+                    .map(|(k, _)| k.clone());
+                match oldest {
+                    Some(k) => {
+                        store.remove(&k);
+                    }
+                    None => break,
+                }
+            }
+            tracing::debug!(
+                "[ResponseStore] Stored response {} ({} total)",
+                id,
+                store.len()
+            );
+        }
+    }
+
+    /// 读取已保存的响应对象 (仅限创建它的 API Key)
+    pub fn get(&self, id: &str, owner: &str) -> Option<Value> {
+        let store = self.responses.lock().ok()?;
+        store
+            .get(id)
+            .filter(|entry| !entry.is_expired() && entry.owner == owner)
+            .map(|entry| entry.response.clone())
+    }
+}
+
+/// 请求所用 API Key 的标识 (SHA-256，不保存原始 Key)；未携带 Key 时为空串
+pub fn key_owner(headers: &HeaderMap) -> String {
+    crate::proxy::middleware::auth::extract_api_key(headers)
+        .map(|key| {
+            Sha256::digest(key.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Responses API 请求中与存储相关的字段 (store / metadata)
+#[derive(Debug, Clone, Default)]
+pub struct ResponseStoreOptions {
+    pub store: bool,
+    pub metadata: Option<Value>,
+    /// 创建者的 Key 标识，见 [`key_owner`]
+    pub owner: String,
+}
+
+impl ResponseStoreOptions {
+    pub fn from_body(body: &Value, owner: String) -> Self {
+        Self {
+            store: body.get("store").and_then(|v| v.as_bool()).unwrap_or(false),
+            metadata: body.get("metadata").filter(|v| !v.is_null()).cloned(),
+            owner,
+        }
+    }
+
+    /// 将 metadata 回写到最终响应对象中，并在 store: true 时保存
+    pub fn finalize(&self, response: &mut Value) {
+        if let Some(obj) = response.as_object_mut() {
+            obj.insert(
+                "metadata".to_string(),
+                self.metadata
+                    .clone()
+                    .unwrap_or_else(|| Value::Object(Default::default())),
+            );
+            obj.insert("store".to_string(), Value::Bool(self.store));
+        }
+        if self.store {
+            if let Some(id) = response.get("id").and_then(|v| v.as_str()) {
+                ResponseStore::global().put(id, &self.owner, response.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_finalize_echoes_metadata_and_stores() {
+        let opts = ResponseStoreOptions::from_body(
+            &json!({
+                "model": "gemini-2.5-flash",
+                "input": "hi",
+                "store": true,
+                "metadata": { "job": "nightly" }
+            }),
+            "owner-a".to_string(),
+        );
+        assert!(opts.store);
+
+        let id = format!("resp-test-{}", uuid::Uuid::new_v4());
+        let mut response = json!({ "id": id, "object": "response", "status": "completed" });
+        opts.finalize(&mut response);
+        assert_eq!(response["metadata"]["job"], "nightly");
+        assert_eq!(response["store"], true);
+        assert_eq!(ResponseStore::global().get(&id, "owner-a"), Some(response));
+        // 其他 Key 读取不到
+        assert!(ResponseStore::global().get(&id, "owner-b").is_none());
+        assert!(ResponseStore::global().get(&id, "").is_none());
+
+        // store 未开启时仅回写 metadata，不保存
+        let opts = ResponseStoreOptions::from_body(
+            &json!({ "metadata": { "job": "adhoc" } }),
+            String::new(),
+        );
+        let id = format!("resp-test-{}", uuid::Uuid::new_v4());
+        let mut response = json!({ "id": id, "object": "response" });
+        opts.finalize(&mut response);
+        assert_eq!(response["metadata"]["job"], "adhoc");
+        assert_eq!(response["store"], false);
+        assert!(ResponseStore::global().get(&id, "").is_none());
+    }
+
+    #[test]
+    fn test_store_evicts_oldest_beyond_limit() {
+        let store = ResponseStore::new();
+        for i in 0..RESPONSE_STORE_LIMIT + 5 {
+            store.put(&format!("r{}", i), "", json!({ "id": format!("r{}", i) }));
+        }
+        assert_eq!(store.responses.lock().unwrap().len(), RESPONSE_STORE_LIMIT);
+    }
+}
@@ -1,10 +1,11 @@
-use crate::proxy::config::{ProxyAuthMode, ProxyConfig, SecurityMonitorConfig};
+use crate::proxy::config::{ApiKeyEntry, ProxyAuthMode, ProxyConfig, SecurityMonitorConfig};
 
 #[derive(Debug, Clone)]
 pub struct ProxySecurityConfig {
     pub auth_mode: ProxyAuthMode,
     pub api_key: String,
     pub admin_password: Option<String>,
+    pub api_keys: Vec<ApiKeyEntry>,
     pub allow_lan_access: bool,
     pub port: u16,
     pub security_monitor: SecurityMonitorConfig,
@@ -16,12 +17,32 @@ impl ProxySecurityConfig {
             auth_mode: config.auth_mode.clone(),
             api_key: config.api_key.clone(),
             admin_password: config.admin_password.clone(),
+            api_keys: config.api_keys.clone(),
             allow_lan_access: config.allow_lan_access,
             port: config.port,
             security_monitor: config.security_monitor.clone(),
         }
     }
 
+    /// 是否配置了任何可用于代理接口的 key
+    pub fn has_proxy_keys(&self) -> bool {
+        !self.api_key.is_empty() || self.api_keys.iter().any(|k| k.enabled && !k.key.is_empty())
+    }
+
+    /// 代理接口 key 校验: 匹配主 api_key 或已启用的附加 key，返回 key 名称 (用于日志)
+    pub fn match_proxy_key(&self, key: &str) -> Option<&str> {
+        if key.is_empty() {
+            return None;
+        }
+        if key == self.api_key {
+            return Some("default");
+        }
+        self.api_keys
+            .iter()
+            .find(|k| k.enabled && k.key == key)
+            .map(|k| if k.name.is_empty() { "unnamed" } else { k.name.as_str() })
+    }
+
     pub fn effective_auth_mode(&self) -> ProxyAuthMode {
         match self.auth_mode {
             ProxyAuthMode::Auto => {
@@ -46,6 +67,7 @@ mod tests {
             auth_mode: ProxyAuthMode::Auto,
             api_key: "sk-test".to_string(),
             admin_password: None,
+            api_keys: Vec::new(),
             allow_lan_access: false,
             port: 8080,
             security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
@@ -59,6 +81,7 @@ mod tests {
             auth_mode: ProxyAuthMode::Auto,
             api_key: "sk-test".to_string(),
             admin_password: None,
+            api_keys: Vec::new(),
             allow_lan_access: true,
             port: 8080,
             security_monitor: crate::proxy::config::SecurityMonitorConfig::default(),
@@ -116,6 +116,70 @@ pub struct AppState {
     pub port: u16,                     // [NEW] 本地监听端口 (v4.0.8 修复)
     pub proxy_pool_state: Arc<tokio::sync::RwLock<crate::proxy::config::ProxyPoolConfig>>, // [FIX Web Mode]
     pub proxy_pool_manager: Arc<crate::proxy::proxy_pool::ProxyPoolManager>, // [FIX Web Mode]
+    pub metrics: Arc<crate::proxy::metrics::ProxyMetrics>, // [NEW] Prometheus 指标
+    pub request_dedup: Arc<crate::proxy::middleware::dedup::RequestDeduplicator>, // [NEW] 相同请求并发去重
+    pub idempotency: Arc<crate::proxy::middleware::idempotency::IdempotencyCache>, // [NEW] Idempotency-Key 结果缓存
+    pub user_usage: Arc<crate::proxy::user_usage::UserUsageTracker>, // [NEW] 终端用户请求统计与限流
+    pub prompt_filter: Arc<RwLock<crate::proxy::prompt_filter::PromptFilter>>, // [NEW] 提示词屏蔽规则 (已编译)
+    pub user_rate_limit: Arc<RwLock<crate::proxy::config::UserRateLimitConfig>>, // [NEW] 终端用户限流配置
+    pub streaming: Arc<RwLock<crate::proxy::config::StreamingConfig>>, // [NEW] 流式响应缓冲 / peek 心跳配置
+}
+
+#[cfg(test)]
+impl AppState {
+    /// 测试用最小状态: 指定账号池与上游客户端，其余使用默认配置
+    pub fn for_test(
+        token_manager: Arc<TokenManager>,
+        upstream: Arc<crate::proxy::upstream::client::UpstreamClient>,
+    ) -> Self {
+        let proxy_pool_state =
+            Arc::new(RwLock::new(crate::proxy::config::ProxyPoolConfig::default()));
+        let integration = crate::modules::integration::SystemManager::Headless;
+        Self {
+            token_manager: token_manager.clone(),
+            custom_mapping: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            request_timeout: 300,
+            thought_signature_map: Arc::new(tokio::sync::Mutex::new(
+                std::collections::HashMap::new(),
+            )),
+            upstream_proxy: Arc::new(RwLock::new(Default::default())),
+            upstream,
+            zai: Arc::new(RwLock::new(Default::default())),
+            provider_rr: Arc::new(AtomicUsize::new(0)),
+            zai_vision_mcp: Arc::new(crate::proxy::zai_vision_mcp::ZaiVisionMcpState::new()),
+            monitor: Arc::new(crate::proxy::monitor::ProxyMonitor::new(100, None)),
+            experimental: Arc::new(RwLock::new(Default::default())),
+            debug_logging: Arc::new(RwLock::new(Default::default())),
+            switching: Arc::new(RwLock::new(false)),
+            integration: integration.clone(),
+            account_service: Arc::new(crate::modules::account_service::AccountService::new(
+                integration,
+            )),
+            security: Arc::new(RwLock::new(crate::proxy::ProxySecurityConfig {
+                auth_mode: crate::proxy::ProxyAuthMode::Off,
+                api_key: String::new(),
+                admin_password: None,
+                api_keys: Vec::new(),
+                allow_lan_access: false,
+                port: 8045,
+                security_monitor: Default::default(),
+            })),
+            cloudflared_state: Arc::new(crate::commands::cloudflared::CloudflaredState::new()),
+            is_running: Arc::new(RwLock::new(true)),
+            port: 8045,
+            proxy_pool_state: proxy_pool_state.clone(),
+            proxy_pool_manager: Arc::new(crate::proxy::proxy_pool::ProxyPoolManager::new(
+                proxy_pool_state,
+            )),
+            metrics: Arc::new(crate::proxy::metrics::ProxyMetrics::new(token_manager)),
+            request_dedup: Arc::new(Default::default()),
+            idempotency: Arc::new(Default::default()),
+            user_usage: Arc::new(Default::default()),
+            prompt_filter: Arc::new(RwLock::new(Default::default())),
+            user_rate_limit: Arc::new(RwLock::new(Default::default())),
+            streaming: Arc::new(RwLock::new(Default::default())),
+        }
+    }
 }
 
 // 为 AppState 实现 FromRef，以便中间件提取 security 状态
@@ -125,6 +189,12 @@ impl axum::extract::FromRef<AppState> for Arc<RwLock<crate::proxy::ProxySecurity
     }
 }
 
+impl axum::extract::FromRef<AppState> for Arc<crate::proxy::metrics::ProxyMetrics> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     error: String,
@@ -150,6 +220,11 @@ struct AccountResponse {
     quota: Option<QuotaResponse>,
     device_bound: bool,
     last_used: i64,
+    /// [NEW] 账号 + 模型级熔断状态 (仅包含有失败记录的模型)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    circuit_breakers: Vec<crate::proxy::circuit_breaker::BreakerStatus>,
+    /// [NEW] 排空 (维护模式) 开始时间，未排空时为空
+    drained_since: Option<i64>,
 }
 
 #[derive(Serialize)]
@@ -209,6 +284,8 @@ fn to_account_response(
         validation_blocked: account.validation_blocked,
         validation_blocked_until: account.validation_blocked_until,
         validation_blocked_reason: account.validation_blocked_reason.clone(),
+        circuit_breakers: Vec::new(),
+        drained_since: None,
     }
 }
 
@@ -223,6 +300,9 @@ pub struct AxumServer {
     zai_state: Arc<RwLock<crate::proxy::ZaiConfig>>,
     experimental: Arc<RwLock<crate::proxy::config::ExperimentalConfig>>,
     debug_logging: Arc<RwLock<crate::proxy::config::DebugLoggingConfig>>,
+    prompt_filter: Arc<RwLock<crate::proxy::prompt_filter::PromptFilter>>,
+    user_rate_limit: Arc<RwLock<crate::proxy::config::UserRateLimitConfig>>,
+    streaming: Arc<RwLock<crate::proxy::config::StreamingConfig>>,
     pub cloudflared_state: Arc<crate::commands::cloudflared::CloudflaredState>,
     pub is_running: Arc<RwLock<bool>>,
     pub token_manager: Arc<TokenManager>, // [NEW] 暴露出 TokenManager 供反代服务复用
@@ -230,6 +310,154 @@ pub struct AxumServer {
     pub proxy_pool_manager: Arc<crate::proxy::proxy_pool::ProxyPoolManager>, // [NEW] 暴露代理池管理器供命令调用
 }
 
+/// 主 AI 代理路由 (遵循 auth_mode 配置)，含各协议接口与按请求生效的中间件层
+pub(crate) fn build_proxy_router(state: &AppState) -> Router<AppState> {
+    use crate::proxy::handlers;
+    use crate::proxy::middleware::{
+        access_log_middleware, auth_middleware, compression_layer, dedup_middleware,
+        idempotency_middleware, ip_filter_middleware, monitor_middleware,
+        openai_body_limit_middleware, request_span_middleware, upstream_latency_middleware,
+    };
+
+    Router::new()
+        .route("/health", get(health_check_handler))
+        .route("/healthz", get(health_check_handler))
+        .route("/readyz", get(readiness_handler))
+        .route("/metrics", get(crate::proxy::metrics::handle_metrics))
+        // OpenAI Protocol
+        .route("/v1/models", get(handlers::openai::handle_list_models))
+        .route(
+            "/v1/chat/completions",
+            post(handlers::openai::handle_chat_completions)
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    dedup_middleware,
+                ))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    idempotency_middleware,
+                )),
+        )
+        // [NEW] 批量对话: 逐项走 handle_chat_completions，不经过整体的 dedup / idempotency
+        .route("/v1/chat/batch", post(handlers::openai::handle_chat_batch))
+        .route(
+            "/v1/completions",
+            post(handlers::openai::handle_completions)
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    idempotency_middleware,
+                )),
+        )
+        .route(
+            "/v1/responses",
+            post(handlers::openai::handle_completions)
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    idempotency_middleware,
+                )),
+        ) // 兼容 Codex CLI
+        .route("/v1/responses/:id", get(handlers::openai::handle_get_response))
+        .route("/v1/tokenize", post(handlers::openai::handle_count_tokens)) // 输入 token 统计
+        .route(
+            "/v1/images/generations",
+            post(handlers::openai::handle_images_generations).layer(
+                axum::middleware::from_fn_with_state(state.clone(), idempotency_middleware),
+            ),
+        ) // 图像生成 API
+        .route(
+            "/v1/images/edits",
+            post(handlers::openai::handle_images_edits).layer(
+                axum::middleware::from_fn_with_state(state.clone(), idempotency_middleware),
+            ),
+        ) // 图像编辑 API
+        .route(
+            "/v1/audio/transcriptions",
+            post(handlers::audio::handle_audio_transcription),
+        ) // 音频转录 API
+        // Claude Protocol
+        .route(
+            "/v1/messages",
+            post(handlers::claude::handle_messages).layer(
+                axum::middleware::from_fn_with_state(state.clone(), idempotency_middleware),
+            ),
+        )
+        .route(
+            "/v1/messages/count_tokens",
+            post(handlers::claude::handle_count_tokens),
+        )
+        .route(
+            "/v1/models/claude",
+            get(handlers::claude::handle_list_models),
+        )
+        // z.ai MCP (optional reverse-proxy)
+        .route(
+            "/mcp/web_search_prime/mcp",
+            any(handlers::mcp::handle_web_search_prime),
+        )
+        .route("/mcp/web_reader/mcp", any(handlers::mcp::handle_web_reader))
+        .route(
+            "/mcp/zai-mcp-server/mcp",
+            any(handlers::mcp::handle_zai_mcp_server),
+        )
+        // Gemini Protocol (Native)
+        .route("/v1beta/models", get(handlers::gemini::handle_list_models))
+        // Handle both GET (get info) and POST (generateContent with colon) at the same route
+        .route(
+            "/v1beta/models/:model",
+            get(handlers::gemini::handle_get_model).post(handlers::gemini::handle_generate),
+        )
+        .route(
+            "/v1beta/models/:model/countTokens",
+            post(handlers::gemini::handle_count_tokens),
+        ) // Specific route priority
+        // [NEW] 原生 Gemini 请求透传 (仅账号轮换，不做协议转换)
+        .route(
+            "/v1/gemini/:method",
+            post(handlers::gemini::handle_raw_passthrough),
+        )
+        .route(
+            "/v1/models/detect",
+            post(handlers::common::handle_detect_model),
+        )
+        .route("/internal/warmup", post(handlers::warmup::handle_warmup)) // 内部预热端点
+        .route("/v1/api/event_logging/batch", post(silent_ok_handler))
+        .route("/v1/api/event_logging", post(silent_ok_handler))
+        // 应用 AI 服务特定的层
+        // 注意：Axum layer 执行顺序是从下往上（洋葱模型）
+        // 请求: request_span -> access_log -> compression -> ip_filter -> auth -> body_limit -> monitor -> metrics -> upstream_latency -> handler
+        // 响应: handler -> upstream_latency -> metrics -> monitor -> body_limit -> auth -> ip_filter -> compression -> access_log -> request_span
+        // monitor 需要在 auth 之后执行才能获取 UserTokenIdentity
+        .layer(axum::middleware::from_fn_with_state(
+            state.experimental.clone(),
+            upstream_latency_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.metrics.clone(),
+            crate::proxy::metrics::metrics_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            monitor_middleware,
+        ))
+        // [NEW] OpenAI 接口请求体大小限制: 位于 monitor / idempotency / dedup 之外 (三者都会缓冲整个请求体)，
+        // 超限请求在被缓冲之前即返回 413
+        .layer(axum::middleware::from_fn(openai_body_limit_middleware))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            ip_filter_middleware,
+        ))
+        // [NEW] 响应压缩 (位于 monitor / metrics 之外，二者看到的仍是未压缩的响应体)
+        .layer(compression_layer(state.experimental.clone()))
+        // 访问日志 (位于 ip_filter 之外，被拒绝的请求同样记录)
+        .layer(axum::middleware::from_fn(access_log_middleware))
+        // 请求关联 span (最外层，使以上各层日志均带 trace_id)
+        .layer(axum::middleware::from_fn(request_span_middleware))
+}
+
 impl AxumServer {
     pub async fn update_mapping(&self, config: &crate::proxy::config::ProxyConfig) {
         {
@@ -284,6 +512,41 @@ impl AxumServer {
         tracing::info!("User-Agent 配置已热更新: {:?}", config.user_agent_override);
     }
 
+    pub async fn update_prompt_filter(&self, config: &crate::proxy::config::ProxyConfig) {
+        let filter = crate::proxy::prompt_filter::PromptFilter::from_config(&config.prompt_filter);
+        *self.prompt_filter.write().await = filter;
+        tracing::info!(
+            "提示词屏蔽规则已热更新: enabled={}, {} 条规则",
+            config.prompt_filter.enabled,
+            config.prompt_filter.rules.len()
+        );
+    }
+
+    pub async fn update_user_rate_limit(&self, config: &crate::proxy::config::ProxyConfig) {
+        *self.user_rate_limit.write().await = config.user_rate_limit.clone();
+        tracing::info!(
+            "终端用户限流配置已热更新: requests_per_minute={}, tokens_per_minute={}",
+            config.user_rate_limit.requests_per_minute,
+            config.user_rate_limit.tokens_per_minute
+        );
+    }
+
+    pub async fn update_streaming(&self, config: &crate::proxy::config::ProxyConfig) {
+        *self.streaming.write().await = config.streaming.clone();
+        tracing::info!(
+            "流式响应配置已热更新: flush_threshold_bytes={}, peek_heartbeat_ms={}",
+            config.streaming.flush_threshold_bytes,
+            config.streaming.peek_heartbeat_ms
+        );
+    }
+
+    pub async fn update_upstream_base_urls(&self, config: &crate::proxy::config::ProxyConfig) {
+        self.upstream
+            .set_base_urls(config.upstream_base_urls.clone())
+            .await;
+        tracing::info!("上游端点配置已热更新: {:?}", config.upstream_base_urls);
+    }
+
     pub async fn set_running(&self, running: bool) {
         let mut r = self.is_running.write().await;
         *r = running;
@@ -320,6 +583,7 @@ impl AxumServer {
         let zai_state = Arc::new(RwLock::new(zai_config));
         let provider_rr = Arc::new(AtomicUsize::new(0));
         let zai_vision_mcp_state = Arc::new(crate::proxy::zai_vision_mcp::ZaiVisionMcpState::new());
+        let upstream_http = experimental_config.upstream_http.clone();
         let experimental_state = Arc::new(RwLock::new(experimental_config));
         let debug_logging_state = Arc::new(RwLock::new(debug_logging));
         let is_running_state = Arc::new(RwLock::new(true));
@@ -333,10 +597,13 @@ impl AxumServer {
             )),
             upstream_proxy: proxy_state.clone(),
             upstream: {
-                let u = Arc::new(crate::proxy::upstream::client::UpstreamClient::new(
-                    Some(upstream_proxy.clone()),
-                    Some(proxy_pool_manager.clone()),
-                ));
+                let u = Arc::new(
+                    crate::proxy::upstream::client::UpstreamClient::with_http_config(
+                        Some(upstream_proxy.clone()),
+                        Some(proxy_pool_manager.clone()),
+                        upstream_http,
+                    ),
+                );
                 // 初始化 User-Agent 覆盖
                 if user_agent_override.is_some() {
                     u.set_user_agent_override(user_agent_override).await;
@@ -360,97 +627,23 @@ impl AxumServer {
             port,
             proxy_pool_state: proxy_pool_state.clone(),
             proxy_pool_manager: proxy_pool_manager.clone(),
+            metrics: Arc::new(crate::proxy::metrics::ProxyMetrics::new(token_manager.clone())),
+            request_dedup: Arc::new(Default::default()),
+            idempotency: Arc::new(Default::default()),
+            user_usage: Arc::new(Default::default()),
+            prompt_filter: Arc::new(RwLock::new(Default::default())),
+            user_rate_limit: Arc::new(RwLock::new(Default::default())),
+            streaming: Arc::new(RwLock::new(Default::default())),
         };
 
         // 构建路由 - 使用新架构的 handlers！
         use crate::proxy::handlers;
         use crate::proxy::middleware::{
-            admin_auth_middleware, auth_middleware, cors_layer, ip_filter_middleware,
-            monitor_middleware, service_status_middleware,
+            admin_auth_middleware, cors_layer, service_status_middleware,
         };
 
         // 1. 构建主 AI 代理路由 (遵循 auth_mode 配置)
-        let proxy_routes = Router::new()
-            .route("/health", get(health_check_handler))
-            .route("/healthz", get(health_check_handler))
-            // OpenAI Protocol
-            .route("/v1/models", get(handlers::openai::handle_list_models))
-            .route(
-                "/v1/chat/completions",
-                post(handlers::openai::handle_chat_completions),
-            )
-            .route(
-                "/v1/completions",
-                post(handlers::openai::handle_completions),
-            )
-            .route("/v1/responses", post(handlers::openai::handle_completions)) // 兼容 Codex CLI
-            .route(
-                "/v1/images/generations",
-                post(handlers::openai::handle_images_generations),
-            ) // 图像生成 API
-            .route(
-                "/v1/images/edits",
-                post(handlers::openai::handle_images_edits),
-            ) // 图像编辑 API
-            .route(
-                "/v1/audio/transcriptions",
-                post(handlers::audio::handle_audio_transcription),
-            ) // 音频转录 API
-            // Claude Protocol
-            .route("/v1/messages", post(handlers::claude::handle_messages))
-            .route(
-                "/v1/messages/count_tokens",
-                post(handlers::claude::handle_count_tokens),
-            )
-            .route(
-                "/v1/models/claude",
-                get(handlers::claude::handle_list_models),
-            )
-            // z.ai MCP (optional reverse-proxy)
-            .route(
-                "/mcp/web_search_prime/mcp",
-                any(handlers::mcp::handle_web_search_prime),
-            )
-            .route("/mcp/web_reader/mcp", any(handlers::mcp::handle_web_reader))
-            .route(
-                "/mcp/zai-mcp-server/mcp",
-                any(handlers::mcp::handle_zai_mcp_server),
-            )
-            // Gemini Protocol (Native)
-            .route("/v1beta/models", get(handlers::gemini::handle_list_models))
-            // Handle both GET (get info) and POST (generateContent with colon) at the same route
-            .route(
-                "/v1beta/models/:model",
-                get(handlers::gemini::handle_get_model).post(handlers::gemini::handle_generate),
-            )
-            .route(
-                "/v1beta/models/:model/countTokens",
-                post(handlers::gemini::handle_count_tokens),
-            ) // Specific route priority
-            .route(
-                "/v1/models/detect",
-                post(handlers::common::handle_detect_model),
-            )
-            .route("/internal/warmup", post(handlers::warmup::handle_warmup)) // 内部预热端点
-            .route("/v1/api/event_logging/batch", post(silent_ok_handler))
-            .route("/v1/api/event_logging", post(silent_ok_handler))
-            // 应用 AI 服务特定的层
-            // 注意：Axum layer 执行顺序是从下往上（洋葱模型）
-            // 请求: ip_filter -> auth -> monitor -> handler
-            // 响应: handler -> monitor -> auth -> ip_filter
-            // monitor 需要在 auth 之后执行才能获取 UserTokenIdentity
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                monitor_middleware,
-            ))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                auth_middleware,
-            ))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                ip_filter_middleware,
-            ));
+        let proxy_routes = build_proxy_router(&state);
 
         // 2. 构建管理 API (强制鉴权)
         let admin_routes = Router::new()
@@ -464,6 +657,8 @@ impl AxumServer {
             .route("/accounts/refresh", post(admin_refresh_all_quotas))
             .route("/accounts/:accountId", delete(admin_delete_account))
             .route("/accounts/:accountId/bind-device", post(admin_bind_device))
+            .route("/accounts/:accountId/drain", post(admin_drain_account))
+            .route("/accounts/:accountId/undrain", post(admin_undrain_account))
             .route(
                 "/accounts/:accountId/device-profiles",
                 get(admin_get_device_profiles),
@@ -525,11 +720,23 @@ impl AxumServer {
             .route("/proxy/start", post(admin_start_proxy_service))
             .route("/proxy/stop", post(admin_stop_proxy_service))
             .route("/proxy/mapping", post(admin_update_model_mapping))
+            .route(
+                "/mappings",
+                get(admin_get_model_mappings).post(admin_patch_model_mappings),
+            )
+            .route("/mappings/reload", post(admin_reload_model_mappings))
             .route("/proxy/api-key/generate", post(admin_generate_api_key))
+            .route("/proxy/session-bindings", get(admin_get_proxy_session_bindings))
+            .route("/stats/users", get(admin_get_user_usage).delete(admin_clear_user_usage))
+            .route("/stats/account-usage", get(admin_get_account_usage))
             .route(
                 "/proxy/session-bindings/clear",
                 post(admin_clear_proxy_session_bindings),
             )
+            .route(
+                "/proxy/session-bindings/:sessionId",
+                delete(admin_evict_proxy_session_binding),
+            )
             .route("/proxy/rate-limits", delete(admin_clear_all_rate_limits))
             .route(
                 "/proxy/rate-limits/:accountId",
@@ -539,6 +746,10 @@ impl AxumServer {
                 "/proxy/preferred-account",
                 get(admin_get_preferred_account).post(admin_set_preferred_account),
             )
+            .route(
+                "/proxy/pinned-rotation",
+                get(admin_get_pinned_rotation).post(admin_set_pinned_rotation),
+            )
             .route("/accounts/oauth/prepare", post(admin_prepare_oauth_url))
             .route("/accounts/oauth/start", post(admin_start_oauth_login))
             .route("/accounts/oauth/complete", post(admin_complete_oauth_login))
@@ -658,6 +869,17 @@ impl AxumServer {
                 admin_auth_middleware,
             ));
 
+        // 调试接口: 位于 /v1 下但要求管理员鉴权 (不消耗配额，仅返回转换结果)
+        let debug_routes = Router::new()
+            .route(
+                "/v1/debug/transform",
+                post(handlers::openai::handle_debug_transform),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                admin_auth_middleware,
+            ));
+
         // 3. 整合并应用全局层
         // 从环境变量读取 body 大小限制，默认 50MB
         let max_body_size: usize = std::env::var("ABV_MAX_BODY_SIZE")
@@ -668,6 +890,7 @@ impl AxumServer {
 
         let app = Router::new()
             .nest("/api", admin_routes)
+            .merge(debug_routes)
             .merge(proxy_routes)
             // 公开路由 (无需鉴权)
             .route("/auth/callback", get(handle_oauth_callback))
@@ -700,7 +923,7 @@ impl AxumServer {
         tracing::info!("反代服务器启动在 http://{}", addr);
 
         // 创建关闭通道
-        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
         let server_instance = Self {
             shutdown_tx: Arc::new(tokio::sync::Mutex::new(Some(shutdown_tx))),
@@ -711,6 +934,9 @@ impl AxumServer {
             zai_state,
             experimental: experimental_state.clone(),
             debug_logging: debug_logging_state.clone(),
+            prompt_filter: state.prompt_filter.clone(),
+            user_rate_limit: state.user_rate_limit.clone(),
+            streaming: state.streaming.clone(),
             cloudflared_state,
             is_running: is_running_state,
             token_manager: token_manager.clone(),
@@ -719,50 +945,12 @@ impl AxumServer {
         };
 
         // 在新任务中启动服务器
-        let handle = tokio::spawn(async move {
-            use hyper::server::conn::http1;
-            use hyper_util::rt::TokioIo;
-            use hyper_util::service::TowerToHyperService;
-
-            loop {
-                tokio::select! {
-                    res = listener.accept() => {
-                        match res {
-                            Ok((stream, remote_addr)) => {
-                                let io = TokioIo::new(stream);
-                                
-                                // 注入 ConnectInfo (用于获取真实 IP)
-                                use tower::ServiceExt;
-                                use hyper::body::Incoming;
-                                let app_with_info = app.clone().map_request(move |mut req: axum::http::Request<Incoming>| {
-                                    req.extensions_mut().insert(axum::extract::ConnectInfo(remote_addr));
-                                    req
-                                });
-
-                                let service = TowerToHyperService::new(app_with_info);
-
-                                tokio::task::spawn(async move {
-                                    if let Err(err) = http1::Builder::new()
-                                        .serve_connection(io, service)
-                                        .with_upgrades() // 支持 WebSocket (如果以后需要)
-                                        .await
-                                    {
-                                        debug!("连接处理结束或出错: {:?}", err);
-                                    }
-                                });
-                            }
-                            Err(e) => {
-                                error!("接收连接失败: {:?}", e);
-                            }
-                        }
-                    }
-                    _ = &mut shutdown_rx => {
-                        tracing::info!("反代服务器停止监听");
-                        break;
-                    }
-                }
-            }
-        });
+        let handle = tokio::spawn(serve_until_shutdown(
+            listener,
+            app,
+            shutdown_rx,
+            experimental_state,
+        ));
 
         Ok((server_instance, handle))
     }
@@ -780,6 +968,106 @@ impl AxumServer {
     }
 }
 
+/// 接受连接并提供服务，直到收到关闭信号
+/// [NEW] 优雅停机: 收到信号后立即停止接受新连接，已建立的连接处理完当前请求 (包括流式响应) 后关闭；
+/// 超过 experimental.shutdown_grace_secs 仍未完成的连接被强制中止，请求持有的账号随之释放
+async fn serve_until_shutdown(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    mut shutdown_rx: oneshot::Receiver<()>,
+    experimental: Arc<RwLock<crate::proxy::config::ExperimentalConfig>>,
+) {
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper_util::rt::TokioIo;
+    use hyper_util::service::TowerToHyperService;
+    use tower::ServiceExt;
+
+    let (drain_tx, drain_rx) = tokio::sync::watch::channel(false);
+    let mut connections = tokio::task::JoinSet::new();
+
+    loop {
+        tokio::select! {
+            res = listener.accept() => {
+                match res {
+                    Ok((stream, remote_addr)) => {
+                        let io = TokioIo::new(stream);
+
+                        // 注入 ConnectInfo (用于获取真实 IP)
+                        let app_with_info = app.clone().map_request(move |mut req: axum::http::Request<Incoming>| {
+                            req.extensions_mut().insert(axum::extract::ConnectInfo(remote_addr));
+                            req
+                        });
+
+                        let service = TowerToHyperService::new(app_with_info);
+                        let mut drain_rx = drain_rx.clone();
+
+                        connections.spawn(async move {
+                            let conn = http1::Builder::new()
+                                .serve_connection(io, service)
+                                .with_upgrades(); // 支持 WebSocket (如果以后需要)
+                            tokio::pin!(conn);
+                            let mut draining = false;
+                            loop {
+                                tokio::select! {
+                                    res = conn.as_mut() => {
+                                        if let Err(err) = res {
+                                            debug!("连接处理结束或出错: {:?}", err);
+                                        }
+                                        break;
+                                    }
+                                    // 停机: 当前请求完成后关闭连接，不再处理 keep-alive 的后续请求
+                                    _ = drain_rx.changed(), if !draining => {
+                                        draining = true;
+                                        conn.as_mut().graceful_shutdown();
+                                    }
+                                }
+                            }
+                        });
+                        // 回收已结束的连接任务
+                        while connections.try_join_next().is_some() {}
+                    }
+                    Err(e) => {
+                        error!("接收连接失败: {:?}", e);
+                    }
+                }
+            }
+            _ = &mut shutdown_rx => {
+                tracing::info!("反代服务器停止监听");
+                break;
+            }
+        }
+    }
+
+    drop(listener);
+    let _ = drain_tx.send(true);
+    while connections.try_join_next().is_some() {}
+    if connections.is_empty() {
+        return;
+    }
+
+    let grace = std::time::Duration::from_secs(experimental.read().await.shutdown_grace_secs);
+    tracing::info!(
+        "[Shutdown] Waiting up to {}s for {} in-flight connection(s) to finish",
+        grace.as_secs(),
+        connections.len()
+    );
+    let drained = tokio::time::timeout(grace, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await
+    .is_ok();
+    if drained {
+        tracing::info!("[Shutdown] All in-flight requests completed");
+    } else {
+        tracing::warn!(
+            "[Shutdown] Grace period elapsed, aborting {} connection(s)",
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+}
+
 // ===== API 处理器 (旧代码已移除，由 src/proxy/handlers/* 接管) =====
 
 /// 健康检查处理器
@@ -791,6 +1079,28 @@ async fn health_check_handler() -> Response {
     .into_response()
 }
 
+/// 就绪检查: 账号池中没有可服务的账号 (全部限流 / 排空 / 被阻止) 时返回 503，供负载均衡摘除
+async fn readiness_handler(State(state): State<AppState>) -> Response {
+    readiness_response(&state.token_manager).await
+}
+
+async fn readiness_response(token_manager: &TokenManager) -> Response {
+    let readiness = token_manager.pool_readiness().await;
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "status": if readiness.is_ready() { "ready" } else { "unavailable" },
+            "accounts": readiness,
+        })),
+    )
+        .into_response()
+}
+
 /// 静默成功处理器 (用于拦截遥测日志等)
 async fn silent_ok_handler() -> Response {
     StatusCode::OK.into_response()
@@ -833,6 +1143,8 @@ async fn admin_list_accounts(
                 is_forbidden: q.is_forbidden,
             });
 
+            let circuit_breakers = state.token_manager.model_breaker_statuses(&acc.id);
+            let drained_since = state.token_manager.drained_since(&acc.id);
             AccountResponse {
                 id: acc.id,
                 email: acc.email,
@@ -851,6 +1163,8 @@ async fn admin_list_accounts(
                 quota,
                 device_bound: acc.device_profile.is_some(),
                 last_used: acc.last_used,
+                circuit_breakers,
+                drained_since,
             }
         })
         .collect();
@@ -910,6 +1224,8 @@ async fn admin_get_current_account(
                 is_forbidden: q.is_forbidden,
             });
 
+            let circuit_breakers = state.token_manager.model_breaker_statuses(&acc.id);
+            let drained_since = state.token_manager.drained_since(&acc.id);
             AccountResponse {
                 id: acc.id,
                 email: acc.email,
@@ -928,6 +1244,8 @@ async fn admin_get_current_account(
                 quota,
                 device_bound: acc.device_profile.is_some(),
                 last_used: acc.last_used,
+                circuit_breakers,
+                drained_since,
             }
         })
     } else {
@@ -1469,17 +1787,196 @@ async fn admin_update_model_mapping(
     Ok(StatusCode::OK)
 }
 
+/// 增量修改模型映射 (POST /api/mappings)
+/// upsert 中的条目新增或覆盖，delete 中的条目删除 (先 upsert 后 delete)
+#[derive(Deserialize, Default)]
+struct MappingChanges {
+    #[serde(default)]
+    upsert: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    delete: Vec<String>,
+}
+
+/// 应用到内存中的映射表并返回更新后的完整映射
+async fn apply_custom_mapping_changes(
+    mapping: &tokio::sync::RwLock<std::collections::HashMap<String, String>>,
+    changes: MappingChanges,
+) -> std::collections::HashMap<String, String> {
+    let mut mapping = mapping.write().await;
+    for (from, to) in changes.upsert {
+        mapping.insert(from, to);
+    }
+    for from in &changes.delete {
+        mapping.remove(from);
+    }
+    mapping.clone()
+}
+
+async fn admin_get_model_mappings(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({ "mapping": *state.custom_mapping.read().await }))
+}
+
+async fn admin_patch_model_mappings(
+    State(state): State<AppState>,
+    Json(changes): Json<MappingChanges>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    if changes.upsert.iter().any(|(from, to)| from.trim().is_empty() || to.trim().is_empty()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "mapping source and target must not be empty".to_string(),
+            }),
+        ));
+    }
+    let (upserted, deleted) = (changes.upsert.len(), changes.delete.len());
+
+    // 1. 热更新内存状态 (模型列表 /v1/models 实时读取映射表，无需额外失效缓存)
+    let mapping = apply_custom_mapping_changes(&state.custom_mapping, changes).await;
+
+    // 2. 持久化
+    let mut app_config = crate::modules::config::load_app_config().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )
+    })?;
+    app_config.proxy.custom_mapping = mapping.clone();
+    crate::modules::config::save_app_config(&app_config).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )
+    })?;
+
+    logger::log_info(&format!(
+        "[API] 模型映射已增量更新: {} 项新增/修改, {} 项删除",
+        upserted, deleted
+    ));
+    Ok(Json(serde_json::json!({ "mapping": mapping })))
+}
+
+/// 从配置文件重新读取映射表并整体替换内存中的映射 (在磁盘上手动编辑后无需重启)
+/// 返回新的映射表与 /v1/models 中的模型数量
+async fn reload_custom_mapping_from(
+    mapping: &tokio::sync::RwLock<std::collections::HashMap<String, String>>,
+    config_path: &std::path::Path,
+) -> Result<(std::collections::HashMap<String, String>, usize), String> {
+    let (app_config, _) = crate::modules::config::read_app_config(config_path)?;
+    let new_mapping = app_config.proxy.custom_mapping;
+    *mapping.write().await = new_mapping.clone();
+    let models = crate::proxy::common::model_mapping::get_all_dynamic_models(mapping)
+        .await
+        .len();
+    Ok((new_mapping, models))
+}
+
+/// POST /api/mappings/reload
+async fn admin_reload_model_mappings(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let to_error = |e: String| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )
+    };
+    let config_path = crate::modules::config::get_config_path().map_err(to_error)?;
+    let (mapping, models) = reload_custom_mapping_from(&state.custom_mapping, &config_path)
+        .await
+        .map_err(to_error)?;
+
+    logger::log_info(&format!(
+        "[API] 模型映射已从配置文件重新加载: {} 项映射, {} 个模型",
+        mapping.len(),
+        models
+    ));
+    Ok(Json(serde_json::json!({ "mapping": mapping, "models": models })))
+}
+
 async fn admin_generate_api_key() -> impl IntoResponse {
     let new_key = format!("sk-{}", uuid::Uuid::new_v4().to_string().replace("-", ""));
     Json(new_key)
 }
 
+async fn admin_get_proxy_session_bindings(State(state): State<AppState>) -> impl IntoResponse {
+    // 顺带清理已过期的绑定，返回的数量仅包含有效会话
+    let evicted = state.token_manager.evict_expired_sessions().await;
+    Json(serde_json::json!({
+        "count": state.token_manager.session_count(),
+        "evicted": evicted,
+    }))
+}
+
+async fn admin_get_user_usage(State(state): State<AppState>) -> impl IntoResponse {
+    let users = state.user_usage.stats();
+    let limits = state.user_rate_limit.read().await.clone();
+    Json(serde_json::json!({
+        "count": users.len(),
+        "requests_per_minute": limits.requests_per_minute,
+        "tokens_per_minute": limits.tokens_per_minute,
+        "users": users,
+    }))
+}
+
+async fn admin_get_account_usage(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.token_manager.account_usage_report())
+}
+
+async fn admin_clear_user_usage(State(state): State<AppState>) -> impl IntoResponse {
+    state.user_usage.clear();
+    StatusCode::NO_CONTENT
+}
+
+async fn admin_evict_proxy_session_binding(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    if state.token_manager.clear_session_binding(&session_id) {
+        logger::log_info(&format!("[API] 已清除会话绑定: {}", session_id));
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
 async fn admin_clear_proxy_session_bindings(State(state): State<AppState>) -> impl IntoResponse {
     state.token_manager.clear_all_sessions();
     logger::log_info("[API] 已清除所有会话绑定");
     StatusCode::OK
 }
 
+/// 排空账号: 不再分配新请求，进行中的请求正常完成 (路径参数可为 email 或 account_id)
+async fn admin_drain_account(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let account_id = state
+        .token_manager
+        .drain_account(&account)
+        .map_err(|e| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: e })))?;
+    logger::log_info(&format!("[API] 账号 {} 已排空 (维护模式)", account));
+    Ok(Json(serde_json::json!({
+        "account_id": account_id,
+        "drained": true,
+        "drained_since": state.token_manager.drained_since(&account_id),
+    })))
+}
+
+async fn admin_undrain_account(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let account_id = state
+        .token_manager
+        .undrain_account(&account)
+        .map_err(|e| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: e })))?;
+    logger::log_info(&format!("[API] 账号 {} 已恢复轮换", account));
+    Ok(Json(serde_json::json!({
+        "account_id": account_id,
+        "drained": false,
+    })))
+}
+
 async fn admin_clear_all_rate_limits(State(state): State<AppState>) -> impl IntoResponse {
     state.token_manager.clear_all_rate_limits();
     logger::log_info("[API] 已清除所有限流记录");
@@ -1521,6 +2018,24 @@ async fn admin_set_preferred_account(
     StatusCode::OK
 }
 
+/// 固定顺序调度 (调试用): 所有请求按固定顺序选择账号，请勿在生产环境开启
+async fn admin_get_pinned_rotation(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({ "enabled": state.token_manager.is_pinned_rotation() }))
+}
+
+#[derive(Deserialize)]
+struct SetPinnedRotationRequest {
+    enabled: bool,
+}
+
+async fn admin_set_pinned_rotation(
+    State(state): State<AppState>,
+    Json(payload): Json<SetPinnedRotationRequest>,
+) -> impl IntoResponse {
+    state.token_manager.set_pinned_rotation(payload.enabled);
+    StatusCode::OK
+}
+
 async fn admin_fetch_zai_models(
     Path(id): Path<String>,
     Json(payload): Json<serde_json::Value>, // 复用前端传来的参数
@@ -3465,3 +3980,188 @@ async fn admin_get_droid_config_content(
             Json(ErrorResponse { error: e }),
         ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::common::model_mapping::{get_all_dynamic_models, resolve_model_route};
+    use crate::proxy::tests::harness::TestPool;
+
+    #[tokio::test]
+    async fn test_mapping_update_applies_to_next_request() {
+        let mapping = Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::from([
+            ("old-alias".to_string(), "gemini-2.5-flash".to_string()),
+        ])));
+        assert_ne!(
+            resolve_model_route("team-default", &*mapping.read().await),
+            "gemini-2.5-pro"
+        );
+
+        let changes: MappingChanges = serde_json::from_value(serde_json::json!({
+            "upsert": { "team-default": "gemini-2.5-pro" },
+            "delete": ["old-alias"]
+        }))
+        .unwrap();
+        let updated = apply_custom_mapping_changes(&mapping, changes).await;
+        assert_eq!(updated.get("team-default").map(String::as_str), Some("gemini-2.5-pro"));
+        assert!(!updated.contains_key("old-alias"));
+
+        // 下一个请求按新映射路由，模型列表同步可见
+        assert_eq!(
+            resolve_model_route("team-default", &*mapping.read().await),
+            "gemini-2.5-pro"
+        );
+        assert!(get_all_dynamic_models(&mapping)
+            .await
+            .contains(&"team-default".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_mapping_reload_from_config_file() {
+        let pool = TestPool::new(&[]);
+        let config_path = pool.root().join("gui_config.json");
+
+        let mapping = Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::from([
+            ("stale-alias".to_string(), "gemini-2.5-flash".to_string()),
+        ])));
+
+        // 在磁盘上编辑映射后重新加载
+        let mut app_config = AppConfig::new();
+        app_config.proxy.custom_mapping = std::collections::HashMap::from([(
+            "ops-added-alias".to_string(),
+            "gemini-2.5-pro".to_string(),
+        )]);
+        std::fs::write(&config_path, serde_json::to_string(&app_config).unwrap()).unwrap();
+
+        let (reloaded, models) = reload_custom_mapping_from(&mapping, &config_path)
+            .await
+            .unwrap();
+        assert_eq!(reloaded.len(), 1);
+        let listed = get_all_dynamic_models(&mapping).await;
+        assert_eq!(models, listed.len());
+        assert!(listed.contains(&"ops-added-alias".to_string()));
+        assert!(!listed.contains(&"stale-alias".to_string()));
+        assert_eq!(
+            resolve_model_route("ops-added-alias", &*mapping.read().await),
+            "gemini-2.5-pro"
+        );
+
+        // 文件损坏时保留当前映射
+        std::fs::write(&config_path, "{ not json").unwrap();
+        assert!(reload_custom_mapping_from(&mapping, &config_path).await.is_err());
+        assert!(mapping.read().await.contains_key("ops-added-alias"));
+    }
+
+    #[tokio::test]
+    async fn test_readyz_returns_503_when_all_accounts_limited() {
+        let pool = TestPool::new(&[("acc1", "a@test.com"), ("acc2", "b@test.com")]);
+        let token_manager = pool.token_manager().await;
+
+        let body_of = |response: Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let response = readiness_response(&token_manager).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_of(response).await;
+        assert_eq!(body["accounts"]["total"], 2);
+        assert_eq!(body["accounts"]["available"], 2);
+
+        token_manager
+            .mark_rate_limited("a@test.com", 429, Some("120"), "")
+            .await;
+        assert_eq!(readiness_response(&token_manager).await.status(), StatusCode::OK);
+
+        token_manager
+            .mark_rate_limited("b@test.com", 429, Some("120"), "")
+            .await;
+        let response = readiness_response(&token_manager).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = body_of(response).await;
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["accounts"]["available"], 0);
+        assert_eq!(body["accounts"]["rate_limited"], 2);
+        assert!(token_manager
+            .get_token("gemini", false, None, "gemini-2.5-flash")
+            .await
+            .is_err());
+
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_stream() {
+        use std::time::Duration;
+
+        // 每 100ms 输出一段的流式响应；/endless 永不结束
+        let app = Router::new()
+            .route(
+                "/stream",
+                get(|| async {
+                    use futures::StreamExt;
+                    let chunks = futures::stream::iter(0..3).then(|i| async move {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Ok::<_, std::io::Error>(format!("data: {}\n\n", i))
+                    });
+                    axum::body::Body::from_stream(chunks)
+                }),
+            )
+            .route(
+                "/endless",
+                get(|| async {
+                    axum::body::Body::from_stream(futures::stream::pending::<
+                        Result<String, std::io::Error>,
+                    >())
+                }),
+            );
+
+        let start = |grace_secs: u64| {
+            let app = app.clone();
+            async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                let (shutdown_tx, shutdown_rx) = oneshot::channel();
+                let experimental =
+                    Arc::new(RwLock::new(crate::proxy::config::ExperimentalConfig {
+                        shutdown_grace_secs: grace_secs,
+                        ..Default::default()
+                    }));
+                let handle = tokio::spawn(serve_until_shutdown(
+                    listener,
+                    app,
+                    shutdown_rx,
+                    experimental,
+                ));
+                (addr, shutdown_tx, handle)
+            }
+        };
+
+        // 宽限期内: 进行中的流完整输出，之后不再接受新连接
+        let (addr, shutdown_tx, handle) = start(5).await;
+        let resp = reqwest::get(format!("http://{}/stream", addr))
+            .await
+            .unwrap();
+        shutdown_tx.send(()).unwrap();
+        let body = resp.text().await.unwrap();
+        assert_eq!(body, "data: 0\n\ndata: 1\n\ndata: 2\n\n");
+        tokio::time::timeout(Duration::from_secs(2), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+
+        // 超出宽限期: 未完成的连接被强制中止
+        let (addr, shutdown_tx, handle) = start(0).await;
+        let resp = reqwest::get(format!("http://{}/endless", addr))
+            .await
+            .unwrap();
+        shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(2), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(resp.text().await.is_err());
+    }
+}
@@ -80,6 +80,13 @@ impl SessionManager {
     pub fn extract_openai_session_id(request: &OpenAIRequest) -> String {
         let mut hasher = Sha256::new();
 
+        // [NEW] 携带 user 时混入指纹，不同终端用户的相同对话不共享粘性账号
+        if let Some(user) = request.user.as_deref().filter(|u| !u.is_empty()) {
+            hasher.update(b"user:");
+            hasher.update(user.as_bytes());
+            hasher.update([0u8]);
+        }
+
         let mut content_found = false;
         for msg in &request.messages {
             if msg.role != "user" { continue; }
@@ -25,6 +25,10 @@ pub struct StickySessionConfig {
     pub mode: SchedulingMode,
     /// 缓存优先模式下的最大等待时间 (秒)
     pub max_wait_seconds: u64,
+    /// 会话绑定的空闲过期时间 (秒)，超时未使用的会话将解除与账号的绑定；0 表示永不过期
+    pub session_ttl_seconds: u64,
+    /// 最多跟踪的会话绑定数量 (LRU 淘汰)；0 表示不限制
+    pub max_sessions: usize,
 }
 
 impl Default for StickySessionConfig {
@@ -32,6 +36,8 @@ impl Default for StickySessionConfig {
         Self {
             mode: SchedulingMode::Balance,
             max_wait_seconds: 60,
+            session_ttl_seconds: 3600,
+            max_sessions: 10000,
         }
     }
 }
@@ -0,0 +1,148 @@
+// ==================================================================================
+// 测试共享夹具: 本地假上游 + 临时账号池
+// 供 handlers / middleware / token_manager 等单元测试复用，避免各处手写
+// TcpListener 绑定与临时目录账号初始化
+// ==================================================================================
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::proxy::server::AppState;
+use crate::proxy::upstream::client::UpstreamClient;
+use crate::proxy::TokenManager;
+
+/// 在 127.0.0.1 的随机端口上启动 app，返回监听地址
+pub async fn spawn_upstream(app: axum::Router) -> SocketAddr {
+    spawn_upstream_with(|_| app).await
+}
+
+/// 同 spawn_upstream，app 需要知道自身地址时使用 (如返回指向自身的上传地址)
+pub async fn spawn_upstream_with(build: impl FnOnce(SocketAddr) -> axum::Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = build(addr);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    addr
+}
+
+/// 返回一个已关闭端口的地址，连接会被立即拒绝
+pub async fn dead_addr() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// 假上游对应的 v1internal 基础地址
+pub fn v1internal_url(addr: SocketAddr) -> String {
+    format!("http://{}/v1internal", addr)
+}
+
+/// 只指向 addr 的 UpstreamClient
+pub async fn upstream_client(addr: SocketAddr) -> Arc<UpstreamClient> {
+    let upstream = Arc::new(UpstreamClient::new(None, None));
+    upstream.set_base_urls(vec![v1internal_url(addr)]).await;
+    upstream
+}
+
+/// 启动假上游并返回指向它的 UpstreamClient
+pub async fn spawn_upstream_client(app: axum::Router) -> Arc<UpstreamClient> {
+    upstream_client(spawn_upstream(app).await).await
+}
+
+/// 假上游 + 给定账号组成的 AppState；返回的 TestPool 需存活到测试结束
+pub async fn proxy_state(app: axum::Router, accounts: &[(&str, &str)]) -> (AppState, TestPool) {
+    let upstream = spawn_upstream_client(app).await;
+    let pool = TestPool::new(accounts);
+    let state = AppState::for_test(pool.token_manager().await, upstream);
+    (state, pool)
+}
+
+/// 临时数据目录下的账号池 (<root>/accounts)，Drop 时删除整个目录
+pub struct TestPool {
+    root: PathBuf,
+}
+
+impl TestPool {
+    pub fn new(accounts: &[(&str, &str)]) -> Self {
+        let root = std::env::temp_dir().join(format!("antigravity-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("accounts")).unwrap();
+        let pool = Self { root };
+        for (id, email) in accounts {
+            pool.add_account(id, email);
+        }
+        pool
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn accounts_dir(&self) -> PathBuf {
+        self.root.join("accounts")
+    }
+
+    /// 写入可被 load_accounts 加载的账号文件 <id>.json (token 一小时后过期)
+    pub fn add_account(&self, id: &str, email: &str) {
+        self.add_account_with(id, email, serde_json::json!({}));
+    }
+
+    /// 同 add_account，extra 中的字段合并到账号 JSON 顶层 (对象字段浅合并，如 token、quota)
+    pub fn add_account_with(&self, id: &str, email: &str, extra: serde_json::Value) {
+        let now = chrono::Utc::now().timestamp();
+        let mut account = serde_json::json!({
+            "id": id,
+            "email": email,
+            "token": {
+                "access_token": format!("atk-{}", id),
+                "refresh_token": format!("rtk-{}", id),
+                "expires_in": 3600,
+                "expiry_timestamp": now + 3600,
+                "project_id": format!("pid-{}", id)
+            },
+            "disabled": false,
+            "proxy_disabled": false,
+            "created_at": now,
+            "last_used": now
+        });
+        if let (Some(base), Some(extra)) = (account.as_object_mut(), extra.as_object()) {
+            for (key, value) in extra {
+                match (base.get_mut(key), value) {
+                    (
+                        Some(serde_json::Value::Object(existing)),
+                        serde_json::Value::Object(fields),
+                    ) => {
+                        existing.extend(fields.clone());
+                    }
+                    _ => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        self.write_account_json(id, &account);
+    }
+
+    /// 原样写入账号 JSON，用于构造字段不完整或状态特殊的账号
+    pub fn write_account_json(&self, id: &str, account: &serde_json::Value) {
+        std::fs::write(
+            self.accounts_dir().join(format!("{}.json", id)),
+            serde_json::to_string_pretty(account).unwrap(),
+        )
+        .unwrap();
+    }
+
+    /// 以该目录创建 TokenManager 并加载账号
+    pub async fn token_manager(&self) -> Arc<TokenManager> {
+        let token_manager = Arc::new(TokenManager::new(self.root.clone()));
+        token_manager.load_accounts().await.unwrap();
+        token_manager
+    }
+}
+
+impl Drop for TestPool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod comprehensive;
+pub mod harness;
 pub mod security_ip_tests;
 pub mod security_integration_tests;
 pub mod quota_protection;
@@ -9,6 +9,7 @@ mod tests {
 
     use crate::models::QuotaProtectionConfig;
     use crate::proxy::common::model_mapping::normalize_to_standard_id;
+    use crate::proxy::tests::harness::TestPool;
     use crate::proxy::token_manager::ProxyToken;
 
     // ==================================================================================
@@ -980,9 +981,9 @@ mod tests {
 
     #[test]
     fn test_sorting_uses_target_model_quota_not_max() {
-        // 使用 std::env::temp_dir() 创建临时目录
-        let temp_dir = std::env::temp_dir().join(format!("test_sorting_{}", uuid::Uuid::new_v4()));
-        std::fs::create_dir_all(&temp_dir).expect("Failed to create temp dir");
+        // 临时账号目录，测试结束时自动清理
+        let pool = TestPool::new(&[]);
+        let temp_dir = pool.accounts_dir();
 
         // 账号 A: max=100 (gemini), sonnet=40
         let account_a_json = serde_json::json!({
@@ -1064,9 +1065,6 @@ mod tests {
             tokens[2].email, "carmelioventori@example.com",
             "sonnet=40% 的账号应该排第三"
         );
-
-        // 清理临时目录
-        let _ = std::fs::remove_dir_all(&temp_dir);
     }
 
     // ==================================================================================
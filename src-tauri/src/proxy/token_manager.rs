@@ -2,13 +2,86 @@
 use dashmap::DashMap;
 use std::collections::{HashSet, HashMap};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
+use crate::proxy::account_usage::{
+    AccountUsageTracker, DailyUsageTracker, FirstTokenLatencyTracker,
+};
+use crate::proxy::circuit_breaker::{BreakerSettings, BreakerStatus, ModelCircuitBreaker};
 use crate::proxy::rate_limit::RateLimitTracker;
 use crate::proxy::sticky_config::StickySessionConfig;
 
+/// 账号用量报告 (GET /api/stats/account-usage)
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AccountUsageReport {
+    pub account_id: String,
+    pub email: String,
+    pub window_seconds: i64,
+    /// 当前窗口结束时间 (unix 秒)，窗口内无请求时为空
+    pub window_resets_at: Option<i64>,
+    #[serde(flatten)]
+    pub usage: crate::proxy::account_usage::AccountUsageWindow,
+    pub total_tokens: u64,
+    pub rate_limited: bool,
+    pub rate_limit_reset_seconds: Option<u64>,
+    /// 配额刷新时间戳 (来自账号配额信息)
+    pub quota_reset_time: Option<i64>,
+    /// 排空开始时间 (维护模式)，未排空时为空
+    pub drained_since: Option<i64>,
+    /// [NEW] 每日预算用量与剩余额度
+    pub daily: DailyQuotaStatus,
+    /// [NEW] 最近窗口内的平均首字延迟 (毫秒)，无样本时为空
+    pub avg_first_token_ms: Option<u64>,
+    /// 是否因首字延迟过高被降级
+    pub slow: bool,
+}
+
+/// 账号当日预算状态
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DailyQuotaStatus {
+    pub requests: u64,
+    pub tokens: u64,
+    /// 剩余请求数，未配置上限时为空
+    pub remaining_requests: Option<u64>,
+    /// 剩余 token 数，未配置上限时为空
+    pub remaining_tokens: Option<u64>,
+    pub exhausted: bool,
+    /// 下一次重置时间 (unix 秒)
+    pub resets_at: i64,
+}
+
+/// 账号池就绪状态 (GET /readyz)
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct PoolReadiness {
+    pub total: usize,
+    /// 当前可被 get_token 选中的账号数
+    pub available: usize,
+    pub rate_limited: usize,
+    pub drained: usize,
+    pub validation_blocked: usize,
+}
+
+impl PoolReadiness {
+    pub fn is_ready(&self) -> bool {
+        self.available > 0
+    }
+}
+
+/// 后台预刷新检查间隔 (秒)
+const TOKEN_PREREFRESH_INTERVAL_SECS: u64 = 60;
+/// 距离过期不足该时间 (秒) 的 token 提前刷新；需大于 get_token 内的 300 秒同步刷新阈值
+const TOKEN_PREREFRESH_MARGIN_SECS: i64 = 600;
+
+/// 一轮 token 预刷新 / 预热的结果
+#[derive(Debug, Clone, Default)]
+pub struct TokenRefreshSummary {
+    pub refreshed: usize,
+    /// 刷新失败的账号 (email, 错误)
+    pub failed: Vec<(String, String)>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum OnDiskAccountState {
     Enabled,
@@ -42,13 +115,24 @@ pub struct TokenManager {
     last_used_account: Arc<tokio::sync::Mutex<Option<(String, std::time::Instant)>>>,
     data_dir: PathBuf,
     rate_limit_tracker: Arc<RateLimitTracker>, // 新增: 限流跟踪器
+    usage_tracker: Arc<AccountUsageTracker>,   // [NEW] 账号窗口用量估算
+    daily_usage: Arc<DailyUsageTracker>,       // [NEW] 账号当日用量 (每日预算)
+    first_token_latency: Arc<FirstTokenLatencyTracker>, // [NEW] 账号首字延迟 (慢账号降级)
+    model_breaker: Arc<ModelCircuitBreaker>,   // [NEW] 账号 + 模型级熔断
+    drained_accounts: Arc<DashMap<String, i64>>, // [NEW] 维护排空中的账号 (account_id -> drained_at)
     sticky_config: Arc<tokio::sync::RwLock<StickySessionConfig>>, // 新增：调度配置
     session_accounts: Arc<DashMap<String, String>>, // 新增：会话与账号映射 (SessionID -> AccountID)
+    session_last_seen: Arc<DashMap<String, std::time::Instant>>, // [NEW] 会话最近使用时间 (用于 TTL 过期)
     preferred_account_id: Arc<tokio::sync::RwLock<Option<String>>>, // [FIX #820] 优先使用的账号ID（固定账号模式）
+    pinned_rotation: Arc<AtomicBool>, // [NEW] 固定顺序调度 (仅用于调试)
     health_scores: Arc<DashMap<String, f32>>,                       // account_id -> health_score
     circuit_breaker_config: Arc<tokio::sync::RwLock<crate::models::CircuitBreakerConfig>>, // [NEW] 熔断配置缓存
+    pool_outage_probe_at: Arc<AtomicI64>, // [NEW] 账号池全部限流时最近一次放行试探的时间 (0 表示未处于短路)
+    account_usage_config: Arc<std::sync::RwLock<crate::proxy::config::AccountUsageConfig>>, // [NEW] 账号用量统计配置 (窗口 / 每日预算 / 慢账号)
+    workload_routing_config: Arc<std::sync::RwLock<crate::proxy::config::WorkloadRoutingConfig>>, // [NEW] 工作负载路由配置
     /// 支持优雅关闭时主动 abort 后台任务
     auto_cleanup_handle: Arc<tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    prerefresh_handle: Arc<tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>, // [NEW] token 预刷新任务
     cancel_token: CancellationToken,
 }
 
@@ -61,21 +145,36 @@ impl TokenManager {
             last_used_account: Arc::new(tokio::sync::Mutex::new(None)),
             data_dir,
             rate_limit_tracker: Arc::new(RateLimitTracker::new()),
+            usage_tracker: Arc::new(AccountUsageTracker::default()),
+            daily_usage: Arc::new(DailyUsageTracker::default()),
+            first_token_latency: Arc::new(FirstTokenLatencyTracker::default()),
+            model_breaker: Arc::new(ModelCircuitBreaker::default()),
+            drained_accounts: Arc::new(DashMap::new()),
             sticky_config: Arc::new(tokio::sync::RwLock::new(StickySessionConfig::default())),
             session_accounts: Arc::new(DashMap::new()),
+            session_last_seen: Arc::new(DashMap::new()),
             preferred_account_id: Arc::new(tokio::sync::RwLock::new(None)), // [FIX #820]
+            pinned_rotation: Arc::new(AtomicBool::new(false)),
             health_scores: Arc::new(DashMap::new()),
+            pool_outage_probe_at: Arc::new(AtomicI64::new(0)),
+            account_usage_config: Arc::new(std::sync::RwLock::new(Default::default())),
+            workload_routing_config: Arc::new(std::sync::RwLock::new(Default::default())),
             circuit_breaker_config: Arc::new(tokio::sync::RwLock::new(
                 crate::models::CircuitBreakerConfig::default(),
             )),
             auto_cleanup_handle: Arc::new(tokio::sync::Mutex::new(None)),
+            prerefresh_handle: Arc::new(tokio::sync::Mutex::new(None)),
             cancel_token: CancellationToken::new(),
         }
     }
 
     /// 启动限流记录自动清理后台任务（每15秒检查并清除过期记录）
+    /// 同时清理超过 TTL 的会话绑定
     pub async fn start_auto_cleanup(&self) {
         let tracker = self.rate_limit_tracker.clone();
+        let sticky_config = self.sticky_config.clone();
+        let session_accounts = self.session_accounts.clone();
+        let session_last_seen = self.session_last_seen.clone();
         let cancel = self.cancel_token.child_token();
 
         let handle = tokio::spawn(async move {
@@ -94,6 +193,19 @@ impl TokenManager {
                                 cleaned
                             );
                         }
+
+                        let ttl = sticky_config.read().await.session_ttl_seconds;
+                        let evicted = Self::evict_expired_sessions_in(
+                            &session_accounts,
+                            &session_last_seen,
+                            ttl,
+                        );
+                        if evicted > 0 {
+                            tracing::debug!(
+                                "Auto-cleanup: Evicted {} idle session binding(s)",
+                                evicted
+                            );
+                        }
                     }
                 }
             }
@@ -110,6 +222,145 @@ impl TokenManager {
         tracing::info!("Rate limit auto-cleanup task started (interval: 15s)");
     }
 
+    /// [NEW] 启动 token 预刷新后台任务
+    /// 启动时先预热 (刷新所有账号的 token，验证每个账号的 refresh_token 仍然有效并汇总失败账号)，
+    /// 之后每 60 秒刷新 10 分钟内过期的 token，使 get_token 几乎不需要在请求路径上同步刷新
+    /// 任务只持有弱引用，TokenManager 释放后自动退出
+    pub async fn start_token_prerefresh(self: &Arc<Self>) {
+        let manager = Arc::downgrade(self);
+        let cancel = self.cancel_token.child_token();
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+                TOKEN_PREREFRESH_INTERVAL_SECS,
+            ));
+            let mut warmed_up = false;
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        tracing::info!("Token pre-refresh task received cancel signal");
+                        break;
+                    }
+                    _ = interval.tick() => {
+                        let Some(manager) = manager.upgrade() else {
+                            break;
+                        };
+                        let summary = if warmed_up {
+                            manager
+                                .refresh_expiring_tokens(TOKEN_PREREFRESH_MARGIN_SECS)
+                                .await
+                        } else {
+                            manager.validate_all_tokens().await
+                        };
+                        if !warmed_up {
+                            warmed_up = true;
+                            tracing::info!(
+                                "Token warmup: {} account(s), {} validated, {} failed",
+                                manager.len(),
+                                summary.refreshed,
+                                summary.failed.len()
+                            );
+                            if !summary.failed.is_empty() {
+                                let failed: Vec<&str> =
+                                    summary.failed.iter().map(|(email, _)| email.as_str()).collect();
+                                tracing::warn!(
+                                    "Token warmup: accounts failing validation: {}",
+                                    failed.join(", ")
+                                );
+                            }
+                        } else if summary.refreshed > 0 {
+                            tracing::debug!(
+                                "Token pre-refresh: refreshed {} token(s)",
+                                summary.refreshed
+                            );
+                        }
+                        for (email, error) in &summary.failed {
+                            tracing::warn!("Token pre-refresh failed ({}): {}", email, error);
+                        }
+                    }
+                }
+            }
+        });
+
+        let mut guard = self.prerefresh_handle.lock().await;
+        if let Some(old) = guard.take() {
+            old.abort();
+            tracing::warn!("Aborted previous token pre-refresh task");
+        }
+        *guard = Some(handle);
+
+        tracing::info!(
+            "Token pre-refresh task started (interval: {}s, margin: {}s)",
+            TOKEN_PREREFRESH_INTERVAL_SECS,
+            TOKEN_PREREFRESH_MARGIN_SECS
+        );
+    }
+
+    /// 距离过期不足 margin_secs 秒的账号 (account_id)
+    fn accounts_needing_refresh(&self, now: i64, margin_secs: i64) -> Vec<String> {
+        self.tokens
+            .iter()
+            .filter(|entry| now >= entry.timestamp - margin_secs)
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// 刷新所有即将过期的 token
+    pub async fn refresh_expiring_tokens(&self, margin_secs: i64) -> TokenRefreshSummary {
+        let now = chrono::Utc::now().timestamp();
+        self.refresh_tokens(self.accounts_needing_refresh(now, margin_secs))
+            .await
+    }
+
+    /// [NEW] 刷新所有账号的 token，用于启动时验证每个账号 (无论 token 是否临近过期)
+    pub async fn validate_all_tokens(&self) -> TokenRefreshSummary {
+        let account_ids: Vec<String> = self.tokens.iter().map(|e| e.key().clone()).collect();
+        self.refresh_tokens(account_ids).await
+    }
+
+    /// 逐个串行刷新指定账号的 token，避免瞬间并发请求 OAuth 接口
+    async fn refresh_tokens(&self, account_ids: Vec<String>) -> TokenRefreshSummary {
+        let mut summary = TokenRefreshSummary::default();
+        for account_id in account_ids {
+            let Some((email, refresh_token)) = self
+                .tokens
+                .get(&account_id)
+                .map(|t| (t.email.clone(), t.refresh_token.clone()))
+            else {
+                continue;
+            };
+            match crate::modules::oauth::refresh_access_token(&refresh_token, Some(&account_id))
+                .await
+            {
+                Ok(token_response) => {
+                    let now = chrono::Utc::now().timestamp();
+                    if let Some(mut entry) = self.tokens.get_mut(&account_id) {
+                        entry.access_token = token_response.access_token.clone();
+                        entry.expires_in = token_response.expires_in;
+                        entry.timestamp = now + token_response.expires_in;
+                    }
+                    if let Err(e) = self
+                        .save_refreshed_token(&account_id, &token_response)
+                        .await
+                    {
+                        tracing::debug!("保存刷新后的 token 失败 ({}): {}", email, e);
+                    }
+                    summary.refreshed += 1;
+                }
+                Err(e) => {
+                    // 与 get_token 一致: refresh_token 失效时禁用账号
+                    if e.contains("invalid_grant") {
+                        let _ = self
+                            .disable_account(&account_id, &format!("invalid_grant: {}", e))
+                            .await;
+                    }
+                    summary.failed.push((email, e));
+                }
+            }
+        }
+        summary
+    }
+
     /// 从主应用账号目录加载所有账号
     pub async fn load_accounts(&self) -> Result<usize, String> {
         let accounts_dir = self.data_dir.join("accounts");
@@ -204,8 +455,13 @@ impl TokenManager {
         // 2. 清理相关的健康分数
         self.health_scores.remove(account_id);
 
-        // 3. 清理该账号的所有限流记录
+        // 3. 清理该账号的所有限流记录与用量统计
         self.clear_rate_limit(account_id);
+        self.usage_tracker.remove(account_id);
+        // 当日用量 (daily_usage) 保留: 账号重新加载后仍计入当日预算
+        self.first_token_latency.remove(account_id);
+        self.model_breaker.remove_account(account_id);
+        self.drained_accounts.remove(account_id);
 
         // 4. 清理涉及该账号的所有会话绑定
         self.session_accounts.retain(|_, v| v != account_id);
@@ -910,6 +1166,7 @@ impl TokenManager {
     /// abort() 仅设置取消标志，必须 await 确认清理完成
     pub async fn abort_background_tasks(&self) {
         Self::abort_task(&self.auto_cleanup_handle, "Auto-cleanup task").await;
+        Self::abort_task(&self.prerefresh_handle, "Token pre-refresh task").await;
     }
 
     /// 中止单个后台任务并记录结果
@@ -983,6 +1240,25 @@ impl TokenManager {
         }
     }
 
+    /// [NEW] 获取 Token，支持 X-Account-Override 强制指定账号
+    /// 指定账号时直接返回该账号的 Token，跳过健康度/粘性/轮换逻辑
+    pub async fn get_token_with_override(
+        &self,
+        account_override: Option<&str>,
+        quota_group: &str,
+        force_rotate: bool,
+        session_id: Option<&str>,
+        target_model: &str,
+    ) -> Result<(String, String, String, String, u64), String> {
+        match account_override {
+            Some(email) => self.get_token_by_email(email).await,
+            None => {
+                self.get_token(quota_group, force_rotate, session_id, target_model)
+                    .await
+            }
+        }
+    }
+
     /// 内部实现：获取 Token 的核心逻辑
     async fn get_token_internal(
         &self,
@@ -993,11 +1269,69 @@ impl TokenManager {
     ) -> Result<(String, String, String, String, u64), String> {
         let mut tokens_snapshot: Vec<ProxyToken> =
             self.tokens.iter().map(|e| e.value().clone()).collect();
-        let mut total = tokens_snapshot.len();
-        if total == 0 {
+        if tokens_snapshot.is_empty() {
             return Err("Token pool is empty".to_string());
         }
 
+        // [NEW] 排空 (drain) 中的账号不再接收新请求；已分配的进行中请求不受影响
+        if !self.drained_accounts.is_empty() {
+            tokens_snapshot.retain(|t| !self.drained_accounts.contains_key(&t.account_id));
+            if tokens_snapshot.is_empty() {
+                return Err("All accounts are drained for maintenance".to_string());
+            }
+        }
+
+        // [NEW] 每日预算: 当日已达上限的账号跳过，到重置时间后自动恢复
+        let daily_quota = self.get_account_usage_config().daily_quota;
+        if daily_quota.is_enabled() {
+            let now = chrono::Utc::now().timestamp();
+            tokens_snapshot.retain(|t| {
+                let exhausted = self.daily_quota_status(&daily_quota, t, now).exhausted;
+                if exhausted {
+                    tracing::debug!("[Daily-Quota] Skipping {}: daily budget reached", t.email);
+                }
+                !exhausted
+            });
+            if tokens_snapshot.is_empty() {
+                return Err("All accounts have reached their daily budget".to_string());
+            }
+        }
+
+        // [NEW] 工作负载路由: 该分类配置了专用账号时仅在其中选择；配置的账号均不可用时回退到全部账号
+        let routing = self.get_workload_routing_config();
+        if let Some(routed) = routing.accounts_for(quota_group) {
+            let dedicated: Vec<ProxyToken> = tokens_snapshot
+                .iter()
+                .filter(|t| routed.iter().any(|e| e.eq_ignore_ascii_case(&t.email)))
+                .cloned()
+                .collect();
+            // [FIX] 专用账号均处于限流中时同样回退，避免等待冷却而闲置其它账号
+            let routed_target =
+                crate::proxy::common::model_mapping::normalize_to_standard_id(target_model)
+                    .unwrap_or_else(|| target_model.to_string());
+            let mut any_available = false;
+            for t in &dedicated {
+                if !self.is_rate_limited(&t.account_id, Some(&routed_target)).await {
+                    any_available = true;
+                    break;
+                }
+            }
+            if !any_available {
+                tracing::warn!(
+                    "[Workload-Routing] No configured account available for {}, using full pool",
+                    quota_group
+                );
+            } else {
+                tracing::debug!(
+                    "[Workload-Routing] {} routed to {} dedicated account(s)",
+                    quota_group,
+                    dedicated.len()
+                );
+                tokens_snapshot = dedicated;
+            }
+        }
+        let mut total = tokens_snapshot.len();
+
         // ===== 【优化】Quota-First 排序: 保护低配额账号，均衡使用 =====
         // 优先级: 目标模型配额 > 健康分 > 订阅等级 > 刷新时间
         // -> 高配额账号优先被选中，避免 PRO/ULTRA 先用完丢失5小时刷新周期
@@ -1073,6 +1407,16 @@ impl TokenManager {
             )).collect::<Vec<_>>()
         );
 
+        // [NEW] 慢账号降级: 首字延迟持续超过阈值的账号后移 (仍可在其他账号不可用时使用)
+        let slow_account = self.get_account_usage_config().slow_account;
+        self.demote_slow_accounts(&mut tokens_snapshot, &slow_account);
+
+        // [NEW] 固定顺序调试模式: 忽略配额/健康度排序，按邮箱固定排序
+        let pinned_rotation = self.pinned_rotation.load(Ordering::Relaxed);
+        if pinned_rotation {
+            tokens_snapshot.sort_by(|a, b| a.email.cmp(&b.email));
+        }
+
         // 0. 读取当前调度配置
         let scheduling = self.sticky_config.read().await.clone();
         use crate::proxy::sticky_config::SchedulingMode;
@@ -1234,13 +1578,44 @@ impl TokenManager {
             let normalized_target = crate::proxy::common::model_mapping::normalize_to_standard_id(target_model)
                 .unwrap_or_else(|| target_model.to_string());
 
+            // 模式 P: [NEW] 固定顺序调试模式，按顺序选择第一个可用账号 (跳过限流 / 配额保护 / 已失败的账号)
+            if pinned_rotation {
+                for t in &tokens_snapshot {
+                    if attempted.contains(&t.account_id)
+                        || (quota_protection_enabled
+                            && t.protected_models.contains(&normalized_target))
+                        || self
+                            .is_rate_limited(&t.account_id, Some(&normalized_target))
+                            .await
+                    {
+                        continue;
+                    }
+                    tracing::debug!("📌 [Pinned Rotation] Selected account {}", t.email);
+                    target_token = Some(t.clone());
+                    break;
+                }
+            }
+
             // 模式 A: 粘性会话处理 (CacheFirst 或 Balance 且有 session_id)
-            if !rotate
+            // 绑定账号冷却中时为 true: 本次请求改用其他账号，但保留原绑定
+            let mut sticky_bypassed = false;
+            if !pinned_rotation
+                && !rotate
                 && session_id.is_some()
                 && scheduling.mode != SchedulingMode::PerformanceFirst
             {
                 let sid = session_id.unwrap();
 
+                // [NEW] 0. 会话空闲超过 TTL 则解除绑定，让其重新分配到更健康的账号
+                if self.is_session_expired(sid, scheduling.session_ttl_seconds) {
+                    tracing::debug!(
+                        "Sticky Session: Binding for session {} expired (ttl={}s), unbinding",
+                        sid,
+                        scheduling.session_ttl_seconds
+                    );
+                    self.clear_session_binding(sid);
+                }
+
                 // 1. 检查会话是否已绑定账号
                 if let Some(bound_id) = self.session_accounts.get(sid).map(|v| v.clone()) {
                     // 【修复】先通过 account_id 找到对应的账号，获取其 email
@@ -1253,20 +1628,27 @@ impl TokenManager {
                             .unwrap_or_else(|| bound_token.account_id.clone());
                         // [FIX] Pass None for specific model wait time if not applicable
                         let reset_sec = self.rate_limit_tracker.get_remaining_wait(&key, None);
-                        if reset_sec > 0 {
-                            // 【修复 Issue #284】立即解绑并切换账号，不再阻塞等待
+                        // [NEW] 模型级限流 / 熔断同样触发切换，避免会话卡在失败账号上
+                        let model_limited = self
+                            .is_rate_limited(&bound_token.account_id, Some(&normalized_target))
+                            .await;
+                        if reset_sec > 0 || model_limited {
+                            // 【修复 Issue #284】立即切换账号，不再阻塞等待
                             // 原因：阻塞等待会导致并发请求时客户端 socket 超时 (UND_ERR_SOCKET)
+                            // [NEW] 冷却期间仅临时绕过绑定（不改绑），账号恢复后会话自动回到原账号；
+                            // 若冷却时间超过 TTL，绑定将因空闲过期而被重新分配
                             tracing::debug!(
-                                "Sticky Session: Bound account {} is rate-limited ({}s), unbinding and switching.",
+                                "Sticky Session: Bound account {} is cooling down ({}s), temporarily bypassing binding.",
                                 bound_token.email, reset_sec
                             );
-                            self.session_accounts.remove(sid);
+                            sticky_bypassed = true;
                         } else if !attempted.contains(&bound_id)
                             && !(quota_protection_enabled
                                 && bound_token.protected_models.contains(&normalized_target))
                         {
                             // 3. 账号可用且未被标记为尝试失败，优先复用
                             tracing::debug!("Sticky Session: Successfully reusing bound account {} for session {}", bound_token.email, sid);
+                            self.touch_session(sid);
                             target_token = Some(bound_token.clone());
                         } else if quota_protection_enabled
                             && bound_token.protected_models.contains(&normalized_target)
@@ -1288,6 +1670,7 @@ impl TokenManager {
             // 模式 B: 原子化 60s 全局锁定 (针对无 session_id 情况的默认保护)
             // 【修复】性能优先模式应跳过 60s 锁定；
             if target_token.is_none()
+                && !pinned_rotation
                 && !rotate
                 && quota_group != "image_gen"
                 && scheduling.mode != SchedulingMode::PerformanceFirst
@@ -1346,9 +1729,11 @@ impl TokenManager {
 
                         // 如果是会话首次分配且需要粘性，在此建立绑定
                         if let Some(sid) = session_id {
-                            if scheduling.mode != SchedulingMode::PerformanceFirst {
+                            if scheduling.mode != SchedulingMode::PerformanceFirst && !sticky_bypassed {
                                 self.session_accounts
                                     .insert(sid.to_string(), selected.account_id.clone());
+                                self.touch_session(sid);
+                                self.enforce_session_cap(scheduling.max_sessions);
                                 tracing::debug!(
                                     "Sticky Session: Bound new account {} to session {}",
                                     selected.email,
@@ -1358,7 +1743,7 @@ impl TokenManager {
                         }
                     }
                 }
-            } else if target_token.is_none() {
+            } else if target_token.is_none() && !pinned_rotation {
                 // 模式 C: P2C 选择 (替代纯轮询)
                 tracing::debug!(
                     "🔄 [Mode C] P2C selection from {} candidates",
@@ -1671,6 +2056,20 @@ impl TokenManager {
         self.tokens.len()
     }
 
+    /// 各账号当前是否处于账号级限流 (account_id, is_limited)，供 /metrics 导出
+    /// 指标标签使用账号 ID 而非邮箱，避免抓取端暴露账号邮箱
+    pub fn account_rate_limit_states(&self) -> Vec<(String, bool)> {
+        self.tokens
+            .iter()
+            .map(|t| {
+                (
+                    t.account_id.clone(),
+                    self.rate_limit_tracker.is_rate_limited(&t.account_id, None),
+                )
+            })
+            .collect()
+    }
+
     /// 通过 email 获取指定账号的 Token（用于预热等需要指定账号的场景）
     /// 此方法会自动刷新过期的 token
     pub async fn get_token_by_email(
@@ -1764,6 +2163,8 @@ impl TokenManager {
         retry_after_header: Option<&str>,
         error_body: &str,
     ) {
+        self.record_rate_limit_hit(email, status);
+
         // [NEW] 检查熔断是否启用 (使用内存缓存，极快)
         let config = self.circuit_breaker_config.read().await.clone();
         if !config.enabled {
@@ -1791,6 +2192,7 @@ impl TokenManager {
             return false;
         }
         self.rate_limit_tracker.is_rate_limited(account_id, model)
+            || self.is_model_circuit_open(account_id, model)
     }
 
     /// [NEW] 检查账号是否在限流中 (同步版本，仅用于 Iterator)
@@ -1801,6 +2203,164 @@ impl TokenManager {
             return false;
         }
         self.rate_limit_tracker.is_rate_limited(account_id, model)
+            || self.is_model_circuit_open(account_id, model)
+    }
+
+    /// 统计账号池中当前可服务的账号 (排空 / 账号级限流冷却 / 验证阻止的账号视为不可用)
+    pub async fn pool_readiness(&self) -> PoolReadiness {
+        let now = chrono::Utc::now().timestamp();
+        let snapshot: Vec<(String, bool)> = self
+            .tokens
+            .iter()
+            .map(|e| {
+                let t = e.value();
+                (t.account_id.clone(), t.validation_blocked && t.validation_blocked_until > now)
+            })
+            .collect();
+        let mut readiness = PoolReadiness {
+            total: snapshot.len(),
+            ..Default::default()
+        };
+        for (account_id, validation_blocked) in snapshot {
+            if self.drained_accounts.contains_key(&account_id) {
+                readiness.drained += 1;
+            } else if validation_blocked {
+                readiness.validation_blocked += 1;
+            } else if self.is_rate_limited(&account_id, None).await {
+                readiness.rate_limited += 1;
+            } else {
+                readiness.available += 1;
+            }
+        }
+        readiness
+    }
+
+    /// [NEW] 账号池级短路: 全部账号处于限流冷却时，pool_outage_seconds 内的新请求直接快速失败，
+    /// 避免每个请求仍轮换多个账号放大故障期间的负载。返回建议的 Retry-After 秒数；
+    /// 每个周期放行一个试探请求 (返回 None)，试探成功或有账号恢复后解除短路
+    pub async fn pool_outage_retry_after(&self) -> Option<u64> {
+        let config = self.circuit_breaker_config.read().await.clone();
+        if !config.enabled || config.pool_outage_seconds == 0 {
+            return None;
+        }
+        self.pool_outage_retry_after_at(
+            config.pool_outage_seconds as i64,
+            chrono::Utc::now().timestamp(),
+        )
+        .await
+    }
+
+    async fn pool_outage_retry_after_at(&self, window_secs: i64, now: i64) -> Option<u64> {
+        let probe_at = self.pool_outage_probe_at.load(Ordering::Acquire);
+        if probe_at > 0 && now - probe_at < window_secs {
+            return Some((probe_at + window_secs - now) as u64);
+        }
+
+        let readiness = self.pool_readiness().await;
+        if readiness.available > 0 || readiness.rate_limited == 0 {
+            self.pool_outage_probe_at.store(0, Ordering::Release);
+            return None;
+        }
+
+        // 整个池仍处于限流: 首个到达的请求作为试探放行，其余请求继续快速失败
+        if self
+            .pool_outage_probe_at
+            .compare_exchange(probe_at, now, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            if probe_at == 0 {
+                tracing::warn!(
+                    "[Pool-Outage] All {} accounts are rate limited, failing fast for {}s between probes",
+                    readiness.rate_limited,
+                    window_secs
+                );
+            } else {
+                tracing::info!("[Pool-Outage] Letting a probe request through");
+            }
+            return None;
+        }
+        Some(window_secs as u64)
+    }
+
+    // ===== 账号排空 (维护模式) =====
+
+    /// 将账号 (email 或 account_id) 标记为排空：get_token 不再选中，进行中的请求正常完成。
+    /// 返回 account_id
+    pub fn drain_account(&self, email_or_id: &str) -> Result<String, String> {
+        let account_id = self.resolve_account_id(email_or_id)?;
+        self.drained_accounts
+            .entry(account_id.clone())
+            .or_insert_with(|| chrono::Utc::now().timestamp());
+        tracing::info!("[Drain] Account {} drained, no new requests will be routed", email_or_id);
+        Ok(account_id)
+    }
+
+    /// 取消排空，返回 account_id
+    pub fn undrain_account(&self, email_or_id: &str) -> Result<String, String> {
+        let account_id = self.resolve_account_id(email_or_id)?;
+        if self.drained_accounts.remove(&account_id).is_some() {
+            tracing::info!("[Drain] Account {} returned to rotation", email_or_id);
+        }
+        Ok(account_id)
+    }
+
+    /// 排空开始时间 (未排空返回 None)
+    pub fn drained_since(&self, account_id: &str) -> Option<i64> {
+        self.drained_accounts.get(account_id).map(|v| *v)
+    }
+
+    fn resolve_account_id(&self, email_or_id: &str) -> Result<String, String> {
+        if self.tokens.contains_key(email_or_id) {
+            return Ok(email_or_id.to_string());
+        }
+        self.email_to_account_id(email_or_id)
+            .ok_or_else(|| format!("Account not found: {}", email_or_id))
+    }
+
+    // ===== 账号 + 模型级熔断 =====
+
+    /// 熔断按配额组归一化后的模型名记录，与调度时的 normalized_target 一致
+    fn breaker_model_key(model: &str) -> String {
+        crate::proxy::common::model_mapping::normalize_to_standard_id(model)
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    fn is_model_circuit_open(&self, account_id: &str, model: Option<&str>) -> bool {
+        model.is_some_and(|m| {
+            self.model_breaker.is_open(
+                account_id,
+                &Self::breaker_model_key(m),
+                std::time::Instant::now(),
+            )
+        })
+    }
+
+    /// 记录 (账号, 模型) 的一次上游 5xx 失败，连续失败达到阈值后熔断该组合
+    pub async fn record_model_failure(&self, account_id: &str, model: &str) {
+        let settings = BreakerSettings::from_config(&*self.circuit_breaker_config.read().await);
+        self.model_breaker.record_failure(
+            account_id,
+            &Self::breaker_model_key(model),
+            &settings,
+            std::time::Instant::now(),
+        );
+    }
+
+    /// 请求成功: 关闭 (账号, 模型) 的熔断 (half_open 试探成功)
+    pub fn record_model_success(&self, email: &str, model: &str) {
+        // 任一请求成功即解除账号池短路
+        if self.pool_outage_probe_at.swap(0, Ordering::AcqRel) > 0 {
+            tracing::info!("[Pool-Outage] Request succeeded, pool short-circuit cleared");
+        }
+        let account_id = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
+        self.model_breaker
+            .record_success(&account_id, &Self::breaker_model_key(model));
+    }
+
+    /// 指定账号的熔断状态 (管理 API 展示)
+    pub fn model_breaker_statuses(&self, account_id: &str) -> Vec<BreakerStatus> {
+        self.model_breaker
+            .statuses(account_id, std::time::Instant::now())
     }
 
     /// 获取距离限流重置还有多少秒
@@ -1809,6 +2369,27 @@ impl TokenManager {
         self.rate_limit_tracker.get_reset_seconds(account_id)
     }
 
+    /// [NEW] 按邮箱查询剩余冷却秒数 (账号级与模型级锁取其一)，未限流时返回 0
+    pub fn get_cooldown_seconds_by_email(&self, email: &str, model: Option<&str>) -> u64 {
+        let account_id = self
+            .email_to_account_id(email)
+            .unwrap_or_else(|| email.to_string());
+        self.rate_limit_tracker.get_remaining_wait(&account_id, model)
+    }
+
+    /// [NEW] 账号池中最早结束的限流冷却 (秒)，没有账号处于冷却时返回 None
+    /// 用于所有账号均失败时的 Retry-After
+    pub fn soonest_cooldown_seconds(&self, model: Option<&str>) -> Option<u64> {
+        self.tokens
+            .iter()
+            .map(|entry| {
+                self.rate_limit_tracker
+                    .get_remaining_wait(&entry.value().account_id, model)
+            })
+            .filter(|&wait| wait > 0)
+            .min()
+    }
+
     /// 清除过期的限流记录
     #[allow(dead_code)]
     pub fn clean_expired_rate_limits(&self) {
@@ -1842,6 +2423,182 @@ impl TokenManager {
         self.rate_limit_tracker.mark_success(account_id);
     }
 
+    // ===== 账号用量估算 =====
+
+    fn usage_window_secs(&self) -> i64 {
+        self.get_account_usage_config().window_seconds()
+    }
+
+    /// 累计一次请求的 token 用量 (由监控中间件在解析到 usage 后调用)
+    pub fn record_account_usage(&self, email: &str, input_tokens: u32, output_tokens: u32) {
+        let key = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
+        let now = chrono::Utc::now().timestamp();
+        self.usage_tracker.record(
+            &key,
+            input_tokens as u64,
+            output_tokens as u64,
+            self.usage_window_secs(),
+            now,
+        );
+        let reset_offset = self
+            .get_account_usage_config()
+            .daily_quota
+            .reset_offset_secs();
+        self.daily_usage.record(
+            &key,
+            input_tokens as u64,
+            output_tokens as u64,
+            crate::proxy::account_usage::daily_period_start(now, reset_offset),
+        );
+    }
+
+    /// [NEW] 记录一次首字延迟 (由 handler 在 peek 到首个有效数据块时调用)
+    pub fn record_first_token_latency(&self, email: &str, latency_ms: u64) {
+        let key = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
+        let window_size = self
+            .get_account_usage_config()
+            .slow_account
+            .window_size;
+        let now = chrono::Utc::now().timestamp();
+        self.first_token_latency.record(&key, latency_ms, window_size, now);
+    }
+
+    /// 各账号窗口内的平均首字延迟 (account_id, 毫秒)，用于指标导出
+    pub fn account_first_token_latencies(&self) -> Vec<(String, u64)> {
+        let max_age = self
+            .get_account_usage_config()
+            .slow_account
+            .max_sample_age_secs;
+        let now = chrono::Utc::now().timestamp();
+        let mut latencies: Vec<(String, u64)> = self
+            .tokens
+            .iter()
+            .filter_map(|entry| {
+                let (avg, _) = self.first_token_latency.average_ms(
+                    &entry.value().account_id,
+                    max_age,
+                    now,
+                )?;
+                Some((entry.value().account_id.clone(), avg))
+            })
+            .collect();
+        latencies.sort();
+        latencies
+    }
+
+    /// 将慢账号稳定地移到队尾，其余账号保持原有排序
+    fn demote_slow_accounts(
+        &self,
+        tokens: &mut [ProxyToken],
+        config: &crate::proxy::config::SlowAccountConfig,
+    ) {
+        if !config.is_enabled() {
+            return;
+        }
+        // 每个账号只判定一次，避免排序比较时重复计算与重复日志
+        let now = chrono::Utc::now().timestamp();
+        let slow: HashSet<String> = tokens
+            .iter()
+            .filter(|t| self.first_token_latency.is_slow(&t.account_id, config, now))
+            .map(|t| {
+                tracing::debug!("[Slow-Account] Demoting {}: high time-to-first-token", t.email);
+                t.account_id.clone()
+            })
+            .collect();
+        if !slow.is_empty() {
+            tokens.sort_by_key(|t| slow.contains(&t.account_id));
+        }
+    }
+
+    /// 账号在 now 所在每日周期内的预算状态
+    fn daily_quota_status(
+        &self,
+        config: &crate::proxy::config::DailyQuotaConfig,
+        token: &ProxyToken,
+        now: i64,
+    ) -> DailyQuotaStatus {
+        let reset_offset = config.reset_offset_secs();
+        let usage = self.daily_usage.get(
+            &token.account_id,
+            crate::proxy::account_usage::daily_period_start(now, reset_offset),
+        );
+        let budget = config.budget_for(&token.email);
+        let remaining = |limit: u64, used: u64| (limit > 0).then(|| limit.saturating_sub(used));
+        DailyQuotaStatus {
+            requests: usage.requests,
+            tokens: usage.total_tokens(),
+            remaining_requests: remaining(budget.max_requests, usage.requests),
+            remaining_tokens: remaining(budget.max_tokens, usage.total_tokens()),
+            exhausted: budget.is_exhausted(usage.requests, usage.total_tokens()),
+            resets_at: crate::proxy::account_usage::next_daily_reset(now, reset_offset),
+        }
+    }
+
+    fn record_rate_limit_hit(&self, email: &str, status: u16) {
+        if status != 429 {
+            return;
+        }
+        let key = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
+        self.usage_tracker.record_rate_limit(
+            &key,
+            self.usage_window_secs(),
+            chrono::Utc::now().timestamp(),
+        );
+    }
+
+    /// 各账号当前窗口的用量估算，按 token 总量降序 (用于管理 API 查看负载分布)
+    pub fn account_usage_report(&self) -> Vec<AccountUsageReport> {
+        let window_secs = self.usage_window_secs();
+        let usage_config = self.get_account_usage_config();
+        let now = chrono::Utc::now().timestamp();
+        let mut report: Vec<AccountUsageReport> = self
+            .tokens
+            .iter()
+            .map(|entry| {
+                let token = entry.value();
+                let usage = self
+                    .usage_tracker
+                    .get(&token.account_id, window_secs, now)
+                    .unwrap_or_default();
+                let window_resets_at =
+                    (usage.window_start > 0).then(|| usage.window_start + window_secs);
+                AccountUsageReport {
+                    account_id: token.account_id.clone(),
+                    email: token.email.clone(),
+                    window_seconds: window_secs,
+                    window_resets_at,
+                    total_tokens: usage.total_tokens(),
+                    usage,
+                    // 直接读取限流记录 (blocking_read 不能在 async 上下文中调用)
+                    rate_limited: self
+                        .rate_limit_tracker
+                        .is_rate_limited(&token.account_id, None),
+                    rate_limit_reset_seconds: self.get_rate_limit_reset_seconds(&token.account_id),
+                    quota_reset_time: token.reset_time,
+                    drained_since: self.drained_since(&token.account_id),
+                    daily: self.daily_quota_status(&usage_config.daily_quota, token, now),
+                    avg_first_token_ms: self
+                        .first_token_latency
+                        .average_ms(
+                            &token.account_id,
+                            usage_config.slow_account.max_sample_age_secs,
+                            now,
+                        )
+                        .map(|(avg, _)| avg),
+                    slow: self
+                        .first_token_latency
+                        .is_slow(&token.account_id, &usage_config.slow_account, now),
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| {
+            b.total_tokens
+                .cmp(&a.total_tokens)
+                .then(a.email.cmp(&b.email))
+        });
+        report
+    }
+
     /// 检查是否有可用的 Google 账号
     ///
     /// 用于"仅兜底"模式的智能判断:当所有 Google 账号不可用时才使用外部提供商。
@@ -2051,6 +2808,8 @@ impl TokenManager {
         error_body: &str,
         model: Option<&str>, // 🆕 新增模型参数
     ) {
+        self.record_rate_limit_hit(email, status);
+
         // [NEW] 检查熔断是否启用
         let config = self.circuit_breaker_config.read().await.clone();
         if !config.enabled {
@@ -2060,6 +2819,11 @@ impl TokenManager {
         // [FIX] Convert email to account_id for consistent tracking
         let account_id = self.email_to_account_id(email).unwrap_or_else(|| email.to_string());
 
+        // [NEW] 5xx 计入 (账号, 模型) 熔断
+        if let Some(m) = model.filter(|_| matches!(status, 500 | 503 | 529)) {
+            self.record_model_failure(&account_id, m).await;
+        }
+
         // 检查 API 是否返回了精确的重试时间
         let has_explicit_retry_time = retry_after_header.is_some() ||
             error_body.contains("quotaResetDelay");
@@ -2164,15 +2928,135 @@ impl TokenManager {
         self.circuit_breaker_config.read().await.clone()
     }
 
+    /// [NEW] 更新账号用量统计配置
+    pub fn update_account_usage_config(&self, config: crate::proxy::config::AccountUsageConfig) {
+        if let Ok(mut lock) = self.account_usage_config.write() {
+            tracing::info!(
+                "[Account-Usage] Config updated: window_minutes={}",
+                config.window_minutes
+            );
+            *lock = config;
+        }
+    }
+
+    /// [NEW] 获取账号用量统计配置
+    pub fn get_account_usage_config(&self) -> crate::proxy::config::AccountUsageConfig {
+        self.account_usage_config
+            .read()
+            .map(|cfg| cfg.clone())
+            .unwrap_or_default()
+    }
+
+    /// [NEW] 更新工作负载路由配置
+    pub fn update_workload_routing_config(
+        &self,
+        config: crate::proxy::config::WorkloadRoutingConfig,
+    ) {
+        if let Ok(mut lock) = self.workload_routing_config.write() {
+            tracing::info!(
+                "[Workload-Routing] Config updated: {} routed workload(s)",
+                config.accounts.len()
+            );
+            *lock = config;
+        }
+    }
+
+    /// [NEW] 获取工作负载路由配置
+    pub fn get_workload_routing_config(&self) -> crate::proxy::config::WorkloadRoutingConfig {
+        self.workload_routing_config
+            .read()
+            .map(|cfg| cfg.clone())
+            .unwrap_or_default()
+    }
+
     /// 清除特定会话的粘性映射
-    #[allow(dead_code)]
-    pub fn clear_session_binding(&self, session_id: &str) {
-        self.session_accounts.remove(session_id);
+    pub fn clear_session_binding(&self, session_id: &str) -> bool {
+        self.session_last_seen.remove(session_id);
+        self.session_accounts.remove(session_id).is_some()
     }
 
     /// 清除所有会话的粘性映射
     pub fn clear_all_sessions(&self) {
         self.session_accounts.clear();
+        self.session_last_seen.clear();
+    }
+
+    /// [NEW] 当前已绑定账号的会话数量
+    pub fn session_count(&self) -> usize {
+        self.session_accounts.len()
+    }
+
+    /// [NEW] 立即清理所有超过 TTL 的会话绑定，返回清理数量
+    pub async fn evict_expired_sessions(&self) -> usize {
+        let ttl = self.sticky_config.read().await.session_ttl_seconds;
+        Self::evict_expired_sessions_in(&self.session_accounts, &self.session_last_seen, ttl)
+    }
+
+    /// 刷新会话最近使用时间
+    fn touch_session(&self, session_id: &str) {
+        self.session_last_seen
+            .insert(session_id.to_string(), std::time::Instant::now());
+    }
+
+    /// [NEW] LRU 上限：会话数超过 cap 时淘汰最久未使用的绑定 (cap=0 表示不限制)
+    /// 一次淘汰至 cap 的 90%，避免每个新会话都触发全量排序
+    fn enforce_session_cap(&self, cap: usize) {
+        if cap == 0 || self.session_accounts.len() <= cap {
+            return;
+        }
+        let target = cap - cap / 10;
+        let mut entries: Vec<(String, Option<std::time::Instant>)> = self
+            .session_accounts
+            .iter()
+            .map(|e| {
+                let sid = e.key().clone();
+                let seen = self.session_last_seen.get(&sid).map(|t| *t);
+                (sid, seen)
+            })
+            .collect();
+        // 无时间戳的条目视为最旧
+        entries.sort_by_key(|(_, seen)| *seen);
+        let evict = entries.len().saturating_sub(target);
+        for (sid, _) in entries.into_iter().take(evict) {
+            self.clear_session_binding(&sid);
+        }
+        tracing::debug!(
+            "Sticky Session: LRU cap {} reached, evicted {} least-recently-used binding(s)",
+            cap,
+            evict
+        );
+    }
+
+    /// 会话是否已空闲超过 TTL (ttl=0 表示永不过期)
+    fn is_session_expired(&self, session_id: &str, ttl_secs: u64) -> bool {
+        ttl_secs > 0
+            && self
+                .session_last_seen
+                .get(session_id)
+                .map(|t| t.elapsed().as_secs() >= ttl_secs)
+                .unwrap_or(false)
+    }
+
+    fn evict_expired_sessions_in(
+        session_accounts: &DashMap<String, String>,
+        session_last_seen: &DashMap<String, std::time::Instant>,
+        ttl_secs: u64,
+    ) -> usize {
+        // 先清理已被其他路径解绑 (账号移除/封禁) 的孤立时间戳
+        session_last_seen.retain(|sid, _| session_accounts.contains_key(sid));
+        if ttl_secs == 0 {
+            return 0;
+        }
+
+        let before = session_accounts.len();
+        session_accounts.retain(|sid, _| {
+            session_last_seen
+                .get(sid)
+                .map(|t| t.elapsed().as_secs() < ttl_secs)
+                .unwrap_or(true)
+        });
+        session_last_seen.retain(|sid, _| session_accounts.contains_key(sid));
+        before - session_accounts.len()
     }
 
     // ===== [FIX #820] 固定账号模式相关方法 =====
@@ -2194,6 +3078,22 @@ impl TokenManager {
         self.preferred_account_id.read().await.clone()
     }
 
+    /// [NEW] 固定顺序调度 (调试用，请勿在生产环境开启)
+    /// 开启后无粘性会话、无 60s 锁定、无 P2C 随机选择，所有请求按邮箱排序选择第一个可用账号，
+    /// 便于稳定复现与特定账号相关的问题；固定账号模式 (preferred account) 仍优先生效
+    pub fn set_pinned_rotation(&self, enabled: bool) {
+        self.pinned_rotation.store(enabled, Ordering::Relaxed);
+        if enabled {
+            tracing::warn!("📌 Pinned rotation enabled (debug only, do not use in production)");
+        } else {
+            tracing::info!("🔄 Pinned rotation disabled");
+        }
+    }
+
+    pub fn is_pinned_rotation(&self) -> bool {
+        self.pinned_rotation.load(Ordering::Relaxed)
+    }
+
     /// 使用 Authorization Code 交换 Refresh Token (Web OAuth)
     pub async fn exchange_code(&self, code: &str, redirect_uri: &str) -> Result<String, String> {
         crate::modules::oauth::exchange_code(code, redirect_uri)
@@ -2447,21 +3347,16 @@ fn truncate_reason(reason: &str, max_len: usize) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proxy::tests::harness::TestPool;
     use std::cmp::Ordering;
 
     #[tokio::test]
     async fn test_reload_account_purges_cache_when_account_becomes_proxy_disabled() {
-        let tmp_root = std::env::temp_dir().join(format!(
-            "antigravity-token-manager-test-{}",
-            uuid::Uuid::new_v4()
-        ));
-        let accounts_dir = tmp_root.join("accounts");
-        std::fs::create_dir_all(&accounts_dir).unwrap();
+        let pool = TestPool::new(&[]);
 
         let account_id = "acc1";
         let email = "a@test.com";
         let now = chrono::Utc::now().timestamp();
-        let account_path = accounts_dir.join(format!("{}.json", account_id));
 
         let account_json = serde_json::json!({
             "id": account_id,
@@ -2477,10 +3372,9 @@ mod tests {
             "created_at": now,
             "last_used": now
         });
-        std::fs::write(&account_path, serde_json::to_string_pretty(&account_json).unwrap()).unwrap();
+        pool.write_account_json(account_id, &account_json);
 
-        let manager = TokenManager::new(tmp_root.clone());
-        manager.load_accounts().await.unwrap();
+        let manager = pool.token_manager().await;
         assert!(manager.tokens.get(account_id).is_some());
 
         // Prime extra caches to ensure remove_account() is really called.
@@ -2497,55 +3391,35 @@ mod tests {
         disabled_json["proxy_disabled"] = serde_json::Value::Bool(true);
         disabled_json["proxy_disabled_reason"] = serde_json::Value::String("manual".to_string());
         disabled_json["proxy_disabled_at"] = serde_json::Value::Number(now.into());
-        std::fs::write(&account_path, serde_json::to_string_pretty(&disabled_json).unwrap()).unwrap();
+        pool.write_account_json(account_id, &disabled_json);
 
         manager.reload_account(account_id).await.unwrap();
 
         assert!(manager.tokens.get(account_id).is_none());
         assert!(manager.session_accounts.get("sid1").is_none());
         assert!(manager.preferred_account_id.read().await.is_none());
-
-        let _ = std::fs::remove_dir_all(&tmp_root);
     }
 
     #[tokio::test]
     async fn test_fixed_account_mode_skips_preferred_when_disabled_on_disk_without_reload() {
-        let tmp_root = std::env::temp_dir().join(format!(
-            "antigravity-token-manager-test-fixed-mode-{}",
-            uuid::Uuid::new_v4()
-        ));
-        let accounts_dir = tmp_root.join("accounts");
-        std::fs::create_dir_all(&accounts_dir).unwrap();
-
-        let now = chrono::Utc::now().timestamp();
+        let pool = TestPool::new(&[]);
 
         let write_account = |id: &str, email: &str, proxy_disabled: bool| {
-            let account_path = accounts_dir.join(format!("{}.json", id));
-            let json = serde_json::json!({
-                "id": id,
-                "email": email,
-                "token": {
-                    "access_token": format!("atk-{}", id),
-                    "refresh_token": format!("rtk-{}", id),
-                    "expires_in": 3600,
-                    "expiry_timestamp": now + 3600,
-                    "project_id": format!("pid-{}", id)
-                },
-                "disabled": false,
-                "proxy_disabled": proxy_disabled,
-                "proxy_disabled_reason": if proxy_disabled { "manual" } else { "" },
-                "created_at": now,
-                "last_used": now
-            });
-            std::fs::write(&account_path, serde_json::to_string_pretty(&json).unwrap()).unwrap();
+            pool.add_account_with(
+                id,
+                email,
+                serde_json::json!({
+                    "proxy_disabled": proxy_disabled,
+                    "proxy_disabled_reason": if proxy_disabled { "manual" } else { "" }
+                }),
+            );
         };
 
         // Two accounts in pool.
         write_account("acc1", "a@test.com", false);
         write_account("acc2", "b@test.com", false);
 
-        let manager = TokenManager::new(tmp_root.clone());
-        manager.load_accounts().await.unwrap();
+        let manager = pool.token_manager().await;
 
         // Enable fixed account mode for acc1.
         manager.set_preferred_account(Some("acc1".to_string())).await;
@@ -2563,53 +3437,68 @@ mod tests {
         assert_eq!(email, "b@test.com");
         assert!(manager.tokens.get("acc1").is_none());
         assert!(manager.get_preferred_account().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_rotation_picks_same_first_account() {
+        let pool = TestPool::new(&[("acc3", "c@test.com"), ("acc1", "a@test.com"), ("acc2", "b@test.com")]);
+
+        let manager = pool.token_manager().await;
+        // 正常调度下健康度最低的账号会被排到最后
+        if let Some(mut entry) = manager.tokens.get_mut("acc1") {
+            entry.health_score = 0.1;
+        }
+        manager.set_pinned_rotation(true);
+
+        let mut picked = Vec::new();
+        for _ in 0..2 {
+            let (_, _, email, _, _) = manager
+                .get_token("gemini", false, None, "gemini-1.5-flash")
+                .await
+                .unwrap();
+            picked.push(email);
+        }
+        assert_eq!(picked, vec!["a@test.com", "a@test.com"]);
 
-        let _ = std::fs::remove_dir_all(&tmp_root);
+        // 限流中的账号被跳过，按顺序选择下一个
+        manager.rate_limit_tracker.set_lockout_until(
+            "acc1",
+            std::time::SystemTime::now() + std::time::Duration::from_secs(300),
+            crate::proxy::rate_limit::RateLimitReason::QuotaExhausted,
+            None,
+        );
+        let (_, _, email, _, _) = manager
+            .get_token("gemini", false, None, "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(email, "b@test.com");
     }
 
     #[tokio::test]
     async fn test_sticky_session_skips_bound_account_when_disabled_on_disk_without_reload() {
-        let tmp_root = std::env::temp_dir().join(format!(
-            "antigravity-token-manager-test-sticky-disabled-{}",
-            uuid::Uuid::new_v4()
-        ));
-        let accounts_dir = tmp_root.join("accounts");
-        std::fs::create_dir_all(&accounts_dir).unwrap();
-
-        let now = chrono::Utc::now().timestamp();
+        let pool = TestPool::new(&[]);
 
         let write_account = |id: &str, email: &str, percentage: i64, proxy_disabled: bool| {
-            let account_path = accounts_dir.join(format!("{}.json", id));
-            let json = serde_json::json!({
-                "id": id,
-                "email": email,
-                "token": {
-                    "access_token": format!("atk-{}", id),
-                    "refresh_token": format!("rtk-{}", id),
-                    "expires_in": 3600,
-                    "expiry_timestamp": now + 3600,
-                    "project_id": format!("pid-{}", id)
-                },
-                "quota": {
-                    "models": [
-                        { "name": "gemini-1.5-flash", "percentage": percentage }
-                    ]
-                },
-                "disabled": false,
-                "proxy_disabled": proxy_disabled,
-                "proxy_disabled_reason": if proxy_disabled { "manual" } else { "" },
-                "created_at": now,
-                "last_used": now
-            });
-            std::fs::write(&account_path, serde_json::to_string_pretty(&json).unwrap()).unwrap();
+            pool.add_account_with(
+                id,
+                email,
+                serde_json::json!({
+                    "quota": {
+                        "models": [
+                            { "name": "gemini-1.5-flash", "percentage": percentage }
+                        ]
+                    },
+                    "proxy_disabled": proxy_disabled,
+                    "proxy_disabled_reason": if proxy_disabled { "manual" } else { "" }
+                }),
+            );
         };
 
         // Two accounts in pool. acc1 has higher quota -> should be selected and bound first.
         write_account("acc1", "a@test.com", 90, false);
         write_account("acc2", "b@test.com", 10, false);
 
-        let manager = TokenManager::new(tmp_root.clone());
-        manager.load_accounts().await.unwrap();
+        let manager = pool.token_manager().await;
 
         // Prime: first request should bind the session to acc1.
         let (_token, _project_id, _email, account_id, _wait_ms) = manager
@@ -2638,8 +3527,136 @@ mod tests {
             manager.session_accounts.get("sid1").map(|v| v.clone()),
             Some("acc1".to_string())
         );
+    }
+
+    #[tokio::test]
+    async fn test_sticky_session_ttl_and_repin_on_rate_limit() {
+        let pool = TestPool::new(&[]);
+
+        let write_account = |id: &str, email: &str, percentage: i64| {
+            pool.add_account_with(
+                id,
+                email,
+                serde_json::json!({
+                    "quota": { "models": [{ "name": "gemini-1.5-flash", "percentage": percentage }] }
+                }),
+            );
+        };
 
-        let _ = std::fs::remove_dir_all(&tmp_root);
+        write_account("acc1", "a@test.com", 90);
+        write_account("acc2", "b@test.com", 10);
+
+        let manager = pool.token_manager().await;
+        manager
+            .update_sticky_config(StickySessionConfig {
+                session_ttl_seconds: 60,
+                ..Default::default()
+            })
+            .await;
+
+        // Prime: session binds to acc1.
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, Some("sid1"), "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc1");
+        assert_eq!(manager.session_count(), 1);
+
+        // acc1 enters a model-level cooldown -> the session temporarily breaks affinity instead of failing.
+        manager.rate_limit_tracker.set_lockout_until(
+            "acc1",
+            std::time::SystemTime::now() + std::time::Duration::from_secs(300),
+            crate::proxy::rate_limit::RateLimitReason::QuotaExhausted,
+            Some("gemini-1.5-flash".to_string()),
+        );
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, Some("sid1"), "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc2");
+        // Binding is kept so the session can return once acc1 recovers.
+        assert_eq!(
+            manager.session_accounts.get("sid1").map(|v| v.clone()),
+            Some("acc1".to_string())
+        );
+
+        // Cooldown over -> re-pinned to the original account.
+        manager.rate_limit_tracker.clear_all();
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, Some("sid1"), "gemini-1.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "acc1");
+
+        // Idle longer than TTL -> binding expires on next use and is reassigned.
+        // Stale binding points at acc2 while a fresh P2C pick prefers acc1 (higher quota);
+        // clear the 60s reuse window so the reassignment goes through P2C and re-binds.
+        *manager.last_used_account.lock().await = None;
+        manager
+            .session_accounts
+            .insert("sid-idle".to_string(), "acc2".to_string());
+        manager.session_last_seen.insert(
+            "sid-idle".to_string(),
+            std::time::Instant::now() - std::time::Duration::from_secs(120),
+        );
+        let (_, _, _, account_id, _) = manager
+            .get_token("gemini", false, Some("sid-idle"), "gemini-1.5-flash")
+            .await
+            .unwrap();
+        // Expired binding dropped -> re-bound to acc1 with a fresh last_seen.
+        assert_eq!(account_id, "acc1");
+        assert_eq!(
+            manager.session_accounts.get("sid-idle").map(|v| v.clone()),
+            Some("acc1".to_string())
+        );
+        let last_seen = *manager
+            .session_last_seen
+            .get("sid-idle")
+            .expect("rebound session should record last_seen");
+        assert!(last_seen.elapsed() < std::time::Duration::from_secs(5));
+        manager.clear_session_binding("sid-idle");
+
+        // Idle longer than TTL -> evicted by the sweeper.
+        manager.session_last_seen.insert(
+            "sid1".to_string(),
+            std::time::Instant::now() - std::time::Duration::from_secs(120),
+        );
+        assert_eq!(manager.evict_expired_sessions().await, 1);
+        assert_eq!(manager.session_count(), 0);
+
+        // Manual eviction.
+        manager
+            .session_accounts
+            .insert("sid2".to_string(), "acc2".to_string());
+        assert!(manager.clear_session_binding("sid2"));
+        assert!(!manager.clear_session_binding("sid2"));
+    }
+
+    #[test]
+    fn test_session_lru_cap_evicts_least_recently_used() {
+        let pool = TestPool::new(&[]);
+        let manager = TokenManager::new(pool.root().to_path_buf());
+        let base = std::time::Instant::now() - std::time::Duration::from_secs(1000);
+        for i in 0..12u64 {
+            let sid = format!("sid{}", i);
+            manager.session_accounts.insert(sid.clone(), "acc1".to_string());
+            manager
+                .session_last_seen
+                .insert(sid, base + std::time::Duration::from_secs(i));
+        }
+
+        // 超出上限 10 -> 淘汰至 9 个，保留最近使用的会话
+        manager.enforce_session_cap(10);
+        assert_eq!(manager.session_count(), 9);
+        assert!(manager.session_accounts.get("sid0").is_none());
+        assert!(manager.session_accounts.get("sid2").is_none());
+        assert!(manager.session_accounts.get("sid3").is_some());
+        assert!(manager.session_accounts.get("sid11").is_some());
+        assert_eq!(manager.session_last_seen.len(), 9);
+
+        // cap=0 不限制
+        manager.enforce_session_cap(0);
+        assert_eq!(manager.session_count(), 9);
     }
 
     /// 创建测试用的 ProxyToken
@@ -3025,4 +4042,420 @@ mod tests {
         let result = manager.select_with_p2c(&candidates, &attempted, "claude-sonnet", false);
         assert!(result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_account_usage_survives_rate_limit_transitions() {
+        let manager = TokenManager::new(PathBuf::from("/tmp/test"));
+        let token = create_test_token("usage@test.com", Some("PRO"), 1.0, None, None);
+        manager.tokens.insert(token.account_id.clone(), token);
+
+        manager.record_account_usage("usage@test.com", 1200, 300);
+        manager
+            .mark_rate_limited("usage@test.com", 429, Some("30"), "")
+            .await;
+        manager.mark_account_success("usage@test.com");
+        manager.clear_rate_limit("usage@test.com");
+        manager.record_account_usage("usage@test.com", 800, 200);
+
+        let report = manager.account_usage_report();
+        assert_eq!(report.len(), 1);
+        let entry = &report[0];
+        assert_eq!(entry.email, "usage@test.com");
+        assert_eq!(entry.usage.requests, 2);
+        assert_eq!(entry.usage.input_tokens, 2000);
+        assert_eq!(entry.total_tokens, 2500);
+        assert_eq!(entry.usage.rate_limit_hits, 1);
+        assert_eq!(
+            entry.window_resets_at,
+            Some(entry.usage.window_start + entry.window_seconds)
+        );
+
+        // 移除账号时一并清理
+        manager.remove_account("usage@test.com");
+        assert!(manager.account_usage_report().is_empty());
+        assert!(manager
+            .usage_tracker
+            .get(
+                "usage@test.com",
+                entry.window_seconds,
+                chrono::Utc::now().timestamp()
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_metric_account_labels_use_account_id() {
+        let manager = TokenManager::new(PathBuf::from("/tmp/test"));
+        let mut token = create_test_token("metrics@test.com", Some("PRO"), 1.0, None, None);
+        token.account_id = "acc-metrics".to_string();
+        manager.tokens.insert(token.account_id.clone(), token);
+        manager.record_first_token_latency("metrics@test.com", 1200);
+
+        assert_eq!(
+            manager.account_rate_limit_states(),
+            vec![("acc-metrics".to_string(), false)]
+        );
+        assert_eq!(
+            manager.account_first_token_latencies(),
+            vec![("acc-metrics".to_string(), 1200)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_soonest_cooldown_across_pool() {
+        let manager = TokenManager::new(PathBuf::from("/tmp/test"));
+        for email in ["slow@test.com", "fast@test.com", "idle@test.com"] {
+            let token = create_test_token(email, Some("PRO"), 1.0, None, None);
+            manager.tokens.insert(token.account_id.clone(), token);
+        }
+        assert_eq!(manager.soonest_cooldown_seconds(None), None);
+
+        manager
+            .mark_rate_limited("slow@test.com", 429, Some("120"), "")
+            .await;
+        manager
+            .mark_rate_limited("fast@test.com", 429, Some("45"), "")
+            .await;
+        // 未限流的账号不参与计算，取最早结束的冷却
+        let soonest = manager.soonest_cooldown_seconds(None).unwrap();
+        assert!((40..=45).contains(&soonest), "soonest = {}", soonest);
+    }
+
+    #[tokio::test]
+    async fn test_model_circuit_breaker_skips_only_failing_model() {
+        let manager = TokenManager::new(PathBuf::from("/tmp/test"));
+        let token = create_test_token("breaker@test.com", Some("PRO"), 1.0, None, None);
+        let account_id = token.account_id.clone();
+        manager.tokens.insert(account_id.clone(), token);
+
+        for _ in 0..3 {
+            manager.record_model_failure(&account_id, "gemini-2.5-flash").await;
+        }
+        assert!(manager.is_rate_limited(&account_id, Some("gemini-2.5-flash")).await);
+        assert!(!manager.is_rate_limited(&account_id, Some("claude-sonnet-4-5")).await);
+        assert!(!manager.is_rate_limited(&account_id, None).await);
+        let statuses = manager.model_breaker_statuses(&account_id);
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].consecutive_failures, 3);
+
+        manager.record_model_success("breaker@test.com", "gemini-2.5-flash");
+        assert!(!manager.is_rate_limited(&account_id, Some("gemini-2.5-flash")).await);
+        assert!(manager.model_breaker_statuses(&account_id).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drained_account_skipped_while_in_flight_completes() {
+        let pool = TestPool::new(&[]);
+
+        for (id, email, percentage) in [
+            ("acc1", "drain-a@test.com", 90),
+            ("acc2", "drain-b@test.com", 10),
+        ] {
+            pool.add_account_with(
+                id,
+                email,
+                serde_json::json!({
+                    "quota": { "models": [{ "name": "gemini-2.5-flash", "percentage": percentage }] }
+                }),
+            );
+        }
+
+        let manager = pool.token_manager().await;
+
+        // 排空前分配到 primary 的请求 (进行中)
+        let (in_flight_token, _, in_flight_email, _, _) = manager
+            .get_token("gemini", false, None, "gemini-2.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(in_flight_email, "drain-a@test.com");
+
+        assert_eq!(manager.drain_account("drain-a@test.com").unwrap(), "acc1");
+        assert!(manager.drained_since("acc1").is_some());
+
+        // 新请求跳过排空账号
+        for _ in 0..3 {
+            let (_, _, email, _, _) = manager
+                .get_token("gemini", false, None, "gemini-2.5-flash")
+                .await
+                .unwrap();
+            assert_eq!(email, "drain-b@test.com");
+        }
+
+        // 进行中的请求正常完成: 账号仍在池中，成功与用量照常记录
+        assert_eq!(in_flight_token, "atk-acc1");
+        manager.mark_account_success(&in_flight_email);
+        manager.record_account_usage(&in_flight_email, 10, 5);
+        let report = manager.account_usage_report();
+        let drained = report
+            .iter()
+            .find(|r| r.email == "drain-a@test.com")
+            .unwrap();
+        assert_eq!(drained.usage.requests, 1);
+        assert!(drained.drained_since.is_some());
+
+        // 恢复后重新参与轮换 (排空另一个账号以确定选中结果)
+        manager.undrain_account("acc1").unwrap();
+        assert!(manager.drained_since("acc1").is_none());
+        manager.drain_account("drain-b@test.com").unwrap();
+        let (_, _, email, _, _) = manager
+            .get_token("gemini", false, None, "gemini-2.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(email, "drain-a@test.com");
+        assert!(manager.drain_account("missing@test.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_account_over_daily_budget_skipped_until_reset() {
+        let pool = TestPool::new(&[]);
+
+        for (id, email, percentage) in [
+            ("acc1", "daily-a@test.com", 90),
+            ("acc2", "daily-b@test.com", 10),
+        ] {
+            pool.add_account_with(
+                id,
+                email,
+                serde_json::json!({
+                    "quota": { "models": [{ "name": "gemini-2.5-flash", "percentage": percentage }] }
+                }),
+            );
+        }
+
+        let manager = pool.token_manager().await;
+
+        // 仅为测试账号单独配置预算，全局不限制
+        let mut usage_config = manager.get_account_usage_config();
+        usage_config.daily_quota.accounts.insert(
+            "daily-a@test.com".to_string(),
+            crate::proxy::config::DailyBudget {
+                max_requests: 2,
+                max_tokens: 0,
+            },
+        );
+        manager.update_account_usage_config(usage_config.clone());
+
+        let (_, _, email, _, _) = manager
+            .get_token("gemini", false, None, "gemini-2.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(email, "daily-a@test.com");
+
+        // 达到当日上限后跳过
+        manager.record_account_usage("daily-a@test.com", 10, 5);
+        manager.record_account_usage("daily-a@test.com", 10, 5);
+        for _ in 0..3 {
+            let (_, _, email, _, _) = manager
+                .get_token("gemini", false, None, "gemini-2.5-flash")
+                .await
+                .unwrap();
+            assert_eq!(email, "daily-b@test.com");
+        }
+
+        let report = manager.account_usage_report();
+        let daily = &report
+            .iter()
+            .find(|r| r.email == "daily-a@test.com")
+            .unwrap()
+            .daily;
+        assert!(daily.exhausted);
+        assert_eq!(daily.remaining_requests, Some(0));
+        assert_eq!(daily.remaining_tokens, None);
+
+        // 跨过重置时间后恢复可用
+        let token = manager.tokens.get("acc1").unwrap().clone();
+        let status = manager.daily_quota_status(&usage_config.daily_quota, &token, daily.resets_at);
+        assert!(!status.exhausted);
+        assert_eq!(status.remaining_requests, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_workload_routing_falls_back_when_dedicated_accounts_limited() {
+        let pool = TestPool::new(&[]);
+
+        // 专用账号配额较低，未路由时不会被优先选中
+        for (id, email, percentage) in [
+            ("acc1", "routing-a@test.com", 10),
+            ("acc2", "routing-b@test.com", 90),
+        ] {
+            pool.add_account_with(
+                id,
+                email,
+                serde_json::json!({
+                    "quota": { "models": [{ "name": "gemini-2.5-flash", "percentage": percentage }] }
+                }),
+            );
+        }
+
+        let manager = pool.token_manager().await;
+
+        // 仅为测试专用的分类配置路由，不影响其它分类
+        let group = "routing-fallback-test";
+        let mut routing = manager.get_workload_routing_config();
+        routing
+            .accounts
+            .insert(group.to_string(), vec!["routing-a@test.com".to_string()]);
+        manager.update_workload_routing_config(routing);
+
+        let (_, _, email, _, _) = manager
+            .get_token(group, false, None, "gemini-2.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(email, "routing-a@test.com");
+
+        // 专用账号存在但处于限流中: 回退到全部账号，而不是等待冷却
+        manager
+            .mark_rate_limited("routing-a@test.com", 429, Some("60"), "")
+            .await;
+        let (_, _, email, _, _) = manager
+            .get_token(group, true, None, "gemini-2.5-flash")
+            .await
+            .unwrap();
+        assert_eq!(email, "routing-b@test.com");
+    }
+
+    #[test]
+    fn test_slow_account_demoted_by_first_token_latency() {
+        let pool = TestPool::new(&[]);
+        let manager = TokenManager::new(pool.root().to_path_buf());
+        let config = crate::proxy::config::SlowAccountConfig {
+            ttft_threshold_ms: 3000,
+            window_size: 10,
+            min_samples: 3,
+            max_sample_age_secs: 600,
+        };
+
+        // slow 持续超过阈值；flaky 仅偶发慢请求；new 样本不足
+        for latency in [4200, 5100, 3900, 4800] {
+            manager.record_first_token_latency("slow@test.com", latency);
+        }
+        for latency in [800, 9000, 700, 900] {
+            manager.record_first_token_latency("flaky@test.com", latency);
+        }
+        for latency in [6000, 7000] {
+            manager.record_first_token_latency("new@test.com", latency);
+        }
+
+        let mut tokens: Vec<ProxyToken> = ["slow", "fast", "flaky", "new"]
+            .iter()
+            .map(|name| create_test_token(&format!("{}@test.com", name), None, 1.0, None, None))
+            .collect();
+        manager.demote_slow_accounts(&mut tokens, &config);
+        let order: Vec<&str> = tokens.iter().map(|t| t.email.as_str()).collect();
+        assert_eq!(
+            order,
+            vec!["fast@test.com", "flaky@test.com", "new@test.com", "slow@test.com"]
+        );
+
+        // 阈值为 0 时不降级
+        let mut tokens = tokens.into_iter().rev().collect::<Vec<_>>();
+        manager.demote_slow_accounts(&mut tokens, &Default::default());
+        assert_eq!(tokens[0].email, "slow@test.com");
+
+        // 被降级后不再产生新样本：旧样本过期后恢复参与正常排序
+        let now = chrono::Utc::now().timestamp();
+        assert!(manager.first_token_latency.is_slow("slow@test.com", &config, now));
+        assert!(!manager.first_token_latency.is_slow("slow@test.com", &config, now + 601));
+
+        // 更快的样本滑出窗口后恢复正常排序
+        for _ in 0..10 {
+            manager.record_first_token_latency("slow@test.com", 500);
+        }
+        assert!(!manager.first_token_latency.is_slow("slow@test.com", &config, now));
+    }
+
+    #[tokio::test]
+    async fn test_pool_outage_short_circuits_until_probe_succeeds() {
+        use crate::proxy::handlers::common::pool_outage_response;
+
+        let pool = TestPool::new(&[]);
+        let now = chrono::Utc::now().timestamp();
+        for id in ["outage-a", "outage-b"] {
+            pool.add_account(id, &format!("{}@test.com", id));
+        }
+        let manager = pool.token_manager().await;
+
+        // 仍有可用账号时不短路
+        assert_eq!(manager.pool_outage_retry_after().await, None);
+        manager
+            .mark_rate_limited("outage-a@test.com", 429, Some("120"), "")
+            .await;
+        assert_eq!(manager.pool_outage_retry_after().await, None);
+
+        // 全部限流: 首个请求作为试探放行，其余请求在窗口内快速失败
+        manager
+            .mark_rate_limited("outage-b@test.com", 429, Some("120"), "")
+            .await;
+        assert_eq!(manager.pool_outage_retry_after_at(15, now).await, None);
+        assert_eq!(
+            manager.pool_outage_retry_after_at(15, now + 5).await,
+            Some(10)
+        );
+        let resp = pool_outage_response(&manager, None).await.unwrap();
+        assert_eq!(resp.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key(axum::http::header::RETRY_AFTER));
+        // 指定账号的请求不受影响
+        assert!(pool_outage_response(&manager, Some("outage-a@test.com"))
+            .await
+            .is_none());
+
+        // 窗口结束后再放行一个试探；试探失败则继续短路
+        assert_eq!(manager.pool_outage_retry_after_at(15, now + 15).await, None);
+        assert_eq!(
+            manager.pool_outage_retry_after_at(15, now + 16).await,
+            Some(14)
+        );
+
+        // 试探成功后解除短路
+        manager.record_model_success("outage-a@test.com", "gemini-2.5-flash");
+        assert_eq!(
+            manager
+                .pool_outage_probe_at
+                .load(std::sync::atomic::Ordering::Acquire),
+            0
+        );
+
+        // 关闭后不再短路
+        let mut config = manager.get_circuit_breaker_config().await;
+        config.pool_outage_seconds = 0;
+        manager.update_circuit_breaker_config(config).await;
+        for _ in 0..3 {
+            assert_eq!(manager.pool_outage_retry_after().await, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prerefresh_selects_only_expiring_tokens() {
+        let pool = TestPool::new(&[]);
+
+        let now = chrono::Utc::now().timestamp();
+        for (id, expires_in) in [("fresh", 3600), ("expiring", 120)] {
+            pool.add_account_with(
+                id,
+                &format!("{}@test.com", id),
+                serde_json::json!({
+                    "token": { "expires_in": expires_in, "expiry_timestamp": now + expires_in }
+                }),
+            );
+        }
+
+        let manager = pool.token_manager().await;
+
+        assert_eq!(
+            manager.accounts_needing_refresh(now, TOKEN_PREREFRESH_MARGIN_SECS),
+            vec!["expiring".to_string()]
+        );
+        assert!(manager.accounts_needing_refresh(now, 0).is_empty());
+        // 已过期的 token 无论 margin 多少都需要刷新
+        assert_eq!(manager.accounts_needing_refresh(now + 3600, 0).len(), 2);
+
+        // 后台任务可被优雅关闭 (首轮预热会验证所有账号，先移除账号以免访问 OAuth 接口)
+        manager.remove_account("expiring");
+        manager.remove_account("fresh");
+        assert_eq!(manager.validate_all_tokens().await.refreshed, 0);
+        manager.start_token_prerefresh().await;
+        assert!(manager.prerefresh_handle.lock().await.is_some());
+        manager.abort_background_tasks().await;
+        assert!(manager.prerefresh_handle.lock().await.is_none());
+    }
 }
@@ -50,6 +50,13 @@ const V1_INTERNAL_BASE_URL_DAILY: &str = "https://daily-cloudcode-pa.googleapis.
 const V1_INTERNAL_BASE_URL_SANDBOX: &str =
     "https://daily-cloudcode-pa.sandbox.googleapis.com/v1internal";
 
+// Gemini Files API (resumable upload)，大体积媒体上传后以 fileData.fileUri 引用
+const GEMINI_FILES_UPLOAD_URL: &str = "https://generativelanguage.googleapis.com/upload/v1beta/files";
+// Files API 文件保留 48 小时，缓存的 URI 提前失效
+const UPLOADED_FILE_TTL: Duration = Duration::from_secs(46 * 3600);
+// 账号 token 未获 Files API 授权 (401 / 403) 后，在此期间内不再尝试上传
+const FILES_API_DENIED_TTL: Duration = Duration::from_secs(3600);
+
 const V1_INTERNAL_BASE_URL_FALLBACKS: [&str; 3] = [
     V1_INTERNAL_BASE_URL_SANDBOX, // 优先级 1: Sandbox (已知有效且稳定)
     V1_INTERNAL_BASE_URL_DAILY,   // 优先级 2: Daily (备用)
@@ -61,6 +68,16 @@ pub struct UpstreamClient {
     proxy_pool: Option<Arc<crate::proxy::proxy_pool::ProxyPoolManager>>,
     client_cache: DashMap<String, Client>, // proxy_id -> Client
     user_agent_override: RwLock<Option<String>>,
+    // [NEW] 自定义上游端点 (镜像 / 多区域)，为空时使用内置的 Sandbox → Daily → Prod
+    base_urls: RwLock<Vec<String>>,
+    // [NEW] Files API 上传地址 (测试时可替换)
+    files_upload_url: RwLock<String>,
+    // [FIX] 已上传文件 (账号 + 内容摘要 -> URI)，重试与重复请求不再重复上传
+    uploaded_files: DashMap<String, (String, std::time::Instant)>,
+    // [FIX] Files API 拒绝了其 token 的账号 -> 记录时间
+    files_api_denied: DashMap<String, std::time::Instant>,
+    // [NEW] 连接池与超时配置 (默认客户端与代理池客户端共用)
+    http_config: crate::proxy::config::UpstreamHttpConfig,
 }
 
 impl UpstreamClient {
@@ -68,7 +85,16 @@ impl UpstreamClient {
         proxy_config: Option<crate::proxy::config::UpstreamProxyConfig>,
         proxy_pool: Option<Arc<crate::proxy::proxy_pool::ProxyPoolManager>>,
     ) -> Self {
-        let default_client = Self::build_client_internal(proxy_config)
+        Self::with_http_config(proxy_config, proxy_pool, Default::default())
+    }
+
+    /// [NEW] 按连接池 / 超时配置构建客户端
+    pub fn with_http_config(
+        proxy_config: Option<crate::proxy::config::UpstreamProxyConfig>,
+        proxy_pool: Option<Arc<crate::proxy::proxy_pool::ProxyPoolManager>>,
+        http_config: crate::proxy::config::UpstreamHttpConfig,
+    ) -> Self {
+        let default_client = Self::build_client_internal(proxy_config, &http_config)
             .expect("Failed to create default HTTP client");
 
         Self {
@@ -76,21 +102,44 @@ impl UpstreamClient {
             proxy_pool,
             client_cache: DashMap::new(),
             user_agent_override: RwLock::new(None),
+            base_urls: RwLock::new(Vec::new()),
+            files_upload_url: RwLock::new(GEMINI_FILES_UPLOAD_URL.to_string()),
+            uploaded_files: DashMap::new(),
+            files_api_denied: DashMap::new(),
+            http_config,
+        }
+    }
+
+    /// 当前生效的连接池与超时配置
+    pub fn http_config(&self) -> &crate::proxy::config::UpstreamHttpConfig {
+        &self.http_config
+    }
+
+    /// 连接池 / 超时等基础设置，默认客户端与代理池客户端共用
+    /// 客户端级总超时取流式超时，非流式请求在发送时单独设置较短的超时
+    fn base_builder(http: &crate::proxy::config::UpstreamHttpConfig) -> reqwest::ClientBuilder {
+        let secs = |v: u64| (v > 0).then(|| Duration::from_secs(v));
+        let mut builder = Client::builder()
+            // Connection settings (优化连接复用，减少建立开销)
+            .pool_max_idle_per_host(http.pool_max_idle_per_host)
+            .pool_idle_timeout(secs(http.pool_idle_timeout_secs))
+            .tcp_keepalive(secs(http.tcp_keepalive_secs))
+            .user_agent(crate::constants::USER_AGENT.as_str());
+        if let Some(connect_timeout) = secs(http.connect_timeout_secs) {
+            builder = builder.connect_timeout(connect_timeout);
         }
+        if let Some(timeout) = secs(http.stream_timeout_secs) {
+            builder = builder.timeout(timeout);
+        }
+        builder
     }
 
     /// Internal helper to build a client with optional upstream proxy config
     fn build_client_internal(
         proxy_config: Option<crate::proxy::config::UpstreamProxyConfig>,
+        http_config: &crate::proxy::config::UpstreamHttpConfig,
     ) -> Result<Client, reqwest::Error> {
-        let mut builder = Client::builder()
-            // Connection settings (优化连接复用，减少建立开销)
-            .connect_timeout(Duration::from_secs(20))
-            .pool_max_idle_per_host(16) // 每主机最多 16 个空闲连接
-            .pool_idle_timeout(Duration::from_secs(90)) // 空闲连接保持 90 秒
-            .tcp_keepalive(Duration::from_secs(60)) // TCP 保活探测 60 秒
-            .timeout(Duration::from_secs(600))
-            .user_agent(crate::constants::USER_AGENT.as_str());
+        let mut builder = Self::base_builder(http_config);
 
         if let Some(config) = proxy_config {
             if config.enabled && !config.url.is_empty() {
@@ -110,13 +159,7 @@ impl UpstreamClient {
         proxy_config: crate::proxy::proxy_pool::PoolProxyConfig,
     ) -> Result<Client, reqwest::Error> {
         // Reuse base settings similar to default client but with specific proxy
-        Client::builder()
-            .connect_timeout(Duration::from_secs(20))
-            .pool_max_idle_per_host(16)
-            .pool_idle_timeout(Duration::from_secs(90))
-            .tcp_keepalive(Duration::from_secs(60))
-            .timeout(Duration::from_secs(600))
-            .user_agent(crate::constants::USER_AGENT.as_str())
+        Self::base_builder(&self.http_config)
             .proxy(proxy_config.proxy) // Apply the specific proxy
             .build()
     }
@@ -137,6 +180,138 @@ impl UpstreamClient {
             .unwrap_or_else(|| crate::constants::USER_AGENT.clone())
     }
 
+    /// Set upstream base URLs (按顺序降级，空列表恢复内置端点)
+    pub async fn set_base_urls(&self, urls: Vec<String>) {
+        let urls: Vec<String> = urls
+            .iter()
+            .map(|u| u.trim().trim_end_matches('/').to_string())
+            .filter(|u| !u.is_empty())
+            .collect();
+        let mut lock = self.base_urls.write().await;
+        *lock = urls;
+        tracing::debug!("UpstreamClient base URLs updated: {:?}", lock);
+    }
+
+    /// Get upstream base URLs in fallback order
+    pub async fn get_base_urls(&self) -> Vec<String> {
+        let urls = self.base_urls.read().await;
+        if urls.is_empty() {
+            V1_INTERNAL_BASE_URL_FALLBACKS
+                .iter()
+                .map(|u| u.to_string())
+                .collect()
+        } else {
+            urls.clone()
+        }
+    }
+
+    /// Set Files API upload URL (空字符串恢复默认地址)
+    pub async fn set_files_upload_url(&self, url: &str) {
+        let url = url.trim().trim_end_matches('/');
+        *self.files_upload_url.write().await = if url.is_empty() {
+            GEMINI_FILES_UPLOAD_URL.to_string()
+        } else {
+            url.to_string()
+        };
+    }
+
+    /// 通过 Gemini Files API 上传媒体文件，返回可在 fileData.fileUri 中引用的文件 URI
+    ///
+    /// 采用 resumable 协议: 先发起上传会话获取 x-goog-upload-url，再一次性写入全部数据并 finalize。
+    /// 上传的文件归属于该 access_token 对应的账号: 同一账号的相同内容在有效期内复用已上传的 URI；
+    /// 账号 token 被 Files API 拒绝 (401 / 403) 后在一段时间内直接返回错误，由调用方回退为内联数据
+    pub async fn upload_file(
+        &self,
+        access_token: &str,
+        account_id: Option<&str>,
+        data: Vec<u8>,
+        mime_type: &str,
+        display_name: &str,
+    ) -> Result<String, String> {
+        use sha2::{Digest, Sha256};
+
+        let account_key = account_id.unwrap_or_default().to_string();
+        if self
+            .files_api_denied
+            .get(&account_key)
+            .is_some_and(|at| at.elapsed() < FILES_API_DENIED_TTL)
+        {
+            return Err("Files API is not authorized for this account".to_string());
+        }
+        let digest: String = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let cache_key = format!("{}:{}:{}", account_key, mime_type, digest);
+        if let Some(entry) = self.uploaded_files.get(&cache_key) {
+            if entry.1.elapsed() < UPLOADED_FILE_TTL {
+                tracing::debug!("[Upstream] Reusing uploaded file {}", entry.0);
+                return Ok(entry.0.clone());
+            }
+        }
+        self.uploaded_files
+            .retain(|_, (_, at)| at.elapsed() < UPLOADED_FILE_TTL);
+
+        let client = self.get_client(account_id).await;
+        let start_url = self.files_upload_url.read().await.clone();
+
+        let start = client
+            .post(&start_url)
+            .bearer_auth(access_token)
+            .header("X-Goog-Upload-Protocol", "resumable")
+            .header("X-Goog-Upload-Command", "start")
+            .header("X-Goog-Upload-Header-Content-Length", data.len())
+            .header("X-Goog-Upload-Header-Content-Type", mime_type)
+            .json(&serde_json::json!({ "file": { "display_name": display_name } }))
+            .send()
+            .await
+            .map_err(|e| format!("File upload start failed: {}", e))?;
+        if !start.status().is_success() {
+            let status = start.status();
+            if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+                self.files_api_denied
+                    .insert(account_key, std::time::Instant::now());
+            }
+            let text = start.text().await.unwrap_or_default();
+            return Err(format!("File upload start returned {}: {}", status, text));
+        }
+        let upload_url = start
+            .headers()
+            .get("x-goog-upload-url")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| "File upload start response missing x-goog-upload-url".to_string())?;
+
+        let size = data.len();
+        let finish = client
+            .post(&upload_url)
+            .bearer_auth(access_token)
+            .header("X-Goog-Upload-Command", "upload, finalize")
+            .header("X-Goog-Upload-Offset", 0)
+            .header(header::CONTENT_LENGTH, size)
+            .body(data)
+            .send()
+            .await
+            .map_err(|e| format!("File upload failed: {}", e))?;
+        if !finish.status().is_success() {
+            let status = finish.status();
+            let text = finish.text().await.unwrap_or_default();
+            return Err(format!("File upload returned {}: {}", status, text));
+        }
+        let json: Value = finish
+            .json()
+            .await
+            .map_err(|e| format!("Parse file upload response failed: {}", e))?;
+        let uri = json
+            .pointer("/file/uri")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "File upload response missing file.uri".to_string())?;
+        tracing::debug!("[Upstream] Uploaded {} bytes ({}) as {}", size, mime_type, uri);
+        self.uploaded_files
+            .insert(cache_key, (uri.to_string(), std::time::Instant::now()));
+        Ok(uri.to_string())
+    }
+
     /// Get client for a specific account (or default if no proxy bound)
     pub async fn get_client(&self, account_id: Option<&str>) -> Client {
         if let Some(pool) = &self.proxy_pool {
@@ -270,28 +445,32 @@ impl UpstreamClient {
         // [NEW] 收集降级尝试记录
         let mut fallback_attempts: Vec<FallbackAttemptLog> = Vec::new();
 
-        // 遍历所有端点，失败时自动切换
-        for (idx, base_url) in V1_INTERNAL_BASE_URL_FALLBACKS.iter().enumerate() {
+        // 遍历所有端点，失败时自动切换 (网络错误 / 429 / 5xx，认证错误不切换)
+        let base_urls = self.get_base_urls().await;
+        for (idx, base_url) in base_urls.iter().enumerate() {
             let url = Self::build_url(base_url, method, query_string);
-            let has_next = idx + 1 < V1_INTERNAL_BASE_URL_FALLBACKS.len();
-
-            let response = client
-                .post(&url)
-                .headers(headers.clone())
-                .json(&body)
-                .send()
-                .await;
+            let has_next = idx + 1 < base_urls.len();
+
+            let attempt_start = std::time::Instant::now();
+            let mut request = client.post(&url).headers(headers.clone()).json(&body);
+            // [NEW] 非流式请求使用较短的总超时，流式请求沿用客户端级的长超时
+            if !method.starts_with("stream") && self.http_config.request_timeout_secs > 0 {
+                request =
+                    request.timeout(Duration::from_secs(self.http_config.request_timeout_secs));
+            }
+            let response = request.send().await;
 
             match response {
                 Ok(resp) => {
                     let status = resp.status();
+                    let latency = attempt_start.elapsed();
                     if status.is_success() {
                         if idx > 0 {
                             tracing::info!(
                                 "✓ Upstream fallback succeeded | Endpoint: {} | Status: {} | Next endpoints available: {}",
                                 base_url,
                                 status,
-                                V1_INTERNAL_BASE_URL_FALLBACKS.len() - idx - 1
+                                base_urls.len() - idx - 1
                             );
                         } else {
                             tracing::debug!(
@@ -300,6 +479,9 @@ impl UpstreamClient {
                                 status
                             );
                         }
+                        crate::proxy::middleware::upstream_latency::record_upstream_latency(
+                            latency,
+                        );
                         return Ok(UpstreamCallResult {
                             response: resp,
                             fallback_attempts,
@@ -326,6 +508,7 @@ impl UpstreamClient {
                     }
 
                     // 不可重试的错误或已是最后一个端点，直接返回
+                    crate::proxy::middleware::upstream_latency::record_upstream_latency(latency);
                     return Ok(UpstreamCallResult {
                         response: resp,
                         fallback_attempts,
@@ -404,6 +587,7 @@ impl UpstreamClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proxy::tests::harness::{dead_addr, spawn_upstream, v1internal_url};
 
     #[test]
     fn test_build_url() {
@@ -421,4 +605,90 @@ mod tests {
             "https://cloudcode-pa.googleapis.com/v1internal:streamGenerateContent?alt=sse"
         );
     }
+
+    #[tokio::test]
+    async fn test_base_url_failover_on_network_error() {
+        use axum::{Json, Router};
+
+        // 主端点: 绑定后立即释放的端口 (连接被拒绝)
+        let dead_addr = dead_addr().await;
+
+        // 备用端点: 正常返回
+        let healthy_addr =
+            spawn_upstream(Router::new().fallback(|| async { Json(serde_json::json!({ "ok": true })) }))
+                .await;
+
+        let client = UpstreamClient::new(None, None);
+        assert_eq!(client.get_base_urls().await.len(), 3);
+        client
+            .set_base_urls(vec![
+                v1internal_url(dead_addr),
+                format!("http://{}/v1internal/", healthy_addr),
+                "  ".to_string(),
+            ])
+            .await;
+        assert_eq!(client.get_base_urls().await.len(), 2);
+
+        let result = client
+            .call_v1_internal("generateContent", "token", serde_json::json!({}), None, None)
+            .await
+            .expect("secondary endpoint should serve the request");
+        assert_eq!(result.response.status(), StatusCode::OK);
+        assert_eq!(result.fallback_attempts.len(), 1);
+        assert_eq!(result.fallback_attempts[0].status, None);
+        let body: Value = result.response.json().await.unwrap();
+        assert_eq!(body["ok"], true);
+    }
+
+    #[tokio::test]
+    async fn test_configured_timeouts_applied_per_request_kind() {
+        use axum::{Json, Router};
+
+        // 上游: 1.5 秒后才返回
+        let app = Router::new().fallback(|| async {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            Json(serde_json::json!({ "ok": true }))
+        });
+        let addr = spawn_upstream(app).await;
+
+        // 默认值与此前的内置设置一致
+        let defaults = UpstreamClient::new(None, None);
+        assert_eq!(defaults.http_config().connect_timeout_secs, 20);
+        assert_eq!(defaults.http_config().pool_max_idle_per_host, 16);
+        assert_eq!(defaults.http_config().stream_timeout_secs, 600);
+        assert_eq!(defaults.http_config().request_timeout_secs, 600);
+
+        let http_config = crate::proxy::config::UpstreamHttpConfig {
+            connect_timeout_secs: 2,
+            pool_idle_timeout_secs: 5,
+            pool_max_idle_per_host: 2,
+            tcp_keepalive_secs: 0,
+            stream_timeout_secs: 10,
+            request_timeout_secs: 1,
+        };
+        let client = UpstreamClient::with_http_config(None, None, http_config.clone());
+        assert_eq!(client.http_config(), &http_config);
+        client.set_base_urls(vec![v1internal_url(addr)]).await;
+
+        // 非流式请求超过 request_timeout_secs 即失败
+        let started = std::time::Instant::now();
+        let result = client
+            .call_v1_internal("generateContent", "token", serde_json::json!({}), None, None)
+            .await;
+        assert!(result.is_err(), "non-stream request should time out");
+        assert!(started.elapsed() < Duration::from_millis(1400));
+
+        // 流式请求使用更长的 stream_timeout_secs
+        let result = client
+            .call_v1_internal(
+                "streamGenerateContent",
+                "token",
+                serde_json::json!({}),
+                Some("alt=sse"),
+                None,
+            )
+            .await
+            .expect("stream request should use the longer timeout");
+        assert_eq!(result.response.status(), StatusCode::OK);
+    }
 }
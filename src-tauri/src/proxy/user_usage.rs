@@ -0,0 +1,356 @@
+// 终端用户请求统计与限流
+// 客户端通过 OpenAI `user` 字段标识终端用户时，按用户维护请求计数 (管理 API 可查询)，
+// 并可按 user_rate_limit.requests_per_minute / tokens_per_minute 做固定窗口限流。未携带 user 的请求不受影响
+// token 预算按请求文本估算的输入 token 计算，在获取账号之前判定
+use std::time::{Duration, Instant};
+
+use axum::{
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+use serde::Serialize;
+
+use crate::proxy::config::UserRateLimitConfig;
+use crate::proxy::mappers::context_manager::estimate_tokens_from_str;
+use crate::proxy::mappers::openai::{OpenAIContent, OpenAIContentBlock, OpenAIRequest};
+
+/// 限流窗口
+const WINDOW: Duration = Duration::from_secs(60);
+/// 最多跟踪的用户数，达到上限时先清理一小时内无请求的用户，仍不足时淘汰最久未活跃的用户
+const MAX_TRACKED_USERS: usize = 10_000;
+const IDLE_EVICT_SECS: i64 = 3600;
+/// 按活跃度淘汰时一次降到上限的 90%，避免每个新用户都触发全表排序
+const EVICT_TARGET_USERS: usize = MAX_TRACKED_USERS * 9 / 10;
+
+struct UserUsage {
+    total_requests: u64,
+    rejected_requests: u64,
+    last_request_at: i64,
+    total_tokens: u64,
+    window_start: Instant,
+    window_count: u32,
+    window_tokens: u64,
+}
+
+/// 管理 API 返回的用户统计
+#[derive(Debug, Clone, Serialize)]
+pub struct UserUsageStats {
+    pub user: String,
+    pub total_requests: u64,
+    pub rejected_requests: u64,
+    /// 累计估算输入 token 数
+    pub total_tokens: u64,
+    /// 当前窗口内的请求数
+    pub current_window_requests: u32,
+    /// 当前窗口内的估算输入 token 数
+    pub current_window_tokens: u64,
+    pub last_request_at: i64,
+}
+
+/// 超出每用户限流
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserRateLimitExceeded {
+    pub limit: u32,
+    /// 超出的预算单位: "requests" / "tokens"
+    pub unit: &'static str,
+    /// 距窗口重置的秒数
+    pub reset_secs: u64,
+}
+
+impl UserRateLimitExceeded {
+    /// 429 响应，附带 X-RateLimit-* 与 Retry-After 头
+    pub fn into_response(self, user: &str) -> Response {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({
+                "error": {
+                    "message": format!(
+                        "Rate limit exceeded for user '{}': {} {} per minute. Retry after {}s.",
+                        user, self.limit, self.unit, self.reset_secs
+                    ),
+                    "type": "rate_limit_error",
+                    "code": "user_rate_limit_exceeded",
+                }
+            })),
+        )
+            .into_response();
+        let headers = response.headers_mut();
+        headers.insert("X-RateLimit-Limit", HeaderValue::from(self.limit));
+        headers.insert("X-RateLimit-Remaining", HeaderValue::from(0u32));
+        headers.insert("X-RateLimit-Reset", HeaderValue::from(self.reset_secs));
+        headers.insert("Retry-After", HeaderValue::from(self.reset_secs));
+        response
+    }
+}
+
+#[derive(Default)]
+pub struct UserUsageTracker {
+    users: DashMap<String, UserUsage>,
+}
+
+impl UserUsageTracker {
+    /// 记录一次请求 (estimated_tokens 为估算输入 token)；限额为 0 表示不限流。被拒绝的请求只计入 rejected_requests
+    pub fn record(
+        &self,
+        user: &str,
+        limits: &UserRateLimitConfig,
+        estimated_tokens: u64,
+    ) -> Result<(), UserRateLimitExceeded> {
+        if !self.users.contains_key(user) {
+            self.evict_if_full();
+        }
+
+        let now = Instant::now();
+        let mut usage = self
+            .users
+            .entry(user.to_string())
+            .or_insert_with(|| UserUsage {
+                total_requests: 0,
+                rejected_requests: 0,
+                last_request_at: 0,
+                total_tokens: 0,
+                window_start: now,
+                window_count: 0,
+                window_tokens: 0,
+            });
+        if now.duration_since(usage.window_start) >= WINDOW {
+            usage.window_start = now;
+            usage.window_count = 0;
+            usage.window_tokens = 0;
+        }
+        usage.last_request_at = chrono::Utc::now().timestamp();
+
+        // 窗口内首个请求即使超出 token 预算也放行，避免单个大请求永远无法通过
+        let exceeded =
+            if limits.requests_per_minute > 0 && usage.window_count >= limits.requests_per_minute {
+                Some((limits.requests_per_minute, "requests"))
+            } else if limits.tokens_per_minute > 0
+                && usage.window_count > 0
+                && usage.window_tokens + estimated_tokens > limits.tokens_per_minute as u64
+            {
+                Some((limits.tokens_per_minute, "tokens"))
+            } else {
+                None
+            };
+        if let Some((limit, unit)) = exceeded {
+            usage.rejected_requests += 1;
+            let elapsed = now.duration_since(usage.window_start);
+            return Err(UserRateLimitExceeded {
+                limit,
+                unit,
+                reset_secs: WINDOW.saturating_sub(elapsed).as_secs().max(1),
+            });
+        }
+        usage.window_count += 1;
+        usage.window_tokens += estimated_tokens;
+        usage.total_requests += 1;
+        usage.total_tokens += estimated_tokens;
+        Ok(())
+    }
+
+    pub fn get(&self, user: &str) -> Option<UserUsageStats> {
+        self.users.get(user).map(|u| Self::to_stats(u.key(), &u))
+    }
+
+    /// 所有用户统计，按请求数降序
+    pub fn stats(&self) -> Vec<UserUsageStats> {
+        let mut stats: Vec<UserUsageStats> = self
+            .users
+            .iter()
+            .map(|u| Self::to_stats(u.key(), &u))
+            .collect();
+        stats.sort_by(|a, b| {
+            b.total_requests
+                .cmp(&a.total_requests)
+                .then(a.user.cmp(&b.user))
+        });
+        stats
+    }
+
+    pub fn clear(&self) {
+        self.users.clear();
+    }
+
+    fn to_stats(user: &str, usage: &UserUsage) -> UserUsageStats {
+        let in_window = usage.window_start.elapsed() < WINDOW;
+        UserUsageStats {
+            user: user.to_string(),
+            total_requests: usage.total_requests,
+            rejected_requests: usage.rejected_requests,
+            total_tokens: usage.total_tokens,
+            current_window_requests: if in_window { usage.window_count } else { 0 },
+            current_window_tokens: if in_window { usage.window_tokens } else { 0 },
+            last_request_at: usage.last_request_at,
+        }
+    }
+
+    fn evict_if_full(&self) {
+        if self.users.len() < MAX_TRACKED_USERS {
+            return;
+        }
+        let cutoff = chrono::Utc::now().timestamp() - IDLE_EVICT_SECS;
+        self.users.retain(|_, u| u.last_request_at >= cutoff);
+
+        if self.users.len() >= MAX_TRACKED_USERS {
+            let mut by_activity: Vec<(i64, String)> = self
+                .users
+                .iter()
+                .map(|u| (u.last_request_at, u.key().clone()))
+                .collect();
+            by_activity.sort_unstable();
+            let excess = by_activity.len().saturating_sub(EVICT_TARGET_USERS);
+            for (_, user) in by_activity.into_iter().take(excess) {
+                self.users.remove(&user);
+            }
+        }
+        tracing::debug!(
+            "[User-Usage] Evicted inactive users, {} remaining",
+            self.users.len()
+        );
+    }
+}
+
+/// 估算请求的输入 token (仅统计文本内容，图片等媒体不计入)
+pub fn estimate_request_tokens(request: &OpenAIRequest) -> u64 {
+    request
+        .messages
+        .iter()
+        .map(|msg| match msg.content.as_ref() {
+            Some(OpenAIContent::String(s)) => estimate_tokens_from_str(s) as u64,
+            Some(OpenAIContent::Array(blocks)) => blocks
+                .iter()
+                .map(|block| match block {
+                    OpenAIContentBlock::Text { text } => estimate_tokens_from_str(text) as u64,
+                    _ => 0,
+                })
+                .sum(),
+            None => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mappers::openai::OpenAIRequest;
+    use crate::proxy::session_manager::SessionManager;
+
+    fn request_for(user: Option<&str>) -> OpenAIRequest {
+        let mut body = serde_json::json!({
+            "model": "gemini-2.5-flash",
+            "messages": [{ "role": "user", "content": "same conversation opener for both users" }]
+        });
+        if let Some(user) = user {
+            body["user"] = serde_json::json!(user);
+        }
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn limits(requests_per_minute: u32, tokens_per_minute: u32) -> UserRateLimitConfig {
+        UserRateLimitConfig {
+            requests_per_minute,
+            tokens_per_minute,
+        }
+    }
+
+    #[test]
+    fn test_per_user_counters_and_rate_limit() {
+        // 相同对话内容、不同 user: 粘性会话与计数均相互独立
+        let alice = request_for(Some("alice"));
+        let bob = request_for(Some("bob"));
+        let anonymous = request_for(None);
+        let alice_sid = SessionManager::extract_openai_session_id(&alice);
+        assert_ne!(alice_sid, SessionManager::extract_openai_session_id(&bob));
+        assert_ne!(
+            alice_sid,
+            SessionManager::extract_openai_session_id(&anonymous)
+        );
+        assert_eq!(alice_sid, SessionManager::extract_openai_session_id(&alice));
+
+        let tracker = UserUsageTracker::default();
+        for _ in 0..3 {
+            tracker.record("alice", &limits(0, 0), 0).unwrap();
+        }
+        tracker.record("bob", &limits(0, 0), 0).unwrap();
+        assert_eq!(tracker.get("alice").unwrap().total_requests, 3);
+        assert_eq!(tracker.get("bob").unwrap().total_requests, 1);
+        assert_eq!(tracker.stats()[0].user, "alice");
+
+        // 每分钟 4 次: alice 第 5 次被拒绝，bob 不受影响
+        tracker.record("alice", &limits(4, 0), 0).unwrap();
+        let exceeded = tracker.record("alice", &limits(4, 0), 0).unwrap_err();
+        assert_eq!(exceeded.limit, 4);
+        assert!(exceeded.reset_secs >= 1 && exceeded.reset_secs <= 60);
+        tracker.record("bob", &limits(4, 0), 0).unwrap();
+
+        let alice_stats = tracker.get("alice").unwrap();
+        assert_eq!(alice_stats.total_requests, 4);
+        assert_eq!(alice_stats.rejected_requests, 1);
+
+        let response = exceeded.into_response("alice");
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["X-RateLimit-Limit"], "4");
+        assert_eq!(response.headers()["X-RateLimit-Remaining"], "0");
+        assert!(response.headers().contains_key("X-RateLimit-Reset"));
+        assert!(response.headers().contains_key("Retry-After"));
+    }
+
+    #[test]
+    fn test_tracked_users_capped_when_all_active() {
+        let tracker = UserUsageTracker::default();
+        for i in 0..MAX_TRACKED_USERS {
+            tracker.record(&format!("user-{}", i), &limits(0, 0), 0).unwrap();
+        }
+        // 最早的用户略早于其他用户活跃，淘汰时优先移除
+        tracker.users.get_mut("user-0").unwrap().last_request_at -= 10;
+        assert_eq!(tracker.users.len(), MAX_TRACKED_USERS);
+
+        // 已跟踪的用户不触发淘汰
+        tracker.record("user-1", &limits(0, 0), 0).unwrap();
+        assert_eq!(tracker.users.len(), MAX_TRACKED_USERS);
+
+        // 所有用户都在一小时内活跃: 按活跃度淘汰，不超过上限
+        tracker.record("newcomer", &limits(0, 0), 0).unwrap();
+        assert_eq!(tracker.users.len(), EVICT_TARGET_USERS + 1);
+        assert!(tracker.get("newcomer").is_some());
+        assert!(tracker.get("user-0").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_per_user_token_budget() {
+        let request = request_for(Some("carol"));
+        let tokens = estimate_request_tokens(&request);
+        assert!(tokens > 0);
+
+        // 每分钟 2.5 个请求的 token 预算: 前两次通过，第三次被拒绝，请求数限额不受影响
+        let budget = limits(0, (tokens * 5 / 2) as u32);
+        let tracker = UserUsageTracker::default();
+        tracker.record("carol", &budget, tokens).unwrap();
+        tracker.record("carol", &budget, tokens).unwrap();
+        let exceeded = tracker.record("carol", &budget, tokens).unwrap_err();
+        assert_eq!(exceeded.unit, "tokens");
+        tracker.record("dave", &budget, tokens).unwrap();
+
+        // 窗口内首个请求即使单独超出预算也放行
+        tracker.record("erin", &limits(0, 1), tokens).unwrap();
+
+        let carol = tracker.get("carol").unwrap();
+        assert_eq!(carol.total_requests, 2);
+        assert_eq!(carol.current_window_tokens, tokens * 2);
+        assert_eq!(carol.rejected_requests, 1);
+
+        let response = exceeded.into_response("carol");
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "user_rate_limit_exceeded");
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("tokens per minute"));
+    }
+}
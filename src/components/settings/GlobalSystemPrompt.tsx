@@ -9,6 +9,7 @@ interface GlobalSystemPromptProps {
 const DEFAULT_CONFIG: GlobalSystemPromptConfig = {
     enabled: false,
     content: '',
+    append_content: '',
 };
 
 export default function GlobalSystemPrompt({
@@ -65,6 +66,23 @@ export default function GlobalSystemPrompt({
                             })}
                         </p>
                     </div>
+                    {/* 追加内容 (位于客户端提示词之后) */}
+                    <div className="space-y-1">
+                        <p className="text-xs text-gray-500 dark:text-gray-400">
+                            {t("settings.global_system_prompt.append_label", {
+                                defaultValue: "追加内容 (可选)：位于客户端提示词之后",
+                            })}
+                        </p>
+                        <textarea
+                            value={config.append_content ?? ''}
+                            onChange={(e) => onChange({ ...config, append_content: e.target.value })}
+                            placeholder={t("settings.global_system_prompt.append_placeholder", {
+                                defaultValue: "输入追加在客户端系统提示词之后的内容...",
+                            })}
+                            rows={3}
+                            className="w-full bg-white dark:bg-base-100 border border-gray-200 dark:border-gray-700 rounded-lg px-4 py-3 text-sm focus:ring-2 focus:ring-purple-500/20 outline-none transition-all resize-y min-h-[80px]"
+                        />
+                    </div>
                     {config.content.length > 2000 && (
                         <div className="bg-amber-50 dark:bg-amber-900/20 border border-amber-200 dark:border-amber-700/30 rounded-lg p-3">
                             <p className="text-xs text-amber-700 dark:text-amber-400">
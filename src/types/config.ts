@@ -10,6 +10,8 @@ export interface ProxyConfig {
     port: number;
     api_key: string;
     admin_password?: string;
+    /** 额外允许访问代理接口的 API Key (可单独停用) */
+    api_keys?: ApiKeyEntry[];
     auto_start: boolean;
     custom_mapping?: Record<string, string>;
     request_timeout: number;
@@ -20,10 +22,143 @@ export interface ProxyConfig {
     scheduling?: StickySessionConfig;
     experimental?: ExperimentalConfig;
     user_agent_override?: string;
+    /** 上游 v1internal 端点列表 (按顺序降级)，为空时使用内置端点 */
+    upstream_base_urls?: string[];
     saved_user_agent?: string;
     thinking_budget?: ThinkingBudgetConfig;
     global_system_prompt?: GlobalSystemPromptConfig;
     proxy_pool?: ProxyPoolConfig;
+    streaming?: StreamingConfig;
+    openai_compat?: OpenAICompatConfig;
+    default_model?: DefaultModelConfig;
+    user_rate_limit?: UserRateLimitConfig;
+    account_usage?: AccountUsageConfig;
+    access_log?: AccessLogConfig;
+    prompt_filter?: PromptFilterConfig;
+    workload_routing?: WorkloadRoutingConfig;
+}
+
+/** 代理接口的附加 API Key */
+export interface ApiKeyEntry {
+    key: string;
+    /** 备注名称 (用于日志) */
+    name?: string;
+    enabled: boolean;
+}
+
+/** 工作负载路由: 按请求分类 (code / chat) 限定使用的账号，未配置的分类使用全部账号 */
+export interface WorkloadRoutingConfig {
+    /** 分类 -> 专用账号邮箱列表 */
+    accounts: Record<string, string[]>;
+}
+
+/** 提示词屏蔽规则: 用户消息命中时在转发上游前拒绝 (均不区分大小写) */
+export interface PromptFilterConfig {
+    enabled: boolean;
+    rules: PromptFilterRule[];
+    /** 命中时返回的状态码: 400 (默认) 或 403 */
+    status_code?: number;
+}
+
+export interface PromptFilterRule {
+    /** 规则标识，仅写入日志 */
+    id: string;
+    pattern: string;
+    /** true 时 pattern 按正则解析，否则按子串匹配 */
+    regex?: boolean;
+}
+
+/** 访问日志配置: 每个请求一行 INFO 级别结构化日志 */
+export interface AccessLogConfig {
+    enabled: boolean;
+    format: 'json' | 'logfmt';
+}
+
+/** 账号用量统计配置 */
+export interface AccountUsageConfig {
+    /** 统计窗口长度 (分钟)，窗口结束后计数清零 */
+    window_minutes: number;
+    /** 账号每日预算: 达到上限的账号自动跳过，到重置时间后恢复 */
+    daily_quota?: DailyQuotaConfig;
+    /** 慢账号降级: 首字延迟持续偏高的账号排序后移 */
+    slow_account?: SlowAccountConfig;
+}
+
+/** 慢账号降级配置 */
+export interface SlowAccountConfig {
+    /** 平均首字延迟阈值 (毫秒)，0 表示不降级 */
+    ttft_threshold_ms: number;
+    /** 每个账号保留的最近样本数 */
+    window_size: number;
+    /** 参与判定所需的最少样本数 */
+    min_samples: number;
+    /** 样本有效期 (秒)，过期样本不参与判定；0 表示不过期 */
+    max_sample_age_secs?: number;
+}
+
+/** 账号每日预算 (上限为 0 表示不限制) */
+export interface DailyBudget {
+    max_requests: number;
+    max_tokens: number;
+}
+
+export interface DailyQuotaConfig extends DailyBudget {
+    /** 每日重置时间 (UTC, "HH:MM") */
+    reset_time_utc: string;
+    /** 按账号 email 单独配置的预算 */
+    accounts: Record<string, DailyBudget>;
+}
+
+/** 终端用户 (OpenAI `user` 字段) 限流配置 */
+export interface UserRateLimitConfig {
+    /** 每个 user 每分钟最大请求数 (0 表示不限制，仅统计) */
+    requests_per_minute: number;
+    /** 每个 user 每分钟最大输入 token 数 (按请求文本估算，0 表示不限制) */
+    tokens_per_minute?: number;
+}
+
+/** 未知模型兜底配置 */
+export interface DefaultModelConfig {
+    /** 未传模型或模型无法识别时路由到兜底模型 (默认关闭，保持透传) */
+    enabled: boolean;
+    /** 兜底目标模型 */
+    model: string;
+}
+
+/** 不支持参数的处理策略 */
+export type UnsupportedParamPolicy = 'ignore' | 'reject';
+
+/** 被安全策略截断的响应处理策略 */
+export type SafetyPartialPolicy = 'content_filter' | 'error';
+
+/** OpenAI 兼容性配置 */
+export interface OpenAICompatConfig {
+    /** logit_bias 无法映射到 Gemini: 忽略 (默认) 或返回 400 */
+    logit_bias: UnsupportedParamPolicy;
+    /** 部分输出后被 SAFETY 截断: 返回部分内容 (默认) 或返回错误 */
+    safety_partial?: SafetyPartialPolicy;
+    /** 非流式响应的 model 字段回显请求的模型名 (默认开启)，真实模型见 X-Mapped-Model 头 */
+    echo_requested_model?: boolean;
+    /** chat / completions 请求体大小上限 (字节)，超出返回 413，0 表示不限制 */
+    max_request_body_bytes?: number;
+    /** 丢弃思维链 (reasoning_content)，仅输出最终回答 (默认关闭)，请求体 strip_reasoning 可覆盖 */
+    strip_reasoning?: boolean;
+    /** prediction (Predicted Outputs) 始终忽略；开启后预测内容作为参考文本追加到系统指令 (默认关闭) */
+    prediction_hint?: boolean;
+    /** 音频输出 (audio / modalities: ["audio"]): 返回 400 (默认) 或降级为纯文本 */
+    audio_output?: UnsupportedParamPolicy;
+}
+
+/** 流式响应缓冲配置 */
+export interface StreamingConfig {
+    /** 累积多少字节后写出一次，0 表示不缓冲 */
+    flush_threshold_bytes: number;
+    /** 缓冲数据最长等待时间 (毫秒) */
+    max_flush_delay_ms: number;
+    /** peek 期间发送心跳的间隔 (毫秒)，0 表示关闭 */
+    peek_heartbeat_ms?: number;
+    /** 不单独输出空白 content 分片，合并到相邻正文分片 */
+    suppress_whitespace_deltas?: boolean;
 }
 
 // ============================================================================
@@ -51,6 +186,8 @@ export interface GlobalSystemPromptConfig {
     enabled: boolean;
     /** 提示词内容 */
     content: string;
+    /** 追加在客户端系统提示词之后的内容 (可选) */
+    append_content?: string;
 }
 
 export interface DebugLoggingConfig {
@@ -63,6 +200,8 @@ export type SchedulingMode = 'CacheFirst' | 'Balance' | 'PerformanceFirst';
 export interface StickySessionConfig {
     mode: SchedulingMode;
     max_wait_seconds: number;
+    session_ttl_seconds?: number;
+    max_sessions?: number;
 }
 
 export type ZaiDispatchMode = 'off' | 'exclusive' | 'pooled' | 'fallback';
@@ -110,11 +249,51 @@ export interface ExperimentalConfig {
     context_compression_threshold_l1?: number;
     context_compression_threshold_l2?: number;
     context_compression_threshold_l3?: number;
+    enable_tool_args_repair?: boolean;
+    force_stream_internally?: boolean;
+    enable_image_postprocess?: boolean;
+    enable_request_dedup?: boolean;
+    input_image_max_dimension?: number;
+    input_image_max_bytes?: number;
+    remote_image_allowed_hosts?: string[];
+    max_collected_bytes?: number;
+    media_upload_threshold_bytes?: number;
+    expose_upstream_latency?: boolean;
+    expose_rotation_trace?: boolean;
+    idempotency_ttl_secs?: number;
+    idempotency_max_entries?: number;
+    idempotency_max_bytes?: number;
+    response_compression?: boolean;
+    batch_max_requests?: number;
+    batch_max_concurrency?: number;
+    shutdown_grace_secs?: number;
+    upstream_http?: UpstreamHttpConfig;
+}
+
+/** 上游 HTTP 客户端连接池与超时配置 (重启服务后生效)，超时类取值为 0 表示不限制 */
+export interface UpstreamHttpConfig {
+    connect_timeout_secs: number;
+    pool_idle_timeout_secs: number;
+    pool_max_idle_per_host: number;
+    /** 0 表示关闭 TCP keepalive */
+    tcp_keepalive_secs: number;
+    /** 流式请求总超时 (含读取完整响应流) */
+    stream_timeout_secs: number;
+    /** 非流式请求总超时 */
+    request_timeout_secs: number;
 }
 
 export interface CircuitBreakerConfig {
     enabled: boolean;
     backoff_steps: number[];
+    /** 同一账号 + 模型连续 5xx 多少次后熔断 */
+    model_failure_threshold?: number;
+    /** 连续失败统计窗口 (秒) */
+    model_failure_window_seconds?: number;
+    /** 熔断持续时间 (秒)，之后进入半开状态试探 */
+    model_open_seconds?: number;
+    /** 账号池全部限流后快速失败 (429) 的时长 (秒)，期间每个周期放行一个试探请求；0 表示关闭 */
+    pool_outage_seconds?: number;
 }
 
 export interface AppConfig {